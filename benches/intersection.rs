@@ -0,0 +1,119 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::collision::materials::Lambertian;
+use ray_tracing::collision::objects::{Parallelogram, Sphere, Torus, Triangle};
+use ray_tracing::collision::{Hittable, Material};
+use ray_tracing::*;
+
+use std::hint::black_box;
+use std::sync::Arc;
+
+fn material() -> Arc<dyn Material> {
+    Arc::new(Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    })
+}
+
+/// Rays from around the origin towards the unit cube centered at `(0, 0, -3)`, most of which
+/// hit the objects below.
+fn rays(count: usize) -> Vec<Ray> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| {
+            let origin = Point3::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1), 0.0);
+            let target = Point3::new(
+                rng.gen_range(-0.7..0.7),
+                rng.gen_range(-0.7..0.7),
+                -3.0 + rng.gen_range(-0.5..0.5),
+            );
+            Ray {
+                origin,
+                direction: target - origin,
+            }
+        })
+        .collect()
+}
+
+fn bench_object<T: Hittable>(c: &mut Criterion, name: &str, object: &T) {
+    let rays = rays(1024);
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            rays.iter()
+                .filter(|ray| object.hit(ray, 0.001, f64::INFINITY).is_some())
+                .count()
+        })
+    });
+}
+
+fn sphere(c: &mut Criterion) {
+    let sphere = Sphere {
+        center: Point3::new(0.0, 0.0, -3.0),
+        radius: 0.5,
+        material: material(),
+    };
+    bench_object(c, "sphere hit", &sphere);
+}
+
+fn torus(c: &mut Criterion) {
+    let torus = Torus {
+        center: Point3::new(0.0, 0.0, -3.0),
+        major_radius: 0.5,
+        minor_radius: 0.2,
+        material: material(),
+    };
+    bench_object(c, "torus hit", &torus);
+}
+
+fn parallelogram(c: &mut Criterion) {
+    let parallelogram = Parallelogram::new(
+        Point3::new(-0.5, -0.5, -2.5),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        material(),
+    );
+    bench_object(c, "parallelogram hit", &parallelogram);
+}
+
+fn triangle(c: &mut Criterion) {
+    let triangle = Triangle::new(
+        [
+            Point3::new(-0.7, -0.7, -3.0),
+            Point3::new(0.7, -0.7, -3.0),
+            Point3::new(0.0, 0.7, -3.0),
+        ],
+        material(),
+    )
+    .unwrap()
+    .with_uvs([(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)]);
+    bench_object(c, "triangle hit", &triangle);
+}
+
+fn bvh(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let material = material();
+    let spheres: Vec<Arc<dyn Hittable + Send + Sync>> = (0..1000)
+        .map(|_| {
+            let center = Point3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-4.0..-2.0),
+            );
+            Arc::new(Sphere {
+                center,
+                radius: 0.02,
+                material: material.clone(),
+            }) as Arc<dyn Hittable + Send + Sync>
+        })
+        .collect();
+
+    c.bench_function("bvh build (1000 spheres)", |b| {
+        b.iter(|| Bvh::new(black_box(spheres.clone())))
+    });
+    bench_object(c, "bvh hit (1000 spheres)", &Bvh::new(spheres));
+}
+
+criterion_group!(benches, sphere, torus, parallelogram, triangle, bvh);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::collision::Hittable;
+use ray_tracing::render::{render, PixelOrder, RenderSettings};
+use ray_tracing::scene::Scene;
+use ray_tracing::Camera;
+
+use std::sync::Arc;
+
+fn frame(c: &mut Criterion) {
+    let scene = Scene::load(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/spheres.json")).unwrap();
+    let world = Bvh::new(scene.world().unwrap());
+    let camera = scene
+        .camera_builder()
+        .aspect_ratio(16.0 / 9.0)
+        .build()
+        .unwrap();
+    let settings = RenderSettings {
+        width: 128,
+        height: 72,
+        samples_per_pixel: 4,
+        show_progress: false,
+        seed: Some(0),
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(20);
+    group.bench_function("spheres 128x72 4spp", |b| {
+        b.iter(|| render(&world, &camera, &settings))
+    });
+    let morton = RenderSettings {
+        pixel_order: PixelOrder::Morton,
+        ..settings.clone()
+    };
+    group.bench_function("spheres 128x72 4spp morton", |b| {
+        b.iter(|| render(&world, &camera, &morton))
+    });
+    group.finish();
+}
+
+/// Nothing but sky, so that what's measured is what every sample costs before it hits anything.
+fn samples(c: &mut Criterion) {
+    let world: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
+    let camera = Camera::builder().aspect_ratio(16.0 / 9.0).build().unwrap();
+    let settings = RenderSettings {
+        width: 128,
+        height: 72,
+        samples_per_pixel: 64,
+        show_progress: false,
+        seed: Some(0),
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(20);
+    group.bench_function("sky 128x72 64spp", |b| {
+        b.iter(|| render(&world, &camera, &settings))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, frame, samples);
+criterion_main!(benches);
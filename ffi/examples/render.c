@@ -0,0 +1,61 @@
+// Renders a small scene through the C bindings and writes it to out.ppm.
+//
+//     cargo build --release -p ray_tracing_ffi
+//     cc ffi/examples/render.c -Iffi/include -Ltarget/release -lray_tracing_ffi -lm -o render
+//     LD_LIBRARY_PATH=target/release ./render
+
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "ray_tracing.h"
+
+static int check(RtStatus status) {
+    if (status != RT_STATUS_OK) {
+        const char *message = rt_last_error();
+        fprintf(stderr, "error: %s\n", message ? message : "null pointer");
+        exit(1);
+    }
+    return 0;
+}
+
+int main(void) {
+    RtScene *scene = rt_scene_new();
+
+    uint32_t ground, glass, metal;
+    check(rt_scene_add_lambertian(scene, (RtVec3){0.8, 0.8, 0.0}, &ground));
+    check(rt_scene_add_dielectric(scene, 1.5, &glass));
+    check(rt_scene_add_metal(scene, (RtVec3){0.8, 0.6, 0.2}, 0.1, &metal));
+
+    check(rt_scene_add_sphere(scene, (RtVec3){0.0, -100.5, -1.0}, 100.0, ground));
+    check(rt_scene_add_sphere(scene, (RtVec3){-0.6, 0.0, -1.5}, 0.5, glass));
+    check(rt_scene_add_sphere(scene, (RtVec3){0.6, 0.0, -1.5}, 0.5, metal));
+    check(rt_scene_set_camera(scene, (RtVec3){0.0, 0.5, 1.0}, (RtVec3){0.0, 0.0, -1.5},
+                              (RtVec3){0.0, 1.0, 0.0}, 40.0, 0.0, 0.0));
+
+    RtSettings settings = rt_settings_default();
+    settings.width = 320;
+    settings.height = 180;
+    settings.samples_per_pixel = 50;
+
+    size_t len = rt_buffer_len(settings.width, settings.height);
+    float *pixels = malloc(len * sizeof(float));
+    check(rt_render(scene, &settings, pixels, len));
+    rt_scene_free(scene);
+
+    FILE *out = fopen("out.ppm", "wb");
+    fprintf(out, "P6\n%u %u\n255\n", settings.width, settings.height);
+    for (size_t i = 0; i < len; i += 4) {
+        for (size_t c = 0; c < 3; c++) {
+            // encoded to sRGB, like the renderer's own output
+            float linear = pixels[i + c];
+            float value = linear <= 0.0031308f ? 12.92f * linear
+                                               : 1.055f * powf(linear, 1.0f / 2.4f) - 0.055f;
+            fputc(value >= 1.0f ? 255 : (int)(value * 256.0f), out);
+        }
+    }
+    fclose(out);
+    free(pixels);
+
+    return 0;
+}
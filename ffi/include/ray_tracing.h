@@ -0,0 +1,183 @@
+#ifndef RAY_TRACING_H
+#define RAY_TRACING_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum RtStatus {
+  RT_STATUS_OK = 0,
+  /**
+   * A required pointer argument was null.
+   */
+  RT_STATUS_NULL_POINTER = 1,
+  /**
+   * Anything else, described by `rt_last_error`.
+   */
+  RT_STATUS_ERROR = 2,
+} RtStatus;
+
+/**
+ * A scene under construction. Create one with `rt_scene_new` or `rt_scene_load`, and free it
+ * with `rt_scene_free`.
+ */
+typedef struct RtScene RtScene;
+
+typedef struct RtVec3 {
+  double x;
+  double y;
+  double z;
+} RtVec3;
+
+/**
+ * The subset of the render settings that can be set from C.
+ */
+typedef struct RtSettings {
+  uint32_t width;
+  uint32_t height;
+  uint32_t samples_per_pixel;
+  int32_t max_depth;
+  /**
+   * Whether to use `seed`, making the render reproducible.
+   */
+  bool use_seed;
+  uint64_t seed;
+} RtSettings;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The message of the last error on this thread, or null if there was none. The string belongs
+ * to the library and is valid until the next failing call.
+ */
+const char *rt_last_error(void);
+
+/**
+ * An empty scene, with a camera at the origin looking down the negative z axis.
+ */
+struct RtScene *rt_scene_new(void);
+
+/**
+ * Loads a JSON scene file, storing the new scene in `*out`.
+ *
+ * # Safety
+ *
+ * `path` must be a nul-terminated string and `out` must be valid for writes.
+ */
+enum RtStatus rt_scene_load(const char *path, struct RtScene **out);
+
+/**
+ * # Safety
+ *
+ * `scene` must have come from `rt_scene_new` or `rt_scene_load` and not been freed yet, or be
+ * null.
+ */
+void rt_scene_free(struct RtScene *scene);
+
+/**
+ * Sets the camera. A `focus_dist` of zero or less focuses on `look_at`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene.
+ */
+enum RtStatus rt_scene_set_camera(struct RtScene *scene,
+                                  struct RtVec3 look_from,
+                                  struct RtVec3 look_at,
+                                  struct RtVec3 vup,
+                                  double vertical_fov,
+                                  double aperture,
+                                  double focus_dist);
+
+/**
+ * Adds a diffuse material, storing its id in `*id`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene and `id` valid for writes.
+ */
+enum RtStatus rt_scene_add_lambertian(struct RtScene *scene, struct RtVec3 albedo, uint32_t *id);
+
+/**
+ * Adds a reflective material, storing its id in `*id`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene and `id` valid for writes.
+ */
+enum RtStatus rt_scene_add_metal(struct RtScene *scene,
+                                 struct RtVec3 albedo,
+                                 double fuzz,
+                                 uint32_t *id);
+
+/**
+ * Adds a glass-like material, storing its id in `*id`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene and `id` valid for writes.
+ */
+enum RtStatus rt_scene_add_dielectric(struct RtScene *scene, double refraction_index, uint32_t *id);
+
+/**
+ * Adds a light emitting material, storing its id in `*id`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene and `id` valid for writes.
+ */
+enum RtStatus rt_scene_add_light(struct RtScene *scene, struct RtVec3 emit, uint32_t *id);
+
+/**
+ * # Safety
+ *
+ * `scene` must be a live scene.
+ */
+enum RtStatus rt_scene_add_sphere(struct RtScene *scene,
+                                  struct RtVec3 center,
+                                  double radius,
+                                  uint32_t material);
+
+/**
+ * A box spanned by `u`, `v` and `w` from `corner`.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene.
+ */
+enum RtStatus rt_scene_add_parallelogram(struct RtScene *scene,
+                                         struct RtVec3 corner,
+                                         struct RtVec3 u,
+                                         struct RtVec3 v,
+                                         struct RtVec3 w,
+                                         uint32_t material);
+
+struct RtSettings rt_settings_default(void);
+
+/**
+ * The number of floats `rt_render` needs for an image of this size.
+ */
+uintptr_t rt_buffer_len(uint32_t width, uint32_t height);
+
+/**
+ * Renders the scene into `buffer`, which has to hold exactly `rt_buffer_len` floats: linear
+ * RGBA with premultiplied alpha, starting from the top row.
+ *
+ * # Safety
+ *
+ * `scene` must be a live scene, `settings` valid for reads and `buffer` valid for writes of
+ * `len` floats.
+ */
+enum RtStatus rt_render(const struct RtScene *scene,
+                        const struct RtSettings *settings,
+                        float *buffer,
+                        uintptr_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RAY_TRACING_H */
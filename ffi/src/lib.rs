@@ -0,0 +1,529 @@
+//! C bindings for the renderer.
+//!
+//! Scenes are built up behind an opaque [`RtScene`] handle and rendered into a buffer owned by
+//! the caller, as linear RGBA floats starting from the top row. Every function that can fail
+//! returns an [`RtStatus`], and the reason stays available from [`rt_last_error`] until the next
+//! failure on the same thread.
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::framebuffer::Framebuffer;
+use ray_tracing::render::{RenderSettings, Renderer};
+use ray_tracing::scene::{CameraDescription, MaterialDescription, ObjectDescription, Scene};
+use ray_tracing::scene_graph::SceneGraph;
+use ray_tracing::Vec3;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// A scene under construction. Create one with `rt_scene_new` or `rt_scene_load`, and free it
+/// with `rt_scene_free`.
+pub struct RtScene {
+    scene: Scene,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RtStatus {
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullPointer = 1,
+    /// Anything else, described by `rt_last_error`.
+    Error = 2,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RtVec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl From<RtVec3> for Vec3 {
+    fn from(v: RtVec3) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+/// The subset of the render settings that can be set from C.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RtSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    /// Whether to use `seed`, making the render reproducible.
+    pub use_seed: bool,
+    pub seed: u64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // interior nul bytes would cut the message short anyway
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into a status code.
+fn guard(f: impl FnOnce() -> Result<(), String>) -> RtStatus {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => RtStatus::Ok,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            RtStatus::Error
+        }
+        Err(_) => {
+            set_last_error("the renderer panicked".to_owned());
+            RtStatus::Error
+        }
+    }
+}
+
+/// The message of the last error on this thread, or null if there was none. The string belongs
+/// to the library and is valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn rt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// An empty scene, with a camera at the origin looking down the negative z axis.
+#[no_mangle]
+pub extern "C" fn rt_scene_new() -> *mut RtScene {
+    let scene = Scene {
+        camera: CameraDescription {
+            look_from: Vec3::new(0.0, 0.0, 0.0),
+            look_at: Vec3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vertical_fov: 90.0,
+            aperture: 0.0,
+            focus_dist: None,
+            focus_on: None,
+            vignetting: false,
+            distortion: [0.0; 2],
+            lateral_aberration: 0.0,
+            longitudinal_aberration: 0.0,
+        },
+        materials: HashMap::new(),
+        objects: Vec::new(),
+        prototypes: HashMap::new(),
+        fog: None,
+        sky: None,
+        portals: Vec::new(),
+        graph: SceneGraph::default(),
+        camera_node: None,
+    };
+
+    Box::into_raw(Box::new(RtScene { scene }))
+}
+
+/// Loads a JSON scene file, storing the new scene in `*out`.
+///
+/// # Safety
+///
+/// `path` must be a nul-terminated string and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_load(path: *const c_char, out: *mut *mut RtScene) -> RtStatus {
+    if path.is_null() || out.is_null() {
+        return RtStatus::NullPointer;
+    }
+    let path = CStr::from_ptr(path);
+
+    guard(|| {
+        let path = path.to_str().map_err(|err| err.to_string())?;
+        let scene = Scene::load(path).map_err(|err| err.to_string())?;
+        *out = Box::into_raw(Box::new(RtScene { scene }));
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `scene` must have come from `rt_scene_new` or `rt_scene_load` and not been freed yet, or be
+/// null.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_free(scene: *mut RtScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Sets the camera. A `focus_dist` of zero or less focuses on `look_at`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_set_camera(
+    scene: *mut RtScene,
+    look_from: RtVec3,
+    look_at: RtVec3,
+    vup: RtVec3,
+    vertical_fov: f64,
+    aperture: f64,
+    focus_dist: f64,
+) -> RtStatus {
+    let scene = match scene.as_mut() {
+        Some(scene) => &mut scene.scene,
+        None => return RtStatus::NullPointer,
+    };
+
+    guard(|| {
+        let camera = CameraDescription {
+            look_from: look_from.into(),
+            look_at: look_at.into(),
+            vup: vup.into(),
+            vertical_fov,
+            aperture,
+            focus_dist: Some(focus_dist).filter(|&dist| dist > 0.0),
+            ..scene.camera.clone()
+        };
+        let previous = std::mem::replace(&mut scene.camera, camera);
+
+        // catch bad parameters now rather than when rendering
+        if let Err(err) = scene.camera_builder().build() {
+            scene.camera = previous;
+            return Err(err.to_string());
+        }
+        Ok(())
+    })
+}
+
+/// The name in the scene of the material with `id`. Scene files name their materials however they
+/// like, so the ids skip over names they already use.
+fn material_name(id: u32) -> String {
+    format!("#{}", id)
+}
+
+unsafe fn add_material(
+    scene: *mut RtScene,
+    material: MaterialDescription,
+    id: *mut u32,
+) -> RtStatus {
+    let scene = match scene.as_mut() {
+        Some(scene) => &mut scene.scene,
+        None => return RtStatus::NullPointer,
+    };
+    if id.is_null() {
+        return RtStatus::NullPointer;
+    }
+
+    guard(|| {
+        material.build().map_err(|err| err.to_string())?;
+
+        let next = (scene.materials.len() as u32..=u32::MAX)
+            .find(|&id| !scene.materials.contains_key(&material_name(id)))
+            .ok_or_else(|| "too many materials".to_owned())?;
+        scene.materials.insert(material_name(next), material);
+        *id = next;
+        Ok(())
+    })
+}
+
+/// Adds a diffuse material, storing its id in `*id`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene and `id` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_lambertian(
+    scene: *mut RtScene,
+    albedo: RtVec3,
+    id: *mut u32,
+) -> RtStatus {
+    let albedo = albedo.into();
+    add_material(scene, MaterialDescription::Lambertian { albedo }, id)
+}
+
+/// Adds a reflective material, storing its id in `*id`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene and `id` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_metal(
+    scene: *mut RtScene,
+    albedo: RtVec3,
+    fuzz: f64,
+    id: *mut u32,
+) -> RtStatus {
+    let albedo = albedo.into();
+    add_material(scene, MaterialDescription::Metal { albedo, fuzz }, id)
+}
+
+/// Adds a glass-like material, storing its id in `*id`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene and `id` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_dielectric(
+    scene: *mut RtScene,
+    refraction_index: f64,
+    id: *mut u32,
+) -> RtStatus {
+    let ri = refraction_index;
+    add_material(scene, MaterialDescription::Dielectric { ri }, id)
+}
+
+/// Adds a light emitting material, storing its id in `*id`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene and `id` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_light(
+    scene: *mut RtScene,
+    emit: RtVec3,
+    id: *mut u32,
+) -> RtStatus {
+    let emit = emit.into();
+    let light = MaterialDescription::DiffuseLight {
+        emit,
+        double_sided: true,
+        illuminant: None,
+        ies: None,
+        texture: None,
+    };
+    add_material(scene, light, id)
+}
+
+unsafe fn add_object(scene: *mut RtScene, object: ObjectDescription, material: u32) -> RtStatus {
+    let scene = match scene.as_mut() {
+        Some(scene) => &mut scene.scene,
+        None => return RtStatus::NullPointer,
+    };
+
+    guard(|| {
+        if !scene.materials.contains_key(&material_name(material)) {
+            return Err(format!("unknown material {}", material));
+        }
+
+        scene.objects.push(object);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_sphere(
+    scene: *mut RtScene,
+    center: RtVec3,
+    radius: f64,
+    material: u32,
+) -> RtStatus {
+    let object = ObjectDescription::Sphere {
+        center: center.into(),
+        radius,
+        material: material_name(material),
+        cull_backfaces: false,
+    };
+    add_object(scene, object, material)
+}
+
+/// A box spanned by `u`, `v` and `w` from `corner`.
+///
+/// # Safety
+///
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_parallelogram(
+    scene: *mut RtScene,
+    corner: RtVec3,
+    u: RtVec3,
+    v: RtVec3,
+    w: RtVec3,
+    material: u32,
+) -> RtStatus {
+    let object = ObjectDescription::Parallelogram {
+        corner: corner.into(),
+        u: u.into(),
+        v: v.into(),
+        w: w.into(),
+        material: material_name(material),
+        cull_backfaces: false,
+    };
+    add_object(scene, object, material)
+}
+
+#[no_mangle]
+pub extern "C" fn rt_settings_default() -> RtSettings {
+    let defaults = RenderSettings::default();
+
+    RtSettings {
+        width: defaults.width,
+        height: defaults.height,
+        samples_per_pixel: defaults.samples_per_pixel,
+        max_depth: defaults.max_depth,
+        use_seed: false,
+        seed: 0,
+    }
+}
+
+/// The number of floats `rt_render` needs for an image of this size.
+#[no_mangle]
+pub extern "C" fn rt_buffer_len(width: u32, height: u32) -> usize {
+    Framebuffer::len(width, height)
+}
+
+/// Renders the scene into `buffer`, which has to hold exactly `rt_buffer_len` floats: linear
+/// RGBA with premultiplied alpha, starting from the top row.
+///
+/// # Safety
+///
+/// `scene` must be a live scene, `settings` valid for reads and `buffer` valid for writes of
+/// `len` floats.
+#[no_mangle]
+pub unsafe extern "C" fn rt_render(
+    scene: *const RtScene,
+    settings: *const RtSettings,
+    buffer: *mut f32,
+    len: usize,
+) -> RtStatus {
+    let (scene, settings) = match (scene.as_ref(), settings.as_ref()) {
+        (Some(scene), Some(settings)) => (&scene.scene, settings),
+        _ => return RtStatus::NullPointer,
+    };
+    if buffer.is_null() {
+        return RtStatus::NullPointer;
+    }
+    let buffer = std::slice::from_raw_parts_mut(buffer, len);
+
+    guard(|| {
+        let mut settings = RenderSettings {
+            width: settings.width,
+            height: settings.height,
+            samples_per_pixel: settings.samples_per_pixel,
+            max_depth: settings.max_depth,
+            show_progress: false,
+            seed: Some(settings.seed).filter(|_| settings.use_seed),
+            ..Default::default()
+        };
+        scene.apply(&mut settings).map_err(|err| err.to_string())?;
+
+        let world = Bvh::new(scene.world().map_err(|err| err.to_string())?);
+        let camera = scene
+            .focus(scene.camera_builder(), &world)
+            .map_err(|err| err.to_string())?
+            .aspect_ratio(settings.width as f64 / settings.height as f64)
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Renderer::new(world, camera)
+            .render_into(buffer, &settings)
+            .map_err(|err| err.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vec3(x: f64, y: f64, z: f64) -> RtVec3 {
+        RtVec3 { x, y, z }
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(rt_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn build_and_render() {
+        unsafe {
+            let scene = rt_scene_new();
+            let mut gray = u32::MAX;
+            assert_eq!(
+                rt_scene_add_lambertian(scene, vec3(0.5, 0.5, 0.5), &mut gray),
+                RtStatus::Ok
+            );
+            assert_eq!(
+                rt_scene_add_sphere(scene, vec3(0.0, 0.0, -1.0), 0.5, gray),
+                RtStatus::Ok
+            );
+
+            let settings = RtSettings {
+                width: 16,
+                height: 8,
+                samples_per_pixel: 2,
+                use_seed: true,
+                ..rt_settings_default()
+            };
+            let mut buffer = vec![-1.0; rt_buffer_len(16, 8)];
+            let status = rt_render(scene, &settings, buffer.as_mut_ptr(), buffer.len());
+            assert_eq!(status, RtStatus::Ok);
+            assert!(buffer.iter().all(|&x| x >= 0.0));
+
+            rt_scene_free(scene);
+        }
+    }
+
+    #[test]
+    fn ids_skip_names_in_use() {
+        unsafe {
+            let scene = rt_scene_new();
+            let taken = MaterialDescription::Metal {
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+                fuzz: 0.0,
+            };
+            (*scene).scene.materials.insert("#1".to_owned(), taken);
+
+            let mut id = 0;
+            let gray = vec3(0.5, 0.5, 0.5);
+            assert_eq!(rt_scene_add_lambertian(scene, gray, &mut id), RtStatus::Ok);
+            assert_eq!(id, 2);
+            assert_eq!(rt_scene_add_lambertian(scene, gray, &mut id), RtStatus::Ok);
+            assert_eq!(id, 3);
+            assert_eq!((*scene).scene.materials.len(), 3);
+
+            rt_scene_free(scene);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        unsafe {
+            let scene = rt_scene_new();
+            let mut id = 0;
+
+            let status = rt_scene_add_metal(scene, vec3(0.5, 0.5, 0.5), -1.0, &mut id);
+            assert_eq!(status, RtStatus::Error);
+            assert_eq!(last_error(), "invalid metal fuzz: -1");
+
+            let status = rt_scene_add_sphere(scene, vec3(0.0, 0.0, 0.0), 1.0, 7);
+            assert_eq!(status, RtStatus::Error);
+            assert_eq!(last_error(), "unknown material 7");
+
+            let origin = vec3(0.0, 0.0, 0.0);
+            let up = vec3(0.0, 1.0, 0.0);
+            let status = rt_scene_set_camera(scene, origin, origin, up, 90.0, 0.0, 0.0);
+            assert_eq!(status, RtStatus::Error);
+
+            let settings = rt_settings_default();
+            let mut buffer = vec![0.0; 4];
+            let status = rt_render(scene, &settings, buffer.as_mut_ptr(), buffer.len());
+            assert_eq!(status, RtStatus::Error);
+            assert!(last_error().contains("the image needs"));
+
+            assert_eq!(
+                rt_render(ptr::null(), &settings, buffer.as_mut_ptr(), 4),
+                RtStatus::NullPointer
+            );
+
+            rt_scene_free(scene);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Checks that include/ray_tracing.h matches the extern functions in src/lib.rs. It's generated
+//! here rather than by a build script, so that builds never write into the source tree:
+//! `UPDATE_HEADER=1 cargo test -p ray_tracing_ffi --test header` regenerates it.
+
+use std::path::Path;
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("couldn't read cbindgen.toml");
+
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("couldn't generate the C header")
+        .write(&mut header);
+
+    let path = crate_dir.join("include/ray_tracing.h");
+    if std::env::var_os("UPDATE_HEADER").is_some() {
+        std::fs::write(&path, &header).unwrap();
+        return;
+    }
+    let existing = std::fs::read(&path).unwrap();
+    assert!(
+        existing == header,
+        "{} is out of date, regenerate it with UPDATE_HEADER=1",
+        path.display()
+    );
+}
@@ -0,0 +1,288 @@
+//! Python bindings, built into the `ray_tracing` module by maturin.
+//!
+//! ```python
+//! import ray_tracing as rt
+//!
+//! scene = rt.Scene()
+//! ground = rt.Lambertian((0.8, 0.8, 0.0))
+//! scene.add_sphere((0, -100.5, -1), 100, ground)
+//! scene.add_sphere((0, 0, -1), 0.5, rt.Metal((0.8, 0.6, 0.2), fuzz=0.1))
+//! scene.set_camera(look_from=(0, 0.5, 1), look_at=(0, 0, -1), vertical_fov=40)
+//!
+//! image = scene.render(320, 180, 50)  # linear RGBA floats, shaped (180, 320, 4)
+//! ```
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::framebuffer::Framebuffer;
+use ray_tracing::render::{RenderSettings, Renderer};
+use ray_tracing::scene::{CameraDescription, MaterialDescription, ObjectDescription};
+use ray_tracing::scene_graph::SceneGraph;
+use ray_tracing::{Error, Vec3};
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use std::collections::HashMap;
+
+fn to_py_err(err: Error) -> PyErr {
+    match err {
+        Error::File { .. } | Error::Io(_) => PyIOError::new_err(err.to_string()),
+        _ => PyValueError::new_err(err.to_string()),
+    }
+}
+
+fn vec3((x, y, z): (f64, f64, f64)) -> Vec3 {
+    Vec3::new(x, y, z)
+}
+
+/// A material, to be passed to the `add_*` methods of a scene.
+#[pyclass(frozen)]
+#[derive(Clone)]
+struct Material {
+    description: MaterialDescription,
+}
+
+impl Material {
+    fn new(description: MaterialDescription) -> PyResult<Self> {
+        // check the parameters right away rather than when rendering
+        description.build().map_err(to_py_err)?;
+
+        Ok(Self { description })
+    }
+}
+
+/// A diffuse material. Every component of `albedo` has to be in [0, 1].
+#[pyfunction]
+#[pyo3(name = "Lambertian")]
+fn lambertian(albedo: (f64, f64, f64)) -> PyResult<Material> {
+    let albedo = vec3(albedo);
+    Material::new(MaterialDescription::Lambertian { albedo })
+}
+
+/// A reflective material, blurrier as `fuzz` goes from 0 to 1.
+#[pyfunction]
+#[pyo3(name = "Metal", signature = (albedo, fuzz = 0.0))]
+fn metal(albedo: (f64, f64, f64), fuzz: f64) -> PyResult<Material> {
+    let albedo = vec3(albedo);
+    Material::new(MaterialDescription::Metal { albedo, fuzz })
+}
+
+/// Glass and the like, with the given refraction index.
+#[pyfunction]
+#[pyo3(name = "Dielectric")]
+fn dielectric(ri: f64) -> PyResult<Material> {
+    Material::new(MaterialDescription::Dielectric { ri })
+}
+
+/// A material that emits light. `emit` can go above 1 for brighter lights. Without
+/// `double_sided`, the back of the surface is dark.
+#[pyfunction]
+#[pyo3(name = "DiffuseLight", signature = (emit, double_sided = true))]
+fn diffuse_light(emit: (f64, f64, f64), double_sided: bool) -> PyResult<Material> {
+    let emit = vec3(emit);
+    Material::new(MaterialDescription::DiffuseLight {
+        emit,
+        double_sided,
+        illuminant: None,
+        ies: None,
+        texture: None,
+    })
+}
+
+/// A set of objects and a camera looking at them.
+#[pyclass]
+struct Scene {
+    scene: ray_tracing::scene::Scene,
+}
+
+#[pymethods]
+impl Scene {
+    /// An empty scene, with a camera at the origin looking down the negative z axis.
+    #[new]
+    fn new() -> Self {
+        Self {
+            scene: ray_tracing::scene::Scene {
+                camera: CameraDescription {
+                    look_from: Vec3::new(0.0, 0.0, 0.0),
+                    look_at: Vec3::new(0.0, 0.0, -1.0),
+                    vup: Vec3::new(0.0, 1.0, 0.0),
+                    vertical_fov: 90.0,
+                    aperture: 0.0,
+                    focus_dist: None,
+                    focus_on: None,
+                    vignetting: false,
+                    distortion: [0.0; 2],
+                    lateral_aberration: 0.0,
+                    longitudinal_aberration: 0.0,
+                },
+                materials: HashMap::new(),
+                objects: Vec::new(),
+                prototypes: HashMap::new(),
+                fog: None,
+                sky: None,
+                portals: Vec::new(),
+                graph: SceneGraph::default(),
+                camera_node: None,
+            },
+        }
+    }
+
+    /// Loads a JSON scene file, in the format of `scenes/spheres.json`.
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Self> {
+        let scene = ray_tracing::scene::Scene::load(path).map_err(to_py_err)?;
+        Ok(Self { scene })
+    }
+
+    #[pyo3(signature = (look_from, look_at, vertical_fov, vup = (0.0, 1.0, 0.0), aperture = 0.0, focus_dist = None))]
+    fn set_camera(
+        &mut self,
+        look_from: (f64, f64, f64),
+        look_at: (f64, f64, f64),
+        vertical_fov: f64,
+        vup: (f64, f64, f64),
+        aperture: f64,
+        focus_dist: Option<f64>,
+    ) -> PyResult<()> {
+        let camera = CameraDescription {
+            look_from: vec3(look_from),
+            look_at: vec3(look_at),
+            vup: vec3(vup),
+            vertical_fov,
+            aperture,
+            focus_dist,
+            ..self.scene.camera.clone()
+        };
+        let previous = std::mem::replace(&mut self.scene.camera, camera);
+
+        if let Err(err) = self.scene.camera_builder().build() {
+            self.scene.camera = previous;
+            return Err(to_py_err(err.into()));
+        }
+        Ok(())
+    }
+
+    fn add_sphere(
+        &mut self,
+        center: (f64, f64, f64),
+        radius: f64,
+        material: &Material,
+    ) -> PyResult<()> {
+        self.add_object(material, |material| ObjectDescription::Sphere {
+            center: vec3(center),
+            radius,
+            material,
+            cull_backfaces: false,
+        })
+    }
+
+    /// A box spanned by the edges `u`, `v` and `w` from `corner`.
+    fn add_box(
+        &mut self,
+        corner: (f64, f64, f64),
+        u: (f64, f64, f64),
+        v: (f64, f64, f64),
+        w: (f64, f64, f64),
+        material: &Material,
+    ) -> PyResult<()> {
+        self.add_object(material, |material| ObjectDescription::Parallelogram {
+            corner: vec3(corner),
+            u: vec3(u),
+            v: vec3(v),
+            w: vec3(w),
+            material,
+            cull_backfaces: false,
+        })
+    }
+
+    /// Renders the scene into an array of linear RGBA floats shaped `(height, width, 4)`, with
+    /// the top row first. Encode it to sRGB before displaying it.
+    #[pyo3(signature = (width, height, samples_per_pixel, seed = None, max_depth = 50))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        width: u32,
+        height: u32,
+        samples_per_pixel: u32,
+        seed: Option<u64>,
+        max_depth: i32,
+    ) -> PyResult<Bound<'py, PyArray3<f32>>> {
+        let mut settings = RenderSettings {
+            width,
+            height,
+            samples_per_pixel,
+            max_depth,
+            show_progress: false,
+            seed,
+            ..Default::default()
+        };
+        self.scene.apply(&mut settings).map_err(to_py_err)?;
+        let world = Bvh::new(self.scene.world().map_err(to_py_err)?);
+        let camera = self
+            .scene
+            .focus(self.scene.camera_builder(), &world)
+            .map_err(to_py_err)?
+            .aspect_ratio(width as f64 / height as f64)
+            .build()
+            .map_err(|err| to_py_err(err.into()))?;
+
+        // let other Python threads run in the meantime
+        let framebuffer: Framebuffer = py.detach(|| Renderer::new(world, camera).render(&settings));
+
+        PyArray1::from_vec(py, framebuffer.into_vec()).reshape([
+            height as usize,
+            width as usize,
+            Framebuffer::CHANNELS,
+        ])
+    }
+}
+
+impl Scene {
+    /// Adds the object and its material, after checking that they can be built.
+    fn add_object(
+        &mut self,
+        material: &Material,
+        object: impl FnOnce(String) -> ObjectDescription,
+    ) -> PyResult<()> {
+        // scenes loaded from files name their materials however they like
+        let name = (self.scene.materials.len()..)
+            .map(|id| format!("#{}", id))
+            .find(|name| !self.scene.materials.contains_key(name))
+            .unwrap();
+        let object = object(name.clone());
+
+        let materials = HashMap::from([(name.clone(), material.description.clone())]);
+        let check = ray_tracing::scene::Scene {
+            camera: self.scene.camera.clone(),
+            materials,
+            objects: vec![object.clone()],
+            prototypes: self.scene.prototypes.clone(),
+            fog: None,
+            sky: None,
+            portals: Vec::new(),
+            graph: SceneGraph::default(),
+            camera_node: None,
+        };
+        check.world().map_err(to_py_err)?;
+
+        self.scene
+            .materials
+            .insert(name, material.description.clone());
+        self.scene.objects.push(object);
+        Ok(())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "ray_tracing")]
+fn ray_tracing_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Scene>()?;
+    m.add_class::<Material>()?;
+    m.add_function(wrap_pyfunction!(lambertian, m)?)?;
+    m.add_function(wrap_pyfunction!(metal, m)?)?;
+    m.add_function(wrap_pyfunction!(dielectric, m)?)?;
+    m.add_function(wrap_pyfunction!(diffuse_light, m)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+import pytest
+
+import ray_tracing as rt
+
+
+def small_scene():
+    scene = rt.Scene()
+    scene.add_sphere((0, -100.5, -1), 100, rt.Lambertian((0.8, 0.8, 0.0)))
+    scene.add_sphere((0, 0, -1), 0.5, rt.Metal((0.8, 0.6, 0.2), fuzz=0.1))
+    scene.set_camera(look_from=(0, 0.5, 1), look_at=(0, 0, -1), vertical_fov=40)
+    return scene
+
+
+def test_render_shape():
+    image = small_scene().render(16, 8, 2, seed=1)
+
+    assert image.shape == (8, 16, 4)
+    assert image.dtype.name == "float32"
+    assert (image >= 0).all()
+
+
+def test_seeded_renders_match():
+    scene = small_scene()
+
+    assert (scene.render(16, 8, 2, seed=3) == scene.render(16, 8, 2, seed=3)).all()
+
+
+def test_invalid_parameters():
+    with pytest.raises(ValueError, match="metal fuzz"):
+        rt.Metal((0.5, 0.5, 0.5), fuzz=-1)
+    with pytest.raises(ValueError, match="sphere radius"):
+        rt.Scene().add_sphere((0, 0, 0), -1, rt.Dielectric(1.5))
+    with pytest.raises(OSError):
+        rt.Scene.load("does/not/exist.json")
@@ -0,0 +1,197 @@
+//! Keyframed camera and object motion.
+
+use crate::transform::Transform;
+use crate::{Camera, CameraBuilder, CameraError, Error, Point3, Result, Vec3};
+
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Jump from one keyframe's value to the next.
+    Step,
+    Linear,
+    /// A Catmull-Rom spline through the keyframes, which keeps the motion smooth.
+    Cubic,
+}
+
+/// A value that changes over time, given by its values at some points in time.
+///
+/// Before the first and after the last keyframe the value stays constant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T> {
+    keys: Vec<(f64, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    /// Keyframes are given as `(time, value)` pairs. Panics if there are none.
+    pub fn new(interpolation: Interpolation, mut keys: Vec<(f64, T)>) -> Self {
+        assert!(!keys.is_empty(), "a track needs at least one keyframe");
+        keys.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+        Self {
+            keys,
+            interpolation,
+        }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new(Interpolation::Step, vec![(0.0, value)])
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|(t, _)| *t <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+
+        let (t1, p1) = self.keys[next - 1];
+        let (t2, p2) = self.keys[next];
+        let s = (time - t1) / (t2 - t1);
+
+        match self.interpolation {
+            Interpolation::Step => p1,
+            Interpolation::Linear => p1 + (p2 - p1) * s,
+            Interpolation::Cubic => {
+                // reflect the end points to get tangents at the first and last keyframes
+                let p0 = self
+                    .keys
+                    .get(next.wrapping_sub(2))
+                    .map_or(p1 + (p1 - p2), |k| k.1);
+                let p3 = self.keys.get(next + 1).map_or(p2 + (p2 - p1), |k| k.1);
+
+                let (s2, s3) = (s * s, s * s * s);
+                (p1 * 2.0
+                    + (p2 - p0) * s
+                    + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * s2
+                    + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * s3)
+                    * 0.5
+            }
+        }
+    }
+}
+
+/// Camera motion. Everything not keyframed here is taken from the builder passed to
+/// [`CameraKeyframes::camera_at`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraKeyframes {
+    pub look_from: Track<Point3>,
+    pub look_at: Track<Point3>,
+    /// In degrees.
+    pub vertical_fov: Track<f64>,
+}
+
+impl CameraKeyframes {
+    pub fn camera_at(&self, time: f64, builder: CameraBuilder) -> Result<Camera, CameraError> {
+        builder
+            .look_from(self.look_from.sample(time))
+            .look_at(self.look_at.sample(time))
+            .vertical_fov(self.vertical_fov.sample(time))
+            .build()
+    }
+}
+
+/// Object motion, to be used with [`Transformed`](crate::collision::objects::Transformed).
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformKeyframes {
+    pub translation: Track<Vec3>,
+    /// Angles in degrees around the x, y and z axes.
+    pub rotation: Track<Vec3>,
+    pub scale: Track<Vec3>,
+}
+
+impl TransformKeyframes {
+    pub fn transform_at(&self, time: f64) -> Transform {
+        Transform::new(
+            self.translation.sample(time),
+            self.rotation.sample(time),
+            self.scale.sample(time),
+        )
+    }
+}
+
+impl Default for TransformKeyframes {
+    fn default() -> Self {
+        Self {
+            translation: Track::constant(Vec3::new(0.0, 0.0, 0.0)),
+            rotation: Track::constant(Vec3::new(0.0, 0.0, 0.0)),
+            scale: Track::constant(Vec3::new(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+/// A camera orbiting `target` once over `frames` frames, at a fixed distance and height.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Turntable {
+    pub target: Point3,
+    pub radius: f64,
+    /// Angle above the horizontal plane, in degrees.
+    pub elevation: f64,
+    /// Where the first frame is taken from, in degrees around the vertical axis starting at +z.
+    pub start_angle: f64,
+    pub frames: u32,
+}
+
+impl Turntable {
+    /// An orbit passing through `look_from`. Fails without any frames, or if `look_from` is the
+    /// target itself.
+    pub fn through(look_from: Point3, target: Point3, frames: u32) -> Result<Self> {
+        Error::check("turntable frames", frames as f64, |frames| frames >= 1.0)?;
+        let [x, y, z] = (look_from - target).0;
+        let radius = Error::check(
+            "turntable radius",
+            (look_from - target).length(),
+            |radius| radius > 0.0,
+        )?;
+
+        Ok(Self {
+            target,
+            radius,
+            elevation: (y / radius).asin().to_degrees(),
+            start_angle: x.atan2(z).to_degrees(),
+            frames,
+        })
+    }
+
+    pub fn look_from(&self, frame: u32) -> Point3 {
+        let turn = frame as f64 / self.frames as f64;
+        let azimuth = self.start_angle.to_radians() + std::f64::consts::TAU * turn;
+        let elevation = self.elevation.to_radians();
+
+        self.target
+            + self.radius
+                * Vec3::new(
+                    elevation.cos() * azimuth.sin(),
+                    elevation.sin(),
+                    elevation.cos() * azimuth.cos(),
+                )
+    }
+
+    pub fn camera_at(&self, frame: u32, builder: CameraBuilder) -> Result<Camera, CameraError> {
+        builder
+            .look_from(self.look_from(frame))
+            .look_at(self.target)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turntables_need_frames_and_a_radius() {
+        let (from, target) = (Point3::new(0.0, 1.0, 5.0), Point3::new(0.0, 1.0, 0.0));
+        assert!(Turntable::through(from, target, 0).is_err());
+        assert!(Turntable::through(target, target, 24).is_err());
+
+        let turntable = Turntable::through(from, target, 24).unwrap();
+        assert!((turntable.look_from(0) - from).length() < 1e-9);
+    }
+}
@@ -0,0 +1,659 @@
+use crate::{IMG_HEIGHT, IMG_WIDTH, MAX_DEPTH, SAMPLES_PER_PIXEL};
+
+use ray_tracing::bloom::Bloom;
+use ray_tracing::collision::PACKET_SIZE;
+use ray_tracing::color_space::ColorSpace;
+use ray_tracing::filter::*;
+use ray_tracing::grade::Grade;
+use ray_tracing::light_tracer::LightTracer;
+use ray_tracing::output::{self, SaveOptions};
+use ray_tracing::photons::PhotonMapper;
+use ray_tracing::render::{
+    DirectLighting, PathTracer, PixelOrder, Preview, Region, RenderMode, RenderSettings,
+    StereoLayout, Whitted,
+};
+use ray_tracing::{Error, Projection, Result};
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Args {
+    pub settings: RenderSettings,
+    pub output: String,
+    pub save_options: SaveOptions,
+    /// A `.cube` file for the lookup table of the save options, which is loaded once the
+    /// arguments are, so that workers don't need it.
+    pub lut: Option<String>,
+    pub time_heatmap: bool,
+    /// Also save the object and material IDs of the scene for compositing.
+    pub cryptomatte: bool,
+    /// Also save every pixel as samples sorted by depth, for deep compositing.
+    pub deep: bool,
+    pub preview_ladder: bool,
+    pub projection: Projection,
+    pub stereo: Option<StereoLayout>,
+    pub ipd: f64,
+    /// The first and last frame of an animation to render.
+    pub frames: Option<(u32, u32)>,
+    pub fps: f64,
+    /// Orbit the camera around the scene over this many frames.
+    pub turntable: Option<u32>,
+    /// A scene file to render instead of the built-in scene.
+    pub scene: Option<String>,
+    /// Keep re-rendering a quick preview whenever the scene file changes.
+    pub watch: bool,
+    /// Open an interactive preview window instead of rendering to a file.
+    pub preview: bool,
+    /// A checkpoint left by an interrupted render, to continue from.
+    pub resume: Option<String>,
+    /// Stop rendering after this long, keeping what's done.
+    pub max_time: Option<Duration>,
+    /// Fail before rendering if the scene would take more bytes than this.
+    pub memory_budget: Option<usize>,
+    /// Decode images as textures first look them up, keeping this many bytes of them at most.
+    pub texture_cache: Option<usize>,
+    /// Print the statistics at the end as JSON on stdout, rather than logging them.
+    pub stats_json: bool,
+    /// Render tiles for coordinators connecting to this address, instead of rendering anything
+    /// locally.
+    pub worker: Option<String>,
+    /// Split the frame across workers at these addresses.
+    pub workers: Vec<String>,
+    /// Serve the HTTP API on this address, instead of rendering anything right away.
+    pub serve: Option<String>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    /// Parses the arguments without the program name.
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self {
+            settings: RenderSettings {
+                width: IMG_WIDTH,
+                height: IMG_HEIGHT,
+                samples_per_pixel: SAMPLES_PER_PIXEL,
+                max_depth: MAX_DEPTH,
+                ..Default::default()
+            },
+            output: String::from("output.png"),
+            save_options: SaveOptions::default(),
+            lut: None,
+            time_heatmap: false,
+            cryptomatte: false,
+            deep: false,
+            preview_ladder: false,
+            projection: Projection::Perspective,
+            stereo: None,
+            ipd: 0.064,
+            frames: None,
+            fps: 24.0,
+            turntable: None,
+            scene: None,
+            watch: false,
+            preview: false,
+            resume: None,
+            max_time: None,
+            memory_budget: None,
+            texture_cache: None,
+            stats_json: false,
+            worker: None,
+            workers: Vec::new(),
+            serve: None,
+        };
+        let settings = &mut parsed.settings;
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--clay" => *settings = settings.clone().clay(),
+                "--time-heatmap" => parsed.time_heatmap = true,
+                "--cryptomatte" => parsed.cryptomatte = true,
+                "--deep" => parsed.deep = true,
+                "--preview-ladder" => parsed.preview_ladder = true,
+                "--orthographic" => parsed.projection = Projection::Orthographic,
+                "--fisheye" => {
+                    let fov = args
+                        .next()
+                        .ok_or_else(|| usage("--fisheye expects a field of view"))?;
+                    parsed.projection = Projection::Fisheye {
+                        fov: parse(&arg, &fov)?,
+                    };
+                }
+                "--stereo" => {
+                    parsed.stereo = match args.next().as_deref() {
+                        Some("sbs") => Some(StereoLayout::SideBySide),
+                        Some("ou") => Some(StereoLayout::OverUnder),
+                        _ => return Err(usage("--stereo expects sbs or ou")),
+                    }
+                }
+                "--frames" => {
+                    let mut frame = || -> Result<u32> {
+                        let value = args
+                            .next()
+                            .ok_or_else(|| usage("--frames expects first and last"))?;
+                        parse(&arg, &value)
+                    };
+                    let (first, last) = (frame()?, frame()?);
+                    if first > last {
+                        return Err(usage("--frames must be given in increasing order"));
+                    }
+                    parsed.frames = Some((first, last));
+                }
+                "--scene" => {
+                    let scene = args
+                        .next()
+                        .ok_or_else(|| usage("--scene expects a file name"))?;
+                    parsed.scene = Some(scene);
+                }
+                "--worker" => {
+                    let addr = args
+                        .next()
+                        .ok_or_else(|| usage("--worker expects an address to listen on"))?;
+                    parsed.worker = Some(addr);
+                }
+                "--workers" => {
+                    let addrs = args
+                        .next()
+                        .ok_or_else(|| usage("--workers expects a list of addresses"))?;
+                    parsed.workers = addrs.split(',').map(String::from).collect();
+                }
+                "serve" => {
+                    let addr = match args.peek() {
+                        Some(addr) if !addr.starts_with('-') => args.next().unwrap(),
+                        _ => String::from("127.0.0.1:8000"),
+                    };
+                    parsed.serve = Some(addr);
+                }
+                "--watch" => parsed.watch = true,
+                "--preview" => parsed.preview = true,
+                "--stats-json" => parsed.stats_json = true,
+                "--max-time" => {
+                    let time = args
+                        .next()
+                        .ok_or_else(|| usage("--max-time expects a duration"))?;
+                    parsed.max_time = Some(parse_duration(&time)?);
+                }
+                "--memory-budget" => {
+                    let size = args
+                        .next()
+                        .ok_or_else(|| usage("--memory-budget expects a size"))?;
+                    parsed.memory_budget = Some(parse_size(&size)?);
+                }
+                "--texture-cache" => {
+                    let size = args
+                        .next()
+                        .ok_or_else(|| usage("--texture-cache expects a size"))?;
+                    parsed.texture_cache = Some(parse_size(&size)?);
+                }
+                "--seed" => {
+                    let seed = args
+                        .next()
+                        .ok_or_else(|| usage("--seed expects a number"))?;
+                    settings.seed = Some(parse(&arg, &seed)?);
+                }
+                "--max-samples" => {
+                    let samples = args
+                        .next()
+                        .ok_or_else(|| usage("--max-samples expects a sample count"))?;
+                    settings.samples_per_pixel = parse(&arg, &samples)?;
+                    if settings.samples_per_pixel == 0 {
+                        return Err(usage("--max-samples needs at least one sample"));
+                    }
+                }
+                "--resume" => {
+                    let checkpoint = args
+                        .next()
+                        .ok_or_else(|| usage("--resume expects a checkpoint file"))?;
+                    parsed.resume = Some(checkpoint);
+                }
+                "--turntable" => {
+                    let frames = args
+                        .next()
+                        .ok_or_else(|| usage("--turntable expects a frame count"))?;
+                    let frames: u32 = parse(&arg, &frames)?;
+                    if frames == 0 {
+                        return Err(usage("--turntable needs at least one frame"));
+                    }
+                    parsed.turntable = Some(frames);
+                }
+                "--fps" => {
+                    let fps = args
+                        .next()
+                        .ok_or_else(|| usage("--fps expects a frame rate"))?;
+                    parsed.fps = parse(&arg, &fps)?;
+                }
+                "--ipd" => {
+                    let ipd = args
+                        .next()
+                        .ok_or_else(|| usage("--ipd expects a distance"))?;
+                    parsed.ipd = parse(&arg, &ipd)?;
+                }
+                "--panorama" => parsed.projection = Projection::Equirectangular,
+                "--transparent" => settings.transparent_background = true,
+                "--blue-noise" => settings.blue_noise = true,
+                "--16bit" => parsed.save_options.sixteen_bit = true,
+                "--plain-ppm" => parsed.save_options.plain = true,
+                "--color-space" => {
+                    parsed.save_options.color_space = match args.next().as_deref() {
+                        Some("srgb") => ColorSpace::Srgb,
+                        Some("rec709") => ColorSpace::Rec709,
+                        Some("display-p3") => ColorSpace::DisplayP3,
+                        Some("acescg") => ColorSpace::AcesCg,
+                        Some("linear") => ColorSpace::Linear,
+                        _ => {
+                            return Err(usage(
+                                "--color-space expects srgb, rec709, display-p3, acescg \
+                                        or linear",
+                            ))
+                        }
+                    };
+                }
+                "--lut" => {
+                    let lut = args
+                        .next()
+                        .ok_or_else(|| usage("--lut expects a .cube file"))?;
+                    parsed.lut = Some(lut);
+                }
+                "--bloom" => {
+                    let mut value = || -> Result<f64> {
+                        let value = args.next().ok_or_else(|| {
+                            usage("--bloom expects a threshold, intensity and radius")
+                        })?;
+                        parse(&arg, &value)
+                    };
+                    let (threshold, intensity, radius) = (value()?, value()?, value()?);
+                    parsed.save_options.bloom = Some(Bloom::new(threshold, intensity, radius)?);
+                }
+                "--region" => {
+                    let mut coord = || -> Result<u32> {
+                        let value = args
+                            .next()
+                            .ok_or_else(|| usage("--region expects x0 y0 x1 y1"))?;
+                        parse(&arg, &value)
+                    };
+                    settings.region = Some(Region {
+                        x0: coord()?,
+                        y0: coord()?,
+                        x1: coord()?,
+                        y1: coord()?,
+                    });
+                }
+                "--threads" => {
+                    let count = args
+                        .next()
+                        .ok_or_else(|| usage("--threads expects a thread count"))?;
+                    let count = parse(&arg, &count)?;
+                    if count == 0 {
+                        return Err(usage("--threads needs at least one thread"));
+                    }
+                    settings.threads.count = Some(count);
+                }
+                "--nice" => {
+                    let nice = args
+                        .next()
+                        .ok_or_else(|| usage("--nice expects a nice level"))?;
+                    settings.threads.nice = parse(&arg, &nice)?;
+                    if !(-20..=19).contains(&settings.threads.nice) {
+                        return Err(usage("--nice must be between -20 and 19"));
+                    }
+                }
+                "--pin-threads" => settings.threads.pin = true,
+                "--pixel-order" => {
+                    settings.pixel_order = match args.next().as_deref() {
+                        Some("scanline") => PixelOrder::Scanline,
+                        Some("morton") => PixelOrder::Morton,
+                        _ => return Err(usage("--pixel-order expects scanline or morton")),
+                    }
+                }
+                "--check-nan" => settings.diagnostics.check_finite = true,
+                "--trace-pixel" => {
+                    let mut coord = || -> Result<u32> {
+                        let value = args
+                            .next()
+                            .ok_or_else(|| usage("--trace-pixel expects x y"))?;
+                        parse(&arg, &value)
+                    };
+                    settings.diagnostics.trace_pixel = Some((coord()?, coord()?));
+                }
+                "-o" | "--output" => {
+                    parsed.output = args
+                        .next()
+                        .ok_or_else(|| usage("--output expects a path"))?
+                }
+                "--normals" => settings.mode = RenderMode::Normals,
+                "--depth" => settings.mode = RenderMode::Depth,
+                "--uv" => settings.mode = RenderMode::Uv,
+                "--front-face" => settings.mode = RenderMode::FrontFace,
+                "--heatmap" => settings.mode = RenderMode::Heatmap { max_tests: 64 },
+                "--filter" => {
+                    settings.filter = match args.next().as_deref() {
+                        Some("box") => Arc::new(BoxFilter { radius: 0.5 }),
+                        Some("tent") => Arc::new(TentFilter { radius: 1.0 }),
+                        Some("gaussian") => Arc::new(GaussianFilter {
+                            radius: 1.5,
+                            sigma: 0.5,
+                        }),
+                        Some("mitchell") => Arc::new(MitchellFilter::default()),
+                        _ => return Err(usage("--filter expects box, tent, gaussian or mitchell")),
+                    }
+                }
+                "--integrator" => {
+                    settings.integrator = match args.next().as_deref() {
+                        Some("path") => Arc::new(PathTracer),
+                        Some("direct") => Arc::new(DirectLighting),
+                        Some("whitted") => Arc::new(Whitted),
+                        Some("preview") => Arc::new(Preview::default()),
+                        Some("photons") => Arc::new(PhotonMapper::default()),
+                        Some("light") => Arc::new(LightTracer::default()),
+                        _ => return Err(usage(
+                            "--integrator expects path, direct, whitted, preview, photons or light",
+                        )),
+                    }
+                }
+                "--light-samples" => {
+                    let samples = args
+                        .next()
+                        .ok_or_else(|| usage("--light-samples expects a sample count"))?;
+                    settings.light_samples = parse(&arg, &samples)?;
+                    if !(1..=PACKET_SIZE as u32).contains(&settings.light_samples) {
+                        return Err(usage(format!(
+                            "--light-samples expects from 1 to {} samples",
+                            PACKET_SIZE
+                        )));
+                    }
+                }
+                "--roughen" => {
+                    let angle = args
+                        .next()
+                        .ok_or_else(|| usage("--roughen expects an angle"))?;
+                    settings.roughening = parse(&arg, &angle)?;
+                    if !(0.0..=180.0).contains(&settings.roughening) {
+                        return Err(usage("--roughen expects an angle from 0 to 180 degrees"));
+                    }
+                }
+                "--exposure" => {
+                    let stops = args
+                        .next()
+                        .ok_or_else(|| usage("--exposure expects a number of stops"))?;
+                    settings.grade.exposure = parse(&arg, &stops)?;
+                }
+                "--white-balance" => {
+                    let kelvin = args
+                        .next()
+                        .ok_or_else(|| usage("--white-balance expects a temperature"))?;
+                    settings.grade.temperature = parse(&arg, &kelvin)?;
+                }
+                "--tint" => {
+                    let tint = args
+                        .next()
+                        .ok_or_else(|| usage("--tint expects a number"))?;
+                    settings.grade.tint = parse(&arg, &tint)?;
+                }
+                "--saturation" => {
+                    let saturation = args
+                        .next()
+                        .ok_or_else(|| usage("--saturation expects a number"))?;
+                    settings.grade.saturation = parse(&arg, &saturation)?;
+                }
+                _ => return Err(usage(format!("unknown argument: {}", arg))),
+            }
+        }
+
+        // the size is settled before anything is checked against it, whatever order the
+        // arguments came in
+        if parsed.projection == Projection::Equirectangular {
+            parsed.settings.width = 2 * parsed.settings.height;
+        }
+        if let Some(region) = parsed.settings.region {
+            if region.x0 >= region.x1
+                || region.y0 >= region.y1
+                || region.x1 > parsed.settings.width
+                || region.y1 > parsed.settings.height
+            {
+                return Err(usage(
+                    "--region must be a non-empty rectangle inside the image",
+                ));
+            }
+        }
+
+        let grade = parsed.settings.grade;
+        parsed.settings.grade = Grade::new(
+            grade.exposure,
+            grade.temperature,
+            grade.tint,
+            grade.saturation,
+        )?;
+        if parsed.preview && (parsed.watch || parsed.frames.is_some() || parsed.turntable.is_some())
+        {
+            return Err(usage(
+                "--preview can't be combined with --watch or animations",
+            ));
+        }
+        if parsed.resume.is_some()
+            && (parsed.watch
+                || parsed.preview
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some())
+        {
+            return Err(usage("--resume only works for single images"));
+        }
+        if parsed.watch {
+            if parsed.scene.is_none() {
+                return Err(usage("--watch needs a --scene to watch"));
+            }
+            if parsed.frames.is_some() || parsed.turntable.is_some() {
+                return Err(usage("--watch can't be combined with animations"));
+            }
+        }
+        if output::is_video(&parsed.output) {
+            if parsed.frames.is_none() && parsed.turntable.is_none() {
+                return Err(usage("video output needs --frames or --turntable"));
+            }
+            if parsed.settings.region.is_some() {
+                return Err(usage("--region can't be used with video output"));
+            }
+        }
+        if parsed.serve.is_some()
+            && (parsed.scene.is_some()
+                || parsed.watch
+                || parsed.preview
+                || parsed.resume.is_some()
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some()
+                || parsed.stereo.is_some()
+                || parsed.settings.region.is_some()
+                || !parsed.workers.is_empty())
+        {
+            return Err(usage(
+                "serve renders the scenes it's sent, as single images",
+            ));
+        }
+        if !parsed.workers.is_empty() {
+            if parsed.scene.is_none() {
+                return Err(usage("--workers needs a --scene to send them"));
+            }
+            if parsed.watch
+                || parsed.preview
+                || parsed.preview_ladder
+                || parsed.resume.is_some()
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some()
+                || parsed.stereo.is_some()
+                || parsed.settings.region.is_some()
+            {
+                return Err(usage(
+                    "--workers only renders single images, without --stereo or --region",
+                ));
+            }
+        }
+        if parsed.cryptomatte
+            && (parsed.scene.is_none()
+                || parsed.watch
+                || parsed.preview
+                || parsed.serve.is_some()
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some()
+                || parsed.stereo.is_some()
+                || parsed.settings.region.is_some()
+                || !parsed.workers.is_empty())
+        {
+            return Err(usage(
+                "--cryptomatte only works for single images of a --scene, without --stereo or \
+                 --region",
+            ));
+        }
+        if parsed.deep
+            && (parsed.settings.mode != RenderMode::Shaded
+                || parsed.watch
+                || parsed.preview
+                || parsed.serve.is_some()
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some()
+                || parsed.stereo.is_some()
+                || parsed.settings.region.is_some()
+                || !parsed.workers.is_empty())
+        {
+            return Err(usage(
+                "--deep only works for single shaded images, without --stereo or --region",
+            ));
+        }
+        if let Some((x, y)) = parsed.settings.diagnostics.trace_pixel {
+            if x >= parsed.settings.width || y >= parsed.settings.height {
+                return Err(usage("--trace-pixel must be inside the image"));
+            }
+            if parsed.settings.mode != RenderMode::Shaded
+                || parsed.cryptomatte
+                || parsed.deep
+                || parsed.watch
+                || parsed.preview
+                || parsed.serve.is_some()
+                || parsed.frames.is_some()
+                || parsed.turntable.is_some()
+                || parsed.stereo.is_some()
+                || parsed.settings.region.is_some()
+                || !parsed.workers.is_empty()
+            {
+                return Err(usage(
+                    "--trace-pixel only works for single shaded images, without --stereo or \
+                     --region",
+                ));
+            }
+            // the rest of the image is left as it is, if it's been rendered already
+            parsed.settings.region = Some(Region {
+                x0: x,
+                y0: y,
+                x1: x + 1,
+                y1: y + 1,
+            });
+        }
+        if parsed.stereo.is_some() && parsed.settings.region.is_some() {
+            return Err(usage("--region can't be combined with --stereo"));
+        }
+
+        parsed.save_options.alpha = parsed.settings.transparent_background;
+        Ok(parsed)
+    }
+}
+
+/// An [`Error::Args`] saying what's wrong with the arguments.
+fn usage(message: impl Into<String>) -> Error {
+    Error::Args(message.into())
+}
+
+/// Parses the value given to the argument `arg`.
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| usage(format!("invalid {} {:?}: {}", arg, value, err)))
+}
+
+/// Parses durations like `90`, `45s`, `30m` or `1h30m`, where a bare number is in seconds.
+fn parse_duration(text: &str) -> Result<Duration> {
+    let invalid = || {
+        usage(format!(
+            "invalid duration {:?}, expected something like 1h30m",
+            text
+        ))
+    };
+    if text.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1.0,
+            'm' => 60.0,
+            'h' => 3600.0,
+            _ => return Err(invalid()),
+        };
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        seconds += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<f64>().map_err(|_| invalid())?;
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses sizes like `512M`, `1.5G` or `2T`, in binary units, where a bare number is in bytes.
+fn parse_size(text: &str) -> Result<usize> {
+    let invalid = || {
+        usage(format!(
+            "invalid size {:?}, expected something like 8G",
+            text
+        ))
+    };
+    let (number, unit) = match text.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&text[..i], c.to_ascii_uppercase()),
+        _ => (text, 'B'),
+    };
+    let scale = match unit {
+        'B' => 1u64,
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        'T' => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    let value: f64 = number.parse().map_err(|_| invalid())?;
+    if value <= 0.0 || !value.is_finite() {
+        return Err(invalid());
+    }
+
+    Ok((value * scale as f64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panoramas_are_sized_before_regions_are_checked() {
+        let region = ["--region", "850", "0", "851", "1"];
+        let args = |args: &[&str]| Args::parse_from(args.iter().map(|arg| arg.to_string()));
+
+        let before = args(&[&region[..], &["--panorama"]].concat()).unwrap();
+        let after = args(&[&["--panorama"], &region[..]].concat()).unwrap();
+        assert_eq!(before.settings.width, 2 * IMG_HEIGHT);
+        assert_eq!(after.settings.width, before.settings.width);
+        assert_eq!(after.settings.region, before.settings.region);
+        assert!(matches!(args(&region), Err(Error::Args(_))));
+    }
+}
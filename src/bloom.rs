@@ -0,0 +1,129 @@
+//! Bloom, the glow that light too bright for the film spreads into the pixels around it, as the
+//! lens and sensor of a real camera scatter a little of every light.
+//!
+//! Light above a threshold is blurred with a Gaussian, one axis at a time, and added back onto
+//! the image. It works on the linear light before it's clamped, so a light a hundred times too
+//! bright glows a hundred times as far above the threshold, instead of clipping to flat white.
+
+use crate::{Color, Error, Result};
+
+/// How the bright parts of an image glow.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    /// Only light brighter than this glows, so that the rest of the image stays sharp. 1 is the
+    /// brightest the output can show.
+    pub threshold: f64,
+    /// The fraction of the light above the threshold that's spread out.
+    pub intensity: f64,
+    /// How far the glow spreads, as the standard deviation of the blur over the height of the
+    /// image, so it looks the same at every resolution.
+    pub radius: f64,
+}
+
+impl Bloom {
+    /// Fails if the threshold or intensity is negative, or the radius isn't positive.
+    pub fn new(threshold: f64, intensity: f64, radius: f64) -> Result<Self> {
+        Ok(Self {
+            threshold: Error::check("bloom threshold", threshold, |t| t >= 0.0)?,
+            intensity: Error::check("bloom intensity", intensity, |i| i >= 0.0)?,
+            radius: Error::check("bloom radius", radius, |r| r > 0.0)?,
+        })
+    }
+
+    /// Adds the glow to an image of `width` by `height` linear colors, in rows.
+    pub fn apply(&self, width: usize, height: usize, colors: &mut [Color]) {
+        // the part of each color over the threshold, keeping its hue
+        let mut bright: Vec<Color> = colors
+            .iter()
+            .map(|&color| {
+                let brightest = color.x().max(color.y()).max(color.z());
+                if brightest > self.threshold {
+                    (brightest - self.threshold) / brightest * color
+                } else {
+                    Color::default()
+                }
+            })
+            .collect();
+
+        let kernel = gaussian(self.radius * height as f64);
+        bright = blur(&bright, width, height, &kernel, 1, width);
+        bright = blur(&bright, height, width, &kernel, width, 1);
+
+        for (color, glow) in colors.iter_mut().zip(bright) {
+            *color += self.intensity * glow;
+        }
+    }
+}
+
+/// The weights of a Gaussian out to three standard deviations, from the center outwards, adding
+/// up to 1 over both sides.
+fn gaussian(sigma: f64) -> Vec<f64> {
+    let sigma = sigma.max(0.5);
+    let reach = (3.0 * sigma).ceil() as usize;
+    let weights: Vec<f64> = (0..=reach)
+        .map(|offset| (-0.5 * (offset as f64 / sigma).powi(2)).exp())
+        .collect();
+    let total = 2.0 * weights.iter().sum::<f64>() - weights[0];
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+/// Blurs along lines of `length` pixels, `step` apart within a line and `stride` apart from one
+/// line to the next, over `lines` of them. Light spread past the edge of the image is lost.
+fn blur(
+    colors: &[Color],
+    length: usize,
+    lines: usize,
+    kernel: &[f64],
+    step: usize,
+    stride: usize,
+) -> Vec<Color> {
+    let mut blurred = vec![Color::default(); colors.len()];
+    for line in 0..lines {
+        let start = line * stride;
+        for i in 0..length {
+            let mut sum = kernel[0] * colors[start + i * step];
+            for (offset, &weight) in kernel.iter().enumerate().skip(1) {
+                let neighbours = [
+                    i.checked_sub(offset),
+                    Some(i + offset).filter(|&j| j < length),
+                ];
+                for &j in neighbours.iter().flatten() {
+                    sum += weight * colors[start + j * step];
+                }
+            }
+            blurred[start + i * step] = sum;
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_bright_light_spreads() {
+        let (width, height) = (21, 11);
+        let mut colors = vec![Color::new(0.5, 0.5, 0.5); width * height];
+        colors[5 * width + 10] += Color::new(100.5, 50.5, 0.5);
+        let bloom = Bloom::new(1.0, 0.5, 0.1).unwrap();
+        bloom.apply(width, height, &mut colors);
+
+        // half of the light above the threshold, spread around the light and keeping its hue
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let glow = colors
+            .iter()
+            .fold(Color::default(), |sum, &color| sum + color - gray);
+        let glow = glow - Color::new(100.5, 50.5, 0.5);
+        let light = Color::new(101.0, 51.0, 1.0);
+        assert!((glow - 0.5 * 100.0 / 101.0 * light).length() < 1e-9);
+        let next = colors[5 * width + 11];
+        assert!(next.x() > 0.5 && next.x() > next.y() && next.y() > next.z());
+        assert!(next.x() > colors[5 * width + 13].x());
+        assert_eq!(colors[0], gray);
+
+        let mut dim = vec![Color::new(0.9, 0.2, 0.4); width * height];
+        bloom.apply(width, height, &mut dim);
+        assert!(dim.iter().all(|&c| c == Color::new(0.9, 0.2, 0.4)));
+    }
+}
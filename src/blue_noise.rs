@@ -0,0 +1,307 @@
+//! Blue-noise sampling, which spreads the noise of a few samples per pixel out evenly instead of
+//! in clumps, so a rough render already looks close to the clean one.
+//!
+//! Every pixel looks its random numbers up in a tiled blue-noise mask, shifted by a different
+//! amount for each number a sample takes, and by a random amount for each pass so that passes
+//! stay independent. Neighbouring pixels then get very different numbers, and their errors tend
+//! to cancel out as soon as the eye blurs them together. The samples of one pixel in a pass walk
+//! through a Kronecker sequence, which covers the numbers more evenly than random ones.
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+use std::sync::OnceLock;
+
+/// The mask is this many pixels across, and tiles the image.
+const SIZE: usize = 64;
+/// The random numbers of a sample past this many are plain random ones. The first few decide
+/// the pixel position, the lens position and the first bounce, which is where it shows most.
+const DIMENSIONS: usize = 32;
+/// How far the points of the mask push each other away, in pixels.
+const SIGMA: f64 = 1.9;
+
+/// The square roots of the first primes, whose fractions step through each dimension without
+/// lining up with the other dimensions.
+const PRIMES: [u32; DIMENSIONS] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// A tileable square of every value from 0 to 1 once, with similar values far apart.
+pub struct Mask {
+    values: Vec<f64>,
+}
+
+impl Mask {
+    /// The mask every render uses, made the first time it's needed.
+    pub fn get() -> &'static Self {
+        static MASK: OnceLock<Mask> = OnceLock::new();
+        MASK.get_or_init(|| Self::new(0))
+    }
+
+    /// Makes a mask with the void and cluster method of Ulichney, "The void-and-cluster method
+    /// for dither array generation". The same seed gives the same mask.
+    pub fn new(seed: u64) -> Self {
+        let n = SIZE * SIZE;
+        let wrap = |d: usize| d.min(SIZE - d) as f64;
+        let kernel: Vec<f64> = (0..n)
+            .map(|i| {
+                let (dx, dy) = (wrap(i % SIZE), wrap(i / SIZE));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+
+        // a random tenth of the pixels, spread out evenly by moving the most crowded one to the
+        // emptiest spot until that's where it already was
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pattern = Pattern::new(&kernel);
+        while pattern.count < n / 10 {
+            let i = rng.gen_range(0..n);
+            if !pattern.on[i] {
+                pattern.toggle(i);
+            }
+        }
+        loop {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+            if void == cluster {
+                pattern.toggle(cluster);
+                break;
+            }
+            pattern.toggle(void);
+        }
+
+        // the points of that pattern are ranked by taking the most crowded away first, and the
+        // rest by filling the emptiest spot next
+        let mut ranks = vec![0; n];
+        let initial = pattern.clone();
+        while pattern.count > 0 {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            ranks[cluster] = pattern.count;
+        }
+        let mut pattern = initial;
+        while pattern.count < n {
+            let void = pattern.largest_void();
+            ranks[void] = pattern.count;
+            pattern.toggle(void);
+        }
+
+        Self {
+            values: ranks
+                .into_iter()
+                .map(|rank| (rank as f64 + 0.5) / n as f64)
+                .collect(),
+        }
+    }
+
+    /// The value at pixel (`x`, `y`), repeating every [`SIZE`] pixels.
+    pub fn value(&self, x: usize, y: usize) -> f64 {
+        self.values[(y % SIZE) * SIZE + x % SIZE]
+    }
+}
+
+/// Pixels that are on, and how crowded every pixel is by them.
+#[derive(Clone)]
+struct Pattern<'a> {
+    kernel: &'a [f64],
+    on: Vec<bool>,
+    energy: Vec<f64>,
+    count: usize,
+}
+
+impl<'a> Pattern<'a> {
+    fn new(kernel: &'a [f64]) -> Self {
+        Self {
+            kernel,
+            on: vec![false; kernel.len()],
+            energy: vec![0.0; kernel.len()],
+            count: 0,
+        }
+    }
+
+    fn toggle(&mut self, i: usize) {
+        let sign = if self.on[i] { -1.0 } else { 1.0 };
+        self.on[i] = !self.on[i];
+        self.count = if self.on[i] {
+            self.count + 1
+        } else {
+            self.count - 1
+        };
+
+        let (x, y) = (i % SIZE, i / SIZE);
+        for (j, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (j % SIZE + SIZE - x) % SIZE;
+            let dy = (j / SIZE + SIZE - y) % SIZE;
+            *energy += sign * self.kernel[dy * SIZE + dx];
+        }
+    }
+
+    /// The most crowded pixel that's on.
+    fn tightest_cluster(&self) -> usize {
+        self.most(|on, energy| on.then_some(energy))
+    }
+
+    /// The least crowded pixel that's off.
+    fn largest_void(&self) -> usize {
+        self.most(|on, energy| (!on).then_some(-energy))
+    }
+
+    fn most(&self, score: impl Fn(bool, f64) -> Option<f64>) -> usize {
+        let scores = self.on.iter().zip(&self.energy).enumerate();
+        scores
+            .filter_map(|(i, (&on, &energy))| Some((i, score(on, energy)?)))
+            .fold((0, f64::NEG_INFINITY), |best, (i, score)| {
+                if score > best.1 {
+                    (i, score)
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+}
+
+/// The shifts of one pass over the image, from which every sample of every pixel takes its
+/// random numbers.
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    /// For each dimension, how far the mask is moved across the image and how far its values are
+    /// moved around from 0 to 1.
+    shifts: [(usize, usize, f64); DIMENSIONS],
+}
+
+impl Sequence {
+    /// A pass with its own random shifts, which keeps the passes of a render independent.
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self {
+            shifts: std::array::from_fn(|_| {
+                (rng.gen_range(0..SIZE), rng.gen_range(0..SIZE), rng.gen())
+            }),
+        }
+    }
+
+    /// The random numbers of sample number `index` of pixel (`x`, `y`), carrying on with `rng`
+    /// once the blue-noise ones run out.
+    pub fn sample<'a>(
+        &'a self,
+        x: usize,
+        y: usize,
+        index: u32,
+        rng: &'a mut dyn RngCore,
+    ) -> SampleRng<'a> {
+        SampleRng {
+            sequence: self,
+            mask: Mask::get(),
+            x,
+            y,
+            index,
+            dimension: 0,
+            rng,
+        }
+    }
+}
+
+/// The random numbers of one sample, blue noise across the image for the first few.
+pub struct SampleRng<'a> {
+    sequence: &'a Sequence,
+    mask: &'static Mask,
+    x: usize,
+    y: usize,
+    index: u32,
+    dimension: usize,
+    rng: &'a mut dyn RngCore,
+}
+
+impl SampleRng<'_> {
+    /// The next number from 0 to 1, if it's one of the blue-noise ones.
+    fn next(&mut self) -> Option<f64> {
+        let d = self.dimension;
+        if d >= DIMENSIONS {
+            return None;
+        }
+        self.dimension += 1;
+
+        let (dx, dy, offset) = self.sequence.shifts[d];
+        let step = f64::from(PRIMES[d]).sqrt().fract();
+        let value = self.mask.value(self.x + dx, self.y + dy) + offset + self.index as f64 * step;
+        Some(value.fract())
+    }
+}
+
+impl RngCore for SampleRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        match self.next() {
+            Some(value) => (value * 2f64.powi(32)) as u32,
+            None => self.rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self.next() {
+            Some(value) => (value * 2f64.powi(64)) as u64,
+            None => self.rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_hold_every_value_once() {
+        let mask = Mask::get();
+        let mut ranks: Vec<usize> = (0..SIZE * SIZE)
+            .map(|i| (mask.value(i % SIZE, i / SIZE) * (SIZE * SIZE) as f64) as usize)
+            .collect();
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..SIZE * SIZE));
+        assert_eq!(mask.value(3, 5), mask.value(3 + SIZE, 5 + 2 * SIZE));
+    }
+
+    #[test]
+    fn blocks_average_out() {
+        // the means of blocks of white noise vary by a twelfth over the number of pixels in them
+        let mask = Mask::get();
+        let blocks = SIZE / 4;
+        let mut variance = 0.0;
+        for i in 0..blocks * blocks {
+            let (x, y) = (i % blocks * 4, i / blocks * 4);
+            let mean = (0..16)
+                .map(|j| mask.value(x + j % 4, y + j / 4))
+                .sum::<f64>()
+                / 16.0;
+            variance += (mean - 0.5).powi(2) / (blocks * blocks) as f64;
+        }
+        assert!(variance < 0.3 / 12.0 / 16.0);
+    }
+
+    #[test]
+    fn samples_stay_uniform() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut sums = [0.0; DIMENSIONS + 1];
+        let passes = 100;
+        for _ in 0..passes {
+            let sequence = Sequence::new(&mut rng);
+            for index in 0..8 {
+                let mut sample = sequence.sample(5, 9, index, &mut rng);
+                for sum in &mut sums {
+                    *sum += sample.gen::<f64>();
+                }
+            }
+        }
+        for sum in sums {
+            assert!((sum / (passes * 8) as f64 - 0.5).abs() < 0.05);
+        }
+    }
+}
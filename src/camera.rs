@@ -0,0 +1,795 @@
+use crate::collision::Hittable;
+use crate::{Color, Point3, Ray, RayCone, Vec3};
+
+use image::GrayImage;
+use rand::Rng;
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// All rays share the view direction and start on the film plane.
+    Orthographic,
+    /// An equidistant fisheye lens, where the angle from the view direction grows linearly with
+    /// the distance from the image center. `fov` is the vertical field of view in degrees, and
+    /// may go past 180.
+    Fisheye {
+        fov: f64,
+    },
+    /// A full 360x180 degree panorama, with longitude along the x axis and latitude along y.
+    Equirectangular,
+}
+
+/// The shape of the lens opening, which out-of-focus highlights take on.
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    Disk,
+    /// A regular polygon with one corner per blade, rotated by `rotation` degrees.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    /// An arbitrary shape, where brighter pixels let through more light.
+    Mask(Arc<GrayImage>),
+}
+
+impl Aperture {
+    /// Samples a point on the aperture, within the unit disk (or square, for masks).
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec3 {
+        match self {
+            Aperture::Disk => Vec3::random_in_unit_disk(rng),
+            Aperture::Polygon { blades, rotation } => {
+                // pick one of the triangles between the center and two adjacent corners
+                let step = std::f64::consts::TAU / *blades as f64;
+                let angle = rotation.to_radians() + step * rng.gen_range(0..*blades) as f64;
+                let a = Vec3::new(angle.cos(), angle.sin(), 0.0);
+                let b = Vec3::new((angle + step).cos(), (angle + step).sin(), 0.0);
+
+                let (mut s, mut t): (f64, f64) = (rng.gen(), rng.gen());
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                s * a + t * b
+            }
+            Aperture::Mask(mask) => {
+                let (width, height) = mask.dimensions();
+                // rejection sampling, giving up on masks that are (almost) entirely black
+                for _ in 0..64 {
+                    let x: f64 = rng.gen();
+                    let y: f64 = rng.gen();
+                    let px = ((x * width as f64) as u32).min(width - 1);
+                    let py = ((y * height as f64) as u32).min(height - 1);
+
+                    if rng.gen::<f64>() * 255.0 < mask.get_pixel(px, py).0[0] as f64 {
+                        return Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0);
+                    }
+                }
+                Vec3::default()
+            }
+        }
+    }
+}
+
+/// The flaws of a real lens, which renders can copy to match footage shot through one. Only
+/// perspective cameras have them.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Lens {
+    /// Darkens the image away from its center by the fourth power of the cosine of the angle off
+    /// the view direction, the natural falloff of real lenses.
+    pub vignetting: bool,
+    /// The radial distortion coefficients `k1` and `k2` of the Brown-Conrady model, which moves
+    /// a point `r` away from the center of the view, in units of the focal length, to
+    /// `r * (1 + k1 * r^2 + k2 * r^4)` in the image. Negative values give barrel distortion and
+    /// positive ones pincushion distortion, and they're the same as camera calibration tools
+    /// like OpenCV report.
+    pub distortion: [f64; 2],
+    /// Lateral chromatic aberration: how much larger red shows up than green, as a fraction,
+    /// with blue as much smaller. It fringes the edges of the image with color.
+    pub lateral_aberration: f64,
+    /// Longitudinal chromatic aberration: how much further away red is in focus than green, as a
+    /// fraction of the focus distance, with blue as much closer. It fringes what's out of focus,
+    /// so only shows with an aperture.
+    pub longitudinal_aberration: f64,
+}
+
+impl Lens {
+    /// Whether the lens bends the red, green and blue light apart, so that each needs rays of
+    /// its own.
+    pub fn has_aberration(&self) -> bool {
+        self.lateral_aberration != 0.0 || self.longitudinal_aberration != 0.0
+    }
+
+    /// How many times further from the center a point at `r_squared` ends up.
+    fn radial(&self, r_squared: f64) -> f64 {
+        let [k1, k2] = self.distortion;
+        1.0 + k1 * r_squared + k2 * r_squared * r_squared
+    }
+}
+
+/// What a ray of the color `channel` adds to each channel of its pixel, when the samples of the
+/// pixel take turns tracing red, green and blue: three times its light, for the two others it
+/// stands in for.
+pub fn channel_weights(channel: usize) -> Color {
+    let mut weights = Color::default();
+    weights[channel] = 3.0;
+    weights
+}
+
+/// Enough for the distortion of any real lens to converge far below a pixel.
+const UNDISTORT_ITERATIONS: usize = 10;
+
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct Camera {
+    origin: Point3,
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    lens_radius: f64,
+    aperture: Aperture,
+    projection: Projection,
+    /// For panoramas, the distance of the eye to the right of the center of projection.
+    eye_offset: f64,
+    lens: Lens,
+}
+
+impl Camera {
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        vertical_fov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> Self {
+        let theta = vertical_fov.to_radians();
+        let h = (theta / 2.0).tan();
+
+        let viewport_height = 2.0 * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let w = (look_from - look_at).normalize();
+        let u = vup.cross(&w).normalize();
+        let v = w.cross(&u);
+
+        let origin = look_from;
+        let horizontal = focus_dist * viewport_width * u;
+        let vertical = focus_dist * viewport_height * v;
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
+
+        let lens_radius = aperture / 2.0;
+
+        Self {
+            origin,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius,
+            aperture: Aperture::Disk,
+            projection: Projection::Perspective,
+            eye_offset: 0.0,
+            lens: Lens::default(),
+        }
+    }
+
+    /// A camera described like a real one: a lens of `focal_length` millimeters on a sensor
+    /// `sensor_width` millimeters wide, with the aperture given as an f-number.
+    ///
+    /// The aperture diameter is `focal_length / f_stop`, converted to scene units assuming they
+    /// are meters, just like `focus_dist`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn physical(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        focal_length: f64,
+        sensor_width: f64,
+        f_stop: f64,
+        focus_dist: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        let sensor_height = sensor_width / aspect_ratio;
+        let vertical_fov = 2.0 * (sensor_height / (2.0 * focal_length)).atan().to_degrees();
+        let aperture = focal_length / f_stop / 1000.0;
+
+        Self::new(
+            look_from,
+            look_at,
+            vup,
+            vertical_fov,
+            aspect_ratio,
+            aperture,
+            focus_dist,
+        )
+    }
+
+    /// A camera looking along `look_at - look_from` without any perspective, showing a view
+    /// `view_height` units tall.
+    pub fn orthographic(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        let w = (look_from - look_at).normalize();
+        let u = vup.cross(&w).normalize();
+        let v = w.cross(&u);
+
+        let origin = look_from;
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0;
+
+        Self {
+            origin,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: 0.0,
+            aperture: Aperture::Disk,
+            projection: Projection::Orthographic,
+            eye_offset: 0.0,
+            lens: Lens::default(),
+        }
+    }
+
+    pub fn fisheye(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        Self {
+            projection: Projection::Fisheye { fov },
+            ..Self::new(look_from, look_at, vup, 90.0, aspect_ratio, 0.0, 1.0)
+        }
+    }
+
+    /// A panoramic camera, centered on the direction towards `look_at`. Images rendered with it
+    /// should have an aspect ratio of 2:1.
+    pub fn equirectangular(look_from: Point3, look_at: Point3, vup: Vec3) -> Self {
+        Self {
+            projection: Projection::Equirectangular,
+            ..Self::new(look_from, look_at, vup, 90.0, 2.0, 0.0, 1.0)
+        }
+    }
+
+    /// Where the rays start, the middle of the lens.
+    pub fn origin(&self) -> Point3 {
+        self.origin
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn with_aperture(self, aperture: Aperture) -> Self {
+        Self { aperture, ..self }
+    }
+
+    pub fn with_lens(self, lens: Lens) -> Self {
+        Self { lens, ..self }
+    }
+
+    /// The view of one eye in a stereo pair, `offset` units to the right of this camera (or to
+    /// the left, if negative).
+    ///
+    /// Panoramas use omni-directional stereo, where the eyes orbit the center of projection as
+    /// they look around.
+    pub fn eye(&self, offset: f64) -> Self {
+        if self.projection == Projection::Equirectangular {
+            return Self {
+                eye_offset: offset,
+                ..self.clone()
+            };
+        }
+
+        Self {
+            origin: self.origin + offset * self.u,
+            lower_left_corner: self.lower_left_corner + offset * self.u,
+            ..self.clone()
+        }
+    }
+
+    /// The cone of the rays through one pixel of an image `image_height` pixels tall, leaving
+    /// out the blur of the lens.
+    pub fn pixel_cone(&self, image_height: u32) -> RayCone {
+        let pixels = image_height.max(1) as f64;
+        match self.projection {
+            Projection::Perspective => {
+                let center = self.lower_left_corner + 0.5 * (self.horizontal + self.vertical);
+                RayCone {
+                    width: 0.0,
+                    spread: self.vertical.length() / (center - self.origin).length() / pixels,
+                }
+            }
+            Projection::Orthographic => RayCone {
+                width: self.vertical.length() / pixels,
+                spread: 0.0,
+            },
+            Projection::Fisheye { fov } => RayCone {
+                width: 0.0,
+                spread: fov.to_radians() / pixels,
+            },
+            Projection::Equirectangular => RayCone {
+                width: 0.0,
+                spread: std::f64::consts::PI / pixels,
+            },
+        }
+    }
+
+    /// Where `point` shows up on the film, as the `s` and `t` that [`Camera::get_ray`] takes, and
+    /// the cosine of the angle between the view direction and the way to the point.
+    ///
+    /// Only perspective cameras are supported, as if through a pinhole. `None` for any other
+    /// projection and for points behind the camera.
+    pub fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        if self.projection != Projection::Perspective {
+            return None;
+        }
+
+        let to_point = point - self.origin;
+        let depth = -to_point.dot(&self.w);
+        if depth <= 0.0 {
+            return None;
+        }
+
+        let center = self.lower_left_corner + 0.5 * (self.horizontal + self.vertical);
+        let focus_dist = (self.origin - center).dot(&self.w);
+        let on_film = self.origin + to_point * (focus_dist / depth) - self.lower_left_corner;
+        let s = on_film.dot(&self.horizontal) / self.horizontal.length_squared();
+        let t = on_film.dot(&self.vertical) / self.vertical.length_squared();
+        let (s, t) = self.distort(s, t);
+
+        Some((s, t, depth / to_point.length()))
+    }
+
+    /// How much of the light reaching the film at `s` and `t` the vignetting of the lens lets
+    /// through.
+    pub fn vignetting(&self, s: f64, t: f64) -> f64 {
+        if !self.lens.vignetting || self.projection != Projection::Perspective {
+            return 1.0;
+        }
+
+        let (x, y) = self.view_point(self.undistort(s, t));
+        (1.0 + x * x + y * y).powi(-2)
+    }
+
+    /// How many times larger the lens distortion makes a small patch of the view where it shows
+    /// up around `s` and `t`.
+    pub fn magnification(&self, s: f64, t: f64) -> f64 {
+        if self.projection != Projection::Perspective {
+            return 1.0;
+        }
+
+        let (x, y) = self.view_point(self.undistort(s, t));
+        let r_squared = x * x + y * y;
+        let [k1, k2] = self.lens.distortion;
+        self.lens.radial(r_squared)
+            * (1.0 + 3.0 * k1 * r_squared + 5.0 * k2 * r_squared * r_squared)
+    }
+
+    /// The film coordinates as offsets from the center of the view, in units of the focal length.
+    fn view_point(&self, (s, t): (f64, f64)) -> (f64, f64) {
+        let (width, height) = self.view_size();
+        ((s - 0.5) * width, (t - 0.5) * height)
+    }
+
+    fn film_point(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (width, height) = self.view_size();
+        (x / width + 0.5, y / height + 0.5)
+    }
+
+    /// The size of the film of a perspective camera, scaled to one unit away from the pinhole.
+    fn view_size(&self) -> (f64, f64) {
+        let center = self.lower_left_corner + 0.5 * (self.horizontal + self.vertical);
+        let focus_dist = (self.origin - center).dot(&self.w);
+        (
+            self.horizontal.length() / focus_dist,
+            self.vertical.length() / focus_dist,
+        )
+    }
+
+    /// Where the lens moves the point of the film at `s` and `t`.
+    fn distort(&self, s: f64, t: f64) -> (f64, f64) {
+        if self.lens.distortion == [0.0; 2] {
+            return (s, t);
+        }
+
+        let (x, y) = self.view_point((s, t));
+        let scale = self.lens.radial(x * x + y * y);
+        self.film_point((scale * x, scale * y))
+    }
+
+    /// The point of the film that the lens moves to `s` and `t`, found by fixed-point iteration,
+    /// as there's no closed form.
+    fn undistort(&self, s: f64, t: f64) -> (f64, f64) {
+        if self.lens.distortion == [0.0; 2] {
+            return (s, t);
+        }
+
+        let (x, y) = self.view_point((s, t));
+        let (mut ux, mut uy) = (x, y);
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let scale = self.lens.radial(ux * ux + uy * uy);
+            ux = x / scale;
+            uy = y / scale;
+        }
+        self.film_point((ux, uy))
+    }
+
+    /// The area of the film of a perspective camera, scaled to one unit away from the pinhole.
+    pub fn film_area(&self) -> f64 {
+        let center = self.lower_left_corner + 0.5 * (self.horizontal + self.vertical);
+        let focus_dist = (self.origin - center).dot(&self.w);
+
+        self.horizontal.length() * self.vertical.length() / (focus_dist * focus_dist)
+    }
+
+    pub fn lens(&self) -> Lens {
+        self.lens
+    }
+
+    pub fn get_ray<R: Rng + ?Sized>(&self, s: f64, t: f64, rng: &mut R) -> Ray {
+        self.ray_through(s, t, None, self.lens_sample(rng))
+    }
+
+    /// The ray carrying the light of one color `channel`, 0 for red, 1 for green and 2 for blue,
+    /// which the chromatic aberration of the lens bends differently from the others.
+    pub fn channel_ray<R: Rng + ?Sized>(&self, s: f64, t: f64, channel: usize, rng: &mut R) -> Ray {
+        self.ray_through(s, t, Some(channel), self.lens_sample(rng))
+    }
+
+    /// A random point on the lens for [`Camera::ray_through`], relative to its center. Pinholes
+    /// and projections without a lens always give the center, without drawing anything.
+    pub fn lens_sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec3 {
+        if self.lens_radius == 0.0 || self.projection != Projection::Perspective {
+            return Vec3::default();
+        }
+        self.lens_radius * self.aperture.sample(rng)
+    }
+
+    /// The ray through the film at `s` and `t` and the point `lens` from
+    /// [`Camera::lens_sample`], carrying the light of `channel` if it's just one. For drawing
+    /// the random numbers of many rays at once, ahead of tracing them.
+    pub fn ray_through(&self, s: f64, t: f64, channel: Option<usize>, lens: Vec3) -> Ray {
+        match self.projection {
+            Projection::Perspective => {}
+            Projection::Orthographic => {
+                return Ray {
+                    origin: self.lower_left_corner + s * self.horizontal + t * self.vertical,
+                    direction: -self.w,
+                };
+            }
+            Projection::Fisheye { fov } => {
+                let aspect_ratio = self.horizontal.length() / self.vertical.length();
+                let x = (2.0 * s - 1.0) * aspect_ratio;
+                let y = 2.0 * t - 1.0;
+
+                let r = (x * x + y * y).sqrt();
+                let theta = r * fov.to_radians() / 2.0;
+                let (sin_phi, cos_phi) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+
+                return Ray {
+                    origin: self.origin,
+                    direction: theta.sin() * (cos_phi * self.u + sin_phi * self.v)
+                        - theta.cos() * self.w,
+                };
+            }
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * std::f64::consts::TAU;
+                let theta = (t - 0.5) * std::f64::consts::PI;
+                let right = phi.cos() * self.u + phi.sin() * self.w;
+
+                return Ray {
+                    origin: self.origin + self.eye_offset * right,
+                    direction: theta.cos() * (phi.sin() * self.u - phi.cos() * self.w)
+                        + theta.sin() * self.v,
+                };
+            }
+        }
+
+        let (mut s, mut t) = self.undistort(s, t);
+        // red is bent the least, so it's magnified more and focused further away than green,
+        // and blue the other way around
+        let shift = match channel {
+            Some(channel) => 1.0 - channel.min(2) as f64,
+            None => 0.0,
+        };
+        if shift != 0.0 && self.lens.lateral_aberration != 0.0 {
+            let (x, y) = self.view_point((s, t));
+            let scale = 1.0 + shift * self.lens.lateral_aberration;
+            (s, t) = self.film_point((x / scale, y / scale));
+        }
+        let focus = 1.0 + shift * self.lens.longitudinal_aberration;
+
+        let on_plane = self.u * lens.x() + self.v * lens.y();
+        let to_film =
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin;
+
+        Ray {
+            origin: self.origin + on_plane,
+            direction: (focus * to_film - on_plane).normalize(),
+        }
+    }
+}
+
+/// Why a [`CameraBuilder`] couldn't build a camera.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum CameraError {
+    /// `look_from` and `look_at` are the same point, so there is no view direction.
+    #[error("look_from and look_at are the same point")]
+    NoViewDirection,
+    /// `vup` is zero or parallel to the view direction, so "up" is undefined.
+    #[error("the up vector is zero or parallel to the view direction")]
+    DegenerateUp,
+    /// A parameter is non-finite or outside of its valid range.
+    #[error("invalid camera parameter {name}: {value}")]
+    InvalidParameter { name: &'static str, value: f64 },
+}
+
+/// A less error-prone way to set up a [`Camera`] than the positional constructors.
+///
+/// By default the camera sits at the origin looking down the negative z axis with a 90 degree
+/// vertical field of view, a 16:9 aspect ratio and a pinhole aperture. Unless set explicitly,
+/// the focus distance is the distance to `look_at`.
+#[derive(Clone, Debug)]
+pub struct CameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vertical_fov: f64,
+    aspect_ratio: f64,
+    aperture: f64,
+    aperture_shape: Aperture,
+    focus_dist: Option<f64>,
+    projection: Projection,
+    view_height: f64,
+    lens: Lens,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vertical_fov: 90.0,
+            aspect_ratio: 16.0 / 9.0,
+            aperture: 0.0,
+            aperture_shape: Aperture::Disk,
+            focus_dist: None,
+            projection: Projection::Perspective,
+            view_height: 2.0,
+            lens: Lens::default(),
+        }
+    }
+}
+
+impl Camera {
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+}
+
+impl CameraBuilder {
+    pub fn look_from(self, look_from: Point3) -> Self {
+        Self { look_from, ..self }
+    }
+
+    pub fn look_at(self, look_at: Point3) -> Self {
+        Self { look_at, ..self }
+    }
+
+    pub fn vup(self, vup: Vec3) -> Self {
+        Self { vup, ..self }
+    }
+
+    /// In degrees.
+    pub fn vertical_fov(self, vertical_fov: f64) -> Self {
+        Self {
+            vertical_fov,
+            ..self
+        }
+    }
+
+    pub fn aspect_ratio(self, aspect_ratio: f64) -> Self {
+        Self {
+            aspect_ratio,
+            ..self
+        }
+    }
+
+    /// The diameter of the lens.
+    pub fn aperture(self, aperture: f64) -> Self {
+        Self { aperture, ..self }
+    }
+
+    pub fn aperture_shape(self, aperture_shape: Aperture) -> Self {
+        Self {
+            aperture_shape,
+            ..self
+        }
+    }
+
+    pub fn focus_dist(self, focus_dist: f64) -> Self {
+        Self {
+            focus_dist: Some(focus_dist),
+            ..self
+        }
+    }
+
+    /// Focuses on the first thing in `world` on the way from `look_from` to `point`, or on
+    /// `point` itself if nothing's in the way. The distance is measured along the view direction,
+    /// since that's what the plane in focus is square to.
+    pub fn focus_on<T: Hittable + ?Sized>(self, world: &T, point: Point3) -> Self {
+        let ray = Ray {
+            origin: self.look_from,
+            direction: point - self.look_from,
+        };
+        let view = (self.look_at - self.look_from).normalize();
+        let target = match world.hit(&ray, 0.001, 1.0) {
+            Some(hit) => hit.point,
+            None => point,
+        };
+        let focus_dist = (target - self.look_from).dot(&view);
+        self.focus_dist(focus_dist)
+    }
+
+    pub fn projection(self, projection: Projection) -> Self {
+        Self { projection, ..self }
+    }
+
+    /// The height of the view for orthographic cameras, ignored by other projections.
+    pub fn view_height(self, view_height: f64) -> Self {
+        Self {
+            view_height,
+            ..self
+        }
+    }
+
+    /// Vignetting and distortion, for perspective cameras.
+    pub fn lens(self, lens: Lens) -> Self {
+        Self { lens, ..self }
+    }
+
+    pub fn build(self) -> Result<Camera, CameraError> {
+        let direction = self.look_at - self.look_from;
+        let focus_dist = self.focus_dist.unwrap_or_else(|| direction.length());
+
+        let check = |name, value: f64, valid: bool| {
+            if value.is_finite() && valid {
+                Ok(())
+            } else {
+                Err(CameraError::InvalidParameter { name, value })
+            }
+        };
+        for (name, point) in [("look_from", self.look_from), ("look_at", self.look_at)].iter() {
+            for &value in point.0.iter() {
+                check(name, value, true)?;
+            }
+        }
+        let fov = self.vertical_fov;
+        check("vertical_fov", fov, fov > 0.0 && fov < 180.0)?;
+        check("aspect_ratio", self.aspect_ratio, self.aspect_ratio > 0.0)?;
+        check("aperture", self.aperture, self.aperture >= 0.0)?;
+        match &self.aperture_shape {
+            Aperture::Disk => {}
+            &Aperture::Polygon { blades, rotation } => {
+                check("blades", blades as f64, blades >= 3)?;
+                check("rotation", rotation, true)?;
+            }
+            Aperture::Mask(mask) => {
+                let (width, height) = mask.dimensions();
+                check("mask width", width as f64, width > 0)?;
+                check("mask height", height as f64, height > 0)?;
+            }
+        }
+        check("view_height", self.view_height, self.view_height > 0.0)?;
+        check("focus_dist", focus_dist, focus_dist > 0.0)?;
+        for &k in self.lens.distortion.iter() {
+            check("distortion", k, true)?;
+        }
+        let lens = self.lens;
+        check(
+            "lateral_aberration",
+            lens.lateral_aberration,
+            lens.lateral_aberration.abs() < 1.0,
+        )?;
+        check(
+            "longitudinal_aberration",
+            lens.longitudinal_aberration,
+            lens.longitudinal_aberration.abs() < 1.0,
+        )?;
+        if let Projection::Fisheye { fov } = self.projection {
+            check("fov", fov, fov > 0.0 && fov <= 360.0)?;
+        }
+
+        if direction.near_zero() {
+            return Err(CameraError::NoViewDirection);
+        }
+        if !self.vup.0.iter().all(|x| x.is_finite())
+            || self.vup.cross(&direction).length_squared()
+                < 1e-12 * self.vup.length_squared() * direction.length_squared()
+        {
+            return Err(CameraError::DegenerateUp);
+        }
+
+        let (look_from, look_at, vup) = (self.look_from, self.look_at, self.vup);
+        let camera = match self.projection {
+            Projection::Orthographic => {
+                Camera::orthographic(look_from, look_at, vup, self.view_height, self.aspect_ratio)
+            }
+            projection => Camera {
+                projection,
+                ..Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    self.vertical_fov,
+                    self.aspect_ratio,
+                    self.aperture,
+                    focus_dist,
+                )
+            },
+        };
+
+        Ok(camera
+            .with_aperture(self.aperture_shape)
+            .with_lens(self.lens))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apertures_need_a_shape() {
+        let build = |aperture_shape| {
+            Camera::builder()
+                .aperture(0.1)
+                .aperture_shape(aperture_shape)
+                .build()
+        };
+
+        assert!(build(Aperture::Polygon {
+            blades: 5,
+            rotation: 0.0
+        })
+        .is_ok());
+        assert_eq!(
+            build(Aperture::Polygon {
+                blades: 2,
+                rotation: 0.0
+            })
+            .err(),
+            Some(CameraError::InvalidParameter {
+                name: "blades",
+                value: 2.0
+            })
+        );
+        assert_eq!(
+            build(Aperture::Mask(Arc::new(GrayImage::new(0, 8)))).err(),
+            Some(CameraError::InvalidParameter {
+                name: "mask width",
+                value: 0.0
+            })
+        );
+        assert!(build(Aperture::Mask(Arc::new(GrayImage::new(8, 8)))).is_ok());
+    }
+}
@@ -0,0 +1,761 @@
+use super::lights::AreaLight;
+use super::objects::Transformed;
+use super::{rays_in, Hit, Hittable, Intersection, Ray, RayMask};
+use crate::memory::MemoryUsage;
+use crate::scratch;
+use crate::transform::Transform;
+use crate::{Point3, Vec3};
+
+#[cfg(feature = "parallel")]
+use rayon::join;
+
+use std::cell::Cell;
+use std::sync::Arc;
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(a: Point3, b: Point3) -> Self {
+        Self {
+            min: Vec3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Vec3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            Vec3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let [x, y, z] = (self.max - self.min).0;
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(ray, t_min, t_max).is_some()
+    }
+
+    /// The range of `t` over which the ray is inside the box, clipped to `[t_min, t_max]`.
+    pub fn intersect(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some((t_min, t_max))
+    }
+}
+
+/// Counts of the work done by intersection queries on the current thread.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TraversalStats {
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+thread_local! {
+    static STATS: Cell<TraversalStats> = Cell::new(TraversalStats::default());
+}
+
+/// Returns the traversal counters of the current thread and resets them to zero.
+pub fn take_traversal_stats() -> TraversalStats {
+    STATS.with(|stats| stats.take())
+}
+
+/// Returns the traversal counters of the current thread, leaving them as they are.
+pub fn traversal_stats() -> TraversalStats {
+    STATS.with(|stats| stats.get())
+}
+
+fn record(nodes: u64, primitives: u64) {
+    STATS.with(|stats| {
+        let mut current = stats.get();
+        current.nodes_visited += nodes;
+        current.primitives_tested += primitives;
+        stats.set(current);
+    });
+}
+
+pub(crate) fn record_primitive_test() {
+    record(0, 1);
+}
+
+/// How many bins the centroids are sorted into when looking for the best split.
+const SAH_BINS: usize = 12;
+/// Subtrees with at least this many objects have their halves built at the same time.
+const PARALLEL_BUILD_SIZE: usize = 4096;
+
+#[cfg(not(feature = "parallel"))]
+fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB,
+{
+    (a(), b())
+}
+
+enum Node {
+    Leaf(usize),
+    Interior { left: usize, right: usize },
+}
+
+/// The nodes of a hierarchy over bounding boxes, with leaves pointing wherever their boxes came
+/// from.
+struct Tree {
+    nodes: Vec<(Aabb, Node)>,
+}
+
+impl Tree {
+    fn memory(&self) -> usize {
+        std::mem::size_of::<Self>() + self.nodes.capacity() * std::mem::size_of::<(Aabb, Node)>()
+    }
+
+    fn new(mut items: Vec<(Aabb, usize)>) -> Self {
+        let mut nodes = Vec::with_capacity(2 * items.len());
+        if !items.is_empty() {
+            Self::build(&mut nodes, &mut items);
+        }
+
+        Self { nodes }
+    }
+
+    /// Builds the subtree for `items`, returning the index of its root node.
+    fn build(nodes: &mut Vec<(Aabb, Node)>, items: &mut [(Aabb, usize)]) -> usize {
+        let bbox = items[1..]
+            .iter()
+            .fold(items[0].0, |acc, (bbox, _)| acc.union(bbox));
+
+        let idx = nodes.len();
+        if let [(_, object)] = items {
+            nodes.push((bbox, Node::Leaf(*object)));
+            return idx;
+        }
+
+        // reserve the slot for this node, the children get filled in after they're built
+        nodes.push((bbox, Node::Leaf(0)));
+        let parallel = items.len() >= PARALLEL_BUILD_SIZE;
+        let mid = Self::split(items);
+        let (left_items, right_items) = items.split_at_mut(mid);
+        let (left, right) = if parallel {
+            // the halves are built on their own, then moved in after this node
+            let subtree = |items: &mut [(Aabb, usize)]| {
+                let mut nodes = Vec::with_capacity(2 * items.len());
+                Self::build(&mut nodes, items);
+                nodes
+            };
+            let (left, right) = join(|| subtree(left_items), || subtree(right_items));
+            (Self::append(nodes, left), Self::append(nodes, right))
+        } else {
+            let left = Self::build(nodes, left_items);
+            (left, Self::build(nodes, right_items))
+        };
+        nodes[idx].1 = Node::Interior { left, right };
+
+        idx
+    }
+
+    /// Moves a subtree built on its own to the end of `nodes`, returning the index of its root.
+    fn append(nodes: &mut Vec<(Aabb, Node)>, subtree: Vec<(Aabb, Node)>) -> usize {
+        let offset = nodes.len();
+        nodes.extend(subtree.into_iter().map(|(bbox, node)| match node {
+            Node::Leaf(object) => (bbox, Node::Leaf(object)),
+            Node::Interior { left, right } => (
+                bbox,
+                Node::Interior {
+                    left: left + offset,
+                    right: right + offset,
+                },
+            ),
+        }));
+
+        offset
+    }
+
+    /// Reorders at least two `items` to go on either side of the split with the lowest surface
+    /// area heuristic, returning how many go on the left.
+    ///
+    /// Only the splits between bins of centroids along the axis where they're spread out the
+    /// most are tried, which is nearly as good as trying every split and much faster.
+    fn split(items: &mut [(Aabb, usize)]) -> usize {
+        let (lo, hi) = items.iter().fold(
+            (
+                Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                -Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            ),
+            |(lo, hi), (bbox, _)| {
+                let c = bbox.centroid();
+                (
+                    Vec3::new(lo.x().min(c.x()), lo.y().min(c.y()), lo.z().min(c.z())),
+                    Vec3::new(hi.x().max(c.x()), hi.y().max(c.y()), hi.z().max(c.z())),
+                )
+            },
+        );
+        let extent = hi - lo;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            // all in the same place, any split is as good as another
+            return items.len() / 2;
+        }
+
+        let bin_of = |bbox: &Aabb| {
+            let offset = (bbox.centroid()[axis] - lo[axis]) / extent[axis];
+            ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+        };
+        let mut bins: [(Option<Aabb>, usize); SAH_BINS] = [(None, 0); SAH_BINS];
+        for (bbox, _) in items.iter() {
+            let (bin_box, count) = &mut bins[bin_of(bbox)];
+            *bin_box = Some(bin_box.map_or(*bbox, |b| b.union(bbox)));
+            *count += 1;
+        }
+
+        // sweep from the right for the area and count after each split, then from the left
+        let grow = |(acc, total): (Option<Aabb>, usize), (bbox, count): &(Option<Aabb>, usize)| {
+            let acc = match (acc, bbox) {
+                (Some(acc), Some(bbox)) => Some(acc.union(bbox)),
+                (acc, bbox) => acc.or(*bbox),
+            };
+            (acc, total + count)
+        };
+        let mut right = [(None, 0); SAH_BINS];
+        for bin in (1..SAH_BINS).rev() {
+            let after = right.get(bin + 1).copied().unwrap_or((None, 0));
+            right[bin] = grow(after, &bins[bin]);
+        }
+        let area = |(bbox, count): (Option<Aabb>, usize)| {
+            bbox.map_or(0.0, |bbox| bbox.surface_area() * count as f64)
+        };
+        let (mut left, mut best, mut best_cost) = ((None, 0), 0, f64::INFINITY);
+        for bin in 0..SAH_BINS - 1 {
+            left = grow(left, &bins[bin]);
+            let cost = area(left) + area(right[bin + 1]);
+            if left.1 > 0 && right[bin + 1].1 > 0 && cost < best_cost {
+                best = bin;
+                best_cost = cost;
+            }
+        }
+
+        let mut mid = 0;
+        for i in 0..items.len() {
+            if bin_of(&items[i].0) <= best {
+                items.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        mid
+    }
+
+    /// The closest hit, with `hit_leaf` testing what a leaf points to.
+    fn hit<'a, F>(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        hit_leaf: &F,
+    ) -> Option<Intersection<'a>>
+    where
+        F: Fn(usize, f64) -> Option<Intersection<'a>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        self.hit_node(0, ray, t_min, t_max, hit_leaf)
+    }
+
+    fn hit_node<'a, F>(
+        &self,
+        idx: usize,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        hit_leaf: &F,
+    ) -> Option<Intersection<'a>>
+    where
+        F: Fn(usize, f64) -> Option<Intersection<'a>>,
+    {
+        let (bbox, node) = &self.nodes[idx];
+        record(1, 0);
+
+        if !bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        match *node {
+            Node::Leaf(object) => {
+                record_primitive_test();
+                hit_leaf(object, t_max)
+            }
+            Node::Interior { left, right } => {
+                let left_hit = self.hit_node(left, ray, t_min, t_max, hit_leaf);
+                let t_max = left_hit.as_ref().map_or(t_max, |hit| hit.t);
+                let right_hit = self.hit_node(right, ray, t_min, t_max, hit_leaf);
+
+                right_hit.or(left_hit)
+            }
+        }
+    }
+
+    /// Whether `occluded_leaf` says the ray is blocked by any leaf it reaches, stopping at the
+    /// first one that does.
+    fn occluded<F>(&self, ray: &Ray, t_min: f64, t_max: f64, occluded_leaf: &F) -> bool
+    where
+        F: Fn(usize) -> bool,
+    {
+        scratch::with(|scratch| {
+            let mut stack = scratch.vec(64);
+            stack.push(0);
+            while let Some(idx) = stack.pop() {
+                let Some((bbox, node)) = self.nodes.get(idx) else {
+                    break;
+                };
+                record(1, 0);
+                if !bbox.hit(ray, t_min, t_max) {
+                    continue;
+                }
+                match *node {
+                    Node::Leaf(object) => {
+                        record_primitive_test();
+                        if occluded_leaf(object) {
+                            return true;
+                        }
+                    }
+                    Node::Interior { left, right } => stack.extend([right, left]),
+                }
+            }
+            false
+        })
+    }
+
+    /// Which of the `active` rays of `packet` `occluded_leaf` says are blocked by the leaves
+    /// they reach. Each node is visited once for all the rays reaching its box, and the rays
+    /// blocked on one side aren't taken to the other.
+    fn occluded_packet<F>(
+        &self,
+        packet: &[(Ray, f64)],
+        t_min: f64,
+        active: RayMask,
+        occluded_leaf: &F,
+    ) -> RayMask
+    where
+        F: Fn(usize, RayMask) -> RayMask,
+    {
+        scratch::with(|scratch| {
+            let mut occluded = 0;
+            let mut stack = scratch.vec(64);
+            stack.push((0, active));
+            while let Some((idx, active)) = stack.pop() {
+                let Some((bbox, node)) = self.nodes.get(idx) else {
+                    break;
+                };
+                let active = active & !occluded;
+                if active == 0 {
+                    continue;
+                }
+                record(1, 0);
+                let reaching = rays_in(active)
+                    .filter(|&i| bbox.hit(&packet[i].0, t_min, packet[i].1))
+                    .fold(0, |reaching, i| reaching | 1 << i);
+                if reaching == 0 {
+                    continue;
+                }
+                match *node {
+                    Node::Leaf(object) => {
+                        record_primitive_test();
+                        occluded |= occluded_leaf(object, reaching);
+                    }
+                    Node::Interior { left, right } => {
+                        stack.extend([(right, reaching), (left, reaching)])
+                    }
+                }
+            }
+            occluded
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|(bbox, _)| *bbox)
+    }
+}
+
+/// A bounding volume hierarchy over a list of objects.
+///
+/// Objects without a bounding box are kept outside the tree and always tested.
+pub struct Bvh {
+    objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    unbounded: Vec<Arc<dyn Hittable + Send + Sync>>,
+    tree: Tree,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Arc<dyn Hittable + Send + Sync>>) -> Self {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .partition(|object| object.bounding_box().is_some());
+
+        let items = bounded
+            .iter()
+            .enumerate()
+            .map(|(idx, object)| (object.bounding_box().unwrap(), idx))
+            .collect();
+
+        Self {
+            objects: bounded,
+            unbounded,
+            tree: Tree::new(items),
+        }
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let closest_hit = self.unbounded.hit_t(ray, t_min, t_max);
+        let t_max = closest_hit.as_ref().map_or(t_max, |hit| hit.t);
+
+        let hit_leaf = |object: usize, t_max| self.objects[object].hit_t(ray, t_min, t_max);
+        self.tree.hit(ray, t_min, t_max, &hit_leaf).or(closest_hit)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let occluded_leaf = |object: usize| self.objects[object].occluded(ray, t_min, t_max);
+        self.unbounded.occluded(ray, t_min, t_max)
+            || self.tree.occluded(ray, t_min, t_max, &occluded_leaf)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        let occluded = self.unbounded.occluded_packet(packet, t_min, active);
+        let occluded_leaf =
+            |object: usize, active| self.objects[object].occluded_packet(packet, t_min, active);
+        occluded
+            | self
+                .tree
+                .occluded_packet(packet, t_min, active & !occluded, &occluded_leaf)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.tree.bounding_box()
+        } else {
+            None
+        }
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.bvh += self.tree.memory();
+        self.objects.memory(usage);
+        self.unbounded.memory(usage);
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        self.objects.lights(lights);
+        self.unbounded.lights(lights);
+    }
+}
+
+/// The top level of a two-level hierarchy: a tree over transformed instances of objects, each of
+/// which is usually a [`Bvh`] of its own, like a mesh.
+///
+/// Only the bounding boxes of the instances go into this tree, so moving them around, from one
+/// frame of an animation to the next, rebuilds it without touching the objects underneath.
+pub struct TopLevelBvh {
+    instances: Vec<Transformed>,
+    unbounded: Vec<usize>,
+    tree: Tree,
+}
+
+impl TopLevelBvh {
+    pub fn new(instances: Vec<Transformed>) -> Self {
+        let mut top = Self {
+            instances,
+            unbounded: Vec::new(),
+            tree: Tree::new(Vec::new()),
+        };
+        top.rebuild();
+
+        top
+    }
+
+    pub fn instances(&self) -> &[Transformed] {
+        &self.instances
+    }
+
+    /// Moves the instances at the given indices, then rebuilds the tree over all of them once.
+    ///
+    /// Panics if an index is out of bounds.
+    pub fn set_transforms<I>(&mut self, transforms: I)
+    where
+        I: IntoIterator<Item = (usize, Transform)>,
+    {
+        for (idx, transform) in transforms {
+            self.instances[idx].transform = transform;
+        }
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        let mut items = Vec::with_capacity(self.instances.len());
+        self.unbounded.clear();
+        for (idx, instance) in self.instances.iter().enumerate() {
+            match instance.bounding_box() {
+                Some(bbox) => items.push((bbox, idx)),
+                None => self.unbounded.push(idx),
+            }
+        }
+
+        self.tree = Tree::new(items);
+    }
+}
+
+impl Hittable for TopLevelBvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let mut closest_hit = None;
+        let mut closest = t_max;
+        for &idx in &self.unbounded {
+            if let Some(hit) = self.instances[idx].hit_t(ray, t_min, closest) {
+                closest = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        let hit_leaf = |idx: usize, t_max| self.instances[idx].hit_t(ray, t_min, t_max);
+        self.tree
+            .hit(ray, t_min, closest, &hit_leaf)
+            .or(closest_hit)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let occluded_leaf = |idx: usize| self.instances[idx].occluded(ray, t_min, t_max);
+        self.unbounded.iter().any(|&idx| occluded_leaf(idx))
+            || self.tree.occluded(ray, t_min, t_max, &occluded_leaf)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        let occluded_leaf =
+            |idx: usize, active| self.instances[idx].occluded_packet(packet, t_min, active);
+        let occluded = self.unbounded.iter().fold(0, |occluded, &idx| {
+            occluded | occluded_leaf(idx, active & !occluded)
+        });
+        occluded
+            | self
+                .tree
+                .occluded_packet(packet, t_min, active & !occluded, &occluded_leaf)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.tree.bounding_box()
+        } else {
+            None
+        }
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.bvh += self.tree.memory() + self.unbounded.capacity() * std::mem::size_of::<usize>();
+        self.instances.memory(usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::objects::{Parallelogram, Sphere, Triangle};
+    use crate::collision::{first_rays, PACKET_SIZE};
+    use crate::Color;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn material() -> Arc<Lambertian> {
+        Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        })
+    }
+
+    fn ball(x: f64) -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(Point3::new(x, 0.0, 0.0), 0.5, material()).unwrap())
+    }
+
+    fn down(x: f64) -> Ray {
+        Ray {
+            origin: Point3::new(x, 5.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn big_trees_find_the_closest_hit() {
+        // enough to build some of the tree in parallel
+        let mut rng = StdRng::seed_from_u64(3);
+        let balls: Vec<_> = (0..5000)
+            .map(|_| {
+                let center = Vec3(rng.gen::<[f64; 3]>()) * 20.0;
+                Arc::new(Sphere::new(center, 0.2, material()).unwrap())
+                    as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect();
+        let bvh = Bvh::new(balls.clone());
+
+        for _ in 0..200 {
+            let ray = Ray {
+                origin: Vec3(rng.gen::<[f64; 3]>()) * 20.0,
+                direction: Vec3(rng.gen::<[f64; 3]>()) - Vec3::new(0.5, 0.5, 0.5),
+            };
+            let expected = balls.hit(&ray, 1e-9, f64::INFINITY).map(|hit| hit.t);
+            assert_eq!(
+                bvh.hit(&ray, 1e-9, f64::INFINITY).map(|hit| hit.t),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn shading_the_closest_hit_alone_matches_shading_every_hit() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut point = || Vec3(rng.gen::<[f64; 3]>()) * 10.0;
+        let objects: Vec<Arc<dyn Hittable + Send + Sync>> = (0..300)
+            .map(|i| -> Arc<dyn Hittable + Send + Sync> {
+                match i % 3 {
+                    0 => Arc::new(Sphere::new(point(), 0.5, material()).unwrap()),
+                    1 => Arc::new(Triangle::new([point(), point(), point()], material()).unwrap()),
+                    _ => Arc::new(Parallelogram::new(
+                        point(),
+                        Vec3::new(1.0, 0.0, 0.0),
+                        Vec3::new(0.0, 1.0, 0.0),
+                        Vec3::new(0.0, 0.0, 1.0),
+                        material(),
+                    )),
+                }
+            })
+            .collect();
+        let bvh = Bvh::new(objects.clone());
+
+        for _ in 0..500 {
+            let ray = Ray {
+                origin: point(),
+                direction: point() - Vec3::new(5.0, 5.0, 5.0),
+            };
+            // every object shading its own hit, the way it did before shading was deferred
+            let expected = objects
+                .iter()
+                .filter_map(|object| object.hit(&ray, 1e-9, f64::INFINITY))
+                .min_by(|a, b| a.t.total_cmp(&b.t));
+            let found = bvh.hit(&ray, 1e-9, f64::INFINITY);
+            assert_eq!(found.is_some(), expected.is_some());
+            if let (Some(found), Some(expected)) = (found, expected) {
+                assert_eq!(found.t, expected.t);
+                assert_eq!(found.point, expected.point);
+                assert_eq!(found.normal, expected.normal);
+                assert_eq!((found.u, found.v), (expected.u, expected.v));
+            }
+        }
+    }
+
+    #[test]
+    fn instances_move_without_rebuilding_their_objects() {
+        let mesh = Arc::new(Bvh::new(vec![ball(0.0), ball(2.0)]));
+        let instance = |x| Transformed {
+            object: mesh.clone(),
+            transform: Transform::translation(Vec3::new(x, 0.0, 0.0)),
+        };
+        let mut world = TopLevelBvh::new(vec![instance(0.0), instance(10.0)]);
+        assert!(world.hit(&down(12.0), 0.0, f64::INFINITY).is_some());
+        assert!(world.hit(&down(22.0), 0.0, f64::INFINITY).is_none());
+
+        world.set_transforms(vec![(1, Transform::translation(Vec3::new(20.0, 0.0, 0.0)))]);
+        assert!(world.hit(&down(12.0), 0.0, f64::INFINITY).is_none());
+        let hit = world.hit(&down(22.0), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.point.y() - 0.5).abs() < 1e-9);
+        assert!(Arc::ptr_eq(
+            &world.instances()[1].object,
+            &world.instances()[0].object
+        ));
+    }
+
+    #[test]
+    fn packets_agree_with_single_rays() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let balls: Vec<_> = (0..500)
+            .map(|_| {
+                let center = Vec3(rng.gen::<[f64; 3]>()) * 10.0;
+                Arc::new(Sphere::new(center, 0.3, material()).unwrap())
+                    as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect();
+        let instance = |x| Transformed {
+            object: Arc::new(Bvh::new(balls.clone())),
+            transform: Transform::translation(Vec3::new(x, 0.0, 0.0)),
+        };
+        let world = TopLevelBvh::new(vec![instance(0.0), instance(12.0)]);
+
+        // shadow rays of one point, some stopping short of what's behind them
+        let origin = Point3::new(11.0, 5.0, 5.0);
+        let packet: Vec<_> = (0..PACKET_SIZE)
+            .map(|_| {
+                let ray = Ray {
+                    origin,
+                    direction: Vec3(rng.gen::<[f64; 3]>()) - Vec3::new(0.5, 0.5, 0.5),
+                };
+                (ray, rng.gen_range(0.5..20.0))
+            })
+            .collect();
+        let expected = packet
+            .iter()
+            .enumerate()
+            .filter(|(_, (ray, t_max))| world.hit(ray, 1e-9, *t_max).is_some())
+            .fold(0, |occluded, (i, _)| occluded | 1 << i);
+        assert!(expected != 0 && expected != RayMask::MAX);
+
+        for (i, (ray, t_max)) in packet.iter().enumerate() {
+            assert_eq!(world.occluded(ray, 1e-9, *t_max), expected & 1 << i != 0);
+        }
+        let all = first_rays(PACKET_SIZE);
+        assert_eq!(world.occluded_packet(&packet, 1e-9, all), expected);
+        // rays left out aren't traced
+        let even = 0x5555_5555_5555_5555;
+        assert_eq!(world.occluded_packet(&packet, 1e-9, even), expected & even);
+    }
+}
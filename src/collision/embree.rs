@@ -0,0 +1,342 @@
+//! Triangle meshes traced by [Embree](https://www.embree.org), for big meshes where traversal is
+//! most of the render time. Needs the `embree` feature, and Embree 4 installed where the linker
+//! finds it.
+//!
+//! Embree only finds which triangle a ray hits first. The hit itself is worked out again by the
+//! crate's own [`Triangle`], in double precision, so normals, texture coordinates and materials
+//! come out exactly as they do without Embree. Everything but meshes stays in the crate's BVH,
+//! with each mesh a single object in it.
+
+use super::bvh::Aabb;
+use super::objects::Triangle;
+use super::{Hit, Hittable, Intersection, Ray};
+use crate::memory::MemoryUsage;
+use crate::{Error, Result};
+
+use std::ffi::{c_char, c_void};
+use std::mem::size_of;
+use std::ptr;
+use std::sync::OnceLock;
+
+/// The bindings of the parts of Embree's C API used here, from `embree4/rtcore.h`.
+#[allow(non_camel_case_types, non_snake_case)]
+mod ffi {
+    use std::ffi::{c_char, c_uint, c_void};
+
+    pub type RTCDevice = *mut c_void;
+    pub type RTCScene = *mut c_void;
+    pub type RTCGeometry = *mut c_void;
+
+    pub const RTC_GEOMETRY_TYPE_TRIANGLE: c_uint = 0;
+    pub const RTC_BUFFER_TYPE_INDEX: c_uint = 0;
+    pub const RTC_BUFFER_TYPE_VERTEX: c_uint = 1;
+    pub const RTC_FORMAT_UINT3: c_uint = 0x5003;
+    pub const RTC_FORMAT_FLOAT3: c_uint = 0x9003;
+    pub const RTC_SCENE_FLAG_ROBUST: c_uint = 1 << 2;
+    pub const RTC_BUILD_QUALITY_HIGH: c_uint = 2;
+    pub const RTC_INVALID_GEOMETRY_ID: c_uint = c_uint::MAX;
+
+    #[repr(C, align(16))]
+    pub struct RTCRay {
+        pub org_x: f32,
+        pub org_y: f32,
+        pub org_z: f32,
+        pub tnear: f32,
+        pub dir_x: f32,
+        pub dir_y: f32,
+        pub dir_z: f32,
+        pub time: f32,
+        pub tfar: f32,
+        pub mask: c_uint,
+        pub id: c_uint,
+        pub flags: c_uint,
+    }
+
+    #[repr(C, align(16))]
+    pub struct RTCHit {
+        pub Ng_x: f32,
+        pub Ng_y: f32,
+        pub Ng_z: f32,
+        pub u: f32,
+        pub v: f32,
+        pub primID: c_uint,
+        pub geomID: c_uint,
+        pub instID: [c_uint; 1],
+        /// Only there when Embree is built with instance arrays, as it is by default since 4.3.
+        /// Without them, this is padding Embree doesn't touch.
+        pub instPrimID: [c_uint; 1],
+    }
+
+    #[repr(C)]
+    pub struct RTCRayHit {
+        pub ray: RTCRay,
+        pub hit: RTCHit,
+    }
+
+    #[link(name = "embree4")]
+    extern "C" {
+        pub fn rtcNewDevice(config: *const c_char) -> RTCDevice;
+        pub fn rtcGetDeviceError(device: RTCDevice) -> c_uint;
+        pub fn rtcNewScene(device: RTCDevice) -> RTCScene;
+        pub fn rtcSetSceneFlags(scene: RTCScene, flags: c_uint);
+        pub fn rtcSetSceneBuildQuality(scene: RTCScene, quality: c_uint);
+        pub fn rtcCommitScene(scene: RTCScene);
+        pub fn rtcReleaseScene(scene: RTCScene);
+        pub fn rtcNewGeometry(device: RTCDevice, kind: c_uint) -> RTCGeometry;
+        pub fn rtcSetNewGeometryBuffer(
+            geometry: RTCGeometry,
+            kind: c_uint,
+            slot: c_uint,
+            format: c_uint,
+            byte_stride: usize,
+            item_count: usize,
+        ) -> *mut c_void;
+        pub fn rtcCommitGeometry(geometry: RTCGeometry);
+        pub fn rtcAttachGeometry(scene: RTCScene, geometry: RTCGeometry) -> c_uint;
+        pub fn rtcReleaseGeometry(geometry: RTCGeometry);
+        pub fn rtcIntersect1(scene: RTCScene, rayhit: *mut RTCRayHit, args: *mut c_void);
+    }
+}
+
+/// The Embree device every mesh is built on, made the first time one is.
+struct Device(ffi::RTCDevice);
+
+// SAFETY: Embree devices can be used from any thread, and this one is never released.
+unsafe impl Send for Device {}
+unsafe impl Sync for Device {}
+
+fn device() -> Result<ffi::RTCDevice> {
+    static DEVICE: OnceLock<Device> = OnceLock::new();
+    // SAFETY: a null configuration picks the defaults
+    let device = DEVICE.get_or_init(|| Device(unsafe { ffi::rtcNewDevice(ptr::null::<c_char>()) }));
+    if device.0.is_null() {
+        return Err(Error::Embree(
+            "couldn't create a device, the CPU may not be supported".to_owned(),
+        ));
+    }
+    Ok(device.0)
+}
+
+/// What went wrong on the device since it was last asked, if anything.
+fn check(device: ffi::RTCDevice) -> Result<()> {
+    // SAFETY: the device is valid for as long as the program runs
+    let message = match unsafe { ffi::rtcGetDeviceError(device) } {
+        0 => return Ok(()),
+        2 => "invalid argument",
+        3 => "invalid operation",
+        4 => "out of memory",
+        5 => "unsupported CPU",
+        6 => "cancelled",
+        _ => "unknown error",
+    };
+    Err(Error::Embree(message.to_owned()))
+}
+
+/// Triangles traced by Embree, as one object.
+pub struct EmbreeMesh {
+    scene: ffi::RTCScene,
+    /// Indexed by the triangles' IDs in Embree.
+    triangles: Vec<Triangle>,
+    bbox: Option<Aabb>,
+}
+
+// SAFETY: a committed Embree scene can be traced from any number of threads at once, and isn't
+// changed after it's built.
+unsafe impl Send for EmbreeMesh {}
+unsafe impl Sync for EmbreeMesh {}
+
+impl EmbreeMesh {
+    /// Builds Embree's BVH over `triangles`. Fails if Embree can't be started on this machine,
+    /// runs out of memory, or there are more triangles than it can index.
+    pub fn new(triangles: Vec<Triangle>) -> Result<Self> {
+        Error::check("embree mesh size", triangles.len() as f64, |count| {
+            count * 3.0 <= u32::MAX as f64
+        })?;
+        let device = device()?;
+        let count = triangles.len();
+
+        // SAFETY: the buffers are allocated by Embree with `count` items of the stride given,
+        // and filled before the geometry is committed. The geometry is released once the scene
+        // holds it, and the scene when the mesh is dropped.
+        let scene = unsafe {
+            let scene = ffi::rtcNewScene(device);
+            ffi::rtcSetSceneFlags(scene, ffi::RTC_SCENE_FLAG_ROBUST);
+            ffi::rtcSetSceneBuildQuality(scene, ffi::RTC_BUILD_QUALITY_HIGH);
+            let geometry = ffi::rtcNewGeometry(device, ffi::RTC_GEOMETRY_TYPE_TRIANGLE);
+
+            let vertices = ffi::rtcSetNewGeometryBuffer(
+                geometry,
+                ffi::RTC_BUFFER_TYPE_VERTEX,
+                0,
+                ffi::RTC_FORMAT_FLOAT3,
+                size_of::<[f32; 3]>(),
+                3 * count,
+            ) as *mut [f32; 3];
+            let indices = ffi::rtcSetNewGeometryBuffer(
+                geometry,
+                ffi::RTC_BUFFER_TYPE_INDEX,
+                0,
+                ffi::RTC_FORMAT_UINT3,
+                size_of::<[u32; 3]>(),
+                count,
+            ) as *mut [u32; 3];
+            if (vertices.is_null() || indices.is_null()) && count > 0 {
+                ffi::rtcReleaseGeometry(geometry);
+                ffi::rtcReleaseScene(scene);
+                check(device)?;
+                return Err(Error::Embree("couldn't allocate the mesh".to_owned()));
+            }
+
+            if count > 0 {
+                let vertices = std::slice::from_raw_parts_mut(vertices, 3 * count);
+                let indices = std::slice::from_raw_parts_mut(indices, count);
+                for (i, triangle) in triangles.iter().enumerate() {
+                    for (corner, vertex) in triangle.vertices().iter().enumerate() {
+                        vertices[3 * i + corner] = vertex.0.map(|coordinate| coordinate as f32);
+                    }
+                    let first = 3 * i as u32;
+                    indices[i] = [first, first + 1, first + 2];
+                }
+            }
+
+            ffi::rtcCommitGeometry(geometry);
+            ffi::rtcAttachGeometry(scene, geometry);
+            ffi::rtcReleaseGeometry(geometry);
+            ffi::rtcCommitScene(scene);
+            scene
+        };
+        if let Err(err) = check(device) {
+            // SAFETY: the scene isn't used again
+            unsafe { ffi::rtcReleaseScene(scene) };
+            return Err(err);
+        }
+
+        Ok(Self {
+            scene,
+            bbox: triangles.bounding_box(),
+            triangles,
+        })
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+}
+
+impl Drop for EmbreeMesh {
+    fn drop(&mut self) {
+        // SAFETY: nothing traces the scene anymore
+        unsafe { ffi::rtcReleaseScene(self.scene) };
+    }
+}
+
+impl Hittable for EmbreeMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let [org_x, org_y, org_z] = ray.origin.0.map(|coordinate| coordinate as f32);
+        let [dir_x, dir_y, dir_z] = ray.direction.0.map(|coordinate| coordinate as f32);
+        let mut tnear = t_min as f32;
+
+        // Embree works in single precision, so the triangle it finds can just miss in double
+        // precision, around its edges, or be one that culls the ray. Either way, the ray goes on
+        // past it.
+        loop {
+            let mut rayhit = ffi::RTCRayHit {
+                ray: ffi::RTCRay {
+                    org_x,
+                    org_y,
+                    org_z,
+                    tnear,
+                    dir_x,
+                    dir_y,
+                    dir_z,
+                    time: 0.0,
+                    tfar: t_max as f32,
+                    mask: u32::MAX,
+                    id: 0,
+                    flags: 0,
+                },
+                hit: ffi::RTCHit {
+                    Ng_x: 0.0,
+                    Ng_y: 0.0,
+                    Ng_z: 0.0,
+                    u: 0.0,
+                    v: 0.0,
+                    primID: ffi::RTC_INVALID_GEOMETRY_ID,
+                    geomID: ffi::RTC_INVALID_GEOMETRY_ID,
+                    instID: [ffi::RTC_INVALID_GEOMETRY_ID],
+                    instPrimID: [ffi::RTC_INVALID_GEOMETRY_ID],
+                },
+            };
+            // SAFETY: the scene is committed, and the ray is aligned and lives through the call
+            unsafe { ffi::rtcIntersect1(self.scene, &mut rayhit, ptr::null_mut::<c_void>()) };
+            if rayhit.hit.geomID == ffi::RTC_INVALID_GEOMETRY_ID {
+                return None;
+            }
+
+            let triangle = &self.triangles[rayhit.hit.primID as usize];
+            if let Some(hit) = triangle.hit_t(ray, t_min, t_max) {
+                return Some(hit);
+            }
+            let t = rayhit.ray.tfar;
+            tnear = f32::from_bits(t.to_bits() + 1);
+            if tnear as f64 >= t_max {
+                return None;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    /// Embree's BVH isn't counted, since it doesn't tell how big it is.
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += size_of::<Self>() + self.triangles.capacity() * size_of::<Triangle>();
+        // the copies of the vertices and indices Embree keeps
+        usage.geometry +=
+            self.triangles.len() * (size_of::<[[f32; 3]; 3]>() + size_of::<[u32; 3]>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::bvh::Bvh;
+    use crate::collision::materials::Lambertian;
+    use crate::{Color, Point3, Vec3};
+
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+
+    #[test]
+    fn hits_match_the_crates_own() {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut point = || Point3::new(rng.gen(), rng.gen(), rng.gen());
+        let triangles: Vec<Triangle> = (0..200)
+            .map(|_| Triangle::new([point(), point(), point()], material.clone()).unwrap())
+            .collect();
+        let bvh = Bvh::new(
+            triangles
+                .iter()
+                .map(|triangle| Arc::new(triangle.clone()) as Arc<dyn Hittable + Send + Sync>)
+                .collect(),
+        );
+        let mesh = EmbreeMesh::new(triangles).unwrap();
+        assert_eq!(mesh.bounding_box(), bvh.bounding_box());
+
+        for _ in 0..1000 {
+            let ray = Ray {
+                origin: Point3::new(-1.0, point().y(), point().z()),
+                direction: Vec3::new(1.0, point().y() - 0.5, point().z() - 0.5),
+            };
+            let expected = bvh.hit(&ray, 0.0, f64::INFINITY).map(|hit| hit.t);
+            let found = mesh.hit(&ray, 0.0, f64::INFINITY).map(|hit| hit.t);
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Materials put together from nodes, so layered looks don't each need a [`Material`] of their
+//! own.
+//!
+//! [`Node`]s work out a color wherever a ray hits the surface: constants, textures, and math on
+//! other nodes. They feed the parameters of a [`Bsdf`], which is how light scatters off the
+//! surface, and BSDFs can be mixed by another node. Nodes are shared with [`Arc`]s, so the same
+//! one can feed several parameters.
+
+use super::materials::{self, Dielectric, Lambertian};
+use super::{Hit, Material, Ray};
+use crate::texture::Texture;
+use crate::{Color, Vec3};
+
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+pub enum Node {
+    Constant(Color),
+    Texture(Arc<dyn Texture>),
+    Math {
+        op: MathOp,
+        a: Arc<Node>,
+        b: Arc<Node>,
+    },
+    /// `a` where `factor` is 0 and `b` where it's 1, blended linearly in between.
+    Mix {
+        factor: Arc<Node>,
+        a: Arc<Node>,
+        b: Arc<Node>,
+    },
+    /// How directly the surface faces the ray, from 1 head-on to 0 at a grazing angle.
+    Facing,
+    /// The fraction of the light a dielectric with the refraction index `ior` reflects, which
+    /// rises toward 1 at grazing angles. Layers of clear coat are mixed in by it.
+    Fresnel {
+        ior: f64,
+    },
+}
+
+/// What a [`Node::Math`] does with its two inputs, on each channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// 0 where `b` is.
+    Divide,
+    Power,
+    Minimum,
+    Maximum,
+}
+
+impl MathOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Add => a + b,
+            Self::Subtract => a - b,
+            Self::Multiply => a * b,
+            Self::Divide if b == 0.0 => 0.0,
+            Self::Divide => a / b,
+            Self::Power => a.powf(b),
+            Self::Minimum => a.min(b),
+            Self::Maximum => a.max(b),
+        }
+    }
+}
+
+impl Node {
+    /// The same number in every channel.
+    pub fn value(value: f64) -> Self {
+        Self::Constant(Color::new(value, value, value))
+    }
+
+    /// The color at `hit`, seen along `ray`.
+    pub fn evaluate(&self, ray: &Ray, hit: &Hit) -> Color {
+        match self {
+            Self::Constant(color) => *color,
+            Self::Texture(texture) => texture.filtered(hit.u, hit.v, &hit.point, hit.footprint),
+            Self::Math { op, a, b } => {
+                let (a, b) = (a.evaluate(ray, hit), b.evaluate(ray, hit));
+                Color::new(
+                    op.apply(a.x(), b.x()),
+                    op.apply(a.y(), b.y()),
+                    op.apply(a.z(), b.z()),
+                )
+            }
+            Self::Mix { factor, a, b } => {
+                let factor = factor.evaluate(ray, hit);
+                let (a, b) = (a.evaluate(ray, hit), b.evaluate(ray, hit));
+                a + factor * (b - a)
+            }
+            Self::Facing => {
+                let cos = cos_to_ray(ray, hit);
+                Color::new(cos, cos, cos)
+            }
+            Self::Fresnel { ior } => {
+                let reflected = materials::reflectance(cos_to_ray(ray, hit), *ior);
+                Color::new(reflected, reflected, reflected)
+            }
+        }
+    }
+
+    /// The average of the channels at `hit`, clamped into [0, 1], for nodes feeding numbers
+    /// like roughness.
+    fn gray(&self, ray: &Ray, hit: &Hit) -> f64 {
+        let color = self.evaluate(ray, hit);
+        ((color.x() + color.y() + color.z()) / 3.0).clamp(0.0, 1.0)
+    }
+
+    /// The color at `hit`, clamped into [0, 1] so it doesn't create light.
+    fn albedo(&self, ray: &Ray, hit: &Hit) -> Color {
+        let color = self.evaluate(ray, hit);
+        Color::new(
+            color.x().clamp(0.0, 1.0),
+            color.y().clamp(0.0, 1.0),
+            color.z().clamp(0.0, 1.0),
+        )
+    }
+}
+
+/// The cosine of the angle between the normal and the way back along the ray.
+fn cos_to_ray(ray: &Ray, hit: &Hit) -> f64 {
+    (-ray.direction.normalize())
+        .dot(&hit.normal)
+        .clamp(0.0, 1.0)
+}
+
+/// How light scatters off a surface, with parameters fed by [`Node`]s.
+pub enum Bsdf {
+    /// Like a [`Lambertian`].
+    Diffuse { color: Arc<Node> },
+    /// Like a [`Metal`](materials::Metal), with the roughness as its fuzz.
+    Metal {
+        color: Arc<Node>,
+        roughness: Arc<Node>,
+    },
+    /// Like a [`Dielectric`].
+    Glass { ior: f64 },
+    /// Gives off light of its color, and scatters none.
+    Emission { color: Arc<Node> },
+    /// `a` where `factor` is 0 and `b` where it's 1. Between the two, each ray scatters off one
+    /// of them at random, which mixes them on average.
+    Mix {
+        factor: Arc<Node>,
+        a: Arc<Bsdf>,
+        b: Arc<Bsdf>,
+    },
+}
+
+impl Material for Bsdf {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        match self {
+            Self::Diffuse { color } => {
+                let albedo = color.albedo(ray, hit);
+                Lambertian { albedo }.scatter(ray, hit, rng)
+            }
+            Self::Metal { color, roughness } => {
+                let albedo = color.albedo(ray, hit);
+                materials::reflect_fuzzily(ray, hit, albedo, roughness.gray(ray, hit), rng)
+            }
+            Self::Glass { ior } => Dielectric { ri: *ior }.scatter(ray, hit, rng),
+            Self::Emission { .. } => None,
+            Self::Mix { factor, a, b } => {
+                if rng.gen::<f64>() < factor.gray(ray, hit) {
+                    b.scatter(ray, hit, rng)
+                } else {
+                    a.scatter(ray, hit, rng)
+                }
+            }
+        }
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        match self {
+            Self::Emission { color } => color.evaluate(ray, hit),
+            Self::Mix { factor, a, b } => {
+                let factor = factor.gray(ray, hit);
+                (1.0 - factor) * a.emitted(ray, hit) + factor * b.emitted(ray, hit)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        match self {
+            Self::Diffuse { color } => {
+                let albedo = color.albedo(ray, hit);
+                Lambertian { albedo }.reflectance(ray, hit, light)
+            }
+            Self::Emission { .. } => Some(Color::new(0.0, 0.0, 0.0)),
+            Self::Mix { factor, a, b } => match factor.gray(ray, hit) {
+                factor if factor <= 0.0 => a.reflectance(ray, hit, light),
+                factor if factor >= 1.0 => b.reflectance(ray, hit, light),
+                // only if light from every direction can reach the eye through both
+                factor => {
+                    let (a, b) = (
+                        a.reflectance(ray, hit, light)?,
+                        b.reflectance(ray, hit, light)?,
+                    );
+                    Some((1.0 - factor) * a + factor * b)
+                }
+            },
+            Self::Metal { .. } | Self::Glass { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn ray(direction: Vec3) -> Ray {
+        Ray {
+            origin: -direction,
+            direction,
+        }
+    }
+
+    /// Where `ray` hits the floor at the origin.
+    fn hit(ray: &Ray) -> Hit {
+        let material = Arc::new(Bsdf::Glass { ior: 1.5 });
+        Hit::with_face_normal(ray, Vec3::new(0.0, 1.0, 0.0), 1.0, material)
+    }
+
+    #[test]
+    fn nodes_evaluate() {
+        let half = Arc::new(Node::value(0.5));
+        let red = Arc::new(Node::Constant(Color::new(1.0, 0.0, 0.0)));
+        let product = Node::Math {
+            op: MathOp::Multiply,
+            a: half.clone(),
+            b: red.clone(),
+        };
+        let straight_down = ray(Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(
+            product.evaluate(&straight_down, &hit(&straight_down)),
+            Color::new(0.5, 0.0, 0.0)
+        );
+
+        let mixed = Node::Mix {
+            factor: Arc::new(Node::Facing),
+            a: half,
+            b: red,
+        };
+        assert_eq!(
+            mixed.evaluate(&straight_down, &hit(&straight_down)),
+            Color::new(1.0, 0.0, 0.0)
+        );
+        let grazing = ray(Vec3::new(1.0, -1e-9, 0.0));
+        assert!(
+            (mixed.evaluate(&grazing, &hit(&grazing)) - Color::new(0.5, 0.5, 0.5)).length() < 1e-6
+        );
+
+        let divided = Node::Math {
+            op: MathOp::Divide,
+            a: Arc::new(Node::value(1.0)),
+            b: Arc::new(Node::value(0.0)),
+        };
+        assert_eq!(divided.evaluate(&grazing, &hit(&grazing)), Color::default());
+    }
+
+    #[test]
+    fn fresnel_rises_at_grazing_angles() {
+        let fresnel = Node::Fresnel { ior: 1.5 };
+        let (head_on, grazing) = (
+            ray(Vec3::new(0.0, -1.0, 0.0)),
+            ray(Vec3::new(1.0, -0.01, 0.0)),
+        );
+        let head_on = fresnel.evaluate(&head_on, &hit(&head_on));
+        let grazing = fresnel.evaluate(&grazing, &hit(&grazing));
+        assert!((head_on.x() - 0.04).abs() < 1e-9);
+        assert!(grazing.x() > 0.9);
+    }
+
+    #[test]
+    fn mixes_pick_either_side() {
+        let diffuse = Arc::new(Bsdf::Diffuse {
+            color: Arc::new(Node::value(0.8)),
+        });
+        let light = Arc::new(Bsdf::Emission {
+            color: Arc::new(Node::value(4.0)),
+        });
+        let mix = Bsdf::Mix {
+            factor: Arc::new(Node::value(0.25)),
+            a: diffuse,
+            b: light,
+        };
+
+        let incoming = ray(Vec3::new(0.0, -1.0, 0.0));
+        let hit = hit(&incoming);
+        let mut rng = StdRng::seed_from_u64(1);
+        let scattered = (0..1000)
+            .filter(|_| mix.scatter(&incoming, &hit, &mut rng).is_some())
+            .count();
+        assert!((700..800).contains(&scattered), "{}", scattered);
+        assert_eq!(mix.emitted(&incoming, &hit), Color::new(1.0, 1.0, 1.0));
+
+        let sun = Vec3::new(0.0, 1.0, 0.0);
+        let reflectance = mix.reflectance(&incoming, &hit, sun).unwrap();
+        assert!((reflectance.x() - 0.75 * 0.8 / std::f64::consts::PI).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,374 @@
+//! Scattering copies of an object around the scene, like rocks on a hillside or a field of
+//! flowers. The copies all share the object, and its BVH if it has one, with only a
+//! transformation of their own, so thousands of them take little more memory than one. A
+//! [`TopLevelBvh`] over the copies finds the ones a ray passes by.
+
+use super::bvh::{Aabb, TopLevelBvh};
+use super::lights::AreaLight;
+use super::objects::Transformed;
+use super::{Hit, Hittable, Intersection, Ray, RayMask};
+use crate::memory::MemoryUsage;
+use crate::transform::Transform;
+use crate::{Error, Point3, Result, Vec3};
+
+use nalgebra::{Rotation3, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// The most darts thrown at a surface for each instance, before giving up on finding room for
+/// more.
+const DARTS_PER_INSTANCE: usize = 30;
+
+/// Where the instances of an [`Instancer`] go.
+pub enum Distribution {
+    /// `counts` instances along each axis, evenly spread over the box from `min` to `max` with
+    /// each in the middle of its cell.
+    Grid {
+        min: Point3,
+        max: Point3,
+        counts: [usize; 3],
+    },
+    /// `count` instances anywhere in the box from `min` to `max`.
+    Random {
+        min: Point3,
+        max: Point3,
+        count: usize,
+    },
+    /// Up to `count` instances on top of `surface` as seen from above, none of them closer than
+    /// `spacing` to another. With `align`, they stand along the normal of the surface instead of
+    /// straight up.
+    Surface {
+        surface: Arc<dyn Hittable + Send + Sync>,
+        count: usize,
+        spacing: f64,
+        align: bool,
+    },
+}
+
+/// How much each instance is randomly changed by, at most.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Jitter {
+    /// The angles in degrees to turn by around the x, y and z axes, either way.
+    pub rotation: Vec3,
+    /// The smallest and largest scale, the same along every axis.
+    pub scale: (f64, f64),
+    /// The distances to move by along each axis, either way.
+    pub offset: Vec3,
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Self {
+            rotation: Vec3::default(),
+            scale: (1.0, 1.0),
+            offset: Vec3::default(),
+        }
+    }
+}
+
+/// Copies of a prototype object placed by a [`Distribution`], each changed a little by a
+/// [`Jitter`]. The prototype stands on its origin, which is put where each instance goes, with
+/// its y axis up.
+pub struct Instancer {
+    instances: TopLevelBvh,
+}
+
+impl Instancer {
+    /// The same `seed` places the instances the same way. Fails if the scales of `jitter` aren't
+    /// positive and in order, the spacing is negative, or the surface is unbounded.
+    pub fn new(
+        prototype: Arc<dyn Hittable + Send + Sync>,
+        distribution: &Distribution,
+        jitter: Jitter,
+        seed: u64,
+    ) -> Result<Self> {
+        let (min_scale, max_scale) = jitter.scale;
+        Error::check("instance scale", min_scale, |scale| scale > 0.0)?;
+        Error::check("instance scale", max_scale, |scale| scale >= min_scale)?;
+        for value in jitter.rotation.0.iter().chain(&jitter.offset.0) {
+            Error::check("instance jitter", *value, |_| true)?;
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let placements = distribution.placements(&mut rng)?;
+        let around = |rng: &mut StdRng, limit: f64| limit * rng.gen_range(-1.0..=1.0);
+
+        let instances = placements
+            .into_iter()
+            .map(|(position, up)| {
+                let rotation = Vec3(jitter.rotation.0.map(|limit| around(&mut rng, limit)));
+                let scale = rng.gen_range(min_scale..=max_scale);
+                let offset = Vec3(jitter.offset.0.map(|limit| around(&mut rng, limit)));
+
+                let local =
+                    Transform::new(Vec3::default(), rotation, Vec3::new(scale, scale, scale));
+                let standing = Transform::from_linear(*upright(up).matrix(), position + offset);
+                Transformed {
+                    object: prototype.clone(),
+                    transform: standing.after(&local),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            instances: TopLevelBvh::new(instances),
+        })
+    }
+
+    /// How many instances there are, which can be fewer than asked for on a surface.
+    pub fn count(&self) -> usize {
+        self.instances.instances().len()
+    }
+}
+
+impl Hittable for Instancer {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.instances.hit(ray, t_min, t_max)
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.instances.hit_t(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.instances.occluded(ray, t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        self.instances.occluded_packet(packet, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.instances.bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        self.instances.memory(usage);
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        self.instances.lights(lights)
+    }
+}
+
+impl Distribution {
+    /// The positions of the instances, and the way up for each.
+    fn placements(&self, rng: &mut StdRng) -> Result<Vec<(Point3, Vec3)>> {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let in_box = |min: Point3, max: Point3, fraction: [f64; 3]| {
+            Vec3(std::array::from_fn(|axis| {
+                min[axis] + fraction[axis] * (max[axis] - min[axis])
+            }))
+        };
+        let too_many = Error::InvalidParameter {
+            name: "instance count",
+            value: f64::INFINITY,
+        };
+
+        Ok(match *self {
+            Self::Grid { min, max, counts } => {
+                let [nx, ny, nz] = counts;
+                let count = nx
+                    .checked_mul(ny)
+                    .and_then(|count| count.checked_mul(nz))
+                    .ok_or(too_many)?;
+                let mut placements = Vec::with_capacity(count);
+                for z in 0..nz {
+                    for y in 0..ny {
+                        for x in 0..nx {
+                            let cell = [(x, nx), (y, ny), (z, nz)]
+                                .map(|(i, n)| (i as f64 + 0.5) / n as f64);
+                            placements.push((in_box(min, max, cell), up));
+                        }
+                    }
+                }
+                placements
+            }
+            Self::Random { min, max, count } => (0..count)
+                .map(|_| (in_box(min, max, rng.gen()), up))
+                .collect(),
+            Self::Surface {
+                ref surface,
+                count,
+                spacing,
+                align,
+            } => {
+                let spacing = Error::check("instance spacing", spacing, |s| s >= 0.0)?;
+                let Aabb { min, max } = surface.bounding_box().ok_or(Error::InvalidParameter {
+                    name: "instancer surface size",
+                    value: f64::INFINITY,
+                })?;
+
+                // dart throwing, with the points so far in cells as big as the spacing
+                let cell_of = |point: Point3| point.0.map(|c| (c / spacing).floor() as i64);
+                let mut cells: HashMap<[i64; 3], Vec<Point3>> = HashMap::new();
+                let darts = count.checked_mul(DARTS_PER_INSTANCE).ok_or(too_many)?;
+                let mut placements = Vec::with_capacity(count);
+                for _ in 0..darts {
+                    if placements.len() == count {
+                        break;
+                    }
+
+                    let [x, _, z] = in_box(min, max, rng.gen()).0;
+                    let ray = Ray {
+                        origin: Point3::new(x, max.y() + 1.0, z),
+                        direction: -up,
+                    };
+                    let hit = match surface.hit(&ray, 1e-9, f64::INFINITY) {
+                        Some(hit) => hit,
+                        None => continue,
+                    };
+
+                    if spacing > 0.0 {
+                        let [cx, cy, cz] = cell_of(hit.point);
+                        let crowded = (0..27).any(|i| {
+                            let key = [cx + i % 3 - 1, cy + i / 3 % 3 - 1, cz + i / 9 - 1];
+                            cells.get(&key).is_some_and(|points| {
+                                points
+                                    .iter()
+                                    .any(|point| (*point - hit.point).length() < spacing)
+                            })
+                        });
+                        if crowded {
+                            continue;
+                        }
+                        cells.entry([cx, cy, cz]).or_default().push(hit.point);
+                    }
+
+                    // the normal faces the ray, which came from above
+                    placements.push((hit.point, if align { hit.normal } else { up }));
+                }
+                placements
+            }
+        })
+    }
+}
+
+/// The rotation turning the y axis to `up`.
+fn upright(up: Vec3) -> Rotation3<f64> {
+    let up: Vector3<f64> = up.normalize().into();
+    Rotation3::rotation_between(&Vector3::y(), &up)
+        // upside down
+        .unwrap_or_else(|| Rotation3::from_axis_angle(&Vector3::x_axis(), PI))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::objects::{Parallelogram, Sphere};
+    use crate::collision::{first_rays, Material};
+    use crate::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        })
+    }
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray {
+            origin: Point3::new(x, 5.0, z),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn grids_fill_their_cells() {
+        let pebble = Sphere::new(Point3::new(0.0, 0.1, 0.0), 0.1, material()).unwrap();
+        let grid = Distribution::Grid {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(3.0, 0.0, 2.0),
+            counts: [3, 1, 2],
+        };
+        let instancer = Instancer::new(Arc::new(pebble), &grid, Jitter::default(), 0).unwrap();
+        assert_eq!(instancer.count(), 6);
+
+        for (x, z) in [(0.5, 0.5), (1.5, 0.5), (2.5, 1.5)] {
+            let hit = instancer.hit(&down(x, z), 0.0, f64::INFINITY).unwrap();
+            assert!((hit.point.y() - 0.2).abs() < 1e-9);
+        }
+        assert!(instancer.hit(&down(1.0, 1.0), 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn shadow_rays_see_the_instances() {
+        let pebble = Sphere::new(Point3::new(0.0, 0.1, 0.0), 0.1, material()).unwrap();
+        let grid = Distribution::Grid {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(2.0, 0.0, 2.0),
+            counts: [2, 1, 2],
+        };
+        let instancer = Instancer::new(Arc::new(pebble), &grid, Jitter::default(), 0).unwrap();
+
+        let packet: Vec<_> = [(0.5, 0.5), (1.0, 1.0), (1.5, 0.5), (0.5, 1.0)]
+            .map(|(x, z)| (down(x, z), f64::INFINITY))
+            .to_vec();
+        let occluded = instancer.occluded_packet(&packet, 0.0, first_rays(packet.len()));
+        for (i, (ray, t_max)) in packet.iter().enumerate() {
+            let hit = instancer.hit(ray, 0.0, *t_max).is_some();
+            assert_eq!(instancer.occluded(ray, 0.0, *t_max), hit);
+            assert_eq!(occluded & (1 << i) != 0, hit);
+        }
+        assert_eq!(occluded, 0b101);
+    }
+
+    #[test]
+    fn grids_too_big_to_count_fail() {
+        let pebble = Sphere::new(Point3::new(0.0, 0.1, 0.0), 0.1, material()).unwrap();
+        let grid = Distribution::Grid {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(1.0, 1.0, 1.0),
+            counts: [usize::MAX, 2, 0],
+        };
+        assert!(matches!(
+            Instancer::new(Arc::new(pebble), &grid, Jitter::default(), 0),
+            Err(Error::InvalidParameter {
+                name: "instance count",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn surfaces_are_covered_sparsely() {
+        let ground = Parallelogram::new(
+            Point3::new(-2.0, -1.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 4.0),
+            material(),
+        );
+        let surface = Distribution::Surface {
+            surface: Arc::new(ground),
+            count: 1000,
+            spacing: 0.5,
+            align: true,
+        };
+        let placements = surface.placements(&mut StdRng::seed_from_u64(1)).unwrap();
+
+        // a 4 by 4 square only has room for so many
+        assert!(placements.len() > 20 && placements.len() < 100);
+        for (i, &(point, up)) in placements.iter().enumerate() {
+            assert!(point.y().abs() < 1e-9);
+            assert_eq!(up, Vec3::new(0.0, 1.0, 0.0));
+            for &(other, _) in &placements[..i] {
+                assert!((point - other).length() >= 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn instances_stand_along_the_normal() {
+        let tilted = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let top = upright(tilted).transform_vector(&Vector3::y());
+        assert!((Vec3::from(top) - tilted).length() < 1e-9);
+
+        let flipped = upright(Vec3::new(0.0, -1.0, 0.0)).transform_vector(&Vector3::y());
+        assert!((Vec3::from(flipped) - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+    }
+}
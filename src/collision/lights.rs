@@ -0,0 +1,134 @@
+//! Glowing surfaces picked out of the world, so that light can be traced from them on purpose
+//! instead of only being found by rays that happen to hit them.
+
+use super::{Hittable, Material, Ray};
+use crate::{Color, Point3, Vec3};
+
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A sphere or one face of a parallelogram that gives off the same light all over it, see
+/// [`Material::uniform_emission`].
+#[derive(Clone)]
+pub struct AreaLight {
+    shape: Shape,
+    emit: Color,
+    double_sided: bool,
+    /// The material of the surface, which tells hits on the light apart from others.
+    material: Arc<dyn Material>,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Shape {
+    Sphere {
+        center: Point3,
+        radius: f64,
+    },
+    /// Spanned by `u` and `v` from `corner`, with its front facing along the unit `normal`.
+    Face {
+        corner: Point3,
+        u: Vec3,
+        v: Vec3,
+        normal: Vec3,
+    },
+}
+
+impl AreaLight {
+    /// A glowing sphere, or `None` if `material` doesn't glow evenly or the sphere is a point.
+    pub fn sphere(center: Point3, radius: f64, material: &Arc<dyn Material>) -> Option<Self> {
+        let shape = Shape::Sphere { center, radius };
+        (radius > 0.0).then(|| Self::new(shape, material)).flatten()
+    }
+
+    /// A glowing parallelogram spanned by `u` and `v` from `corner`, with its front facing along
+    /// `normal`, or `None` if `material` doesn't glow evenly or the face has no area.
+    pub fn face(
+        corner: Point3,
+        u: Vec3,
+        v: Vec3,
+        normal: Vec3,
+        material: &Arc<dyn Material>,
+    ) -> Option<Self> {
+        let shape = Shape::Face {
+            corner,
+            u,
+            v,
+            normal: normal.normalize(),
+        };
+        (u.cross(&v).length() > 0.0)
+            .then(|| Self::new(shape, material))
+            .flatten()
+    }
+
+    fn new(shape: Shape, material: &Arc<dyn Material>) -> Option<Self> {
+        let (emit, double_sided) = material.uniform_emission()?;
+        // the inside of a sphere can't light anything outside of it
+        let double_sided = double_sided && matches!(shape, Shape::Face { .. });
+
+        Some(Self {
+            shape,
+            emit,
+            double_sided,
+            material: material.clone(),
+        })
+    }
+
+    pub fn area(&self) -> f64 {
+        match self.shape {
+            Shape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            Shape::Face { u, v, .. } => u.cross(&v).length(),
+        }
+    }
+
+    /// All the light given off, from every side that glows.
+    pub fn power(&self) -> Color {
+        let sides = if self.double_sided { 2.0 } else { 1.0 };
+        self.emit * (PI * self.area() * sides)
+    }
+
+    /// A ray leaving a random point of the light, in a random direction weighted by the cosine
+    /// to the normal of the side it leaves from, which is how the light spreads. Every ray
+    /// carries the same share of [`AreaLight::power`].
+    pub fn emit(&self, rng: &mut dyn RngCore) -> Ray {
+        let (origin, mut normal) = match self.shape {
+            Shape::Sphere { center, radius } => {
+                let normal = Vec3::random_unit_vec(rng);
+                (center + radius * normal, normal)
+            }
+            Shape::Face {
+                corner,
+                u,
+                v,
+                normal,
+            } => (corner + rng.gen::<f64>() * u + rng.gen::<f64>() * v, normal),
+        };
+        if self.double_sided && rng.gen::<bool>() {
+            normal = -normal;
+        }
+
+        let direction = normal + Vec3::random_unit_vec(rng);
+        Ray {
+            origin,
+            direction: if direction.near_zero() {
+                normal
+            } else {
+                direction
+            },
+        }
+    }
+
+    /// Whether `material` is that of the light, meaning that a hit on it is a hit on one of the
+    /// lights made of it.
+    pub fn is_made_of(&self, material: &Arc<dyn Material>) -> bool {
+        Arc::as_ptr(&self.material) as *const () == Arc::as_ptr(material) as *const ()
+    }
+}
+
+/// Every light `world` holds, see [`Hittable::lights`].
+pub fn lights<T: Hittable + ?Sized>(world: &T) -> Vec<AreaLight> {
+    let mut lights = Vec::new();
+    world.lights(&mut lights);
+    lights
+}
@@ -0,0 +1,167 @@
+//! Levels of detail: several versions of the same object, from a detailed one to rough stand-ins,
+//! switched between by how far away the object is, so that a forest of trees far off costs
+//! about as much as a forest of spheres.
+//!
+//! The version is picked for every ray on its own, by how far it starts from the object. Rays
+//! starting close to it, like the ones of its own shadows and reflections, see the detailed
+//! version even when the camera sees a rough one.
+
+use super::bvh::Aabb;
+use super::{Hit, Hittable, Intersection, Ray};
+use crate::memory::MemoryUsage;
+use crate::{Error, Point3, Result};
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+/// How an [`Lod`] switches between its levels.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LodSwitch {
+    /// The distances from the center of the object after which each level gives way to the next,
+    /// increasing.
+    Distance(Vec<f64>),
+    /// The angles in degrees the object's bounding sphere spans, below which each level gives
+    /// way to the next, decreasing. This is how much of the screen it covers, relative to the
+    /// camera's field of view.
+    Coverage(Vec<f64>),
+}
+
+pub struct Lod {
+    /// From the most detailed to the roughest.
+    levels: Vec<Arc<dyn Hittable + Send + Sync>>,
+    /// The distance after which each level but the last gives way to the next.
+    switches: Vec<f64>,
+    center: Point3,
+    bbox: Aabb,
+}
+
+impl Lod {
+    /// Fails if there are no levels, any of them is unbounded, or `switch` doesn't have one
+    /// threshold for each level but the last, positive and in order.
+    pub fn new(levels: Vec<Arc<dyn Hittable + Send + Sync>>, switch: &LodSwitch) -> Result<Self> {
+        let bbox = levels.bounding_box().ok_or(Error::InvalidParameter {
+            name: "level of detail size",
+            value: f64::INFINITY,
+        })?;
+        let center = bbox.centroid();
+        let radius = (bbox.max - center).length();
+
+        let thresholds = match switch {
+            LodSwitch::Distance(distances) | LodSwitch::Coverage(distances) => distances,
+        };
+        Error::check(
+            "level of detail switches",
+            thresholds.len() as f64,
+            |count| count as usize + 1 == levels.len(),
+        )?;
+        let switches = match switch {
+            LodSwitch::Distance(distances) => {
+                for pair in distances.windows(2) {
+                    Error::check("level of detail distance", pair[1], |d| d > pair[0])?;
+                }
+                for &distance in distances {
+                    Error::check("level of detail distance", distance, |d| d > 0.0)?;
+                }
+                distances.clone()
+            }
+            LodSwitch::Coverage(angles) => {
+                for pair in angles.windows(2) {
+                    Error::check("level of detail coverage", pair[1], |a| a < pair[0])?;
+                }
+                angles
+                    .iter()
+                    .map(|&angle| {
+                        let angle = Error::check("level of detail coverage", angle, |a| {
+                            a > 0.0 && a < 180.0
+                        })?;
+                        // a sphere of that radius spans the angle from this far away
+                        Ok(radius / (0.5 * angle.to_radians()).sin())
+                    })
+                    .collect::<Result<_>>()?
+            }
+        };
+
+        Ok(Self {
+            levels,
+            switches,
+            center,
+            bbox,
+        })
+    }
+
+    /// The level seen from `origin`.
+    pub fn level(&self, origin: Point3) -> usize {
+        let distance = (origin - self.center).length();
+        self.switches.partition_point(|&switch| switch <= distance)
+    }
+}
+
+impl Hittable for Lod {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.levels[self.level(ray.origin)].hit(ray, t_min, t_max)
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.levels[self.level(ray.origin)].hit_t(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self) + std::mem::size_of_val(&self.switches[..]);
+        self.levels.memory(usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::objects::Sphere;
+    use crate::{Color, Vec3};
+
+    fn sphere(radius: f64) -> Arc<dyn Hittable + Send + Sync> {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap());
+        Arc::new(Sphere::new(Point3::default(), radius, material).unwrap())
+    }
+
+    #[test]
+    fn farther_rays_see_rougher_levels() {
+        let levels = vec![sphere(1.0), sphere(0.9), sphere(0.8)];
+        let lod = Lod::new(levels, &LodSwitch::Distance(vec![10.0, 100.0])).unwrap();
+        let toward = |distance: f64| Ray {
+            origin: Point3::new(0.0, 0.0, distance),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+
+        for (distance, radius) in [(5.0, 1.0), (50.0, 0.9), (500.0, 0.8)] {
+            let hit = lod.hit(&toward(distance), 0.0, f64::INFINITY).unwrap();
+            assert!((hit.t - (distance - radius)).abs() < 1e-9, "{}", hit.t);
+        }
+        assert_eq!(lod.level(Point3::new(0.0, 10.0, 0.0)), 1);
+    }
+
+    #[test]
+    fn coverage_is_turned_into_distances() {
+        let levels = vec![sphere(1.0), sphere(0.5)];
+        // the sphere around the box of a unit sphere spans 60 degrees from twice its radius
+        let lod = Lod::new(levels, &LodSwitch::Coverage(vec![60.0])).unwrap();
+        let radius = 3_f64.sqrt();
+        assert_eq!(lod.level(Point3::new(1.9 * radius, 0.0, 0.0)), 0);
+        assert_eq!(lod.level(Point3::new(2.1 * radius, 0.0, 0.0)), 1);
+    }
+
+    #[test]
+    fn switches_match_the_levels() {
+        let levels = || vec![sphere(1.0), sphere(0.5)];
+        assert!(Lod::new(levels(), &LodSwitch::Distance(vec![])).is_err());
+        assert!(Lod::new(levels(), &LodSwitch::Distance(vec![1.0, 2.0])).is_err());
+        assert!(Lod::new(levels(), &LodSwitch::Distance(vec![-1.0])).is_err());
+        assert!(Lod::new(levels(), &LodSwitch::Coverage(vec![200.0])).is_err());
+        assert!(Lod::new(vec![], &LodSwitch::Distance(vec![])).is_err());
+    }
+}
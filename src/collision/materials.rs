@@ -1,14 +1,31 @@
+use super::phase::{self, PhaseFunction};
 use super::{Hit, Material};
-use crate::{Color, Ray, Vec3};
+use crate::ies::IesProfile;
+use crate::texture::Texture;
+use crate::{Color, Error, Ray, Result, Vec3};
+
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct Lambertian {
     pub albedo: Color,
 }
 
+impl Lambertian {
+    /// Fails if any component of `albedo` is outside of [0, 1].
+    pub fn new(albedo: Color) -> Result<Self> {
+        check_albedo("lambertian albedo", albedo)?;
+
+        Ok(Self { albedo })
+    }
+}
+
 impl Material for Lambertian {
-    fn scatter(&self, _: &Ray, hit: &Hit) -> Option<(Color, Ray)> {
+    fn scatter(&self, _: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
         let scatter_direction = {
-            let dir: Vec3 = hit.normal + Vec3::random_unit_vec(&mut rand::thread_rng());
+            let dir: Vec3 = hit.normal + Vec3::random_unit_vec(rng);
 
             // Catch degenerate scatter direction
             if dir.near_zero() {
@@ -26,6 +43,31 @@ impl Material for Lambertian {
             },
         ))
     }
+
+    fn reflectance(&self, _: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        Some(self.albedo * (hit.normal.dot(&light).max(0.0) / PI))
+    }
+}
+
+/// A [`Lambertian`] whose albedo comes from a texture.
+pub struct TexturedLambertian {
+    pub texture: Arc<dyn Texture>,
+}
+
+impl Material for TexturedLambertian {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let albedo = self
+            .texture
+            .filtered(hit.u, hit.v, &hit.point, hit.footprint);
+        Lambertian { albedo }.scatter(ray, hit, rng)
+    }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        let albedo = self
+            .texture
+            .filtered(hit.u, hit.v, &hit.point, hit.footprint);
+        Lambertian { albedo }.reflectance(ray, hit, light)
+    }
 }
 
 pub struct Metal {
@@ -34,36 +76,65 @@ pub struct Metal {
 }
 
 impl Metal {
-    pub fn new(albedo: Color, fuzz: f64) -> Self {
-        let fuzz = if fuzz.abs() < 1.0 { fuzz } else { 1.0 };
+    /// Fails if any component of `albedo` or `fuzz` is outside of [0, 1].
+    pub fn new(albedo: Color, fuzz: f64) -> Result<Self> {
+        check_albedo("metal albedo", albedo)?;
+        let fuzz = Error::check("metal fuzz", fuzz, |fuzz| (0.0..=1.0).contains(&fuzz))?;
 
-        Self { albedo, fuzz }
+        Ok(Self { albedo, fuzz })
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Color, Ray)> {
-        let reflected = reflect(&ray.direction, &hit.normal); // Maybe normalize direction??
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        reflect_fuzzily(ray, hit, self.albedo, self.fuzz, rng)
+    }
+}
 
-        // Optimization in case there is no fuzz
-        let direction = if self.fuzz == 0.0 {
-            reflected
-        } else {
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(&mut rand::thread_rng())
-        };
+/// A metal where `metallic` is 1 and a lambertian where it's 0, both with the color of
+/// `base_color`, and as fuzzy as `roughness` is. Metallic and roughness maps are gray, with any
+/// color read as its average.
+///
+/// Only fully diffuse parts are lit by the sun directly, the rest gets its light by finding it,
+/// which is noisier.
+pub struct MetallicRoughness {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+}
 
-        if reflected.dot(&hit.normal) > 0.0 {
-            Some((
-                self.albedo,
-                Ray {
-                    origin: hit.point,
-                    direction,
-                },
-            ))
+impl MetallicRoughness {
+    fn gray(texture: &dyn Texture, hit: &Hit) -> f64 {
+        let color = texture.filtered(hit.u, hit.v, &hit.point, hit.footprint);
+        ((color.x() + color.y() + color.z()) / 3.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let albedo = self
+            .base_color
+            .filtered(hit.u, hit.v, &hit.point, hit.footprint);
+
+        // picking one or the other at random blends them on average
+        if rng.gen::<f64>() < Self::gray(self.metallic.as_ref(), hit) {
+            let fuzz = Self::gray(self.roughness.as_ref(), hit);
+            reflect_fuzzily(ray, hit, albedo, fuzz, rng)
         } else {
-            None
+            Lambertian { albedo }.scatter(ray, hit, rng)
         }
     }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        if Self::gray(self.metallic.as_ref(), hit) > 0.0 {
+            return None;
+        }
+
+        let albedo = self
+            .base_color
+            .filtered(hit.u, hit.v, &hit.point, hit.footprint);
+        Lambertian { albedo }.reflectance(ray, hit, light)
+    }
 }
 
 pub struct Dielectric {
@@ -71,8 +142,18 @@ pub struct Dielectric {
     pub ri: f64,
 }
 
+impl Dielectric {
+    /// Fails unless the refraction index is positive. It may be below 1, for a material
+    /// surrounded by a denser one.
+    pub fn new(ri: f64) -> Result<Self> {
+        let ri = Error::check("refraction index", ri, |ri| ri > 0.0)?;
+
+        Ok(Self { ri })
+    }
+}
+
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Color, Ray)> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
         let refraction_ratio = if hit.front_face {
             1.0 / self.ri
         } else {
@@ -84,12 +165,11 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand::random() {
-                reflect(&unit_direction, &hit.normal)
-            } else {
-                refract(&unit_direction, &hit.normal, refraction_ratio)
-            };
+        let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen() {
+            reflect(&unit_direction, &hit.normal)
+        } else {
+            refract(&unit_direction, &hit.normal, refraction_ratio)
+        };
 
         Some((
             Color::new(1.0, 1.0, 1.0),
@@ -101,11 +181,390 @@ impl Material for Dielectric {
     }
 }
 
+/// Built with [`DiffuseLight::new`] and the methods after it, which leaves room for more settings.
+#[non_exhaustive]
+pub struct DiffuseLight {
+    pub emit: Color,
+    /// Whether the back gives off light too. One-sided lights, like panels in a ceiling, are
+    /// dark from behind.
+    pub double_sided: bool,
+    /// How much of the light goes each way, for fixtures with a measured distribution.
+    pub profile: Option<Arc<IesProfile>>,
+    /// A pattern the light is multiplied by over the surface, like the picture on a screen.
+    pub texture: Option<Arc<dyn Texture>>,
+}
+
+impl DiffuseLight {
+    /// A light giving off `emit` evenly from both sides.
+    pub fn new(emit: Color) -> Self {
+        Self {
+            emit,
+            double_sided: true,
+            profile: None,
+            texture: None,
+        }
+    }
+
+    pub fn double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+
+    pub fn profile(mut self, profile: Arc<IesProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn texture(mut self, texture: Arc<dyn Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _: &Ray, _: &Hit, _: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        None
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        if !hit.front_face && !self.double_sided {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let emit = match &self.texture {
+            Some(texture) => self.emit * texture.filtered(hit.u, hit.v, &hit.point, hit.footprint),
+            None => self.emit,
+        };
+        match &self.profile {
+            Some(profile) => emit * profile.intensity(-ray.direction),
+            None => emit,
+        }
+    }
+
+    fn uniform_emission(&self) -> Option<(Color, bool)> {
+        (self.profile.is_none() && self.texture.is_none()).then_some((self.emit, self.double_sided))
+    }
+}
+
+/// Stands in for the ground of a photograph that rendered objects are composited onto. Camera
+/// rays see through it, except for the shadows and reflections the rest of the scene casts onto
+/// it, which end up in the alpha of the image so that they darken the photograph underneath.
+/// Everything else sees it as `surface`, which should look like the ground in the photograph, so
+/// the objects pick up its light.
+pub struct ShadowCatcher {
+    pub surface: Arc<dyn Material>,
+}
+
+impl Material for ShadowCatcher {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        self.surface.scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.surface.emitted(ray, hit)
+    }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        self.surface.reflectance(ray, hit, light)
+    }
+
+    fn is_shadow_catcher(&self) -> bool {
+        true
+    }
+}
+
+/// Scatters uniformly in every direction, for the inside of participating media.
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Isotropic {
+    /// Fails if any component of `albedo` is outside of [0, 1].
+    pub fn new(albedo: Color) -> Result<Self> {
+        check_albedo("isotropic albedo", albedo)?;
+
+        Ok(Self { albedo })
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        Some((
+            self.albedo,
+            Ray {
+                origin: hit.point,
+                direction: Vec3::random_unit_vec(rng),
+            },
+        ))
+    }
+
+    fn reflectance(&self, ray: &Ray, _: &Hit, light: Vec3) -> Option<Color> {
+        Some(self.albedo * phase::Isotropic.value(-light, -ray.direction))
+    }
+}
+
+/// Scatters inside participating media into the directions a phase function picks.
+pub struct Scattering {
+    pub albedo: Color,
+    pub phase: Arc<dyn PhaseFunction>,
+}
+
+impl Scattering {
+    /// Fails if any component of `albedo` is outside of [0, 1].
+    pub fn new(albedo: Color, phase: Arc<dyn PhaseFunction>) -> Result<Self> {
+        check_albedo("scattering albedo", albedo)?;
+
+        Ok(Self { albedo, phase })
+    }
+}
+
+impl Material for Scattering {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        Some((
+            self.albedo,
+            Ray {
+                origin: hit.point,
+                direction: self.phase.sample(ray.direction.normalize(), rng),
+            },
+        ))
+    }
+
+    fn reflectance(&self, ray: &Ray, _: &Hit, light: Vec3) -> Option<Color> {
+        // the light travels away from the light source, and on along the ray back to its origin
+        let value = self.phase.value(-light, -ray.direction.normalize());
+        Some(self.albedo * value)
+    }
+}
+
+/// The refraction index of hair.
+const HAIR_RI: f64 = 1.55;
+
+/// Hair and fur. Light reflects off the surface of a fiber, passes through it, or reflects once
+/// inside it and comes back out, and the light that went inside is tinted by the pigment. This is
+/// the model of Marschner et al., "Light Scattering from Human Hair Fibers", in the form pbrt
+/// gives it, without the tilt of the scales on the fibers.
+///
+/// The fibers run along the tangent of the hit, which [`Curve`](super::objects::Curve) gives.
+/// Surfaces without one scatter like a lambertian of the color of the hair.
+pub struct Hair {
+    color: Color,
+    /// How much of each channel is absorbed over the width of a fiber.
+    absorption: Color,
+    /// The standard deviations of the angles light is spread by, along the fiber and around it.
+    longitudinal_spread: f64,
+    azimuthal_spread: f64,
+}
+
+/// The directions around a fiber a hit sees it in.
+struct FiberFrame {
+    tangent: Vec3,
+    /// Across the fiber, toward where the ray came from.
+    facing: Vec3,
+    /// Across the fiber, and across the ray.
+    across: Vec3,
+    /// The angle between the way back along the ray and the plane across the fiber.
+    theta: f64,
+    /// Where across the fiber the ray hit, from -1 to 1.
+    offset: f64,
+}
+
+impl Hair {
+    /// `color` is roughly the color a thick head of this hair has, and `roughness` from 0 to 1
+    /// makes its highlights wider and duller. Fails if any component of `color` is outside of
+    /// [0, 1], or `roughness` isn't above 0 and at most 1.
+    pub fn new(color: Color, roughness: f64) -> Result<Self> {
+        check_albedo("hair color", color)?;
+        let beta = Error::check("hair roughness", roughness, |r| r > 0.0 && r <= 1.0)?;
+
+        // fits by Chiang et al., "A Practical and Controllable Hair and Fur Model for Production
+        // Path Tracing", from the color of many fibers to the absorption of one
+        let scale = 5.969 - 0.215 * beta + 2.532 * beta.powi(2) - 10.73 * beta.powi(3)
+            + 5.574 * beta.powi(4)
+            + 0.245 * beta.powi(5);
+        let absorption = Vec3(color.0.map(|c| (c.max(1e-3).ln() / scale).powi(2)));
+        let longitudinal_spread = 0.726 * beta + 0.812 * beta.powi(2) + 3.7 * beta.powi(20);
+        let logistic_scale =
+            0.626_657_069 * (0.265 * beta + 1.194 * beta.powi(2) + 5.372 * beta.powi(22));
+
+        Ok(Self {
+            color,
+            absorption,
+            longitudinal_spread,
+            azimuthal_spread: logistic_scale * PI / 3.0f64.sqrt(),
+        })
+    }
+
+    fn frame(ray: &Ray, hit: &Hit) -> Option<FiberFrame> {
+        let tangent = hit.tangent.normalize();
+        let back = -ray.direction.normalize();
+        let sin_theta = back.dot(&tangent);
+        let facing = (back - sin_theta * tangent).normalize();
+        let across = tangent.cross(&facing);
+        if !facing.0.iter().chain(&tangent.0).all(|c| c.is_finite()) {
+            return None;
+        }
+
+        Some(FiberFrame {
+            tangent,
+            facing,
+            across,
+            theta: sin_theta.clamp(-1.0, 1.0).asin(),
+            offset: hit.normal.dot(&across).clamp(-1.0, 1.0),
+        })
+    }
+
+    /// How much light goes into reflecting off the fiber, passing through it and reflecting
+    /// inside it, and the angle around the fiber, from `facing` toward `across`, each leaves at.
+    fn lobes(&self, frame: &FiberFrame) -> [(Color, f64); 3] {
+        let (sin_theta, cos_theta) = frame.theta.sin_cos();
+        // the fiber seen across, as a circle with a refraction index for the rays in its plane
+        let ri = (HAIR_RI * HAIR_RI - sin_theta * sin_theta).sqrt() / cos_theta;
+        let gamma = frame.offset.asin();
+        let gamma_inside = (frame.offset / ri).clamp(-1.0, 1.0).asin();
+        let cos_theta_inside = (1.0 - (sin_theta / HAIR_RI).powi(2)).sqrt();
+
+        let length = 2.0 * gamma_inside.cos() / cos_theta_inside;
+        let transmittance = Vec3(self.absorption.0.map(|a| (-a * length).exp()));
+        let fresnel = reflectance(cos_theta * gamma.cos(), HAIR_RI);
+        let through = (1.0 - fresnel) * (1.0 - fresnel) * transmittance;
+
+        [
+            (Color::new(fresnel, fresnel, fresnel), 2.0 * gamma),
+            (through, PI + 2.0 * gamma - 2.0 * gamma_inside),
+            (
+                fresnel * through * transmittance,
+                2.0 * PI + 2.0 * gamma - 4.0 * gamma_inside,
+            ),
+        ]
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let frame = match Self::frame(ray, hit) {
+            Some(frame) => frame,
+            None => return Lambertian { albedo: self.color }.scatter(ray, hit, rng),
+        };
+
+        // picking a lobe by its brightness
+        let lobes = self.lobes(&frame);
+        let weights = lobes.map(|(color, _)| (color.x() + color.y() + color.z()) / 3.0);
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut pick = rng.gen::<f64>() * total;
+        let lobe = (0..2)
+            .find(|&i| {
+                pick -= weights[i];
+                pick < 0.0
+            })
+            .unwrap_or(2);
+        let (color, azimuth) = lobes[lobe];
+
+        // leaving on the other side of the plane across the fiber, blurred by its roughness
+        let (first, second) = gaussian_pair(rng);
+        let limit = PI / 2.0 - 1e-3;
+        let theta = (-frame.theta + self.longitudinal_spread * first).clamp(-limit, limit);
+        let phi = azimuth + self.azimuthal_spread * second;
+        let direction = theta.sin() * frame.tangent
+            + theta.cos() * (phi.cos() * frame.facing + phi.sin() * frame.across);
+
+        Some((
+            color * (total / weights[lobe]),
+            Ray {
+                origin: hit.point,
+                direction,
+            },
+        ))
+    }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        let frame = match Self::frame(ray, hit) {
+            Some(frame) => frame,
+            None => return Lambertian { albedo: self.color }.reflectance(ray, hit, light),
+        };
+
+        let sin_theta = light.dot(&frame.tangent).clamp(-1.0, 1.0);
+        let cos_theta = (1.0 - sin_theta * sin_theta).sqrt();
+        if cos_theta < 1e-3 {
+            return Some(Color::default());
+        }
+        let longitudinal = normal_density(sin_theta.asin() + frame.theta, self.longitudinal_spread);
+        let phi = light.dot(&frame.across).atan2(light.dot(&frame.facing));
+
+        // the density of the scattered directions, over the solid angle
+        let reflectance =
+            self.lobes(&frame)
+                .iter()
+                .fold(Color::default(), |sum, &(color, azimuth)| {
+                    let difference = (phi - azimuth + PI).rem_euclid(2.0 * PI) - PI;
+                    let azimuthal: f64 = [-2.0 * PI, 0.0, 2.0 * PI]
+                        .iter()
+                        .map(|wrap| normal_density(difference + wrap, self.azimuthal_spread))
+                        .sum();
+                    sum + color * azimuthal
+                });
+
+        Some(reflectance * (longitudinal / cos_theta))
+    }
+}
+
+/// Two independent samples of the standard normal distribution, by the Box-Muller transform.
+fn gaussian_pair(rng: &mut dyn RngCore) -> (f64, f64) {
+    let radius = (-2.0 * (1.0 - rng.gen::<f64>()).ln()).sqrt();
+    let (sin, cos) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+    (radius * cos, radius * sin)
+}
+
+/// The density of a normal distribution around 0 with standard deviation `sigma`, at `x`.
+fn normal_density(x: f64, sigma: f64) -> f64 {
+    (-0.5 * (x / sigma).powi(2)).exp() / (sigma * (2.0 * PI).sqrt())
+}
+
+fn check_albedo(name: &'static str, albedo: Color) -> Result<()> {
+    for component in albedo.0 {
+        Error::check(name, component, |c| (0.0..=1.0).contains(&c))?;
+    }
+
+    Ok(())
+}
+
+/// The scattering of a metal with the given albedo and fuzz at `hit`.
+pub(super) fn reflect_fuzzily(
+    ray: &Ray,
+    hit: &Hit,
+    albedo: Color,
+    fuzz: f64,
+    rng: &mut dyn RngCore,
+) -> Option<(Color, Ray)> {
+    let reflected = reflect(&ray.direction, &hit.normal); // Maybe normalize direction??
+
+    // Optimization in case there is no fuzz
+    let direction = if fuzz == 0.0 {
+        reflected
+    } else {
+        reflected + fuzz * Vec3::random_in_unit_sphere(rng)
+    };
+
+    if reflected.dot(&hit.normal) > 0.0 {
+        Some((
+            albedo,
+            Ray {
+                origin: hit.point,
+                direction,
+            },
+        ))
+    } else {
+        None
+    }
+}
+
 fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2.0 * v.dot(n) * *n
 }
 
-fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+/// Schlick's approximation of the fraction of light reflected off a dielectric.
+pub(super) fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     let r0 = r0 * r0;
 
@@ -119,3 +578,194 @@ fn refract(r_in: &Vec3, normal: &Vec3, etai_over_etat: f64) -> Vec3 {
 
     r_out_perp + r_out_parallel
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::Material;
+    use crate::texture::SolidColor;
+    use crate::Point3;
+
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    fn unit_vec3() -> impl Strategy<Value = Vec3> {
+        prop::array::uniform3(-1.0..1.0f64)
+            .prop_map(Vec3)
+            .prop_filter("too short to normalize", |v| v.length() > 1e-3)
+            .prop_map(Vec3::normalize)
+    }
+
+    #[derive(Clone, Debug)]
+    enum Kind {
+        Lambertian,
+        Metal { fuzz: f64 },
+        Dielectric { ri: f64 },
+    }
+
+    fn kind() -> impl Strategy<Value = Kind> {
+        prop_oneof![
+            Just(Kind::Lambertian),
+            (0.0..1.0f64).prop_map(|fuzz| Kind::Metal { fuzz }),
+            (1.0..3.0f64).prop_map(|ri| Kind::Dielectric { ri }),
+        ]
+    }
+
+    impl Kind {
+        fn build(&self) -> Arc<dyn Material> {
+            let albedo = Color::new(0.5, 0.5, 0.5);
+            match *self {
+                Kind::Lambertian => Arc::new(Lambertian::new(albedo).unwrap()),
+                Kind::Metal { fuzz } => Arc::new(Metal::new(albedo, fuzz).unwrap()),
+                Kind::Dielectric { ri } => Arc::new(Dielectric::new(ri).unwrap()),
+            }
+        }
+    }
+
+    #[test]
+    fn constructors_reject_invalid_parameters() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+
+        assert!(Lambertian::new(Color::new(0.5, 1.5, 0.5)).is_err());
+        assert!(Lambertian::new(Color::new(f64::NAN, 0.5, 0.5)).is_err());
+        assert!(Metal::new(gray, -0.5).is_err());
+        assert!(Metal::new(gray, 1.5).is_err());
+        assert!(Metal::new(Color::new(-0.1, 0.5, 0.5), 0.5).is_err());
+        assert!(Dielectric::new(0.0).is_err());
+        assert!(Dielectric::new(f64::INFINITY).is_err());
+
+        assert!(Lambertian::new(Color::new(0.0, 1.0, 0.5)).is_ok());
+        assert!(Metal::new(gray, 0.0).is_ok());
+        assert!(Metal::new(gray, 1.0).is_ok());
+        assert!(Dielectric::new(1.0 / 1.3).is_ok());
+    }
+
+    #[test]
+    fn metallic_roughness_follows_its_maps() {
+        let solid = |c: f64| Arc::new(SolidColor(Color::new(c, c, c))) as Arc<dyn Texture>;
+        let material = |metallic| -> Arc<dyn Material> {
+            Arc::new(MetallicRoughness {
+                base_color: solid(0.5),
+                metallic: solid(metallic),
+                roughness: solid(0.0),
+            })
+        };
+        let ray = Ray {
+            origin: Point3::new(-1.0, 1.0, 0.0),
+            direction: Vec3::new(1.0, -1.0, 0.0),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let metal = material(1.0);
+        let hit = Hit::with_face_normal(&ray, Vec3::new(0.0, 1.0, 0.0), 1.0, metal.clone());
+        for _ in 0..10 {
+            let (_, scattered) = metal.scatter(&ray, &hit, &mut rng).unwrap();
+            assert_eq!(scattered.direction, Vec3::new(1.0, 1.0, 0.0));
+        }
+        assert!(metal
+            .reflectance(&ray, &hit, Vec3::new(0.0, 1.0, 0.0))
+            .is_none());
+
+        let diffuse = material(0.0);
+        let light = Vec3::new(0.0, 1.0, 0.0);
+        let reflectance = diffuse.reflectance(&ray, &hit, light).unwrap();
+        assert!((reflectance.x() - 0.5 / PI).abs() < 1e-9);
+    }
+
+    #[test]
+    fn textured_lights_glow_with_their_texture() {
+        let light: Arc<dyn Material> = Arc::new(
+            DiffuseLight::new(Color::new(4.0, 4.0, 4.0))
+                .double_sided(false)
+                .texture(Arc::new(SolidColor(Color::new(1.0, 0.5, 0.0)))),
+        );
+        let ray = Ray {
+            origin: Point3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+        let front = Hit::with_face_normal(&ray, Vec3::new(0.0, 1.0, 0.0), 1.0, light.clone());
+        assert_eq!(light.emitted(&ray, &front), Color::new(4.0, 2.0, 0.0));
+        let back = Hit::with_face_normal(&ray, Vec3::new(0.0, -1.0, 0.0), 1.0, light.clone());
+        assert_eq!(light.emitted(&ray, &back), Color::default());
+    }
+
+    #[test]
+    fn hair_scatters_as_much_light_as_it_reflects() {
+        let hair: Arc<dyn Material> = Arc::new(Hair::new(Color::new(0.6, 0.4, 0.2), 0.3).unwrap());
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.3, 1.0),
+            direction: Vec3::new(0.0, -0.3, -1.0),
+        };
+        // off the middle of a fiber along y
+        let normal = Vec3::new(0.4, 0.0, 1.0).normalize();
+        let hit = Hit::with_face_normal(&ray, normal, 1.0, hair.clone())
+            .with_tangent(Vec3::new(0.0, 1.0, 0.0));
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // the average weight of the samples against the reflectance integrated over the sphere
+        let samples = 200_000;
+        let mut scattered = Color::default();
+        let mut integrated = Color::default();
+        for _ in 0..samples {
+            let (color, _) = hair.scatter(&ray, &hit, &mut rng).unwrap();
+            scattered += color / samples as f64;
+            let light = Vec3::random_unit_vec(&mut rng);
+            let reflectance = hair.reflectance(&ray, &hit, light).unwrap();
+            integrated += reflectance * (4.0 * PI / samples as f64);
+        }
+
+        for channel in 0..3 {
+            assert!(scattered[channel] <= 1.0);
+            let error = (scattered[channel] - integrated[channel]).abs();
+            assert!(error < 0.03, "{:?} != {:?}", scattered, integrated);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn reflect_preserves_length(v in unit_vec3(), n in unit_vec3(), scale in 0.01..100.0f64) {
+            let v = v * scale;
+            prop_assert!((reflect(&v, &n).length() - v.length()).abs() < 1e-9 * scale);
+        }
+
+        #[test]
+        fn refract_obeys_snells_law(v in unit_vec3(), n in unit_vec3(), ratio in 0.2..3.0f64) {
+            // the normal faces against the incoming ray, like the ones in hits do
+            let n = if v.dot(&n) > 0.0 { -n } else { n };
+            let sin_in = v.cross(&n).length();
+            prop_assume!(ratio * sin_in <= 1.0);
+
+            let out = refract(&v, &n, ratio);
+            prop_assert!((out.length() - 1.0).abs() < 1e-9);
+            prop_assert!((out.cross(&n).length() - ratio * sin_in).abs() < 1e-9);
+            // it goes through the surface instead of bouncing off
+            prop_assert!(out.dot(&n) <= 0.0);
+        }
+
+        #[test]
+        fn scatter_directions_are_finite(
+            kind in kind(),
+            direction in unit_vec3(),
+            normal in unit_vec3(),
+            length in 0.01..100.0f64,
+            seed: u64,
+        ) {
+            let ray = Ray {
+                origin: Point3::new(0.0, 0.0, 0.0),
+                direction: direction * length,
+            };
+            let material = kind.build();
+            let hit = Hit::with_face_normal(&ray, normal, 1.0, material.clone());
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            if let Some((attenuation, scattered)) = material.scatter(&ray, &hit, &mut rng) {
+                prop_assert!(attenuation.0.iter().all(|c| c.is_finite()));
+                prop_assert!(scattered.direction.0.iter().all(|c| c.is_finite()));
+                prop_assert!(!scattered.direction.near_zero());
+            }
+        }
+    }
+}
@@ -1,11 +1,27 @@
+use crate::memory::MemoryUsage;
 use crate::{Color, Point3, Vec3};
 
+use bvh::Aabb;
+use lights::AreaLight;
+
+use rand::RngCore;
+
 use std::sync::Arc;
 
+pub mod bvh;
+#[cfg(feature = "embree")]
+pub mod embree;
+pub mod graph;
+pub mod instancer;
+pub mod lights;
+pub mod lod;
 pub mod materials;
 pub mod objects;
+pub mod phase;
+pub mod volumes;
+pub mod voxels;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
@@ -21,12 +37,46 @@ impl Ray {
     }
 }
 
+/// The cone of space a ray stands for, as wide as the pixel it was traced for and growing with
+/// the distance from the camera. Textures use it to tell how much of themselves a hit covers.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RayCone {
+    /// The width at the start of the ray.
+    pub width: f64,
+    /// The angle the cone opens up by, in radians.
+    pub spread: f64,
+}
+
+impl RayCone {
+    /// The cone after the ray has gone `distance` further.
+    pub fn advance(&self, distance: f64) -> Self {
+        Self {
+            width: self.width + self.spread * distance,
+            spread: self.spread,
+        }
+    }
+}
+
 pub struct Hit {
     pub point: Point3,
     pub normal: Vec3,
     pub t: f64,
     pub front_face: bool,
     pub material: Arc<dyn Material>,
+    /// Surface coordinates of the hit, both in [0, 1].
+    pub u: f64,
+    pub v: f64,
+    /// How fast the surface coordinates change with distance along the surface, or 0 where that
+    /// isn't known.
+    pub uv_density: f64,
+    /// The width in surface coordinates of the ray's cone where it hit, set by the renderer for
+    /// filtering textures. 0 means a point.
+    pub footprint: f64,
+    /// The direction fibers run along at the hit, for hair, or zero on other surfaces.
+    pub tangent: Vec3,
+    /// The label of the object hit, for ID passes, or 0 outside of any
+    /// [`Labeled`](objects::Labeled) object.
+    pub object: u32,
 }
 
 impl Hit {
@@ -49,20 +99,181 @@ impl Hit {
             t,
             front_face,
             material,
+            u: 0.0,
+            v: 0.0,
+            uv_density: 0.0,
+            footprint: 0.0,
+            tangent: Vec3::default(),
+            object: 0,
+        }
+    }
+
+    pub fn with_uv(mut self, u: f64, v: f64) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    pub fn with_uv_density(mut self, uv_density: f64) -> Self {
+        self.uv_density = uv_density;
+        self
+    }
+
+    pub fn with_tangent(mut self, tangent: Vec3) -> Self {
+        self.tangent = tangent;
+        self
+    }
+}
+
+/// Where a ray hits an object, as found by [`Hittable::hit_t`]: how far along the ray, and what
+/// it takes to work out the rest of the [`Hit`] with [`shade`](Self::shade), which only the
+/// closest of the hits along a ray needs.
+pub struct Intersection<'a> {
+    pub t: f64,
+    shading: Shading<'a>,
+}
+
+enum Shading<'a> {
+    /// The primitive hit, with the part of it and the coordinates on that part its
+    /// [`Hittable::shading`] takes.
+    Deferred {
+        primitive: &'a dyn Hittable,
+        part: usize,
+        coordinates: (f64, f64),
+    },
+    Done(Hit),
+}
+
+impl<'a> Intersection<'a> {
+    /// A hit on `primitive` that's shaded by its [`Hittable::shading`] when it turns out to be
+    /// the closest.
+    pub fn deferred(
+        t: f64,
+        primitive: &'a dyn Hittable,
+        part: usize,
+        coordinates: (f64, f64),
+    ) -> Self {
+        Self {
+            t,
+            shading: Shading::Deferred {
+                primitive,
+                part,
+                coordinates,
+            },
+        }
+    }
+
+    /// A hit worked out in full already.
+    pub fn shaded(hit: Hit) -> Self {
+        Self {
+            t: hit.t,
+            shading: Shading::Done(hit),
+        }
+    }
+
+    /// The full hit, for the ray that found it.
+    pub fn shade(self, ray: &Ray) -> Hit {
+        match self.shading {
+            Shading::Deferred {
+                primitive,
+                part,
+                coordinates,
+            } => primitive.shading(ray, self.t, part, coordinates),
+            Shading::Done(hit) => hit,
         }
     }
 }
 
+/// The most rays a packet of [`Hittable::occluded_packet`] holds, one for each bit of a
+/// [`RayMask`].
+pub const PACKET_SIZE: usize = 64;
+
+/// Some of the rays of a packet, with bit `i` set for the ray at index `i`.
+pub type RayMask = u64;
+
+/// The mask of the first `count` rays of a packet.
+pub fn first_rays(count: usize) -> RayMask {
+    assert!(count <= PACKET_SIZE);
+    match count {
+        PACKET_SIZE => RayMask::MAX,
+        _ => (1 << count) - 1,
+    }
+}
+
+/// The indices of the rays in `mask`, in order.
+pub fn rays_in(mut mask: RayMask) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        let index = mask.trailing_zeros() as usize;
+        mask &= mask.checked_sub(1)?;
+        Some(index)
+    })
+}
+
 pub trait Hittable {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit>;
+
+    /// Like [`hit`](Self::hit), but leaving the point, normal, texture coordinates and the rest
+    /// to [`Intersection::shade`]. Objects holding others compare their hits by distance alone,
+    /// and only shade the closest. Primitives defer their shading to
+    /// [`shading`](Self::shading), everything else shades its hits right away.
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.hit(ray, t_min, t_max).map(Intersection::shaded)
+    }
+
+    /// The full hit for an [`Intersection::deferred`] this object made for `ray`, `t` along it,
+    /// with the `part` and `coordinates` it gave.
+    fn shading(&self, _ray: &Ray, _t: f64, _part: usize, _coordinates: (f64, f64)) -> Hit {
+        unreachable!(
+            "{} doesn't defer its shading",
+            std::any::type_name::<Self>()
+        )
+    }
+
+    /// Whether the ray hits anything between `t_min` and `t_max`, for shadow rays, which don't
+    /// care what. Objects holding others say so at the first hit they find, rather than looking
+    /// for the closest one.
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(ray, t_min, t_max).is_some()
+    }
+
+    /// Which of the `active` rays of `packet` are [`occluded`](Self::occluded), each going as
+    /// far as the distance alongside it. Objects holding others test the rays together, visiting
+    /// each of the objects once for all the rays reaching it, and dropping rays as soon as
+    /// they're blocked.
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        rays_in(active)
+            .filter(|&i| self.occluded(&packet[i].0, t_min, packet[i].1))
+            .fold(0, |occluded, i| occluded | 1 << i)
+    }
+
+    /// A box containing the whole object, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Adds an estimate of the memory the object takes up to `usage`, itself included. Objects
+    /// holding more on the heap than their own size, or holding other objects, say so.
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self);
+    }
+
+    /// Adds the parts of the object that glow to `lights`. Only spheres and parallelograms of a
+    /// material with a [`Material::uniform_emission`] are lights, outside of any transformation.
+    /// Everything else that glows is left to the rays that happen to hit it.
+    fn lights(&self, _lights: &mut Vec<AreaLight>) {}
 }
 
 impl<T: Hittable> Hittable for [T] {
-    fn hit(&self, ray: &Ray, t_min: f64, mut t_max: f64) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, mut t_max: f64) -> Option<Intersection<'a>> {
         let mut closest_hit = None;
 
         for object in self {
-            if let Some(hit) = object.hit(ray, t_min, t_max) {
+            bvh::record_primitive_test();
+            if let Some(hit) = object.hit_t(ray, t_min, t_max) {
                 t_max = hit.t;
                 closest_hit = Some(hit);
             }
@@ -70,11 +281,110 @@ impl<T: Hittable> Hittable for [T] {
 
         closest_hit
     }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.iter().any(|object| {
+            bvh::record_primitive_test();
+            object.occluded(ray, t_min, t_max)
+        })
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        let mut occluded = 0;
+        for object in self {
+            if occluded == active {
+                break;
+            }
+            bvh::record_primitive_test();
+            occluded |= object.occluded_packet(packet, t_min, active & !occluded);
+        }
+        occluded
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.iter();
+        let first = objects.next()?.bounding_box()?;
+
+        objects.try_fold(first, |acc, object| {
+            Some(acc.union(&object.bounding_box()?))
+        })
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        for object in self {
+            object.memory(usage);
+        }
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        for object in self {
+            object.lights(lights);
+        }
+    }
 }
 
 impl<T: Hittable> Hittable for Vec<T> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
-        (&self[..]).hit(ray, t_min, t_max)
+        self[..].hit(ray, t_min, t_max)
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self[..].hit_t(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self[..].occluded(ray, t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        self[..].occluded_packet(packet, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self[..].bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += (self.capacity() - self.len()) * std::mem::size_of::<T>();
+        self[..].memory(usage);
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        self[..].lights(lights)
+    }
+}
+
+impl<T: Hittable + ?Sized> Hittable for &T {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        (**self).hit_t(ray, t_min, t_max)
+    }
+
+    fn shading(&self, ray: &Ray, t: f64, part: usize, coordinates: (f64, f64)) -> Hit {
+        (**self).shading(ray, t, part, coordinates)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        (**self).occluded(ray, t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        (**self).occluded_packet(packet, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        (**self).memory(usage)
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        (**self).lights(lights)
     }
 }
 
@@ -82,8 +392,70 @@ impl<T: Hittable + ?Sized> Hittable for Arc<T> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
         (**self).hit(ray, t_min, t_max)
     }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        (**self).hit_t(ray, t_min, t_max)
+    }
+
+    fn shading(&self, ray: &Ray, t: f64, part: usize, coordinates: (f64, f64)) -> Hit {
+        (**self).shading(ray, t, part, coordinates)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        (**self).occluded(ray, t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        (**self).occluded_packet(packet, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of::<Self>();
+        // along with the reference counts, once however many objects share it
+        if usage.first_visit(Arc::as_ptr(self)) {
+            usage.geometry += 2 * std::mem::size_of::<usize>();
+            (**self).memory(usage);
+        }
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        (**self).lights(lights)
+    }
 }
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Color, Ray)>;
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)>;
+
+    fn emitted(&self, _ray: &Ray, _hit: &Hit) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// How much of the light arriving from the unit vector `light` is scattered back along the
+    /// ray, per unit of irradiance: the BRDF times the cosine, or the phase function in media.
+    /// `None` for materials that only scatter into a few directions, like mirrors and glass.
+    fn reflectance(&self, _ray: &Ray, _hit: &Hit, _light: Vec3) -> Option<Color> {
+        None
+    }
+
+    /// The light given off, and whether the back glows too, for materials that glow the same
+    /// all over and in every direction. Their objects can then be sampled as lights, see
+    /// [`Hittable::lights`].
+    fn uniform_emission(&self) -> Option<(Color, bool)> {
+        None
+    }
+
+    /// Whether camera rays see through this material to what's behind it, and only the shadows
+    /// and reflections cast onto it, see [`ShadowCatcher`](materials::ShadowCatcher).
+    fn is_shadow_catcher(&self) -> bool {
+        false
+    }
+
+    /// The full path of the type, for debugging.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
@@ -1,7 +1,12 @@
-use super::{Hit, Hittable, Material, Ray};
-use crate::{Point3, Vec3};
+use super::bvh::Aabb;
+use super::lights::AreaLight;
+use super::{Hit, Hittable, Intersection, Material, Ray, RayMask};
+use crate::memory::MemoryUsage;
+use crate::transform::Transform;
+use crate::{Error, Point3, Result, Vec3};
 
 use nalgebra::{Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
 
 use std::sync::Arc;
 
@@ -12,8 +17,28 @@ pub struct Sphere {
     pub material: Arc<dyn Material>,
 }
 
+impl Sphere {
+    /// Fails if the radius is negative or anything isn't finite.
+    pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Result<Self> {
+        for coordinate in center.0 {
+            Error::check("sphere center", coordinate, |_| true)?;
+        }
+        let radius = Error::check("sphere radius", radius, |radius| radius >= 0.0)?;
+
+        Ok(Self {
+            center,
+            radius,
+            material,
+        })
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
         let oc: Vec3 = ray.origin - self.center;
 
         let a = ray.direction.length_squared();
@@ -39,15 +64,32 @@ impl Hittable for Sphere {
             root
         };
 
-        let point = ray.at(root);
+        Some(Intersection::deferred(root, self, 0, (0.0, 0.0)))
+    }
+
+    fn shading(&self, ray: &Ray, t: f64, _part: usize, _coordinates: (f64, f64)) -> Hit {
+        let point = ray.at(t);
         let outward_normal = (point - self.center) / self.radius;
 
-        Some(Hit::with_face_normal(
-            ray,
-            outward_normal,
-            root,
-            self.material.clone(),
-        ))
+        // Spherical coordinates of the normal, with u going around the y axis.
+        let theta = (-outward_normal.y()).acos();
+        let phi = (-outward_normal.z()).atan2(outward_normal.x()) + std::f64::consts::PI;
+
+        // spread evenly, the coordinates would cover the area of the sphere once
+        let uv_density = 0.5 / (self.radius.abs() * std::f64::consts::PI.sqrt());
+
+        Hit::with_face_normal(ray, outward_normal, t, self.material.clone())
+            .with_uv(phi / std::f64::consts::TAU, theta / std::f64::consts::PI)
+            .with_uv_density(uv_density)
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        lights.extend(AreaLight::sphere(self.center, self.radius, &self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - radius, self.center + radius))
     }
 }
 
@@ -91,89 +133,2006 @@ impl Hittable for ImplicitMarched {
 
 const TRIPLETS: [(usize, usize, usize); 3] = [(1, 2, 0), (2, 0, 1), (0, 1, 2)];
 
+/// The roots of the polynomial with `coefficients`, from the constant term up, between `lo` and
+/// `hi`, in increasing order.
+///
+/// The polynomial only rises or falls between the roots of its derivative, so each of those
+/// stretches holds at most one root, which is found by Newton's method, falling back to
+/// bisection whenever a step would leave the stretch. Unlike Newton's method on its own, that
+/// never misses a root near another one or an end of the range, only ones where the polynomial
+/// touches 0 without crossing it.
+fn polynomial_roots(coefficients: &[f64], lo: f64, hi: f64) -> Roots {
+    let mut roots = Roots::default();
+    if coefficients.len() < 2 {
+        return roots;
+    }
+    if let [c, b, a] = *coefficients {
+        // quadratics, the slopes of cubics, are simple enough to solve in closed form
+        let discriminant = b * b - 4.0 * a * c;
+        if a != 0.0 {
+            if discriminant >= 0.0 {
+                let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+                let (first, second) = (q / a, c / q);
+                for root in [first.min(second), first.max(second)] {
+                    if lo < root && root < hi {
+                        roots.push(root);
+                    }
+                }
+            }
+            return roots;
+        }
+    }
+
+    let mut derivative = [0.0; MAX_DEGREE];
+    for power in 1..coefficients.len() {
+        derivative[power - 1] = power as f64 * coefficients[power];
+    }
+    let derivative = &derivative[..coefficients.len() - 1];
+    let value = |t: f64| coefficients.iter().rev().fold(0.0, |sum, &c| sum * t + c);
+    let slope = |t: f64| derivative.iter().rev().fold(0.0, |sum, &c| sum * t + c);
+
+    let turns = polynomial_roots(derivative, lo, hi);
+    let mut below = lo;
+    for &above in turns.iter().chain(std::iter::once(&hi)) {
+        if let Some(root) = monotone_root(value, slope, below, above) {
+            roots.push(root);
+        }
+        below = above;
+    }
+    roots
+}
+
+/// The root of a polynomial that only rises or falls from `below` to `above`, if there is one.
+fn monotone_root(
+    value: impl Fn(f64) -> f64,
+    slope: impl Fn(f64) -> f64,
+    mut below: f64,
+    mut above: f64,
+) -> Option<f64> {
+    let (start, end) = (value(below), value(above));
+    if end == 0.0 {
+        return Some(above);
+    }
+    if start == 0.0 || start.signum() == end.signum() {
+        return None;
+    }
+
+    let mut t = 0.5 * (below + above);
+    for _ in 0..MAX_ROOT_STEPS {
+        let value = value(t);
+        if value == 0.0 {
+            break;
+        }
+        if value.signum() == start.signum() {
+            below = t;
+        } else {
+            above = t;
+        }
+
+        let newton = t - value / slope(t);
+        let next = if newton > below && newton < above {
+            newton
+        } else {
+            0.5 * (below + above)
+        };
+        if next == t || below >= above {
+            break;
+        }
+        t = next;
+    }
+    Some(t)
+}
+
+/// The highest degree of the polynomials [`polynomial_roots`] solves, the quartic of a torus.
+const MAX_DEGREE: usize = 4;
+
+/// The roots of a polynomial, in increasing order, kept off the heap.
+#[derive(Copy, Clone, Debug, Default)]
+struct Roots {
+    values: [f64; MAX_DEGREE],
+    len: usize,
+}
+
+impl Roots {
+    fn push(&mut self, root: f64) {
+        self.values[self.len] = root;
+        self.len += 1;
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, f64> {
+        self.values[..self.len].iter()
+    }
+}
+
+/// Enough for bisection alone to narrow the widest range of floats down to a single one.
+const MAX_ROOT_STEPS: usize = 2100;
+
+/// A torus around the y axis, hit where the ray crosses the quartic of its surface.
 #[derive(Clone)]
-pub struct Parallelogram {
-    corner: Vector3<f64>,
-    axes: [Vector3<f64>; 3],
-    normals: [Vector3<f64>; 3],
+pub struct Torus {
+    pub center: Point3,
+    /// Distance from the center to the middle of the tube.
+    pub major_radius: f64,
+    /// Radius of the tube.
+    pub minor_radius: f64,
     pub material: Arc<dyn Material>,
 }
 
-impl Parallelogram {
-    pub fn new(corner: Point3, u: Vec3, v: Vec3, w: Vec3, material: Arc<dyn Material>) -> Self {
-        let corner: Vector3<f64> = corner.into();
+impl Torus {
+    /// The coefficients of the polynomial in `t`, from the constant term up, that's 0 where the
+    /// ray is on the surface and negative inside the tube: `(|p|² + R² - r²)² - 4R²(x² + z²)`.
+    fn quartic(&self, ray: &Ray) -> [f64; 5] {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let (major, minor) = (self.major_radius, self.minor_radius);
+
+        let a = d.length_squared();
+        let b = 2.0 * o.dot(&d);
+        let c = o.length_squared() + major * major - minor * minor;
+        let ring = 4.0 * major * major;
+        [
+            c * c - ring * (o.x() * o.x() + o.z() * o.z()),
+            2.0 * b * c - 2.0 * ring * (o.x() * d.x() + o.z() * d.z()),
+            b * b + 2.0 * a * c - ring * (d.x() * d.x() + d.z() * d.z()),
+            2.0 * a * b,
+            a * a,
+        ]
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        // a little larger than the torus, so that the search never starts right on its surface
+        let margin = 1e-6 * (self.major_radius + self.minor_radius);
+        let bbox = self.bounding_box()?;
+        let margin = Vec3::new(margin, margin, margin);
+        let padded = Aabb::new(bbox.min - margin, bbox.max + margin);
+        let (t_enter, t_exit) = padded.intersect(ray, t_min, t_max)?;
+
+        // starting from where the ray enters the box keeps the terms of the polynomial small,
+        // rather than cancelling out for rays from far away
+        let entered = Ray {
+            origin: ray.at(t_enter),
+            direction: ray.direction,
+        };
+        let quartic = self.quartic(&entered);
+        let t = t_enter
+            + polynomial_roots(&quartic, 0.0, t_exit - t_enter)
+                .iter()
+                .next()?;
+
+        let point = ray.at(t);
+        let [x, y, z] = (point - self.center).0;
+        let ring = (x * x + z * z).sqrt();
+        let scale = (ring - self.major_radius) / ring;
+        let outward_normal = Vec3::new(x * scale, y, z * scale).normalize();
+
+        // u goes around the y axis, v around the tube
+        let u = z.atan2(x) / std::f64::consts::TAU + 0.5;
+        let v = y.atan2(ring - self.major_radius) / std::f64::consts::TAU + 0.5;
+        let uv_density =
+            1.0 / (std::f64::consts::TAU * (self.major_radius * self.minor_radius).sqrt());
+
+        Some(
+            Hit::with_face_normal(ray, outward_normal, t, self.material.clone())
+                .with_uv(u, v)
+                .with_uv_density(uv_density),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(outer, self.minor_radius, outer);
 
-        let u: Vector3<f64> = u.into();
-        let v: Vector3<f64> = v.into();
-        let w: Vector3<f64> = w.into();
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// The most steps a ray takes through [`Metaballs`] before it's taken to have missed them.
+const METABALL_MAX_STEPS: usize = 512;
+
+/// How the field of a metaball fades with distance, in units of its radius.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Falloff {
+    /// `(1 - x²)³` of Wyvill et al., which reaches 0 at the radius.
+    #[default]
+    Wyvill,
+    /// `e^(-x²)` of Blinn, cut off at 3 radii.
+    Gaussian,
+}
+
+impl Falloff {
+    /// How far out the field reaches.
+    fn reach(self) -> f64 {
+        match self {
+            Self::Wyvill => 1.0,
+            Self::Gaussian => 3.0,
+        }
+    }
+
+    /// The steepest the field gets.
+    fn slope(self) -> f64 {
+        match self {
+            // at 1/√5 and 1/√2
+            Self::Wyvill => 6.0 / 5.0f64.sqrt() * 0.64,
+            Self::Gaussian => (2.0 / std::f64::consts::E).sqrt(),
+        }
+    }
+
+    /// The field at `x`, and its derivative there.
+    fn value(self, x: f64) -> (f64, f64) {
+        if x >= self.reach() {
+            return (0.0, 0.0);
+        }
+        match self {
+            Self::Wyvill => {
+                let s = 1.0 - x * x;
+                (s * s * s, -6.0 * x * s * s)
+            }
+            Self::Gaussian => {
+                // lowered to reach 0 smoothly at the cutoff
+                let e = (-x * x).exp();
+                (e - (-9.0f64).exp(), -2.0 * x * e)
+            }
+        }
+    }
+}
+
+/// A ball of a [`Metaballs`] field, as strong as `strength` at its center. Negative ones carve
+/// into the others.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Metaball {
+    pub center: Point3,
+    pub radius: f64,
+    pub strength: f64,
+}
+
+/// Blobby shapes that melt into each other: the surface where the summed fields of the balls
+/// reach `threshold`. A lone ball with a strength of 1 and the default falloff is a sphere,
+/// smaller than its radius: 0.45 times it for a threshold of 0.5.
+///
+/// The field isn't a distance, so it's sphere traced with steps shortened by how steep it can
+/// get, counting only the balls the ray passes near, and the crossing is then found by
+/// bisection. The normals come from the exact gradient of the field.
+#[derive(Clone)]
+pub struct Metaballs {
+    balls: Vec<Metaball>,
+    threshold: f64,
+    falloff: Falloff,
+    pub material: Arc<dyn Material>,
+}
+
+impl Metaballs {
+    /// Fails if any center or strength isn't finite, a radius isn't positive or the threshold
+    /// isn't positive.
+    pub fn new(
+        balls: Vec<Metaball>,
+        threshold: f64,
+        falloff: Falloff,
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        for ball in &balls {
+            for coordinate in ball.center.0 {
+                Error::check("metaball center", coordinate, |_| true)?;
+            }
+            Error::check("metaball radius", ball.radius, |radius| radius > 0.0)?;
+            Error::check("metaball strength", ball.strength, |_| true)?;
+        }
+        let threshold = Error::check("metaball threshold", threshold, |t| t > 0.0)?;
+
+        Ok(Self {
+            balls,
+            threshold,
+            falloff,
+            material,
+        })
+    }
 
-        fn cross_with_dir(
-            x: &Vector3<f64>,
-            y: &Vector3<f64>,
-            direction: &Vector3<f64>,
-        ) -> Vector3<f64> {
-            let v: Vector3<f64> = x.cross(y);
-            if v.dot(direction) < 0.0 {
-                -v
-            } else {
-                v
+    /// The box around where `ball` reaches.
+    fn reach(&self, ball: &Metaball) -> Aabb {
+        let reach = ball.radius * self.falloff.reach();
+        let extent = Vec3::new(reach, reach, reach);
+        Aabb::new(ball.center - extent, ball.center + extent)
+    }
+
+    /// The field of `balls` at `point`, and its gradient.
+    fn field(&self, balls: &[&Metaball], point: Point3) -> (f64, Vec3) {
+        balls
+            .iter()
+            .fold((0.0, Vec3::default()), |(field, gradient), ball| {
+                let offset = point - ball.center;
+                let distance = offset.length();
+                let (value, slope) = self.falloff.value(distance / ball.radius);
+                let direction = if distance > 0.0 {
+                    offset / distance
+                } else {
+                    Vec3::default()
+                };
+                (
+                    field + ball.strength * value,
+                    gradient + (ball.strength * slope / ball.radius) * direction,
+                )
+            })
+    }
+}
+
+impl Hittable for Metaballs {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        // the balls the ray passes through the reach of, and where it's near the positive ones,
+        // which the surface can only be around
+        let mut near = Vec::new();
+        let (mut start, mut end) = (t_max, t_min);
+        for ball in &self.balls {
+            if let Some((enter, exit)) = self.reach(ball).intersect(ray, t_min, t_max) {
+                near.push(ball);
+                if ball.strength > 0.0 {
+                    start = start.min(enter);
+                    end = end.max(exit);
+                }
+            }
+        }
+        if start >= end {
+            return None;
+        }
+
+        // no step can go further than the field could change by in it, in units of t
+        let steepest: f64 = near
+            .iter()
+            .map(|ball| ball.strength.abs() * self.falloff.slope() / ball.radius)
+            .sum::<f64>()
+            * ray.direction.length();
+        let inside_at = |t: f64| self.field(&near, ray.at(t)).0 >= self.threshold;
+        let started_inside = inside_at(start);
+
+        let mut previous = start;
+        let mut t = start;
+        for _ in 0..METABALL_MAX_STEPS {
+            let (field, _) = self.field(&near, ray.at(t));
+            let crossed = (field >= self.threshold) != started_inside;
+            // the steps shrink as the surface gets closer, and may never cross it
+            if crossed || (field - self.threshold).abs() <= 1e-12 * self.threshold {
+                let (mut before, mut after) = (previous, t);
+                if crossed {
+                    for _ in 0..50 {
+                        let middle = 0.5 * (before + after);
+                        if inside_at(middle) == started_inside {
+                            before = middle;
+                        } else {
+                            after = middle;
+                        }
+                    }
+                }
+
+                let (_, gradient) = self.field(&near, ray.at(after));
+                let outward_normal = (-gradient).normalize();
+                if outward_normal.0.iter().any(|c| !c.is_finite()) {
+                    return None;
+                }
+                return Some(Hit::with_face_normal(
+                    ray,
+                    outward_normal,
+                    after,
+                    self.material.clone(),
+                ));
+            }
+
+            previous = t;
+            t += (field - self.threshold).abs() / steepest;
+            if t > end {
+                return None;
             }
         }
 
-        let vw: Vector3<f64> = cross_with_dir(&v, &w, &u).normalize();
-        let uw: Vector3<f64> = cross_with_dir(&u, &w, &v).normalize();
-        let uv: Vector3<f64> = cross_with_dir(&u, &v, &w).normalize();
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.balls
+            .iter()
+            .filter(|ball| ball.strength > 0.0)
+            .map(|ball| self.reach(ball))
+            .reduce(|acc, bbox| acc.union(&bbox))
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self) + std::mem::size_of_val(&self.balls[..]);
+    }
+}
+
+#[derive(Clone)]
+pub struct Parallelogram {
+    corner: Point3,
+    axes: [Vec3; 3],
+    /// The planes of each pair of faces, in the order of [`TRIPLETS`]. Pairs spanned by parallel
+    /// axes have no faces, like the sides of a flat parallelogram, and are left out.
+    slabs: [Option<Slab>; 3],
+    pub material: Arc<dyn Material>,
+}
+
+/// The two opposite faces of a [`Parallelogram`] spanned by the same pair of axes, worked out
+/// once so that a hit takes a handful of dot products instead of solving a system for each face.
+/// Everything is relative to the corner of the parallelogram.
+#[derive(Clone, Copy)]
+struct Slab {
+    /// Perpendicular to both faces, at any length. The floor is where it dots to 0 with the
+    /// points on it, and the ceiling where it dots to `ceiling`.
+    normal: Vec3,
+    ceiling: f64,
+    /// Dotted with a point on the floor, give its coordinates along the two axes.
+    across: [Vec3; 2],
+    /// The coordinates of the start of the ceiling, to subtract from the ones of its points.
+    ceiling_start: (f64, f64),
+    /// The unit normal of the ceiling, pointing out. The floor's points the other way.
+    outward: Vec3,
+    /// How fast the coordinates change with distance across the faces.
+    uv_density: f64,
+}
+
+impl Slab {
+    fn new(x: Vec3, y: Vec3, z: Vec3) -> Option<Self> {
+        let normal = x.cross(&y);
+        let area = normal.length_squared();
+        if area == 0.0 || !area.is_finite() {
+            return None;
+        }
+        // perpendicular to one axis, and dotting to 1 with the other
+        let across = [y.cross(&normal) / area, normal.cross(&x) / area];
+        let outward = if normal.dot(&z) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        Some(Self {
+            normal,
+            ceiling: normal.dot(&z),
+            across,
+            ceiling_start: (across[0].dot(&z), across[1].dot(&z)),
+            outward: outward.normalize(),
+            // the coordinates cover each face once
+            uv_density: 1.0 / area.sqrt().sqrt(),
+        })
+    }
+}
+
+impl Parallelogram {
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, w: Vec3, material: Arc<dyn Material>) -> Self {
+        let axes = [u, v, w];
+        let slabs = TRIPLETS.map(|(x, y, z)| Slab::new(axes[x], axes[y], axes[z]));
 
         Self {
             corner,
-            axes: [u, v, w],
-            normals: [vw, uw, uv],
+            axes,
+            slabs,
             material,
         }
     }
 }
 
+/// The coordinates across a face of a [`Parallelogram`] that count as on it: its edges and a
+/// sliver more, so that rounding can't let rays through edges and corners slip between
+/// neighboring faces.
+const FACE: std::ops::RangeInclusive<f64> = -1e-12..=1.0 + 1e-12;
+
 impl Hittable for Parallelogram {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    /// The part hit is the pair of faces, in the order of [`TRIPLETS`], times 2, plus 1 for the
+    /// ceiling.
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
         let mut t = t_max;
-        let mut normal: Option<Vector3<f64>> = None;
+        let mut found = None;
+        let origin = ray.origin - self.corner;
+
+        for (pair, slab) in self.slabs.iter().enumerate() {
+            let Some(slab) = slab else {
+                continue;
+            };
+            // rays along the faces divide by zero here, and never get in range
+            let speed = slab.normal.dot(&ray.direction);
+            let height = slab.normal.dot(&origin);
+            let faces = [(0.0, (0.0, 0.0)), (slab.ceiling, slab.ceiling_start)];
+
+            for (side, &(plane, start)) in faces.iter().enumerate() {
+                let t_plane = (plane - height) / speed;
+                if !(t_min..t).contains(&t_plane) {
+                    continue;
+                }
+                let point = origin + t_plane * ray.direction;
+                let uv = (
+                    slab.across[0].dot(&point) - start.0,
+                    slab.across[1].dot(&point) - start.1,
+                );
+                if FACE.contains(&uv.0) && FACE.contains(&uv.1) {
+                    t = t_plane;
+                    found = Some((2 * pair + side, uv));
+                }
+            }
+        }
+
+        let (part, uv) = found?;
+        Some(Intersection::deferred(t, self, part, uv))
+    }
+
+    fn shading(&self, ray: &Ray, t: f64, part: usize, uv: (f64, f64)) -> Hit {
+        let slab = self.slabs[part / 2].expect("only faces that exist are hit");
+        let normal = match part % 2 {
+            0 => -slab.outward,
+            _ => slab.outward,
+        };
+
+        Hit::with_face_normal(ray, normal, t, self.material.clone())
+            .with_uv(uv.0, uv.1)
+            .with_uv_density(slab.uv_density)
+    }
+
+    /// Every face is a light of its own, glowing outwards.
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        for (slab, (x, y, z)) in self.slabs.iter().zip(TRIPLETS) {
+            let Some(slab) = slab else {
+                continue;
+            };
+            let (x, y, z) = (self.axes[x], self.axes[y], self.axes[z]);
+            let floor = AreaLight::face(self.corner, x, y, -slab.outward, &self.material);
+            lights.extend(floor);
+            // flat parallelograms have their floor and ceiling in the same place, and only ever
+            // show the floor
+            if slab.ceiling != 0.0 {
+                let ceiling = AreaLight::face(self.corner + z, x, y, slab.outward, &self.material);
+                lights.extend(ceiling);
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [u, v, w] = self.axes;
+        let corners = [
+            self.corner,
+            self.corner + u,
+            self.corner + v,
+            self.corner + w,
+            self.corner + u + v,
+            self.corner + u + w,
+            self.corner + v + w,
+            self.corner + u + v + w,
+        ];
+
+        let first = Aabb::new(corners[0], corners[0]);
+        Some(
+            corners[1..]
+                .iter()
+                .fold(first, |acc, &c| acc.union(&Aabb::new(c, c))),
+        )
+    }
+}
+
+/// A single triangle, the building block of meshes.
+#[derive(Clone)]
+pub struct Triangle {
+    vertices: [Point3; 3],
+    /// Normals at the vertices, interpolated across the triangle for smooth shading.
+    normals: Option<[Vec3; 3]>,
+    /// Texture coordinates at the vertices. Without them, the barycentric coordinates are used.
+    uvs: Option<[(f64, f64); 3]>,
+    /// Whether rays hitting the back are let through, see [`Culled`].
+    cull_backfaces: bool,
+    pub material: Arc<dyn Material>,
+    /// From the first vertex to the other two, worked out once rather than for every ray.
+    edges: [Vec3; 2],
+    /// The unit normal of the front face.
+    normal: Vec3,
+    /// How fast the texture coordinates change with distance across the triangle.
+    uv_density: f64,
+}
+
+impl Triangle {
+    /// Fails if any coordinate isn't finite. The front face is the one the vertices go around
+    /// counterclockwise on.
+    pub fn new(vertices: [Point3; 3], material: Arc<dyn Material>) -> Result<Self> {
+        for vertex in &vertices {
+            for coordinate in vertex.0 {
+                Error::check("triangle vertex", coordinate, |_| true)?;
+            }
+        }
+
+        let [a, b, c] = vertices;
+        let edges = [b - a, c - a];
+        let cross = edges[0].cross(&edges[1]);
+        Ok(Self {
+            vertices,
+            normals: None,
+            uvs: None,
+            cull_backfaces: false,
+            material,
+            edges,
+            normal: cross.normalize(),
+            // the barycentric coordinates cover the triangle once
+            uv_density: (1.0 / cross.length()).sqrt(),
+        })
+    }
+
+    pub fn vertices(&self) -> [Point3; 3] {
+        self.vertices
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        let [uv0, uv1, uv2] = uvs;
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let uv_area = (du1 * dv2 - du2 * dv1).abs();
+        // both areas are doubled, which cancels out
+        let area = self.edges[0].cross(&self.edges[1]).length();
+        self.uv_density = (uv_area / area).sqrt();
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Lets rays hitting the back through, like [`Culled`] but without working out the hit
+    /// first.
+    pub fn culling_backfaces(mut self) -> Self {
+        self.cull_backfaces = true;
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.hit_t(ray, t_min, t_max).map(|hit| hit.shade(ray))
+    }
+
+    /// The coordinates are the barycentric ones of the second and third vertices.
+    fn hit_t<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        // Möller–Trumbore, solving for t and the barycentric coordinates at once
+        let [edge1, edge2] = self.edges;
+
+        let p = ray.direction.cross(&edge2);
+        let det = edge1.dot(&p);
+        // the determinant is negative for rays coming from the back
+        if det.abs() < 1e-12 || (self.cull_backfaces && det < 0.0) {
+            // parallel to the triangle, or the triangle is degenerate
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let s = ray.origin - self.vertices[0];
+        let u = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = ray.direction.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inv_det;
+        if !(t_min..t_max).contains(&t) {
+            return None;
+        }
+
+        Some(Intersection::deferred(t, self, 0, (u, v)))
+    }
+
+    fn shading(&self, ray: &Ray, t: f64, _part: usize, (u, v): (f64, f64)) -> Hit {
+        let w = 1.0 - u - v;
+        let outward_normal = self.normal;
+        let (tex_u, tex_v) = match self.uvs {
+            Some([uv0, uv1, uv2]) => (
+                w * uv0.0 + u * uv1.0 + v * uv2.0,
+                w * uv0.1 + u * uv1.1 + v * uv2.1,
+            ),
+            None => (u, v),
+        };
+        let mut hit = Hit::with_face_normal(ray, outward_normal, t, self.material.clone())
+            .with_uv(tex_u, tex_v)
+            .with_uv_density(self.uv_density);
+
+        if let Some([n0, n1, n2]) = self.normals {
+            let mut shading = (w * n0 + u * n1 + v * n2).normalize();
+            // front and back are decided by the geometry, whichever way the normals point
+            if shading.dot(&outward_normal) < 0.0 {
+                shading = -shading;
+            }
+            if shading.0.iter().all(|c| c.is_finite()) {
+                hit.normal = if hit.front_face { shading } else { -shading };
+            }
+        }
+
+        hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [a, b, c] = self.vertices;
+        let bbox = Aabb::new(a, b).union(&Aabb::new(c, c));
+
+        // axis-aligned triangles would have a flat box, which rays along its plane can miss
+        let padding = Vec3::new(1e-8, 1e-8, 1e-8);
+        Some(Aabb::new(bbox.min - padding, bbox.max + padding))
+    }
+}
+
+/// A patch of the surface swept by a line moving between two edges, bent like a saddle when
+/// its corners aren't on a plane. The corners go around it, at `u`, `v` of (0, 0), (1, 0),
+/// (1, 1) and (0, 1), and its front is the side they go around counterclockwise.
+#[derive(Clone)]
+pub struct BilinearPatch {
+    corners: [Point3; 4],
+    pub material: Arc<dyn Material>,
+}
+
+impl BilinearPatch {
+    /// Fails if any coordinate isn't finite.
+    pub fn new(corners: [Point3; 4], material: Arc<dyn Material>) -> Result<Self> {
+        for corner in &corners {
+            for coordinate in corner.0 {
+                Error::check("patch corner", coordinate, |_| true)?;
+            }
+        }
+
+        Ok(Self { corners, material })
+    }
+}
+
+impl Hittable for BilinearPatch {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t, u, v) = intersect_bilinear(&self.corners, ray, t_min, t_max)?;
+
+        let [p00, p10, p11, p01] = self.corners;
+        let du = (1.0 - v) * (p10 - p00) + v * (p11 - p01);
+        let dv = (1.0 - u) * (p01 - p00) + u * (p11 - p10);
+        patch_hit(ray, t, (u, v), du, dv, self.material.clone())
+    }
 
-        let ro: Vector3<f64> = ray.origin.into();
-        let rd: Vector3<f64> = ray.direction.into();
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [a, b, c, d] = self.corners;
+        let bbox = Aabb::new(a, b).union(&Aabb::new(c, d));
 
-        let mut lu;
-        let mut floor: Vector3<f64>;
-        let mut ceiling: Vector3<f64>;
-        // in each iteration, go for the two parallel planes generated by the x and y vectors
-        for (x, y, z) in TRIPLETS.iter().copied() {
-            lu = Matrix3::from_columns(&[self.axes[x], self.axes[y], -rd]).lu();
-            floor = ro - self.corner;
-            ceiling = ro - self.corner - self.axes[z];
+        // flat patches would have a flat box, like triangles
+        let padding = Vec3::new(1e-8, 1e-8, 1e-8);
+        Some(Aabb::new(bbox.min - padding, bbox.max + padding))
+    }
+}
+
+/// The number of bilinear patches a [`BezierPatch`] is approximated by along each side, to find
+/// a starting point for refining its hits.
+const BEZIER_CELLS: usize = 8;
+
+/// A bicubic Bézier patch, the curved pieces the Utah teapot is made of. Its 16 control points
+/// come in rows of 4 along `u`, one row after the other along `v`, and it passes through the
+/// corner ones.
+///
+/// Rays are intersected with a grid of bilinear patches through points of the surface first,
+/// then the hit is moved onto the exact surface with Newton's method.
+#[derive(Clone)]
+pub struct BezierPatch {
+    control_points: [[Point3; 4]; 4],
+    /// The bilinear approximation, with a box around the part of the surface each patch stands
+    /// for.
+    cells: Vec<([Point3; 4], Aabb)>,
+    bbox: Aabb,
+    pub material: Arc<dyn Material>,
+}
+
+impl BezierPatch {
+    /// Fails if any coordinate isn't finite.
+    pub fn new(control_points: [[Point3; 4]; 4], material: Arc<dyn Material>) -> Result<Self> {
+        for point in control_points.iter().flatten() {
+            for coordinate in point.0 {
+                Error::check("bezier control point", coordinate, |_| true)?;
+            }
+        }
+
+        let mut patch = Self {
+            control_points,
+            cells: Vec::with_capacity(BEZIER_CELLS * BEZIER_CELLS),
+            // the surface stays within the hull of its control points
+            bbox: control_points
+                .iter()
+                .flatten()
+                .map(|&point| Aabb::new(point, point))
+                .reduce(|acc, bbox| acc.union(&bbox))
+                .unwrap(),
+            material,
+        };
+
+        let step = 1.0 / BEZIER_CELLS as f64;
+        for j in 0..BEZIER_CELLS {
+            for i in 0..BEZIER_CELLS {
+                let at = |di: f64, dj: f64| {
+                    patch
+                        .evaluate((i as f64 + di) * step, (j as f64 + dj) * step)
+                        .0
+                };
+                let corners = [at(0.0, 0.0), at(1.0, 0.0), at(1.0, 1.0), at(0.0, 1.0)];
+
+                // the box of a few points of the surface, padded for the bulges between them
+                let mut bbox = Aabb::new(corners[0], corners[0]);
+                for sj in 0..=4 {
+                    for si in 0..=4 {
+                        let point = at(si as f64 / 4.0, sj as f64 / 4.0);
+                        bbox = bbox.union(&Aabb::new(point, point));
+                    }
+                }
+                let size = (bbox.max - bbox.min).length();
+                let padding = Vec3::new(1.0, 1.0, 1.0) * (0.1 * size + 1e-8);
+                patch
+                    .cells
+                    .push((corners, Aabb::new(bbox.min - padding, bbox.max + padding)));
+            }
+        }
+
+        Ok(patch)
+    }
 
-            let floor_solved = lu.solve_mut(&mut floor);
-            let ceiling_solved = lu.solve_mut(&mut ceiling);
+    /// The point at `u`, `v`, and the derivatives of the surface along `u` and `v` there.
+    fn evaluate(&self, u: f64, v: f64) -> (Point3, Vec3, Vec3) {
+        let (bu, du) = bernstein(u);
+        let (bv, dv) = bernstein(v);
 
-            if floor_solved
-                && (t_min..t).contains(&floor[2])
-                && (0.0..1.0).contains(&floor[0])
-                && (0.0..1.0).contains(&floor[1])
-            {
-                t = floor[2];
-                normal = Some(-self.normals[z]);
+        let mut point = Point3::default();
+        let mut tangent_u = Vec3::default();
+        let mut tangent_v = Vec3::default();
+        for (row, points) in self.control_points.iter().enumerate() {
+            for (column, &control) in points.iter().enumerate() {
+                point += bv[row] * bu[column] * control;
+                tangent_u += bv[row] * du[column] * control;
+                tangent_v += dv[row] * bu[column] * control;
             }
+        }
+
+        (point, tangent_u, tangent_v)
+    }
 
-            if ceiling_solved
-                && (t_min..t).contains(&ceiling[2])
-                && (0.0..1.0).contains(&ceiling[0])
-                && (0.0..1.0).contains(&ceiling[1])
-            {
-                t = ceiling[2];
-                normal = Some(self.normals[z]);
+    /// Moves a guess of `t`, `u` and `v` onto the exact surface, if it gets there.
+    fn refine(&self, ray: &Ray, (mut t, mut u, mut v): (f64, f64, f64)) -> Option<(f64, f64, f64)> {
+        let scale = self.bbox.max - self.bbox.min;
+        let tolerance = 1e-10 * scale.length().max(1e-10);
+        for _ in 0..8 {
+            let (point, du, dv) = self.evaluate(u, v);
+            let error = point - ray.at(t);
+            if error.length() < tolerance {
+                return Some((t, u, v));
             }
+
+            // the surface and the ray meet where the error vanishes
+            let jacobian = Matrix3::from_columns(&[du.into(), dv.into(), (-ray.direction).into()]);
+            let step = jacobian.lu().solve(&Vector3::from(error))?;
+            u -= step[0];
+            v -= step[1];
+            t -= step[2];
         }
 
-        let normal = normal?.into();
+        None
+    }
+}
+
+impl Hittable for BezierPatch {
+    fn hit(&self, ray: &Ray, t_min: f64, mut t_max: f64) -> Option<Hit> {
+        self.bbox.intersect(ray, t_min, t_max)?;
+
+        let step = 1.0 / BEZIER_CELLS as f64;
+        let mut closest = None;
+        for (index, (corners, bbox)) in self.cells.iter().enumerate() {
+            if bbox.intersect(ray, t_min, t_max).is_none() {
+                continue;
+            }
+            let (t, u, v) = match intersect_bilinear(corners, ray, t_min, t_max) {
+                Some(hit) => hit,
+                None => continue,
+            };
+
+            let (i, j) = (index % BEZIER_CELLS, index / BEZIER_CELLS);
+            let guess = (t, (i as f64 + u) * step, (j as f64 + v) * step);
+            // the approximation is better than nothing when Newton's method strays
+            let (t, u, v) = match self.refine(ray, guess) {
+                Some((t, u, v))
+                    if (t_min..t_max).contains(&t)
+                        && (-1e-9..=1.0 + 1e-9).contains(&u)
+                        && (-1e-9..=1.0 + 1e-9).contains(&v) =>
+                {
+                    (t, u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+                }
+                _ => guess,
+            };
+
+            t_max = t;
+            closest = Some((t, u, v));
+        }
+
+        let (t, u, v) = closest?;
+        let (_, mut du, mut dv) = self.evaluate(u, v);
+        if du.cross(&dv).near_zero() {
+            // a corner where an edge shrinks to a point, like the top of the teapot's lid: the
+            // normal is the limit from inside of the patch
+            let (_, nudged_u, nudged_v) = self.evaluate(u + 1e-4 * (0.5 - u), v + 1e-4 * (0.5 - v));
+            du = nudged_u;
+            dv = nudged_v;
+        }
+        patch_hit(ray, t, (u, v), du, dv, self.material.clone())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let padding = Vec3::new(1e-8, 1e-8, 1e-8);
+        Some(Aabb::new(self.bbox.min - padding, self.bbox.max + padding))
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self) + std::mem::size_of_val(&self.cells[..]);
+    }
+}
+
+/// The cubic Bernstein polynomials at `t`, and their derivatives.
+fn bernstein(t: f64) -> ([f64; 4], [f64; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+/// Intersects a ray with the bilinear patch through `corners`, going around it like in
+/// [`BilinearPatch`], returning `t`, `u` and `v`.
+///
+/// This is the method of Reshetov, "Cool Patches: A Geometric Approach to Ray/Bilinear Patch
+/// Intersections", which solves a quadratic for `u` and finds `t` and `v` from there.
+fn intersect_bilinear(
+    corners: &[Point3; 4],
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let [q00, q10, q11, q01] = *corners;
+    let e10 = q10 - q00;
+    let e11 = q11 - q10;
+    let e00 = q01 - q00;
+    let normal = e10.cross(&(q01 - q11));
+    let q00 = q00 - ray.origin;
+    let q10 = q10 - ray.origin;
+    let d = ray.direction;
+
+    let a = q00.cross(&d).dot(&e00);
+    let c = normal.dot(&d);
+    let b = q10.cross(&d).dot(&e11) - a - c;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+
+    // the stable form of the roots, with a single one for parallelograms
+    let (u1, u2) = if c == 0.0 {
+        (-a / b, -1.0)
+    } else {
+        let q = (-b - root.copysign(b)) / 2.0;
+        (q / c, a / q)
+    };
+
+    let mut closest: Option<(f64, f64, f64)> = None;
+    for u in [u1, u2] {
+        if !(0.0..=1.0).contains(&u) {
+            continue;
+        }
+
+        // the line across the patch at this u, from the edge at v = 0
+        let start = (1.0 - u) * q00 + u * q10;
+        let along = (1.0 - u) * e00 + u * e11;
+        let n = d.cross(&along);
+        let length_squared = n.length_squared();
+        let n = n.cross(&start);
+        let t = n.dot(&along) / length_squared;
+        let v = n.dot(&d) / length_squared;
+
+        let nearer = closest.is_none_or(|(closest, _, _)| t < closest);
+        if (t_min..t_max).contains(&t) && (0.0..=1.0).contains(&v) && nearer {
+            closest = Some((t, u, v));
+        }
+    }
+
+    closest
+}
+
+/// The hit at `t` on a patch with the derivatives `du` and `dv` there.
+fn patch_hit(
+    ray: &Ray,
+    t: f64,
+    (u, v): (f64, f64),
+    du: Vec3,
+    dv: Vec3,
+    material: Arc<dyn Material>,
+) -> Option<Hit> {
+    let cross = du.cross(&dv);
+    let outward_normal = cross.normalize();
+    if outward_normal.0.iter().any(|c| !c.is_finite()) {
+        return None;
+    }
+
+    Some(
+        Hit::with_face_normal(ray, outward_normal, t, material)
+            .with_uv(u, v)
+            .with_uv_density(1.0 / cross.length().sqrt()),
+    )
+}
+
+/// The most times a [`Curve`] is halved to find hits, for the most strongly bent ones.
+const CURVE_MAX_DEPTH: u32 = 10;
+
+/// A hair or a blade of grass: a cubic Bézier curve swept into a ribbon that always faces the
+/// ray. Its radius goes linearly from the first radius at the first control point to the second
+/// at the last, and its normals turn across it like a tube's, so that it looks round.
+///
+/// `u` goes along the curve and `v` across it, and hits carry the direction of the curve as
+/// their tangent, for [`Hair`](super::materials::Hair).
+///
+/// Rays are intersected like pbrt does, by halving the curve until the pieces are nearly
+/// straight and skipping the ones whose boxes the ray misses. Long curves are better split into
+/// [`segments`](Self::segments) first, which the BVH can skip on their own.
+#[derive(Clone)]
+pub struct Curve {
+    control_points: [Point3; 4],
+    /// The part of the curve this one stands for, and its control points.
+    range: (f64, f64),
+    piece: [Point3; 4],
+    radii: [f64; 2],
+    /// How many times to halve the piece.
+    depth: u32,
+    pub material: Arc<dyn Material>,
+}
+
+impl Curve {
+    /// Fails if any coordinate isn't finite, or either radius isn't positive.
+    pub fn new(
+        control_points: [Point3; 4],
+        radii: [f64; 2],
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        for point in &control_points {
+            for coordinate in point.0 {
+                Error::check("curve control point", coordinate, |_| true)?;
+            }
+        }
+        for radius in radii {
+            Error::check("curve radius", radius, |radius| radius > 0.0)?;
+        }
+
+        Ok(Self {
+            control_points,
+            range: (0.0, 1.0),
+            piece: control_points,
+            radii,
+            depth: Self::depth(&control_points, radii),
+            material,
+        })
+    }
+
+    /// The curve split into `count` curves along its length, which together look the same.
+    pub fn segments(&self, count: usize) -> Vec<Self> {
+        (0..count)
+            .map(|i| {
+                let (start, end) = (i as f64 / count as f64, (i + 1) as f64 / count as f64);
+                let (_, rest) = split_bezier(self.control_points, start);
+                let (piece, _) = split_bezier(rest, (end - start) / (1.0 - start));
+                Self {
+                    range: (start, end),
+                    piece,
+                    depth: Self::depth(&piece, self.radii),
+                    ..self.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// How many times to halve a piece for the halves to be within a tenth of the radius of
+    /// their chords.
+    fn depth(&[p0, p1, p2, p3]: &[Point3; 4], radii: [f64; 2]) -> u32 {
+        let bend = (p0 - 2.0 * p1 + p2)
+            .0
+            .iter()
+            .chain(&(p1 - 2.0 * p2 + p3).0)
+            .fold(0.0f64, |max, c| max.max(c.abs()));
+        let tolerance = 0.1 * radii[0].max(radii[1]);
+        let depth = ((2.0f64.sqrt() * 6.0 * bend / (8.0 * tolerance)).log2() / 2.0).round();
+
+        depth.clamp(0.0, CURVE_MAX_DEPTH as f64) as u32
+    }
+
+    fn radius(&self, u: f64) -> f64 {
+        (1.0 - u) * self.radii[0] + u * self.radii[1]
+    }
+
+    /// The point at `u`, and the derivative of the curve there.
+    fn evaluate(points: &[Point3; 4], u: f64) -> (Point3, Vec3) {
+        let (b, d) = bernstein(u);
+        (0..4).fold(
+            (Point3::default(), Vec3::default()),
+            |(point, tangent), i| (point + b[i] * points[i], tangent + d[i] * points[i]),
+        )
+    }
+
+    /// The closest hit of the ray with the piece of the curve between `u0` and `u1`, as its
+    /// distance along the ray and `u`. Both the piece and the whole curve are in the space of the
+    /// ray, where it starts at the origin and goes along z.
+    fn intersect_piece(
+        &self,
+        whole: &[Point3; 4],
+        piece: [Point3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64)> {
+        let radius = self.radius(u0).max(self.radius(u1));
+        let bbox = piece
+            .iter()
+            .map(|&point| Aabb::new(point, point))
+            .reduce(|acc, bbox| acc.union(&bbox))
+            .unwrap();
+        if bbox.min.x() - radius > 0.0
+            || bbox.max.x() + radius < 0.0
+            || bbox.min.y() - radius > 0.0
+            || bbox.max.y() + radius < 0.0
+            || bbox.min.z() - radius > z_max
+            || bbox.max.z() + radius < z_min
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split_bezier(piece, 0.5);
+            let middle = 0.5 * (u0 + u1);
+            let near = self.intersect_piece(whole, first, (u0, middle), depth - 1, z_min, z_max);
+            let z_max = near.map_or(z_max, |(z, _)| z);
+            let far = self.intersect_piece(whole, second, (middle, u1), depth - 1, z_min, z_max);
+            return far.or(near);
+        }
+
+        // the ray has to pass between the ends of the piece, which meet those of its neighbours
+        let [p0, p1, p2, p3] = piece;
+        let dot = |a: Vec3, b: Vec3| a.x() * b.x() + a.y() * b.y();
+        if dot(p1 - p0, -p0) < 0.0 || dot(p2 - p3, -p3) < 0.0 {
+            return None;
+        }
+
+        // the closest point to the ray on the piece, taken as a line
+        let chord = p3 - p0;
+        let length_squared = dot(chord, chord);
+        if length_squared == 0.0 {
+            return None;
+        }
+        let w = dot(-p0, chord) / length_squared;
+        let u = (u0 + w * (u1 - u0)).clamp(u0, u1);
+
+        let (point, _) = Self::evaluate(whole, u);
+        let radius = self.radius(u);
+        if dot(point, point) > radius * radius || !(z_min..z_max).contains(&point.z()) {
+            return None;
+        }
+
+        Some((point.z(), u))
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let length = ray.direction.length();
+        let forward = ray.direction / length;
+        let side = if forward.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let side = side.cross(&forward).normalize();
+        let up = forward.cross(&side);
+        let to_ray_space = |point: Point3| {
+            let offset = point - ray.origin;
+            Point3::new(offset.dot(&side), offset.dot(&up), offset.dot(&forward))
+        };
+
+        let (z, u) = self.intersect_piece(
+            &self.control_points.map(to_ray_space),
+            self.piece.map(to_ray_space),
+            self.range,
+            self.depth,
+            t_min * length,
+            t_max * length,
+        )?;
+        let t = z / length;
+
+        // the ribbon faces the ray, and its normal turns from side to side across it
+        let (center, tangent) = Self::evaluate(&self.control_points, u);
+        let tangent = tangent.normalize();
+        let across = tangent.cross(&forward).normalize();
+        let facing = tangent.cross(&across);
+        let (normal, offset) = if across.0.iter().all(|c| c.is_finite()) {
+            let offset = ((ray.at(t) - center).dot(&across) / self.radius(u)).clamp(-1.0, 1.0);
+            let normal = offset * across + (1.0 - offset * offset).sqrt() * facing;
+            (normal, offset)
+        } else {
+            // looking right along the curve
+            (-forward, 0.0)
+        };
+
+        Some(
+            Hit::with_face_normal(ray, normal, t, self.material.clone())
+                .with_uv(u, 0.5 * (offset + 1.0))
+                .with_tangent(tangent),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // the curve stays within the hull of its control points
+        let [a, b, c, d] = self.piece;
+        let bbox = Aabb::new(a, b).union(&Aabb::new(c, d));
+        let radius = self.radius(self.range.0).max(self.radius(self.range.1));
+        let padding = Vec3::new(radius, radius, radius);
+        Some(Aabb::new(bbox.min - padding, bbox.max + padding))
+    }
+}
+
+/// Splits a cubic Bézier curve in two at `t`, by de Casteljau's algorithm.
+fn split_bezier([p0, p1, p2, p3]: [Point3; 4], t: f64) -> ([Point3; 4], [Point3; 4]) {
+    let lerp = |a: Point3, b: Point3| (1.0 - t) * a + t * b;
+    let p01 = lerp(p0, p1);
+    let p12 = lerp(p1, p2);
+    let p23 = lerp(p2, p3);
+    let p012 = lerp(p01, p12);
+    let p123 = lerp(p12, p23);
+    let split = lerp(p012, p123);
+
+    ([p0, p01, p012, split], [split, p123, p23, p3])
+}
+
+/// An object whose hits carry a label, so that ID passes can tell it apart from the others.
+#[derive(Clone)]
+pub struct Labeled {
+    pub object: Arc<dyn Hittable + Send + Sync>,
+    pub label: u32,
+}
+
+impl Hittable for Labeled {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let mut hit = self.object.hit(ray, t_min, t_max)?;
+        hit.object = self.label;
+        Some(hit)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(ray, t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        self.object.occluded_packet(packet, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self);
+        self.object.memory(usage);
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        self.object.lights(lights)
+    }
+}
+
+/// An object only seen from the front, which rays going through it from the back pass right
+/// through. One-sided panels are invisible from behind, and the insides of closed meshes are
+/// never shaded.
+#[derive(Clone)]
+pub struct Culled {
+    pub object: Arc<dyn Hittable + Send + Sync>,
+}
+
+impl Hittable for Culled {
+    fn hit(&self, ray: &Ray, mut t_min: f64, t_max: f64) -> Option<Hit> {
+        loop {
+            let hit = self.object.hit(ray, t_min, t_max)?;
+            if hit.front_face {
+                return Some(hit);
+            }
+            // there may be a front further along, behind the back that was hit
+            t_min = hit.t + 1e-9 * hit.t.abs().max(1.0);
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self);
+        self.object.memory(usage);
+    }
+
+    fn lights(&self, lights: &mut Vec<AreaLight>) {
+        self.object.lights(lights)
+    }
+}
+
+/// Places an object in the scene with a transformation applied to it.
+#[derive(Clone)]
+pub struct Transformed {
+    pub object: Arc<dyn Hittable + Send + Sync>,
+    pub transform: Transform,
+}
+
+impl Transformed {
+    /// The ray in the object's space. The direction isn't renormalized, so that t stays the same
+    /// in both spaces.
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.transform.inverse_transform_point(ray.origin),
+            direction: self.transform.inverse_transform_vector(ray.direction),
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let hit = self.object.hit(&self.local_ray(ray), t_min, t_max)?;
+        let outward_normal = if hit.front_face {
+            hit.normal
+        } else {
+            -hit.normal
+        };
+        let normal = self.transform.transform_normal(outward_normal);
+        // how much the transformation stretches areas of the surface
+        let stretch = self.transform.determinant().abs() * normal.length();
+
+        let object = hit.object;
+        let mut hit = Hit::with_face_normal(ray, normal.normalize(), hit.t, hit.material)
+            .with_uv(hit.u, hit.v)
+            .with_uv_density(hit.uv_density / stretch.sqrt())
+            .with_tangent(self.transform.transform_vector(hit.tangent));
+        hit.object = object;
+
+        Some(hit)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(&self.local_ray(ray), t_min, t_max)
+    }
+
+    fn occluded_packet(&self, packet: &[(Ray, f64)], t_min: f64, active: RayMask) -> RayMask {
+        let local: Vec<_> = packet
+            .iter()
+            .map(|(ray, t_max)| (self.local_ray(ray), *t_max))
+            .collect();
+        self.object.occluded_packet(&local, t_min, active)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let Aabb { min, max } = self.object.bounding_box()?;
+
+        let corners = (0..8).map(|i| {
+            let pick = |bit, axis: usize| if i & bit == 0 { min[axis] } else { max[axis] };
+            self.transform
+                .transform_point(Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)))
+        });
+
+        corners
+            .map(|corner| Aabb::new(corner, corner))
+            .reduce(|acc, bbox| acc.union(&bbox))
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self);
+        self.object.memory(usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        })
+    }
+
+    fn ray(origin: [f64; 3], direction: [f64; 3]) -> Ray {
+        let [x, y, z] = origin;
+        let [dx, dy, dz] = direction;
+        Ray {
+            origin: Point3::new(x, y, z),
+            direction: Vec3::new(dx, dy, dz),
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    fn unit_sphere() -> Sphere {
+        Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: material(),
+        }
+    }
+
+    fn unit_cube() -> Parallelogram {
+        Parallelogram::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            material(),
+        )
+    }
+
+    fn torus() -> Torus {
+        Torus {
+            center: Point3::new(0.0, 0.0, 0.0),
+            major_radius: 1.0,
+            minor_radius: 0.25,
+            material: material(),
+        }
+    }
+
+    #[test]
+    fn sphere_rejects_invalid_parameters() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        assert!(Sphere::new(origin, -1.0, material()).is_err());
+        assert!(Sphere::new(origin, f64::NAN, material()).is_err());
+        assert!(Sphere::new(Point3::new(f64::INFINITY, 0.0, 0.0), 1.0, material()).is_err());
+        assert!(Sphere::new(origin, 0.0, material()).is_ok());
+    }
+
+    #[test]
+    fn sphere_head_on() {
+        let hit = unit_sphere()
+            .hit(&ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+
+        assert_eq!(hit.t, 4.0);
+        assert!(hit.front_face);
+        assert_close(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn sphere_tangent() {
+        let sphere = unit_sphere();
+
+        let hit = sphere
+            .hit(&ray([0.0, 1.0, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert_eq!(hit.t, 5.0);
+        assert_close(hit.point, Point3::new(0.0, 1.0, 0.0));
+        // grazing, so which side got hit is a toss-up
+        assert_eq!(hit.normal.y().abs(), 1.0);
+
+        let above = ray([0.0, 1.0 + 1e-9, 5.0], [0.0, 0.0, -1.0]);
+        assert!(sphere.hit(&above, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn sphere_from_inside() {
+        // an unnormalized direction, so t isn't the distance
+        let hit = unit_sphere()
+            .hit(&ray([0.0, 0.0, 0.0], [2.0, 0.0, 0.0]), 0.001, f64::INFINITY)
+            .unwrap();
+
+        assert_eq!(hit.t, 0.5);
+        assert!(!hit.front_face);
+        assert_close(hit.point, Point3::new(1.0, 0.0, 0.0));
+        assert_close(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sphere_behind() {
+        let away = ray([0.0, 0.0, 5.0], [0.0, 0.0, 1.0]);
+        assert!(unit_sphere().hit(&away, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn sphere_range_is_half_open() {
+        let sphere = unit_sphere();
+        let r = ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]);
+
+        // the near side is at t = 4 and the far side at t = 6
+        assert_eq!(sphere.hit(&r, 4.0, f64::INFINITY).unwrap().t, 4.0);
+        assert!(sphere.hit(&r, 0.0, 4.0).is_none());
+        assert_eq!(sphere.hit(&r, 0.0, 4.0 + 1e-9).unwrap().t, 4.0);
+
+        let far = sphere.hit(&r, 4.0 + 1e-9, f64::INFINITY).unwrap();
+        assert_eq!(far.t, 6.0);
+        assert!(!far.front_face);
+
+        assert!(sphere.hit(&r, 4.0 + 1e-9, 6.0).is_none());
+        assert!(sphere.hit(&r, 6.0 + 1e-9, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn parallelogram_face() {
+        let hit = unit_cube()
+            .hit(
+                &ray([0.25, 0.75, 5.0], [0.0, 0.0, -1.0]),
+                0.0,
+                f64::INFINITY,
+            )
+            .unwrap();
+
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert_close(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flat_patches_match_parallelograms() {
+        let corners = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(2.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        let control_points = [0.0, 1.0, 2.0, 3.0].map(|row| {
+            [0.0, 1.0, 2.0, 3.0].map(|column| Point3::new(column * 2.0 / 3.0, row / 3.0, 0.0))
+        });
+        let bilinear = BilinearPatch::new(corners, material()).unwrap();
+        let bezier = BezierPatch::new(control_points, material()).unwrap();
+
+        let r = ray([0.5, 0.25, 3.0], [0.0, 0.0, -2.0]);
+        for hit in [
+            bilinear.hit(&r, 0.0, f64::INFINITY).unwrap(),
+            bezier.hit(&r, 0.0, f64::INFINITY).unwrap(),
+        ] {
+            assert!((hit.t - 1.5).abs() < 1e-9);
+            assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+            assert!(hit.front_face);
+            assert_close(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+        assert!(bilinear
+            .hit(&ray([2.5, 0.5, 3.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn curved_patch_hits_lie_on_the_surface() {
+        // a saddle, and a dome over the unit square
+        let saddle = BilinearPatch::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 1.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 1.0),
+            ],
+            material(),
+        )
+        .unwrap();
+        let height = [
+            [0.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0, 0.0],
+            [0.0; 4],
+        ];
+        let mut control_points = [[Point3::default(); 4]; 4];
+        for (row, points) in control_points.iter_mut().enumerate() {
+            for (column, point) in points.iter_mut().enumerate() {
+                *point = Point3::new(column as f64 / 3.0, row as f64 / 3.0, height[row][column]);
+            }
+        }
+        let dome = BezierPatch::new(control_points, material()).unwrap();
+
+        for i in 0..50 {
+            let x = 0.1 + 0.75 * (i as f64 * 0.618).fract();
+            let y = 0.1 + 0.8 * (i as f64 * 0.382).fract();
+            let r = ray([x, y, 1.5], [0.05, -0.025, -1.0]);
+
+            let hit = saddle.hit(&r, 0.0, f64::INFINITY).unwrap();
+            let [p00, p10, p11, p01] = saddle.corners;
+            let expected = (1.0 - hit.v) * ((1.0 - hit.u) * p00 + hit.u * p10)
+                + hit.v * ((1.0 - hit.u) * p01 + hit.u * p11);
+            assert_close(hit.point, expected);
+
+            let hit = dome.hit(&r, 0.0, f64::INFINITY).unwrap();
+            assert_close(hit.point, dome.evaluate(hit.u, hit.v).0);
+            assert!(hit.point.z() > 0.0);
+        }
+    }
+
+    #[test]
+    fn curves_taper_and_face_the_ray() {
+        let straight = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0].map(|x| Point3::new(x, 0.0, 0.0));
+        let curve = Curve::new(straight, [0.2, 0.1], material()).unwrap();
+
+        // 0.15 wide in the middle, and 0.105 near the end
+        let hit = curve
+            .hit(&ray([0.0, 0.14, 5.0], [0.0, 0.0, -2.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-9 && (hit.u - 0.5).abs() < 1e-9);
+        assert_close(hit.tangent, Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit.normal.y() > 0.9 && hit.normal.z() > 0.0);
+        assert!(curve
+            .hit(&ray([0.0, 0.16, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .is_none());
+        assert!(curve
+            .hit(&ray([0.9, -0.1, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .is_some());
+        assert!(curve
+            .hit(
+                &ray([0.9, -0.11, 5.0], [0.0, 0.0, -1.0]),
+                0.0,
+                f64::INFINITY
+            )
+            .is_none());
+        assert!(curve
+            .hit(&ray([1.05, 0.0, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .is_none());
+
+        // a hook, hit from the side, whole and in segments
+        let bent = [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.5, 0.0],
+            [1.0, 0.5, 0.0],
+        ]
+        .map(|[x, y, z]| Point3::new(x, y, z));
+        let curve = Curve::new(bent, [0.01, 0.01], material()).unwrap();
+        let segments = curve.segments(3);
+        for i in 1..20 {
+            let u = i as f64 / 20.0;
+            let (point, _) = Curve::evaluate(&bent, u);
+            let r = ray([point.x(), point.y(), 3.0], [0.0, 0.0, -1.0]);
+            for hit in [
+                curve.hit(&r, 0.0, f64::INFINITY),
+                segments.hit(&r, 0.0, f64::INFINITY),
+            ] {
+                let hit = hit.unwrap();
+                let (nearest, _) = Curve::evaluate(&bent, hit.u);
+                assert!((hit.point - nearest).length() <= 0.01 + 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn uv_density_follows_scaling() {
+        let r = ray([0.25, 0.75, 5.0], [0.0, 0.0, -1.0]);
+        let hit = unit_cube().hit(&r, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.uv_density - 1.0).abs() < 1e-9);
+
+        // twice as big, with the coordinates spread over four times the area
+        let scaled = Transformed {
+            object: Arc::new(unit_cube()),
+            transform: Transform::new(
+                Vec3::default(),
+                Vec3::new(0.0, 30.0, 0.0),
+                Vec3::new(2.0, 2.0, 2.0),
+            ),
+        };
+        let hit = scaled.hit(&r, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.uv_density - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parallelogram_corner() {
+        let cube = unit_cube();
+
+        let hit = cube
+            .hit(&ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+
+        let outside = ray([-1e-9, -1e-9, 5.0], [0.0, 0.0, -1.0]);
+        assert!(cube.hit(&outside, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn parallelogram_edges() {
+        let cube = unit_cube();
+
+        // coming in at 45 degrees onto the top edge at x = 0
+        let hit = cube
+            .hit(&ray([-1.0, 0.5, 2.0], [1.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-9);
+        assert_close(hit.point, Point3::new(0.0, 0.5, 1.0));
+
+        // faces include all of their edges
+        let inside = ray([1.0 - 1e-9, 0.5, 5.0], [0.0, 0.0, -1.0]);
+        assert!(cube.hit(&inside, 0.0, f64::INFINITY).is_some());
+        let on = ray([1.0, 1.0, 5.0], [0.0, 0.0, -1.0]);
+        assert!((cube.hit(&on, 0.0, f64::INFINITY).unwrap().t - 4.0).abs() < 1e-9);
+        let past = ray([1.0 + 1e-9, 0.5, 5.0], [0.0, 0.0, -1.0]);
+        assert!(cube.hit(&past, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn parallelogram_grazing_edges() {
+        let cube = unit_cube();
+
+        // skimming along the top, the ray meets the sides only at their top edges
+        for (origin, direction, t) in [
+            ([-1.0, 0.5, 1.0], [1.0, 0.0, 0.0], 1.0),
+            ([0.5, 0.5, 1.0], [1.0, 0.0, 0.0], 0.5),
+            ([0.3, 2.0, 1.0], [0.0, -1.0, 0.0], 1.0),
+            ([-1.0, -1.0, 1.0], [1.0, 1.0, 0.0], 1.0),
+        ] {
+            let hit = cube.hit(&ray(origin, direction), 0.001, f64::INFINITY);
+            assert!((hit.unwrap().t - t).abs() < 1e-9, "{:?}", origin);
+        }
+    }
+
+    #[test]
+    fn parallelogram_from_inside() {
+        let hit = unit_cube()
+            .hit(&ray([0.5, 0.5, 0.5], [0.0, 1.0, 0.0]), 0.001, f64::INFINITY)
+            .unwrap();
+
+        assert!((hit.t - 0.5).abs() < 1e-9);
+        assert!(!hit.front_face);
+        assert_close(hit.normal, Vec3::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn parallelogram_range() {
+        let cube = unit_cube();
+        let r = ray([0.5, 0.5, 5.0], [0.0, 0.0, -1.0]);
+
+        assert!(cube.hit(&r, 0.0, 3.9).is_none());
+        let far = cube.hit(&r, 4.1, f64::INFINITY).unwrap();
+        assert!((far.t - 5.0).abs() < 1e-9);
+        assert!(!far.front_face);
+        assert!(cube.hit(&r, 5.1, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn torus_tube() {
+        let hit = torus()
+            .hit(&ray([1.0, 5.0, 0.0], [0.0, -1.0, 0.0]), 0.0, f64::INFINITY)
+            .unwrap();
+
+        assert!((hit.t - 4.75).abs() < 1e-6);
+        assert!(hit.front_face);
+        assert_close(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn torus_through_the_hole() {
+        let torus = torus();
+
+        let down = ray([0.0, 5.0, 0.0], [0.0, -1.0, 0.0]);
+        assert!(torus.hit(&down, 0.0, f64::INFINITY).is_none());
+
+        // along the x axis the ray passes through the hole, hitting the inside of the far tube
+        let across = ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let near = torus.hit(&across, 0.0, f64::INFINITY).unwrap();
+        assert!((near.t - 3.75).abs() < 1e-6);
+        let far = torus.hit(&across, 4.5, f64::INFINITY).unwrap();
+        assert!((far.t - 5.75).abs() < 1e-6);
+        assert!(far.front_face);
+        assert_close(far.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn torus_inner_ring() {
+        let torus = torus();
+
+        // straight down past the inside of the tube, hitting it a hair from where it'd only
+        // touch, which steps along the distance to the surface used to crawl towards forever
+        for gap in [1e-6, 1e-9] {
+            let r = ray([0.75 + gap, 5.0, 0.0], [0.0, -1.0, 0.0]);
+            let hit = torus.hit(&r, 0.0, f64::INFINITY).unwrap();
+            let height = (0.25f64.powi(2) - (0.25 - gap).powi(2)).sqrt();
+            assert!((hit.t - (5.0 - height)).abs() < 1e-6);
+            assert!(
+                (torus.hit(&r, hit.t + 1e-9, f64::INFINITY).unwrap().t - (5.0 + height)).abs()
+                    < 1e-6
+            );
+
+            let r = ray([0.75 - gap, 5.0, 0.0], [0.0, -1.0, 0.0]);
+            assert!(torus.hit(&r, 0.0, f64::INFINITY).is_none());
+        }
+
+        // leaving a point on the inner ring along it, into the tube, without hitting the point
+        let along = ray([0.75, 0.0, 0.0], [0.0, 0.0, 1.0]);
+        let hit = torus.hit(&along, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6 && !hit.front_face);
+    }
+
+    #[test]
+    fn torus_from_inside() {
+        let hit = torus()
+            .hit(&ray([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]), 0.001, f64::INFINITY)
+            .unwrap();
+
+        assert!(!hit.front_face);
+        assert!((hit.t - (1.25f64.powi(2) - 1.0).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn torus_range() {
+        let torus = torus();
+        let r = ray([1.0, 5.0, 0.0], [0.0, -1.0, 0.0]);
+
+        assert!(torus.hit(&r, 0.0, 4.7).is_none());
+        let bottom = torus.hit(&r, 4.8, f64::INFINITY).unwrap();
+        assert!((bottom.t - 5.25).abs() < 1e-6);
+        assert!(torus.hit(&r, 5.3, f64::INFINITY).is_none());
+    }
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            material(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn triangle_rejects_invalid_vertices() {
+        let vertices = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(f64::NAN, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        assert!(Triangle::new(vertices, material()).is_err());
+    }
+
+    #[test]
+    fn triangle_face() {
+        let triangle = triangle();
+
+        let hit = triangle
+            .hit(&ray([0.25, 0.5, 2.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert_close(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9);
+
+        let back = triangle
+            .hit(&ray([0.25, 0.5, -2.0], [0.0, 0.0, 1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!(!back.front_face);
+        assert_close(back.normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn backfaces_are_culled() {
+        let culled = triangle().culling_backfaces();
+        let front = ray([0.25, 0.5, 2.0], [0.0, 0.0, -1.0]);
+        assert!(culled.hit(&front, 0.0, f64::INFINITY).is_some());
+        let back = ray([0.25, 0.5, -2.0], [0.0, 0.0, 1.0]);
+        assert!(culled.hit(&back, 0.0, f64::INFINITY).is_none());
+
+        // from inside, the far side of a sphere is its back too
+        let sphere = Culled {
+            object: Arc::new(unit_sphere()),
+        };
+        let outside = ray([0.0, 0.0, 3.0], [0.0, 0.0, -1.0]);
+        let hit = sphere.hit(&outside, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-9);
+        assert!(sphere
+            .hit(&ray([0.0; 3], [0.0, 0.0, 1.0]), 0.0, f64::INFINITY)
+            .is_none());
+
+        // the back of the triangle hides the front of the sphere behind it
+        let world = Culled {
+            object: Arc::new(crate::collision::bvh::Bvh::new(vec![
+                Arc::new(triangle()),
+                Arc::new(Sphere {
+                    center: Point3::new(0.25, 0.5, 3.0),
+                    ..unit_sphere()
+                }),
+            ])),
+        };
+        let hit = world.hit(&back, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn triangle_misses() {
+        let triangle = triangle();
+
+        // past the hypotenuse
+        let outside = ray([0.6, 0.6, 2.0], [0.0, 0.0, -1.0]);
+        assert!(triangle.hit(&outside, 0.0, f64::INFINITY).is_none());
+        // along the plane of the triangle
+        let grazing = ray([-1.0, 0.25, 0.0], [1.0, 0.0, 0.0]);
+        assert!(triangle.hit(&grazing, 0.0, f64::INFINITY).is_none());
+        // out of range
+        let r = ray([0.25, 0.25, 2.0], [0.0, 0.0, -1.0]);
+        assert!(triangle.hit(&r, 0.0, 1.9).is_none());
+    }
+
+    #[test]
+    fn triangle_interpolates_normals_and_uvs() {
+        let tilted = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let triangle = triangle()
+            // pointing backwards, which the geometry overrides
+            .with_normals([-tilted, -tilted, -tilted])
+            .with_uvs([(0.0, 0.0), (0.5, 0.0), (0.0, 0.5)]);
+
+        let hit = triangle
+            .hit(&ray([0.5, 0.5, 2.0], [0.0, 0.0, -1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!(hit.front_face);
+        assert_close(hit.normal, tilted);
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn metaballs_blend_and_have_exact_normals() {
+        let ball = |x: f64| Metaball {
+            center: Point3::new(x, 0.0, 0.0),
+            radius: 1.0,
+            strength: 1.0,
+        };
+
+        // alone, the surface is where (1 - r²)³ = 0.5
+        let lone = Metaballs::new(vec![ball(0.0)], 0.5, Falloff::Wyvill, material()).unwrap();
+        let radius = (1.0 - 0.5f64.cbrt()).sqrt();
+        let direction = Vec3::new(-1.0, -0.5, -0.2).normalize();
+        let hit = lone
+            .hit(&ray([3.0, 1.5, 0.6], direction.0), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!((hit.point.length() - radius).abs() < 1e-9);
+        assert_close(hit.normal, hit.point / radius);
+
+        // from inside, out through the back
+        let hit = lone
+            .hit(&ray([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]), 0.0, f64::INFINITY)
+            .unwrap();
+        assert!(!hit.front_face && (hit.t - radius).abs() < 1e-9);
+
+        // two apart don't reach the middle on their own, but do together
+        let pair = Metaballs::new(
+            vec![ball(-0.6), ball(0.6)],
+            0.5,
+            Falloff::Wyvill,
+            material(),
+        );
+        let middle = ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]);
+        assert!(lone
+            .hit(&ray([0.6, 0.0, 5.0], [0.0, 0.0, -1.0]), 0.0, 4.5)
+            .is_none());
+        let hit = pair.unwrap().hit(&middle, 0.0, f64::INFINITY).unwrap();
+        assert_close(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        // a negative ball carves a hole
+        let carved = Metaballs::new(
+            vec![
+                ball(0.0),
+                Metaball {
+                    strength: -1.0,
+                    ..ball(0.0)
+                },
+            ],
+            0.5,
+            Falloff::Gaussian,
+            material(),
+        )
+        .unwrap();
+        assert!(carved.hit(&middle, 0.0, f64::INFINITY).is_none());
+    }
 
-        Some(Hit::with_face_normal(ray, normal, t, self.material.clone()))
+    #[test]
+    fn triangle_bounding_box_is_never_flat() {
+        let bbox = triangle().bounding_box().unwrap();
+        assert!(bbox.max.z() > bbox.min.z());
     }
 }
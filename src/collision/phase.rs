@@ -0,0 +1,140 @@
+//! How light inside participating media is spread over the directions it scatters into.
+//!
+//! Directions are the ways light travels, so scattering by an angle of 0 means carrying on
+//! straight ahead.
+
+use crate::{Error, Result, Vec3};
+
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+
+pub trait PhaseFunction: Send + Sync {
+    /// A random direction for light traveling along the unit vector `incoming` to continue in.
+    fn sample(&self, incoming: Vec3, rng: &mut dyn RngCore) -> Vec3;
+
+    /// The density, per steradian, of light traveling along `incoming` scattering into
+    /// `outgoing`. Both are unit vectors, and [`sample`](Self::sample) picks directions with this
+    /// density.
+    fn value(&self, incoming: Vec3, outgoing: Vec3) -> f64;
+}
+
+/// Scatters into every direction alike.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn sample(&self, _: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::random_unit_vec(rng)
+    }
+
+    fn value(&self, _: Vec3, _: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// The Henyey-Greenstein phase function, which mostly scatters forward for a positive `g` and
+/// back for a negative one. Fog and clouds scatter forward, with a `g` of about 0.8, which makes
+/// lights behind them glow with a halo.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HenyeyGreenstein {
+    /// The average cosine of the scattering angle.
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    /// Fails unless `g` is strictly between -1 and 1.
+    pub fn new(g: f64) -> Result<Self> {
+        let g = Error::check("phase asymmetry", g, |g| g > -1.0 && g < 1.0)?;
+
+        Ok(Self { g })
+    }
+
+    pub fn g(&self) -> f64 {
+        self.g
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn sample(&self, incoming: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let g = self.g;
+        let xi = rng.gen::<f64>();
+        // inverting the cumulative distribution of the cosine
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+
+        let w = incoming;
+        let helper = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let u = w.cross(&helper).normalize();
+        let v = w.cross(&u);
+
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+
+    fn value(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        let g = self.g;
+        let cos_theta = incoming.dot(&outgoing);
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn henyey_greenstein_is_normalized() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(g).unwrap();
+            let incoming = Vec3::new(0.0, 0.0, 1.0);
+
+            // integrating over the cosine of the angle, with the whole circle around it at once
+            let steps = 100_000;
+            let integral: f64 = (0..steps)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / steps as f64;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let outgoing = Vec3::new(sin_theta, 0.0, cos_theta);
+                    2.0 * PI * phase.value(incoming, outgoing) * 2.0 / steps as f64
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g = {}: {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn samples_average_to_g() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let incoming = Vec3::new(1.0, 2.0, -2.0).normalize();
+        for g in [-0.5, 0.0, 0.8] {
+            let phase = HenyeyGreenstein::new(g).unwrap();
+            let samples = 20_000;
+            let mean = (0..samples)
+                .map(|_| {
+                    let outgoing = phase.sample(incoming, &mut rng);
+                    assert!((outgoing.length() - 1.0).abs() < 1e-9);
+                    outgoing.dot(&incoming)
+                })
+                .sum::<f64>()
+                / samples as f64;
+            assert!((mean - g).abs() < 0.02, "g = {}: {}", g, mean);
+        }
+
+        assert!(HenyeyGreenstein::new(1.0).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+//! Participating media like smoke and clouds, which rays scatter inside of rather than off of.
+
+use super::bvh::Aabb;
+use super::{Hit, Hittable, Material, Ray};
+use crate::illuminant;
+use crate::memory::MemoryUsage;
+use crate::noise::Perlin;
+use crate::{Color, Error, Point3, Result, Vec3};
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+use std::sync::Arc;
+
+/// A density that varies over space.
+pub trait DensityField: Send + Sync {
+    /// The density at `point`, never negative.
+    fn density(&self, point: Point3) -> f64;
+
+    /// An upper bound of the density anywhere in the field.
+    fn max_density(&self) -> f64;
+
+    /// A box outside of which the density is zero.
+    fn bounds(&self) -> Aabb;
+
+    /// An estimate of the memory the field takes up, see [`Hittable::memory`].
+    fn memory(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+/// A density given by a function of position, inside of a box.
+pub struct FnDensity<F> {
+    function: F,
+    max_density: f64,
+    bounds: Aabb,
+}
+
+impl<F: Fn(Point3) -> f64 + Send + Sync> FnDensity<F> {
+    /// The density is `function` clamped between 0 and `max_density`, which fails if it's
+    /// negative or not finite. The tighter `max_density` is, the faster the medium renders.
+    pub fn new(bounds: Aabb, max_density: f64, function: F) -> Result<Self> {
+        let max_density = Error::check("max density", max_density, |max| max >= 0.0)?;
+
+        Ok(Self {
+            function,
+            max_density,
+            bounds,
+        })
+    }
+}
+
+impl<F: Fn(Point3) -> f64 + Send + Sync> DensityField for FnDensity<F> {
+    fn density(&self, point: Point3) -> f64 {
+        let density = (self.function)(point);
+        // NaN becomes 0
+        density.max(0.0).min(self.max_density)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+/// Wispy density from Perlin turbulence filling a box, between 0 and 1. It fades out over the
+/// outer fifth of the box on every side, so the box's shape doesn't show.
+#[derive(Clone)]
+pub struct NoiseDensity {
+    noise: Perlin,
+    /// How many noise features fit in a unit of distance.
+    frequency: f64,
+    octaves: u32,
+    bounds: Aabb,
+}
+
+impl NoiseDensity {
+    /// Fails unless `frequency` is positive and `octaves` between 1 and 16.
+    pub fn new(bounds: Aabb, frequency: f64, octaves: u32, seed: u64) -> Result<Self> {
+        let frequency = Error::check("noise frequency", frequency, |f| f > 0.0)?;
+        Error::check("noise octaves", octaves as f64, |o| {
+            (1.0..=16.0).contains(&o)
+        })?;
+
+        Ok(Self {
+            noise: Perlin::new(seed),
+            frequency,
+            octaves,
+            bounds,
+        })
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, point: Point3) -> f64 {
+        let mut falloff = 1.0;
+        for axis in 0..3 {
+            let (min, max) = (self.bounds.min[axis], self.bounds.max[axis]);
+            let edge = (point[axis] - min).min(max - point[axis]) / (0.2 * (max - min));
+            let t = edge.clamp(0.0, 1.0);
+            falloff *= t * t * (3.0 - 2.0 * t);
+        }
+
+        // turbulence stays below 2
+        falloff * 0.5 * self.noise.turbulence(point * self.frequency, self.octaves)
+    }
+
+    fn max_density(&self) -> f64 {
+        1.0
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+/// A medium whose density varies from point to point, with rays scattering at a random distance
+/// found by delta (or Woodcock) tracking.
+///
+/// Delta tracking steps through the medium as if it had its maximum density everywhere, and
+/// keeps each of those collisions with the probability that it's real. That's unbiased however
+/// quickly the density changes, without marching in fixed steps.
+#[derive(Clone)]
+pub struct HeterogeneousMedium {
+    field: Arc<dyn DensityField>,
+    /// Multiplies the density of the field, to make it thicker or thinner.
+    density_scale: f64,
+    /// The material scattered rays get, usually [`Isotropic`](super::materials::Isotropic).
+    pub phase_function: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    /// Fails if the density scale is negative or not finite.
+    pub fn new(
+        field: Arc<dyn DensityField>,
+        density_scale: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Result<Self> {
+        let density_scale = Error::check("density scale", density_scale, |scale| scale >= 0.0)?;
+
+        Ok(Self {
+            field,
+            density_scale,
+            phase_function,
+        })
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t_enter, t_exit) = self.field.bounds().intersect(ray, t_min, t_max)?;
+        let majorant = self.field.max_density() * self.density_scale;
+        if majorant <= 0.0 || !majorant.is_finite() {
+            return None;
+        }
+
+        // densities are per world unit, t is in units of the ray's direction
+        let speed = ray.direction.length();
+        let mut rng = ray_rng(ray);
+        let mut t = t_enter;
+        loop {
+            t -= (1.0 - rng.gen::<f64>()).ln() / (majorant * speed);
+            if t >= t_exit {
+                return None;
+            }
+
+            let point = ray.at(t);
+            let density = self.field.density(point) * self.density_scale;
+            if rng.gen::<f64>() * majorant < density {
+                return Some(Hit {
+                    point,
+                    // scattering inside a medium doesn't depend on the normal
+                    normal: Vec3::new(1.0, 0.0, 0.0),
+                    t,
+                    front_face: true,
+                    material: self.phase_function.clone(),
+                    u: 0.0,
+                    v: 0.0,
+                    uv_density: 0.0,
+                    footprint: 0.0,
+                    tangent: Vec3::default(),
+                    object: 0,
+                });
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.field.bounds())
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self);
+        if usage.first_visit(Arc::as_ptr(&self.field)) {
+            usage.geometry += self.field.memory();
+        }
+    }
+}
+
+/// A material that glows with the color of a blackbody, at a temperature that varies over space,
+/// for fire and hot gas. Every collision inside the medium adds the light of the temperature
+/// there, so denser parts glow brighter until the medium turns opaque.
+pub struct Blackbody {
+    /// How the medium scatters light, usually [`Isotropic`](super::materials::Isotropic).
+    pub phase_function: Arc<dyn Material>,
+    /// The field to read the temperature from, in `temperature_scale` kelvin per unit.
+    temperature: Arc<dyn DensityField>,
+    temperature_scale: f64,
+    intensity: f64,
+    /// Colors every [`TABLE_STEP`] kelvin, from 0 to the hottest the table covers.
+    colors: Vec<Color>,
+}
+
+/// The temperature difference between the entries of the color table, in kelvin.
+const TABLE_STEP: f64 = 50.0;
+/// Above this, the color of a blackbody barely changes anymore.
+const TABLE_MAX: f64 = 40_000.0;
+
+impl Blackbody {
+    /// Fails if the temperature scale or intensity is negative or not finite.
+    pub fn new(
+        phase_function: Arc<dyn Material>,
+        temperature: Arc<dyn DensityField>,
+        temperature_scale: f64,
+        intensity: f64,
+    ) -> Result<Self> {
+        let temperature_scale =
+            Error::check("temperature scale", temperature_scale, |scale| scale >= 0.0)?;
+        let intensity = Error::check("emission intensity", intensity, |i| i >= 0.0)?;
+        let colors = (0..=(TABLE_MAX / TABLE_STEP) as usize)
+            .map(|i| blackbody(i as f64 * TABLE_STEP))
+            .collect();
+
+        Ok(Self {
+            phase_function,
+            temperature,
+            temperature_scale,
+            intensity,
+            colors,
+        })
+    }
+}
+
+impl Material for Blackbody {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        self.phase_function.scatter(ray, hit, rng)
+    }
+
+    fn reflectance(&self, ray: &Ray, hit: &Hit, light: Vec3) -> Option<Color> {
+        self.phase_function.reflectance(ray, hit, light)
+    }
+
+    fn emitted(&self, _: &Ray, hit: &Hit) -> Color {
+        let kelvin = self.temperature.density(hit.point) * self.temperature_scale;
+        if kelvin.is_nan() || kelvin <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let position = kelvin.min(TABLE_MAX) / TABLE_STEP;
+        let i = (position as usize).min(self.colors.len() - 2);
+        let fraction = position - i as f64;
+        let color = (1.0 - fraction) * self.colors[i] + fraction * self.colors[i + 1];
+
+        // the Stefan-Boltzmann law, relative to 1000 K
+        color * self.intensity * (kelvin / 1000.0).powi(4)
+    }
+}
+
+/// The color of a blackbody at `kelvin`, in linear sRGB with a luminance of 1. Bodies colder than
+/// 500 K have the color of one at 500 K.
+pub fn blackbody(kelvin: f64) -> Color {
+    let kelvin = kelvin.max(500.0);
+    illuminant::spectrum_color(|nm| illuminant::planck(kelvin, nm))
+}
+
+/// Random numbers for tracking one ray.
+///
+/// [`Hittable::hit`] has no random number generator to use, so one is seeded from the ray
+/// itself. Rays come from the renderer's generator, which keeps seeded renders reproducible.
+fn ray_rng(ray: &Ray) -> StdRng {
+    let mut hash = 0x9e37_79b9_7f4a_7c15_u64;
+    for component in ray.origin.0.iter().chain(&ray.direction.0) {
+        // a round of splitmix64 per component
+        hash = (hash ^ component.to_bits()).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+    }
+
+    StdRng::seed_from_u64(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Isotropic;
+    use crate::Color;
+
+    /// A unit cube, half of it empty.
+    struct HalfCube;
+
+    impl DensityField for HalfCube {
+        fn density(&self, point: Point3) -> f64 {
+            if point.x() < 0.5 {
+                0.0
+            } else {
+                2.0
+            }
+        }
+
+        fn max_density(&self) -> f64 {
+            2.0
+        }
+
+        fn bounds(&self) -> Aabb {
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0))
+        }
+    }
+
+    fn medium() -> HeterogeneousMedium {
+        let phase = Arc::new(Isotropic {
+            albedo: Color::new(1.0, 1.0, 1.0),
+        });
+        HeterogeneousMedium::new(Arc::new(HalfCube), 1.0, phase).unwrap()
+    }
+
+    fn ray(y: f64) -> Ray {
+        Ray {
+            origin: Point3::new(-1.0, y, 0.5),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn scatters_only_where_there_is_density() {
+        let medium = medium();
+
+        let mut hits = 0;
+        for i in 0..1000 {
+            let ray = ray(i as f64 / 1000.0);
+            if let Some(hit) = medium.hit(&ray, 0.0, f64::INFINITY) {
+                assert!((0.5..1.0).contains(&hit.point.x()));
+                hits += 1;
+            }
+        }
+
+        // the rays cross half a unit at a density of 2, so 1 - e^-1 of them scatter
+        let expected = 1000.0 * (1.0 - (-1.0f64).exp());
+        assert!((hits as f64 - expected).abs() < 60.0, "{} hits", hits);
+    }
+
+    #[test]
+    fn hits_are_reproducible() {
+        let medium = medium();
+        for i in 0..100 {
+            let ray = ray(i as f64 / 100.0);
+            let first = medium.hit(&ray, 0.0, f64::INFINITY).map(|hit| hit.t);
+            let second = medium.hit(&ray, 0.0, f64::INFINITY).map(|hit| hit.t);
+            assert_eq!(first, second);
+        }
+    }
+
+    #[test]
+    fn closures_are_clamped() {
+        let bounds = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let field = FnDensity::new(bounds, 1.0, |point: Point3| 3.0 * point.x() - 1.0).unwrap();
+        assert_eq!(field.density(Point3::new(0.0, 0.5, 0.5)), 0.0);
+        assert_eq!(field.density(Point3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(field.density(Point3::new(1.0, 0.5, 0.5)), 1.0);
+
+        let nan = FnDensity::new(bounds, 1.0, |_| f64::NAN).unwrap();
+        assert_eq!(nan.density(Point3::new(0.5, 0.5, 0.5)), 0.0);
+        assert!(FnDensity::new(bounds, -1.0, |_| 0.0).is_err());
+    }
+
+    #[test]
+    fn blackbodies_go_from_red_to_blue() {
+        let ember = blackbody(1500.0);
+        assert!(ember.x() > ember.y() && ember.y() > ember.z());
+
+        // about the white point of sRGB
+        let daylight = blackbody(6500.0);
+        for component in daylight.0 {
+            assert!((component - 1.0).abs() < 0.1, "{}", daylight);
+        }
+
+        let sky = blackbody(20_000.0);
+        assert!(sky.z() > sky.y() && sky.y() > sky.x());
+    }
+
+    #[test]
+    fn glows_with_the_temperature() {
+        let phase = Arc::new(Isotropic {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        // 2 times 1000 K in the dense half
+        let material = Blackbody::new(phase, Arc::new(HalfCube), 1000.0, 1.0).unwrap();
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.5, 0.5),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        };
+        let hit = |x: f64| Hit {
+            point: Point3::new(x, 0.5, 0.5),
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            t: 1.0,
+            front_face: true,
+            material: medium().phase_function,
+            u: 0.0,
+            v: 0.0,
+            uv_density: 0.0,
+            footprint: 0.0,
+            tangent: Vec3::default(),
+            object: 0,
+        };
+
+        assert_eq!(
+            material.emitted(&ray, &hit(0.25)),
+            Color::new(0.0, 0.0, 0.0)
+        );
+        let glow = material.emitted(&ray, &hit(0.75));
+        let expected = blackbody(2000.0) * 16.0;
+        assert!((glow - expected).length() < 1e-9, "{} {}", glow, expected);
+    }
+
+    #[test]
+    fn respects_the_range() {
+        let medium = medium();
+        for i in 0..100 {
+            let ray = ray(i as f64 / 100.0);
+            assert!(medium.hit(&ray, 0.0, 1.5).is_none());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Grids of cubes, traced cell by cell instead of as one object per cube.
+
+use super::bvh::Aabb;
+use super::{Hit, Hittable, Material, Ray};
+use crate::memory::MemoryUsage;
+use crate::{Error, Point3, Result, Vec3};
+
+use std::sync::Arc;
+
+/// The width of the cubes of voxels that are stored, or left out when they're empty.
+const BRICK_SIZE: usize = 8;
+const EMPTY_BRICK: u32 = u32::MAX;
+
+/// A box of voxels, each either empty or filled with one of up to 255 materials.
+///
+/// Storage is sparse, so mostly empty grids are cheap, and rays step through the cells in order
+/// so that they only test the voxels they pass through.
+#[derive(Clone)]
+pub struct VoxelGrid {
+    corner: Point3,
+    voxel_size: f64,
+    dims: [usize; 3],
+    /// An index into `bricks` for every brick of the grid, or [`EMPTY_BRICK`].
+    brick_indices: Vec<u32>,
+    bricks: Vec<[u8; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE]>,
+    /// The material of each voxel value, starting from 1.
+    materials: Vec<Arc<dyn Material>>,
+}
+
+impl VoxelGrid {
+    /// An empty grid of `dims` voxels, extending along the positive axes from `corner`.
+    ///
+    /// Fails if the corner isn't finite, the voxel size isn't positive, or the grid has no
+    /// voxels or too many to index.
+    pub fn new(
+        corner: Point3,
+        voxel_size: f64,
+        dims: [u32; 3],
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Result<Self> {
+        for coordinate in corner.0 {
+            Error::check("voxel grid corner", coordinate, |_| true)?;
+        }
+        let voxel_size = Error::check("voxel size", voxel_size, |size| size > 0.0)?;
+        for dim in dims {
+            Error::check("voxel grid size", dim as f64, |dim| dim > 0.0)?;
+        }
+
+        let dims = dims.map(|dim| dim as usize);
+        let bricks = dims
+            .iter()
+            .try_fold(1usize, |bricks, dim| {
+                bricks.checked_mul(dim.div_ceil(BRICK_SIZE))
+            })
+            .filter(|&bricks| bricks < EMPTY_BRICK as usize)
+            .ok_or(Error::InvalidParameter {
+                name: "voxel grid size",
+                value: dims.iter().map(|&dim| dim as f64).product(),
+            })?;
+
+        Ok(Self {
+            corner,
+            voxel_size,
+            dims,
+            brick_indices: vec![EMPTY_BRICK; bricks],
+            bricks: Vec::new(),
+            materials,
+        })
+    }
+
+    /// The value of the voxel at `position`, 0 if it's empty.
+    pub fn get(&self, position: [u32; 3]) -> u8 {
+        let [x, y, z] = position.map(|p| p as usize);
+        let (brick, cell) = self.locate(x, y, z);
+        match self.brick_indices[brick] {
+            EMPTY_BRICK => 0,
+            index => self.bricks[index as usize][cell],
+        }
+    }
+
+    /// Fills the voxel at `position` with `materials[value - 1]`, or empties it if `value` is 0.
+    ///
+    /// Panics if the position is outside of the grid or there's no such material.
+    pub fn set(&mut self, position: [u32; 3], value: u8) {
+        assert!(
+            value as usize <= self.materials.len(),
+            "no material for voxel value {}",
+            value
+        );
+        let [x, y, z] = position.map(|p| p as usize);
+        let (brick, cell) = self.locate(x, y, z);
+
+        if self.brick_indices[brick] == EMPTY_BRICK {
+            if value == 0 {
+                return;
+            }
+            self.brick_indices[brick] = self.bricks.len() as u32;
+            self.bricks.push([0; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE]);
+        }
+        self.bricks[self.brick_indices[brick] as usize][cell] = value;
+    }
+
+    /// The index of the brick a voxel is in, and of the voxel within the brick.
+    fn locate(&self, x: usize, y: usize, z: usize) -> (usize, usize) {
+        let [width, height, depth] = self.dims;
+        assert!(
+            x < width && y < height && z < depth,
+            "voxel ({}, {}, {}) is outside of the grid",
+            x,
+            y,
+            z
+        );
+
+        let bricks_x = width.div_ceil(BRICK_SIZE);
+        let bricks_y = height.div_ceil(BRICK_SIZE);
+        let brick = x / BRICK_SIZE + bricks_x * (y / BRICK_SIZE + bricks_y * (z / BRICK_SIZE));
+        let (x, y, z) = (x % BRICK_SIZE, y % BRICK_SIZE, z % BRICK_SIZE);
+
+        (brick, x + BRICK_SIZE * (y + BRICK_SIZE * z))
+    }
+
+    fn contains(&self, cell: [i64; 3]) -> bool {
+        (0..3).all(|axis| (0..self.dims[axis] as i64).contains(&cell[axis]))
+    }
+
+    /// Like [`VoxelGrid::get`], but with everything outside of the grid empty.
+    fn value(&self, cell: [i64; 3]) -> u8 {
+        if self.contains(cell) {
+            self.get(cell.map(|c| c as u32))
+        } else {
+            0
+        }
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64, outward_normal: Vec3, value: u8, axis: usize) -> Hit {
+        let hit = Hit::with_face_normal(
+            ray,
+            outward_normal,
+            t,
+            self.materials[value as usize - 1].clone(),
+        );
+
+        // position on the face of the voxel
+        let local = (hit.point - self.corner) / self.voxel_size;
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        hit.with_uv(local[u].rem_euclid(1.0), local[v].rem_euclid(1.0))
+            .with_uv_density(1.0 / self.voxel_size)
+    }
+}
+
+impl Hittable for VoxelGrid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t_enter, _) = self.bounding_box()?.intersect(ray, t_min, t_max)?;
+        let start = ray.at(t_enter);
+
+        // a 3D DDA, stepping to whichever neighbouring cell the ray reaches first
+        let mut cell = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        // the axis of the face the ray comes in through, if it starts outside the grid
+        let mut entry_axis = 0;
+        let mut entry_t = f64::NEG_INFINITY;
+        for axis in 0..3 {
+            let local = (start[axis] - self.corner[axis]) / self.voxel_size;
+            cell[axis] = (local.floor() as i64).clamp(0, self.dims[axis] as i64 - 1);
+
+            let direction = ray.direction[axis];
+            if direction == 0.0 {
+                continue;
+            }
+            step[axis] = if direction > 0.0 { 1 } else { -1 };
+            let next_boundary = cell[axis] + (direction > 0.0) as i64;
+            let boundary = self.corner[axis] + next_boundary as f64 * self.voxel_size;
+            t_next[axis] = (boundary - ray.origin[axis]) / direction;
+            t_delta[axis] = self.voxel_size / direction.abs();
+
+            let face = if direction > 0.0 {
+                self.corner[axis]
+            } else {
+                self.corner[axis] + self.dims[axis] as f64 * self.voxel_size
+            };
+            let t_face = (face - ray.origin[axis]) / direction;
+            if t_face > entry_t {
+                entry_t = t_face;
+                entry_axis = axis;
+            }
+        }
+
+        let mut current = self.value(cell);
+        if t_enter > t_min && current != 0 {
+            let mut normal = Vec3::default();
+            normal[entry_axis] = -step[entry_axis] as f64;
+            return Some(self.hit_at(ray, t_enter, normal, current, entry_axis));
+        }
+
+        loop {
+            let axis = (0..3)
+                .min_by(|&a, &b| t_next[a].total_cmp(&t_next[b]))
+                .unwrap();
+            let t = t_next[axis];
+            if t >= t_max {
+                return None;
+            }
+
+            cell[axis] += step[axis];
+            let next = self.value(cell);
+            if next != current && t >= t_min {
+                // the normal points out of whichever voxel is filled, into the other one
+                let mut normal = Vec3::default();
+                normal[axis] = step[axis] as f64;
+                return Some(if next != 0 {
+                    self.hit_at(ray, t, -normal, next, axis)
+                } else {
+                    self.hit_at(ray, t, normal, current, axis)
+                });
+            }
+
+            if !self.contains(cell) {
+                return None;
+            }
+            current = next;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3::new(
+            self.dims[0] as f64,
+            self.dims[1] as f64,
+            self.dims[2] as f64,
+        ) * self.voxel_size;
+
+        Some(Aabb::new(self.corner, self.corner + extent))
+    }
+
+    fn memory(&self, usage: &mut MemoryUsage) {
+        usage.geometry += std::mem::size_of_val(self)
+            + self.brick_indices.capacity() * std::mem::size_of::<u32>()
+            + self.bricks.capacity()
+                * std::mem::size_of::<[u8; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE]>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::Color;
+
+    fn grid() -> VoxelGrid {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let mut grid = VoxelGrid::new(
+            Point3::new(0.0, 0.0, 0.0),
+            0.5,
+            [20, 4, 4],
+            vec![material.clone(), material],
+        )
+        .unwrap();
+        grid.set([1, 1, 1], 1);
+        grid.set([12, 1, 1], 2);
+        grid
+    }
+
+    fn ray(origin: [f64; 3], direction: [f64; 3]) -> Ray {
+        let [x, y, z] = origin;
+        let [dx, dy, dz] = direction;
+        Ray {
+            origin: Point3::new(x, y, z),
+            direction: Vec3::new(dx, dy, dz),
+        }
+    }
+
+    #[test]
+    fn storage_is_sparse() {
+        let mut grid = grid();
+        assert_eq!(grid.bricks.len(), 2);
+        assert_eq!(grid.get([1, 1, 1]), 1);
+        assert_eq!(grid.get([12, 1, 1]), 2);
+        assert_eq!(grid.get([2, 1, 1]), 0);
+
+        grid.set([19, 3, 3], 0);
+        assert_eq!(grid.bricks.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let corner = Point3::new(0.0, 0.0, 0.0);
+        assert!(VoxelGrid::new(corner, 0.0, [1, 1, 1], Vec::new()).is_err());
+        assert!(VoxelGrid::new(corner, 1.0, [1, 0, 1], Vec::new()).is_err());
+        let huge = [u32::MAX; 3];
+        assert!(VoxelGrid::new(corner, 1.0, huge, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn hits_the_first_filled_voxel() {
+        let grid = grid();
+
+        // in through the side of the grid, past empty cells
+        let along = ray([-1.0, 0.75, 0.75], [1.0, 0.0, 0.0]);
+        let hit = grid.hit(&along, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        // starting past the first voxel, onto the second
+        let hit = grid.hit(&along, 2.5, f64::INFINITY).unwrap();
+        assert!((hit.t - 7.0).abs() < 1e-9);
+
+        // from above, straight onto the top of the voxel
+        let down = ray([0.6, 5.0, 0.7], [0.0, -1.0, 0.0]);
+        let hit = grid.hit(&down, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn misses_empty_space() {
+        let grid = grid();
+
+        let beside = ray([-1.0, 1.75, 0.75], [1.0, 0.0, 0.0]);
+        assert!(grid.hit(&beside, 0.0, f64::INFINITY).is_none());
+        let short = ray([-1.0, 0.75, 0.75], [1.0, 0.0, 0.0]);
+        assert!(grid.hit(&short, 0.0, 1.4).is_none());
+    }
+
+    #[test]
+    fn leaves_filled_voxels_through_their_back_face() {
+        let grid = grid();
+
+        let from_inside = ray([0.75, 0.75, 0.75], [0.0, 0.0, 1.0]);
+        let hit = grid.hit(&from_inside, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 0.25).abs() < 1e-9);
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn filled_voxels_at_the_edge_of_the_grid() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let mut grid =
+            VoxelGrid::new(Point3::new(0.0, 0.0, 0.0), 1.0, [1, 1, 1], vec![material]).unwrap();
+        grid.set([0, 0, 0], 1);
+
+        let from_inside = ray([0.5, 0.5, 0.5], [1.0, 0.0, 0.0]);
+        let hit = grid.hit(&from_inside, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-9);
+        assert!(!hit.front_face);
+    }
+}
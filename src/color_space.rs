@@ -0,0 +1,213 @@
+//! The color spaces images are read and written in.
+//!
+//! The renderer works in linear light with the primaries of sRGB and Rec.709, which scene files
+//! give their colors in. Textures in other spaces are converted into it when they're loaded,
+//! and saved images out of it, so renders fit into pipelines working in wider gamuts. A space
+//! is its primaries and white point, which say what its red, green, blue and white are, along
+//! with the curve its values are encoded with.
+
+use crate::{srgb, Color};
+
+use nalgebra::{Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// The space of most images and screens, and the default.
+    #[default]
+    Srgb,
+    /// HD video, with the primaries of sRGB but the camera curve of Rec.709.
+    Rec709,
+    /// The wider gamut of recent Apple screens, with the sRGB curve.
+    DisplayP3,
+    /// The linear working space of the ACES pipeline, with a gamut covering nearly every color
+    /// that can be seen.
+    #[serde(rename = "acescg")]
+    AcesCg,
+    /// Linear light with the primaries of sRGB, which is what the renderer works in. Data like
+    /// normal, roughness and metallic maps is read in it too, which leaves it as it is.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Encodes a linear value of this space with its curve. Values are clamped into [0, 1] for
+    /// every space but the linear ones, which are left as they are.
+    pub fn encode(self, linear: f64) -> f64 {
+        match self {
+            Self::Srgb | Self::DisplayP3 => srgb::encode(linear.clamp(0.0, 1.0)),
+            Self::Rec709 => {
+                let linear = linear.clamp(0.0, 1.0);
+                if linear < REC709_BETA {
+                    4.5 * linear
+                } else {
+                    REC709_ALPHA * linear.powf(0.45) - (REC709_ALPHA - 1.0)
+                }
+            }
+            Self::AcesCg | Self::Linear => linear,
+        }
+    }
+
+    /// Undoes [`ColorSpace::encode`].
+    pub fn decode(self, encoded: f64) -> f64 {
+        match self {
+            Self::Srgb | Self::DisplayP3 => srgb::decode(encoded),
+            Self::Rec709 => {
+                if encoded < 4.5 * REC709_BETA {
+                    encoded / 4.5
+                } else {
+                    ((encoded + REC709_ALPHA - 1.0) / REC709_ALPHA).powf(1.0 / 0.45)
+                }
+            }
+            Self::AcesCg | Self::Linear => encoded,
+        }
+    }
+
+    /// The CIE xy chromaticities of the red, green and blue primaries, and of white.
+    pub fn chromaticities(self) -> [[f64; 2]; 4] {
+        match self {
+            Self::Srgb | Self::Rec709 | Self::Linear => {
+                [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06], D65]
+            }
+            Self::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060], D65],
+            Self::AcesCg => [
+                [0.713, 0.293],
+                [0.165, 0.830],
+                [0.128, 0.044],
+                [0.32168, 0.33767],
+            ],
+        }
+    }
+
+    /// The matrix from linear values of this space to CIE XYZ.
+    fn to_xyz(self) -> Matrix3<f64> {
+        let [red, green, blue, white] = self.chromaticities();
+        let primaries = Matrix3::from_columns(&[xyz(red), xyz(green), xyz(blue)]);
+        // scaled so that 1 in every channel is the white
+        let scale = primaries
+            .try_inverse()
+            .expect("the primaries are independent")
+            * xyz(white);
+        primaries * Matrix3::from_diagonal(&scale)
+    }
+}
+
+/// The constants of the Rec.709 curve, usually rounded to 1.099 and 0.018, to more digits so
+/// that its two pieces meet.
+const REC709_ALPHA: f64 = 1.099_296_826_809_44;
+const REC709_BETA: f64 = 0.018_053_968_510_807;
+
+/// The white point of sRGB, Rec.709 and Display P3.
+const D65: [f64; 2] = [0.3127, 0.3290];
+
+/// The color with chromaticity `[x, y]` and a luminance of 1.
+fn xyz([x, y]: [f64; 2]) -> Vector3<f64> {
+    Vector3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+/// The cone responses of the Bradford chromatic adaptation transform.
+#[rustfmt::skip]
+fn bradford() -> Matrix3<f64> {
+    Matrix3::new(
+        0.8951, 0.2664, -0.1614,
+        -0.7502, 1.7135, 0.0367,
+        0.0389, -0.0685, 1.0296,
+    )
+}
+
+/// Converts linear light from one color space to another. Spaces with different white points
+/// are adapted to each other's white the way the eye does, with the Bradford transform, so
+/// white stays white.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Conversion {
+    matrix: Matrix3<f64>,
+}
+
+impl Conversion {
+    pub fn new(from: ColorSpace, to: ColorSpace) -> Self {
+        let white = |space: ColorSpace| xyz(space.chromaticities()[3]);
+        let cones = bradford();
+        let (source, target) = (cones * white(from), cones * white(to));
+        let adaptation = cones.try_inverse().expect("the transform is invertible")
+            * Matrix3::from_diagonal(&target.component_div(&source))
+            * cones;
+
+        let to_rgb = to
+            .to_xyz()
+            .try_inverse()
+            .expect("the primaries are independent");
+        Self {
+            matrix: to_rgb * adaptation * from.to_xyz(),
+        }
+    }
+
+    /// From the space the renderer works in.
+    pub fn from_working(to: ColorSpace) -> Self {
+        Self::new(ColorSpace::Linear, to)
+    }
+
+    /// Into the space the renderer works in.
+    pub fn to_working(from: ColorSpace) -> Self {
+        Self::new(from, ColorSpace::Linear)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        (self.matrix - Matrix3::identity()).amax() < 1e-9
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        (self.matrix * Vector3::from(color)).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 5] = [
+        ColorSpace::Srgb,
+        ColorSpace::Rec709,
+        ColorSpace::DisplayP3,
+        ColorSpace::AcesCg,
+        ColorSpace::Linear,
+    ];
+
+    #[test]
+    fn white_stays_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for from in SPACES {
+            for to in SPACES {
+                let converted = Conversion::new(from, to).apply(white);
+                assert!((converted - white).length() < 1e-9, "{:?} {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_to_acescg() {
+        // the matrix in the ACES documentation, adapted with Bradford
+        let conversion = Conversion::from_working(ColorSpace::AcesCg);
+        let red = conversion.apply(Color::new(1.0, 0.0, 0.0));
+        assert!(
+            (red - Color::new(0.6131, 0.0702, 0.0206)).length() < 1e-3,
+            "{}",
+            red
+        );
+        assert!(Conversion::from_working(ColorSpace::Srgb).is_identity());
+        assert!(!conversion.is_identity());
+
+        // a pure green of a wider gamut lies outside of sRGB
+        let green = Conversion::to_working(ColorSpace::DisplayP3).apply(Color::new(0.0, 1.0, 0.0));
+        assert!(green.x() < 0.0 && green.z() < 0.0);
+    }
+
+    #[test]
+    fn decoding_undoes_encoding() {
+        for space in SPACES {
+            for i in 0..=100 {
+                let linear = i as f64 / 100.0;
+                assert!((space.decode(space.encode(linear)) - linear).abs() < 1e-9);
+            }
+        }
+    }
+}
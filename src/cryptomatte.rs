@@ -0,0 +1,308 @@
+//! ID passes in the Cryptomatte format of Friedman and Jones, "Fully Automatic ID Mattes with
+//! Support for Motion Blur and Transparency", which compositors use to pick out single objects
+//! and materials of a finished render.
+//!
+//! Every pixel keeps the few IDs covering most of it, each with how much of the pixel it covers.
+//! An ID is a hash of the name of the object or material, stored as a float, and the names are
+//! listed in a manifest so compositors can show them.
+
+use crate::collision::{Hit, Hittable, Material};
+use crate::filter::{FilterSample, MIN_TOTAL_WEIGHT};
+use crate::render::{self, RenderSettings};
+use crate::Camera;
+
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+#[cfg(feature = "parallel")]
+use rayon::slice::ParallelSliceMut;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// How many IDs each pixel keeps, the ones covering most of it first. Cryptomatte stores them in
+/// pairs, two to a set of RGBA channels.
+pub const RANKS: usize = 6;
+/// Enough samples to tell how much of a pixel each ID covers, however many the render takes.
+const MAX_SAMPLES: u32 = 64;
+
+/// The names of the labeled objects and of the materials, which the IDs are made from.
+#[derive(Clone, Debug, Default)]
+pub struct Names {
+    objects: Vec<String>,
+    /// By the address of the material.
+    materials: HashMap<usize, String>,
+}
+
+impl Names {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names the next object, returning the label to give it with
+    /// [`Labeled`](crate::collision::objects::Labeled).
+    pub fn add_object(&mut self, name: impl Into<String>) -> u32 {
+        self.objects.push(name.into());
+        self.objects.len() as u32
+    }
+
+    pub fn add_material(&mut self, material: &Arc<dyn Material>, name: impl Into<String>) {
+        self.materials.insert(address(material), name.into());
+    }
+
+    pub(crate) fn object(&self, hit: &Hit) -> Option<&str> {
+        let index = (hit.object as usize).checked_sub(1)?;
+        self.objects.get(index).map(String::as_str)
+    }
+
+    pub(crate) fn material(&self, hit: &Hit) -> Option<&str> {
+        self.materials
+            .get(&address(&hit.material))
+            .map(String::as_str)
+    }
+}
+
+fn address(material: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const () as usize
+}
+
+/// One kind of ID over the whole image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    /// The name the channels start with, like `CryptoObject`.
+    pub name: String,
+    /// Every name seen, with its ID.
+    pub manifest: BTreeMap<String, f32>,
+    /// For every pixel, the IDs covering most of it and how much they cover, in the same order as
+    /// the output of [`render::render`]. Unused ranks are zero.
+    pub pixels: Vec<[(f32, f32); RANKS]>,
+}
+
+impl Layer {
+    /// The key the metadata of the layer is stored under.
+    pub fn key(&self) -> String {
+        format!("{:08x}", murmur3(self.name.as_bytes()))[..7].to_owned()
+    }
+
+    /// The manifest as the JSON object Cryptomatte expects, with the IDs in hexadecimal.
+    pub fn manifest_json(&self) -> String {
+        let manifest: BTreeMap<&str, String> = self
+            .manifest
+            .iter()
+            .map(|(name, id)| (name.as_str(), format!("{:08x}", id.to_bits())))
+            .collect();
+        serde_json::to_string(&manifest).expect("strings always serialize")
+    }
+}
+
+/// The ID of `name`: its MurmurHash3 as the bits of a float, nudged away from infinities, NaNs
+/// and denormals.
+pub fn id(name: &str) -> f32 {
+    let mut hash = murmur3(name.as_bytes());
+    let exponent = hash >> 23 & 255;
+    if exponent == 0 || exponent == 255 {
+        hash ^= 1 << 23;
+    }
+
+    f32::from_bits(hash)
+}
+
+/// The 32-bit MurmurHash3 of Austin Appleby, with a seed of 0.
+fn murmur3(bytes: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let scramble = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash = 0u32;
+    let mut blocks = bytes.chunks_exact(4);
+    for block in &mut blocks {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        hash ^= scramble(k);
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0, |k, &byte| k << 8 | byte as u32);
+        hash ^= scramble(k);
+    }
+
+    hash ^= bytes.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ hash >> 16
+}
+
+/// Renders the object and material IDs of what the camera sees first in each pixel, with the
+/// same pixel filter as the image.
+pub fn render<T>(world: &T, camera: &Camera, settings: &RenderSettings, names: &Names) -> [Layer; 2]
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let mut pixels = vec![[Coverage::default(), Coverage::default()]; width * height];
+
+    render::with_threads(&settings.threads, || {
+        #[cfg(feature = "parallel")]
+        let rows = pixels.par_chunks_mut(width);
+        #[cfg(not(feature = "parallel"))]
+        let rows = pixels.chunks_mut(width);
+        rows.enumerate().for_each(|(row, line)| {
+            let mut rng = render::row_rng(settings.seed, row);
+            for (col, [objects, materials]) in line.iter_mut().enumerate() {
+                let samples = settings.samples_per_pixel.clamp(1, MAX_SAMPLES);
+                let mut total_weight = 0.0;
+                // the same without the weights, for when they cancel out
+                let mut unweighted = [Coverage::default(), Coverage::default()];
+                for _ in 0..samples {
+                    let FilterSample { offset, weight } = settings.filter.sample(&mut rng);
+                    let u = (col as f64 + 0.5 + offset.0) / (settings.width as f64 - 1.0);
+                    let v = (row as f64 + 0.5 + offset.1) / (settings.height as f64 - 1.0);
+                    total_weight += weight;
+
+                    let ray = camera.get_ray(u, v, &mut rng);
+                    if let Some(hit) = world.hit(&ray, 0.001, f64::INFINITY) {
+                        let (object, material) = (names.object(&hit), names.material(&hit));
+                        objects.add(object, weight);
+                        materials.add(material, weight);
+                        unweighted[0].add(object, 1.0);
+                        unweighted[1].add(material, 1.0);
+                    }
+                }
+                if total_weight.abs() < MIN_TOTAL_WEIGHT {
+                    [*objects, *materials] = unweighted;
+                    total_weight = samples as f64;
+                }
+                objects.scale(1.0 / total_weight);
+                materials.scale(1.0 / total_weight);
+            }
+        });
+    });
+
+    let layer = |name: &str, which: usize| {
+        let mut manifest = BTreeMap::new();
+        let pixels = pixels
+            .iter()
+            .map(|coverages| {
+                let coverage = &coverages[which];
+                for name in coverage.0.keys() {
+                    manifest.insert(name.to_string(), id(name));
+                }
+                coverage.ranked()
+            })
+            .collect();
+
+        Layer {
+            name: name.to_owned(),
+            manifest,
+            pixels,
+        }
+    };
+
+    [layer("CryptoObject", 0), layer("CryptoMaterial", 1)]
+}
+
+/// How much of a pixel every name covers.
+#[derive(Clone, Debug, Default)]
+struct Coverage<'a>(HashMap<&'a str, f64>);
+
+impl<'a> Coverage<'a> {
+    fn add(&mut self, name: Option<&'a str>, weight: f64) {
+        if let Some(name) = name {
+            *self.0.entry(name).or_default() += weight;
+        }
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.0.values_mut().for_each(|coverage| *coverage *= factor);
+    }
+
+    /// The names covering most of the pixel as IDs, with ties broken by name so that the order
+    /// doesn't depend on the hash map.
+    fn ranked(&self) -> [(f32, f32); RANKS] {
+        let mut coverages: Vec<_> = self.0.iter().collect();
+        coverages.sort_by(|a, b| b.1.total_cmp(a.1).then(a.0.cmp(b.0)));
+
+        let mut ranked = [(0.0, 0.0); RANKS];
+        for (rank, (name, coverage)) in ranked.iter_mut().zip(coverages) {
+            *rank = (id(name), *coverage as f32);
+        }
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::objects::{Labeled, Sphere};
+    use crate::filter::MitchellFilter;
+    use crate::{Color, Point3};
+
+    #[test]
+    fn hashes_match_murmur3() {
+        assert_eq!(murmur3(b""), 0);
+        assert_eq!(murmur3(b"hello"), 0x248b_fa47);
+        assert_eq!(murmur3(b"Hello, world!"), 0xc036_3e43);
+
+        for name in ["", "hello", "ground", "glass ball"] {
+            let id = id(name);
+            assert!(id.is_finite() && !id.is_subnormal());
+        }
+    }
+
+    #[test]
+    fn pixels_split_between_objects() {
+        let gray: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let mut names = Names::new();
+        names.add_material(&gray, "gray");
+        let sphere = |x: f64| Sphere::new(Point3::new(x, 0.0, -1.0), 0.5, gray.clone()).unwrap();
+        let world: Vec<Labeled> = ["left", "right"]
+            .iter()
+            .zip([-0.5, 0.5])
+            .map(|(name, x)| Labeled {
+                object: Arc::new(sphere(x)),
+                label: names.add_object(*name),
+            })
+            .collect();
+
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+        let settings = RenderSettings {
+            width: 8,
+            height: 4,
+            samples_per_pixel: 64,
+            seed: Some(0),
+            ..Default::default()
+        };
+        let [objects, materials] = render(&world, &camera, &settings, &names);
+        assert_eq!(objects.manifest.len(), 2);
+        assert_eq!(materials.manifest.keys().collect::<Vec<_>>(), ["gray"]);
+        assert_eq!(objects.key().len(), 7);
+
+        // the middle pixel straddles both spheres, and the corners see neither
+        let [first, second, ..] = objects.pixels[8 + 3];
+        assert!(first.1 > 0.3 && second.1 > 0.3);
+        let covered = first.1 + second.1;
+        assert!(covered > 0.8 && covered <= 1.0);
+        assert_eq!(objects.pixels[0], [(0.0, 0.0); RANKS]);
+        let (gray, coverage) = materials.pixels[8 + 3][0];
+        assert_eq!(gray, id("gray"));
+        assert!((coverage - covered).abs() < 1e-6);
+
+        // with only a couple of samples, Mitchell's negative lobe cancels out the rest
+        let settings = RenderSettings {
+            samples_per_pixel: 2,
+            filter: Arc::new(MitchellFilter::default()),
+            ..settings
+        };
+        let [objects, _] = render(&world, &camera, &settings, &names);
+        for pixel in &objects.pixels {
+            assert!(pixel.iter().all(|(_, coverage)| coverage.is_finite()));
+        }
+    }
+}
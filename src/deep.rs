@@ -0,0 +1,175 @@
+//! Deep images, keeping what each pixel sees at every depth apart instead of averaging it all
+//! into one color. Compositors merge deep renders by depth, so that another render can be put
+//! in between the layers of fog or behind a glass ball of this one.
+//!
+//! The camera rays of a pixel are grouped by the depth they first hit something at, and each
+//! group becomes a sample covering as much of the pixel as its rays did. Volumes, where every
+//! ray stops somewhere else, spread out over many samples.
+
+use crate::camera;
+use crate::collision::Hittable;
+use crate::filter::FilterSample;
+use crate::render::{self, RenderMode, RenderSettings};
+use crate::{Camera, Color};
+
+use rand::Rng;
+
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+#[cfg(feature = "parallel")]
+use rayon::slice::ParallelSliceMut;
+
+/// Rays hitting things less than this fraction of their depth apart share a sample.
+const DEPTH_TOLERANCE: f64 = 0.01;
+
+/// What a pixel sees at one depth, to be composited over whatever is behind it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeepSample {
+    /// Linear color, premultiplied by `alpha`.
+    pub color: Color,
+    /// How much of what's behind this sample it hides.
+    pub alpha: f64,
+    /// The distance from the camera, which is infinite for the background.
+    pub depth: f64,
+}
+
+/// Flattens the samples of a pixel, front to back, into its color and alpha.
+pub fn flatten(samples: &[DeepSample]) -> (Color, f64) {
+    samples
+        .iter()
+        .fold((Color::default(), 0.0), |(color, alpha), sample| {
+            let transmittance = 1.0 - alpha;
+            (
+                color + transmittance * sample.color,
+                alpha + transmittance * sample.alpha,
+            )
+        })
+}
+
+/// Renders the samples of every pixel, front to back, in the same order as the pixels of
+/// [`render::render`]. Flattened, they give the same image. Only the `Shaded` mode has depths to
+/// keep apart, and light the light tracer splats onto the film is left out.
+pub fn render<T>(world: &T, camera: &Camera, settings: &RenderSettings) -> Vec<Vec<DeepSample>>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let mut pixels = vec![Vec::new(); width * height];
+    if settings.mode != RenderMode::Shaded {
+        return pixels;
+    }
+    render::with_threads(&settings.threads, || {
+        settings.integrator.begin_pass(&world, camera, settings);
+
+        #[cfg(feature = "parallel")]
+        let rows = pixels.par_chunks_mut(width);
+        #[cfg(not(feature = "parallel"))]
+        let rows = pixels.chunks_mut(width);
+        rows.enumerate().for_each(|(row, line)| {
+            let mut rng = render::row_rng(settings.seed, row);
+            let cone = camera.pixel_cone(settings.height);
+            for (col, samples) in line.iter_mut().enumerate() {
+                // the radiance and weight of every ray, by where it stopped
+                let mut rays = Vec::with_capacity(settings.samples_per_pixel as usize);
+                let mut total_weight = 0.0;
+                let first_channel = camera.lens().has_aberration().then(|| rng.gen_range(0..3));
+                for index in 0..settings.samples_per_pixel as usize {
+                    let FilterSample { offset, weight } = settings.filter.sample(&mut rng);
+                    let u = (col as f64 + 0.5 + offset.0) / (settings.width as f64 - 1.0);
+                    let v = (row as f64 + 0.5 + offset.1) / (settings.height as f64 - 1.0);
+                    total_weight += weight;
+
+                    let (ray, channels) = match first_channel {
+                        Some(first) => {
+                            let channel = (first + index) % 3;
+                            let ray = camera.channel_ray(u, v, channel, &mut rng);
+                            (ray, camera::channel_weights(channel))
+                        }
+                        None => (camera.get_ray(u, v, &mut rng), Color::new(1.0, 1.0, 1.0)),
+                    };
+                    let depth = match world.hit(&ray, 0.001, f64::INFINITY) {
+                        Some(hit) => hit.t * ray.direction.length(),
+                        None if settings.transparent_background => continue,
+                        None => f64::INFINITY,
+                    };
+                    let radiance = settings
+                        .integrator
+                        .radiance(&ray, cone, &world, settings, &mut rng);
+                    let radiance = settings
+                        .grade
+                        .apply(camera.vignetting(u, v) * channels * radiance);
+                    rays.push((depth, weight * radiance, weight));
+                }
+
+                *samples = merge(rays, total_weight);
+            }
+        });
+    });
+
+    pixels
+}
+
+/// Groups rays stopping at about the same depth into samples, which composite over each other
+/// to the average of all the rays.
+fn merge(mut rays: Vec<(f64, Color, f64)>, total_weight: f64) -> Vec<DeepSample> {
+    rays.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut groups: Vec<(f64, Color, f64)> = Vec::new();
+    for (depth, color, weight) in rays {
+        match groups.last_mut() {
+            Some(group) if depth <= group.0 * (1.0 + DEPTH_TOLERANCE) => {
+                group.1 += color;
+                group.2 += weight;
+            }
+            _ => groups.push((depth, color, weight)),
+        }
+    }
+
+    // each sample is only seen through the ones in front of it, which the rays behind them
+    // weren't stopped by
+    let mut covered: f64 = 0.0;
+    groups
+        .into_iter()
+        .map(|(depth, color, weight)| {
+            let (color, coverage) = (color / total_weight, weight / total_weight);
+            let transmittance = (1.0 - covered).max(1e-9);
+            covered += coverage;
+            DeepSample {
+                color: color / transmittance,
+                alpha: coverage / transmittance,
+                depth,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_samples_flatten_to_the_average() {
+        let red = Color::new(1.0, 0.0, 0.0);
+        let blue = Color::new(0.0, 0.0, 1.0);
+        let rays = vec![
+            (5.0, blue, 1.0),
+            (2.0, red, 1.0),
+            (2.01, red, 1.0),
+            // weighted by half, like the rays of a tent filter at the edge of the pixel
+            (f64::INFINITY, Color::new(0.25, 0.25, 0.25), 0.5),
+            (f64::INFINITY, Color::new(0.25, 0.25, 0.25), 0.5),
+        ];
+
+        let samples = merge(rays, 4.0);
+        let depths: Vec<f64> = samples.iter().map(|sample| sample.depth).collect();
+        assert_eq!(depths, [2.0, 5.0, f64::INFINITY]);
+        assert_eq!(samples[0].alpha, 0.5);
+        // a quarter of the pixel out of the half that's left
+        assert_eq!(samples[1].alpha, 0.5);
+        assert_eq!(samples[2].alpha, 1.0);
+
+        let (color, alpha) = flatten(&samples);
+        assert!((color - Color::new(0.625, 0.125, 0.375)).length() < 1e-12);
+        assert_eq!(alpha, 1.0);
+    }
+}
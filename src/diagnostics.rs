@@ -0,0 +1,233 @@
+//! Tracking down where bad light comes from: samples that come out NaN or infinite, and single
+//! pixels traced bounce by bounce.
+//!
+//! While diagnostics are on, every bounce of the path being traced is recorded, and once its
+//! sample is done that record is either printed or searched for the bounce that went wrong.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::collision::{Hit, Material};
+use crate::cryptomatte::Names;
+use crate::{Color, Point3};
+
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    /// Log every sample with NaN or infinite light, and where along its path that came from,
+    /// and count it as black so it doesn't spoil its whole pixel.
+    pub check_finite: bool,
+    /// Log every bounce of every sample taken for this pixel, counting rows from the top.
+    pub trace_pixel: Option<(u32, u32)>,
+    /// To name the objects and materials in the logs, rather than just their labels and types.
+    pub names: Option<Arc<Names>>,
+}
+
+impl Diagnostics {
+    pub fn is_on(&self) -> bool {
+        self.check_finite || self.trace_pixel.is_some()
+    }
+
+    /// Whether the bounces of the pixel at `x` and `y`, counting rows from the top, should be
+    /// recorded.
+    pub fn watches(&self, x: u32, y: u32) -> bool {
+        self.check_finite || self.trace_pixel == Some((x, y))
+    }
+}
+
+/// What a path did at one bounce.
+#[derive(Clone, Debug)]
+struct Bounce {
+    /// 0 for camera rays.
+    depth: i32,
+    /// Where it was, or `None` when it escaped to the sky.
+    surface: Option<Surface>,
+    /// The light leaving it toward the previous bounce.
+    light: Color,
+}
+
+/// The surface a path bounced off, for the logs.
+#[derive(Clone, Debug)]
+pub(crate) struct Surface {
+    point: Point3,
+    object: String,
+    material: String,
+}
+
+impl Surface {
+    pub(crate) fn new(diagnostics: &Diagnostics, hit: &Hit, material: &dyn Material) -> Self {
+        Self {
+            point: hit.point,
+            object: object_name(diagnostics, hit),
+            material: material_name(diagnostics, hit, material),
+        }
+    }
+}
+
+thread_local! {
+    /// The bounces of the sample being taken on this thread, or `None` if it isn't watched.
+    static PATH: RefCell<Option<Vec<Bounce>>> = const { RefCell::new(None) };
+}
+
+/// Starts recording the bounces of a sample on this thread.
+pub(crate) fn start() {
+    PATH.with(|path| *path.borrow_mut() = Some(Vec::new()));
+}
+
+/// Whether the sample on this thread is being recorded.
+pub(crate) fn recording() -> bool {
+    PATH.with(|path| path.borrow().is_some())
+}
+
+/// Records the light leaving `surface` at `depth`, or arriving from the sky without one.
+pub(crate) fn record(depth: i32, surface: Option<Surface>, light: Color) {
+    PATH.with(|path| {
+        if let Some(bounces) = path.borrow_mut().as_mut() {
+            bounces.push(Bounce {
+                depth,
+                surface,
+                light,
+            });
+        }
+    });
+}
+
+/// Stops recording, logging the sample taken for the pixel at `x` and `y` as the diagnostics ask
+/// for. Returns whether its `radiance` is fine to add to the pixel.
+pub(crate) fn finish(
+    diagnostics: &Diagnostics,
+    x: u32,
+    y: u32,
+    index: u32,
+    radiance: Color,
+) -> bool {
+    let mut bounces = PATH
+        .with(|path| path.borrow_mut().take())
+        .unwrap_or_default();
+    // the deepest bounces finish first
+    bounces.sort_by_key(|bounce| bounce.depth);
+
+    if diagnostics.trace_pixel == Some((x, y)) {
+        log::info!("Pixel ({}, {}), sample {}: {}", x, y, index, show(radiance));
+        for bounce in &bounces {
+            log::info!("  {}", describe(bounce));
+        }
+    }
+
+    let finite = is_finite(radiance);
+    if diagnostics.check_finite && !finite {
+        match culprit(&bounces) {
+            Some(bounce) => log::warn!(
+                "Pixel ({}, {}), sample {}: {} light, from {}",
+                x,
+                y,
+                index,
+                show(radiance),
+                describe(bounce)
+            ),
+            None => log::warn!(
+                "Pixel ({}, {}), sample {}: {} light",
+                x,
+                y,
+                index,
+                show(radiance)
+            ),
+        }
+    }
+    finite || !diagnostics.check_finite
+}
+
+/// The bounce the light went bad at, out of the bounces sorted by depth. Every one closer to the
+/// camera than that goes bad along with it.
+fn culprit(bounces: &[Bounce]) -> Option<&Bounce> {
+    bounces.iter().rev().find(|bounce| !is_finite(bounce.light))
+}
+
+fn describe(bounce: &Bounce) -> String {
+    match &bounce.surface {
+        Some(surface) => format!(
+            "bounce {}: {} leaving {} at ({:.4}, {:.4}, {:.4}), {}",
+            bounce.depth,
+            show(bounce.light),
+            surface.object,
+            surface.point.x(),
+            surface.point.y(),
+            surface.point.z(),
+            surface.material
+        ),
+        None => format!(
+            "bounce {}: {} from the sky",
+            bounce.depth,
+            show(bounce.light)
+        ),
+    }
+}
+
+fn show(color: Color) -> String {
+    format!("({:.4}, {:.4}, {:.4})", color.x(), color.y(), color.z())
+}
+
+fn is_finite(color: Color) -> bool {
+    color.x().is_finite() && color.y().is_finite() && color.z().is_finite()
+}
+
+fn object_name(diagnostics: &Diagnostics, hit: &Hit) -> String {
+    let name = diagnostics
+        .names
+        .as_ref()
+        .and_then(|names| names.object(hit));
+    match (name, hit.object) {
+        (Some(name), _) => name.to_owned(),
+        (None, 0) => "an unlabeled object".to_owned(),
+        (None, label) => format!("object {}", label),
+    }
+}
+
+fn material_name(diagnostics: &Diagnostics, hit: &Hit, material: &dyn Material) -> String {
+    let name = diagnostics
+        .names
+        .as_ref()
+        .and_then(|names| names.material(hit));
+    match name {
+        Some(name) => format!("material {}", name),
+        None => {
+            let path = material.type_name();
+            format!("a {}", path.rsplit("::").next().unwrap_or(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn culprit_is_the_deepest_bad_bounce() {
+        let diagnostics = Diagnostics {
+            check_finite: true,
+            ..Default::default()
+        };
+        let nan = Color::new(f64::NAN, 0.0, 0.0);
+        start();
+        assert!(recording());
+        record(2, None, Color::new(1.0, 1.0, 1.0));
+        record(1, None, nan);
+        record(0, None, nan);
+        let mut bounces = PATH.with(|path| path.borrow().clone()).unwrap();
+        bounces.sort_by_key(|bounce| bounce.depth);
+        assert_eq!(culprit(&bounces).map(|bounce| bounce.depth), Some(1));
+
+        assert!(!finish(&diagnostics, 3, 4, 0, nan));
+        assert!(!recording());
+    }
+
+    #[test]
+    fn finite_samples_are_kept() {
+        let diagnostics = Diagnostics {
+            check_finite: true,
+            ..Default::default()
+        };
+        start();
+        record(0, None, Color::new(0.5, 0.5, 0.5));
+        assert!(finish(&diagnostics, 3, 4, 0, Color::new(0.5, 0.5, 0.5)));
+    }
+}
@@ -0,0 +1,307 @@
+//! Rendering a frame on several machines.
+//!
+//! Workers started with `--worker <addr>` listen for coordinators, which connect to every worker
+//! given to `--workers`. Messages are JSON, one per line. The coordinator first sends its command
+//! line and the scene, so that the worker sets up the exact same render, and then asks for tiles
+//! one at a time until there are none left. A worker that fails has its tile handed to another.
+//! Workers check scenes the way [`serve`](crate::serve) does, since anyone can connect.
+
+use crate::args::Args;
+use crate::scene_camera_builder;
+use crate::serve::check_scene;
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::film::Film;
+use ray_tracing::render::{self, CancellationToken, Pixel, Region, RenderSettings};
+use ray_tracing::scene::Scene;
+use ray_tracing::Color;
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The size of the square tiles the frame is split into.
+const TILE_SIZE: u32 = 64;
+/// The longest message read, in bytes. Jobs carry the whole scene, so this is generous.
+const MAX_LINE: u64 = 64 << 20;
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    /// Sets up the render for the tiles that follow.
+    Job {
+        args: Vec<String>,
+        scene: Box<Scene>,
+    },
+    Tile(Region),
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    /// The pixels of a tile in the order of [`Region::crop`], each as red, green, blue, alpha and
+    /// the seconds spent on it.
+    Tile(Vec<[f64; 5]>),
+    Error(String),
+}
+
+/// Serves coordinators one at a time, forever.
+pub fn serve(addr: &str) -> ray_tracing::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("Waiting for a coordinator on {}.", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        log::info!("Rendering for {}.", peer);
+        match serve_coordinator(stream) {
+            Ok(tiles) => log::info!("Rendered {} tiles for {}.", tiles, peer),
+            Err(err) => log::error!("Lost {}: {}", peer, err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the tiles one coordinator asks for, returning how many there were.
+fn serve_coordinator(stream: TcpStream) -> Result<u32, Box<dyn Error>> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut output = BufWriter::new(stream);
+
+    let (args, mut scene) = match receive(&mut input)? {
+        Some(Request::Job { args, scene }) => (args, scene),
+        _ => return Err("expected a job".into()),
+    };
+    // anyone can connect, so the scene is checked like the ones sent to `serve`
+    let setup = check_scene(&mut scene).and_then(|()| {
+        let args = Args::parse_from(args)?;
+        let world = Bvh::new(scene.world()?);
+        let camera = scene_camera_builder(&scene, args.projection, &world)?.build()?;
+        let mut settings = args.settings;
+        scene.apply(&mut settings)?;
+        Ok((settings, camera, world))
+    });
+    let (settings, camera, world) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            send(&mut output, &Response::Error(err.to_string()))?;
+            return Err(err);
+        }
+    };
+
+    let mut tiles = 0;
+    while let Some(request) = receive(&mut input)? {
+        let region = match request {
+            Request::Tile(region) => region,
+            Request::Job { .. } => return Err("expected a tile".into()),
+        };
+        let settings = RenderSettings {
+            show_progress: false,
+            ..settings.clone()
+        };
+        let tile = match render::render_region_cancellable(
+            &world,
+            &camera,
+            &settings,
+            region,
+            &CancellationToken::new(),
+        ) {
+            Ok(tile) => tile,
+            Err(err) => {
+                send(&mut output, &Response::Error(err.to_string()))?;
+                return Err(err.into());
+            }
+        };
+        let tile = tile
+            .iter()
+            .map(|pixel| {
+                let [r, g, b] = pixel.color.0;
+                [r, g, b, pixel.alpha, pixel.time.as_secs_f64()]
+            })
+            .collect();
+        send(&mut output, &Response::Tile(tile))?;
+        tiles += 1;
+    }
+
+    Ok(tiles)
+}
+
+/// Renders the frame on `workers`, returning the pixels just like [`render::render`].
+///
+/// Stops handing out tiles once `cancel` is cancelled, leaving the rest of the frame empty.
+pub fn render(
+    workers: &[String],
+    args: &[String],
+    scene: &Scene,
+    settings: &RenderSettings,
+    cancel: &CancellationToken,
+) -> ray_tracing::Result<Vec<Pixel>> {
+    let (width, height) = (settings.width, settings.height);
+    let tiles = tiles(width, height);
+    let total = tiles.len();
+    let queue = Mutex::new(Queue {
+        tiles,
+        in_flight: 0,
+    });
+    let film = Mutex::new(Film::new(width, height));
+    let done = Mutex::new(0);
+    let errors = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for worker in workers {
+            let (queue, film, done, errors) = (&queue, &film, &done, &errors);
+            scope.spawn(move || {
+                let result = render_on(worker, args, scene, queue, cancel, |region, tile| {
+                    let samples = settings.samples_per_pixel;
+                    film.lock().unwrap().merge(region, &tile, samples);
+
+                    let mut done = done.lock().unwrap();
+                    *done += 1;
+                    eprint!("\rTiles remaining: {} ", total - *done);
+                    let _ = io::stderr().flush();
+                });
+                if let Err(err) = result {
+                    log::warn!("Worker {} failed: {}", worker, err);
+                    errors.lock().unwrap().push(err.to_string());
+                }
+            });
+        }
+    });
+    eprintln!();
+
+    let done = done.into_inner().unwrap();
+    if done < total && !cancel.is_cancelled() {
+        let errors = errors.into_inner().unwrap();
+        let message = format!("every worker failed: {}", errors.join("; "));
+        return Err(io::Error::other(message).into());
+    }
+
+    Ok(film.into_inner().unwrap().pixels())
+}
+
+/// The tiles left to render, shared by every worker.
+struct Queue {
+    tiles: Vec<Region>,
+    /// Tiles handed out to a worker that hasn't finished them yet.
+    in_flight: usize,
+}
+
+/// Feeds tiles to one worker until there are none left, putting back the tile it was working on
+/// if it fails.
+fn render_on(
+    worker: &str,
+    args: &[String],
+    scene: &Scene,
+    queue: &Mutex<Queue>,
+    cancel: &CancellationToken,
+    mut finished: impl FnMut(Region, Vec<Pixel>),
+) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect(worker)?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut output = BufWriter::new(stream);
+    send(
+        &mut output,
+        &Request::Job {
+            args: args.to_vec(),
+            scene: Box::new(scene.clone()),
+        },
+    )?;
+
+    while let Some(region) = next_tile(queue, cancel) {
+        let response = send(&mut output, &Request::Tile(region)).and_then(|_| receive(&mut input));
+        let tile = match response {
+            Ok(Some(Response::Tile(tile)))
+                if tile.len() == (region.width() * region.height()) as usize =>
+            {
+                tile
+            }
+            response => {
+                let mut queue = queue.lock().unwrap();
+                queue.tiles.push(region);
+                queue.in_flight -= 1;
+                return match response {
+                    Ok(Some(Response::Error(err))) => Err(err.into()),
+                    Err(err) => Err(err),
+                    _ => Err("unexpected response".into()),
+                };
+            }
+        };
+
+        let tile = tile
+            .into_iter()
+            .map(|[r, g, b, alpha, time]| Pixel {
+                color: Color::new(r, g, b),
+                alpha,
+                time: Duration::from_secs_f64(time),
+            })
+            .collect();
+        finished(region, tile);
+        queue.lock().unwrap().in_flight -= 1;
+    }
+
+    Ok(())
+}
+
+/// Takes the next tile to render, waiting while there are none left but another worker might
+/// still fail and put its tile back.
+fn next_tile(queue: &Mutex<Queue>, cancel: &CancellationToken) -> Option<Region> {
+    while !cancel.is_cancelled() {
+        let mut queue = queue.lock().unwrap();
+        if let Some(region) = queue.tiles.pop() {
+            queue.in_flight += 1;
+            return Some(region);
+        }
+        if queue.in_flight == 0 {
+            return None;
+        }
+
+        drop(queue);
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    None
+}
+
+/// Splits the frame into tiles, in the order they're handed out when popped.
+fn tiles(width: u32, height: u32) -> Vec<Region> {
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(TILE_SIZE as usize).rev() {
+        for x0 in (0..width).step_by(TILE_SIZE as usize).rev() {
+            tiles.push(Region {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height),
+            });
+        }
+    }
+
+    tiles
+}
+
+fn send<T: Serialize>(output: &mut impl Write, message: &T) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer(&mut *output, message)?;
+    output.write_all(b"\n")?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Reads the next message, or `None` once the other side hangs up. Fails on messages longer
+/// than [`MAX_LINE`].
+fn receive<T: for<'de> Deserialize<'de>>(
+    input: &mut impl BufRead,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let mut line = String::new();
+    let read = input.take(MAX_LINE).read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(format!("a message is longer than {} bytes", MAX_LINE).into());
+    }
+
+    Ok(Some(serde_json::from_str(&line)?))
+}
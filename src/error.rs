@@ -0,0 +1,168 @@
+//! The error type shared by everything in the library.
+
+use crate::memory::format_bytes;
+use crate::render::Region;
+use crate::CameraError;
+
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    #[error("couldn't access {}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// Any other IO failure, like writing to a pipe.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Encoding or decoding a PNG or other 8 and 16-bit image failed.
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[cfg(feature = "fs")]
+    #[error(transparent)]
+    Exr(#[from] exr::error::Error),
+    #[error("couldn't parse {}: {source}", path.display())]
+    SceneParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// An image used as a texture couldn't be loaded.
+    #[error("couldn't load texture {}: {source}", path.display())]
+    Texture {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    /// A line of an OBJ or MTL file is malformed.
+    #[error("{}:{line}: {message}", path.display())]
+    Mesh {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// A line of a `.cube` lookup table is malformed, or the table as a whole at line 0.
+    #[error("{}:{line}: {message}", path.display())]
+    Lut {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// A MagicaVoxel file is malformed.
+    #[error("couldn't read {}: {message}", path.display())]
+    Vox { path: PathBuf, message: String },
+    /// An IES photometric file is malformed.
+    #[error("couldn't read {}: {message}", path.display())]
+    Ies { path: PathBuf, message: String },
+    /// A NanoVDB file is malformed or holds no usable grid.
+    #[error("couldn't read {}: {message}", path.display())]
+    Vdb { path: PathBuf, message: String },
+    /// Embree failed to start, or to build the BVH of a mesh.
+    #[error("embree: {0}")]
+    Embree(String),
+    /// A scene object refers to a material the scene doesn't define.
+    #[error("unknown material {0:?}")]
+    UnknownMaterial(String),
+    #[error("no bounded object named {0:?} to focus on")]
+    UnknownObject(String),
+    /// A scene that may only load files from under the working directory refers to one
+    /// elsewhere, see [`Scene::check_paths`](crate::scene::Scene::check_paths).
+    #[error("{} is outside of the working directory", .0.display())]
+    OutsidePath(PathBuf),
+    /// Scene instances refer to a prototype the scene doesn't define.
+    #[error("unknown prototype {0:?}")]
+    UnknownPrototype(String),
+    #[error("prototype {0:?} contains instances of itself")]
+    RecursivePrototype(String),
+    /// Two nodes of a [`SceneGraph`](crate::scene_graph::SceneGraph) share a name.
+    #[error("more than one scene graph node is named {0:?}")]
+    DuplicateNode(String),
+    #[error("no scene graph node named {0:?} holds a camera")]
+    UnknownCamera(String),
+    #[error(transparent)]
+    Camera(#[from] CameraError),
+    /// An object or material parameter is non-finite or outside of its valid range.
+    #[error("invalid {name}: {value}")]
+    InvalidParameter { name: &'static str, value: f64 },
+    /// An existing image or checkpoint doesn't have the size of the one being rendered.
+    #[error(
+        "{} is {}x{}, but the image is {}x{}",
+        path.display(), found.0, found.1, expected.0, expected.1
+    )]
+    SizeMismatch {
+        path: PathBuf,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// A buffer passed to [`Renderer::render_into`](crate::render::Renderer::render_into) has
+    /// the wrong length.
+    #[error("the buffer holds {found} floats, but the image needs {expected}")]
+    BufferSize { expected: usize, found: usize },
+    /// A render was stopped through its
+    /// [`CancellationToken`](crate::render::CancellationToken).
+    #[error("the render was cancelled")]
+    Cancelled,
+    /// A region to render is empty, or reaches past the edges of the image.
+    #[error("the region {region:?} isn't inside the {width}x{height} image")]
+    RegionOutside {
+        region: Region,
+        width: u32,
+        height: u32,
+    },
+    /// The scene would take more memory to render than it's allowed.
+    #[error(
+        "the render would take about {} of memory, over the budget of {}",
+        format_bytes(*estimate),
+        format_bytes(*budget)
+    )]
+    MemoryBudget { estimate: usize, budget: usize },
+    #[error("{} is not a checkpoint", .0.display())]
+    NotACheckpoint(PathBuf),
+    /// A checkpoint is cut short, or longer than its size calls for.
+    #[error("couldn't read {}: {message}", path.display())]
+    Checkpoint { path: PathBuf, message: String },
+    #[error("couldn't run ffmpeg: {0}")]
+    Ffmpeg(#[source] io::Error),
+    #[error("ffmpeg exited with {0}")]
+    FfmpegFailed(ExitStatus),
+    /// The command line arguments don't make sense, with what's wrong with them.
+    #[error("{0}")]
+    Args(String),
+    /// Videos are encoded with chroma subsampling, which halves both sides of the image.
+    #[error("videos need an even width and height, but the image is {0}x{1}")]
+    OddVideoSize(u32, u32),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Passes `value` through if it's finite and `valid`, failing with
+    /// [`Error::InvalidParameter`] otherwise.
+    pub(crate) fn check(
+        name: &'static str,
+        value: f64,
+        valid: impl FnOnce(f64) -> bool,
+    ) -> Result<f64> {
+        if value.is_finite() && valid(value) {
+            Ok(value)
+        } else {
+            Err(Self::InvalidParameter { name, value })
+        }
+    }
+
+    /// For use with `map_err`, attaching the path to an IO error.
+    #[cfg(feature = "fs")]
+    pub(crate) fn file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::File {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
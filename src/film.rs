@@ -0,0 +1,194 @@
+//! The film a progressive render is exposed onto: running sums of the samples of every pixel,
+//! along with how many samples each pixel has, from which the image can be taken at any point.
+//!
+//! Whole passes and tiles of the frame are merged in, from any number of renders, and each pixel
+//! is averaged over its own samples, so tiles rendered elsewhere or resumed from a checkpoint
+//! don't need to have as many as the rest. Like the output of [`render`](crate::render::render),
+//! the sums are kept starting from the bottom row, but everything taking or giving coordinates
+//! has the origin at the top left corner, like images do.
+
+use crate::render::{Pixel, Region};
+use crate::Color;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    /// Color and alpha summed over every sample, and the total time spent on each pixel.
+    sums: Vec<Pixel>,
+    /// The samples taken of each pixel.
+    samples: Vec<u32>,
+}
+
+impl Film {
+    /// Film without any samples on it yet.
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            sums: vec![Pixel::default(); len],
+            samples: vec![0; len],
+        }
+    }
+
+    /// Film with `sums` already on it, in the order of the output of
+    /// [`render`](crate::render::render), each pixel with as many samples as `samples` has for
+    /// it. Panics if either doesn't have one entry per pixel.
+    pub fn from_sums(width: u32, height: u32, sums: Vec<Pixel>, samples: Vec<u32>) -> Self {
+        let len = width as usize * height as usize;
+        assert_eq!((sums.len(), samples.len()), (len, len));
+        Self {
+            width,
+            height,
+            sums,
+            samples,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The fewest samples any pixel has, which is how far the whole image has got.
+    pub fn samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    /// The sums of every pixel with its number of samples, in the order of the output of
+    /// [`render`](crate::render::render).
+    pub fn sums(&self) -> impl Iterator<Item = (&Pixel, u32)> {
+        self.sums.iter().zip(self.samples.iter().copied())
+    }
+
+    /// Adds a pass over the whole frame, as rendered with `samples` samples per pixel. Panics if
+    /// it's smaller than the film.
+    pub fn add_pass(&mut self, pixels: &[Pixel], samples: u32) {
+        assert!(pixels.len() >= self.sums.len());
+        for ((sum, count), pixel) in self.sums.iter_mut().zip(&mut self.samples).zip(pixels) {
+            add(sum, count, pixel, samples);
+        }
+    }
+
+    /// Adds the pixels of `region`, as cropped by [`Region::crop`] out of a pass rendered with
+    /// `samples` samples per pixel. The pixels around it are left as they are. Panics if the
+    /// region doesn't fit on the film, or the tile is smaller than it.
+    pub fn merge(&mut self, region: Region, tile: &[Pixel], samples: u32) {
+        assert!(region.x1 <= self.width && region.y1 <= self.height);
+        assert!(tile.len() >= (region.width() * region.height()) as usize);
+        if region.width() == 0 {
+            return;
+        }
+
+        let rows = (region.y0..region.y1).rev();
+        for (y, line) in rows.zip(tile.chunks(region.width() as usize)) {
+            let start = self.index(region.x0, y);
+            let sums = &mut self.sums[start..start + line.len()];
+            let counts = &mut self.samples[start..start + line.len()];
+            for ((sum, count), pixel) in sums.iter_mut().zip(counts).zip(line) {
+                add(sum, count, pixel, samples);
+            }
+        }
+    }
+
+    /// The average over the samples of the pixel at image coordinates, with the origin at the
+    /// top left corner.
+    pub fn pixel(&self, x: u32, y: u32) -> Pixel {
+        let index = self.index(x, y);
+        average(&self.sums[index], self.samples[index])
+    }
+
+    /// The color of [`Film::pixel`] alone, for when that's all that's shown.
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let index = self.index(x, y);
+        self.sums[index].color / self.samples[index].max(1) as f64
+    }
+
+    /// The average over the samples of every pixel, in the order of the output of
+    /// [`render`](crate::render::render).
+    pub fn pixels(&self) -> Vec<Pixel> {
+        self.sums()
+            .map(|(sum, samples)| average(sum, samples))
+            .collect()
+    }
+
+    /// Where the pixel at image coordinates is in the sums, which start from the bottom row.
+    fn index(&self, x: u32, y: u32) -> usize {
+        (self.height - 1 - y) as usize * self.width as usize + x as usize
+    }
+}
+
+fn add(sum: &mut Pixel, count: &mut u32, pixel: &Pixel, samples: u32) {
+    sum.color += pixel.color * samples as f64;
+    sum.alpha += pixel.alpha * samples as f64;
+    sum.time += pixel.time;
+    *count += samples;
+}
+
+fn average(sum: &Pixel, samples: u32) -> Pixel {
+    let samples = samples.max(1) as f64;
+    Pixel {
+        color: sum.color / samples,
+        alpha: sum.alpha / samples,
+        time: sum.time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(pixel: Pixel, value: f64) -> bool {
+        (pixel.color - Color::new(value, value, value)).length() < 1e-9
+    }
+
+    fn gray(value: f64) -> Pixel {
+        Pixel {
+            color: Color::new(value, value, value),
+            alpha: 1.0,
+            ..Pixel::default()
+        }
+    }
+
+    #[test]
+    fn passes_are_averaged_by_their_samples() {
+        let mut film = Film::new(2, 1);
+        film.add_pass(&[gray(1.0), gray(0.0)], 1);
+        film.add_pass(&[gray(0.0), gray(0.5)], 3);
+
+        assert_eq!(film.samples(), 4);
+        assert!(close(film.pixel(0, 0), 0.25));
+        assert!(close(film.pixels()[1], 0.375));
+        assert_eq!(film.color(1, 0), film.pixel(1, 0).color);
+    }
+
+    #[test]
+    fn tiles_land_where_they_belong() {
+        // rendered from the bottom row up, so the top left pixel is third
+        let mut film = Film::new(2, 2);
+        film.add_pass(&[gray(0.0), gray(0.1), gray(0.2), gray(0.3)], 1);
+        assert!(close(film.pixel(0, 0), 0.2));
+
+        let top_right = Region {
+            x0: 1,
+            y0: 0,
+            x1: 2,
+            y1: 1,
+        };
+        film.merge(top_right, &[gray(0.7)], 3);
+        assert!(close(film.pixel(1, 0), 0.6));
+        assert!(close(film.pixel(1, 1), 0.1));
+        // the rest of the image is still at one sample
+        assert_eq!(film.samples(), 1);
+
+        // a pass cropped to the tile lands the same way
+        let pass = [gray(0.0), gray(0.0), gray(0.0), gray(0.7)];
+        let mut cropped = Film::new(2, 2);
+        cropped.merge(top_right, &top_right.crop(&pass, 2), 1);
+        assert!(close(cropped.pixel(1, 0), 0.7));
+    }
+}
@@ -0,0 +1,148 @@
+//! Pixel reconstruction filters.
+//!
+//! Instead of weighting samples after the fact, each filter importance-samples the offset of a
+//! sample from the pixel center. Filters with negative lobes return a negative weight for samples
+//! that land in them.
+
+use rand::{Rng, RngCore};
+
+/// Below this, the weights of a pixel's samples add up to nothing, and the samples are averaged
+/// without them instead.
+pub(crate) const MIN_TOTAL_WEIGHT: f64 = 1e-9;
+
+pub struct FilterSample {
+    /// Offset from the pixel center, in pixels.
+    pub offset: (f64, f64),
+    pub weight: f64,
+}
+
+pub trait PixelFilter: Send + Sync {
+    fn sample(&self, rng: &mut dyn RngCore) -> FilterSample;
+}
+
+/// Every point within `radius` of the pixel center is equally likely.
+pub struct BoxFilter {
+    pub radius: f64,
+}
+
+impl PixelFilter for BoxFilter {
+    fn sample(&self, rng: &mut dyn RngCore) -> FilterSample {
+        let mut sample_1d = || self.radius * rng.gen_range(-1.0..1.0);
+
+        FilterSample {
+            offset: (sample_1d(), sample_1d()),
+            weight: 1.0,
+        }
+    }
+}
+
+/// Linear falloff from the pixel center, reaching zero at `radius`.
+pub struct TentFilter {
+    pub radius: f64,
+}
+
+impl PixelFilter for TentFilter {
+    fn sample(&self, rng: &mut dyn RngCore) -> FilterSample {
+        let mut sample_1d = || {
+            let u: f64 = rng.gen();
+            if u < 0.5 {
+                self.radius * ((2.0 * u).sqrt() - 1.0)
+            } else {
+                self.radius * (1.0 - (2.0 - 2.0 * u).sqrt())
+            }
+        };
+
+        FilterSample {
+            offset: (sample_1d(), sample_1d()),
+            weight: 1.0,
+        }
+    }
+}
+
+/// A Gaussian with standard deviation `sigma`, truncated at `radius`.
+pub struct GaussianFilter {
+    pub radius: f64,
+    pub sigma: f64,
+}
+
+impl PixelFilter for GaussianFilter {
+    fn sample(&self, rng: &mut dyn RngCore) -> FilterSample {
+        loop {
+            // Box-Muller transform
+            let u1: f64 = 1.0 - rng.gen::<f64>();
+            let u2: f64 = rng.gen();
+            let r = self.sigma * (-2.0 * u1.ln()).sqrt();
+            let (sin, cos) = (std::f64::consts::TAU * u2).sin_cos();
+            let (x, y) = (r * cos, r * sin);
+
+            if x.abs() < self.radius && y.abs() < self.radius {
+                return FilterSample {
+                    offset: (x, y),
+                    weight: 1.0,
+                };
+            }
+        }
+    }
+}
+
+/// The Mitchell-Netravali cubic, with the usual `b = c = 1/3` giving a good balance between
+/// blurring and ringing.
+pub struct MitchellFilter {
+    pub radius: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl MitchellFilter {
+    fn eval_1d(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x / self.radius).abs();
+
+        if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0
+        }
+    }
+
+    /// Rejection-samples `|f|`, returning the offset and the sign of `f` there.
+    fn sample_1d(&self, rng: &mut dyn RngCore) -> (f64, f64) {
+        let max = self.eval_1d(0.0).abs();
+        loop {
+            let x = self.radius * rng.gen_range(-1.0..1.0);
+            let f = self.eval_1d(x);
+            if rng.gen::<f64>() * max < f.abs() {
+                return (x, f.signum());
+            }
+        }
+    }
+}
+
+impl Default for MitchellFilter {
+    fn default() -> Self {
+        Self {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+}
+
+impl PixelFilter for MitchellFilter {
+    fn sample(&self, rng: &mut dyn RngCore) -> FilterSample {
+        let (x, x_sign) = self.sample_1d(rng);
+        let (y, y_sign) = self.sample_1d(rng);
+
+        FilterSample {
+            offset: (x, y),
+            weight: x_sign * y_sign,
+        }
+    }
+}
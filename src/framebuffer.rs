@@ -0,0 +1,75 @@
+//! Rendered images kept in memory, for using the renderer without going through files.
+
+use crate::render::Pixel;
+
+/// Linear RGBA pixels as `f32`s, row by row starting from the top, with the colors premultiplied
+/// by alpha.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Framebuffer {
+    /// Floats per pixel.
+    pub const CHANNELS: usize = 4;
+
+    /// A transparent black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0.0; Self::len(width, height)],
+        }
+    }
+
+    /// The number of floats in an image of this size.
+    pub fn len(width: u32, height: u32) -> usize {
+        width as usize * height as usize * Self::CHANNELS
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixel at image coordinates, with the origin at the top left corner.
+    pub fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+        let start = (y as usize * self.width as usize + x as usize) * Self::CHANNELS;
+        let mut pixel = [0.0; 4];
+        pixel.copy_from_slice(&self.data[start..start + Self::CHANNELS]);
+        pixel
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> Vec<f32> {
+        self.data
+    }
+
+    /// Converts the output of [`render`](crate::render::render) into the framebuffer layout,
+    /// writing it to `buffer`. Panics if the lengths don't match.
+    pub fn write_pixels(buffer: &mut [f32], pixels: &[Pixel], width: u32) {
+        assert_eq!(buffer.len(), pixels.len() * Self::CHANNELS);
+        if width == 0 {
+            return;
+        }
+
+        // the renderer starts from the bottom row
+        let rows = pixels.chunks(width as usize).rev();
+        for (out, pixel) in buffer.chunks_exact_mut(Self::CHANNELS).zip(rows.flatten()) {
+            let [r, g, b] = pixel.color.0;
+            out.copy_from_slice(&[r as f32, g as f32, b as f32, pixel.alpha as f32]);
+        }
+    }
+}
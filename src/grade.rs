@@ -0,0 +1,161 @@
+//! Color grading of the rendered light: exposure, white balance and saturation, the way a camera
+//! or raw developer would set them. Everything happens in linear light, before the colors are
+//! clamped and gamma-corrected for the output file.
+
+use crate::{Color, Error, Result};
+
+/// The color temperature that white balance leaves alone, roughly that of daylight and of the
+/// white of sRGB.
+pub const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+
+/// How the light of a render is adjusted before it's saved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Grade {
+    /// Brightens the image by this many stops, doubling the light for each one.
+    pub exposure: f64,
+    /// The color temperature of the light to be made white, in kelvin. Lower values make the
+    /// image bluer, to make up for warm light, and higher ones make it warmer.
+    pub temperature: f64,
+    /// Shifts the image from green, at -1, to magenta, at 1, halving the other at the ends.
+    pub tint: f64,
+    /// 0 takes the color out completely, 1 leaves it as it is, and more makes it more vivid.
+    pub saturation: f64,
+}
+
+impl Grade {
+    /// Fails if the temperature isn't from 1667 to 25000 K, the tint from -1 to 1, or the
+    /// saturation is negative, or if any of them isn't finite.
+    pub fn new(exposure: f64, temperature: f64, tint: f64, saturation: f64) -> Result<Self> {
+        Ok(Self {
+            exposure: Error::check("exposure", exposure, |_| true)?,
+            temperature: Error::check("white balance temperature", temperature, |kelvin| {
+                (1667.0..=25000.0).contains(&kelvin)
+            })?,
+            tint: Error::check("tint", tint, |tint| (-1.0..=1.0).contains(&tint))?,
+            saturation: Error::check("saturation", saturation, |s| s >= 0.0)?,
+        })
+    }
+
+    /// Whether the grade leaves every color as it is.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Grades a linear color.
+    pub fn apply(&self, color: Color) -> Color {
+        if self.is_identity() {
+            return color;
+        }
+
+        let balanced = self.gains() * color;
+        let gray = luminance(balanced);
+        let gray = Color::new(gray, gray, gray);
+        let saturated = gray + self.saturation * (balanced - gray);
+        2f64.powf(self.exposure) * saturated
+    }
+
+    /// What each channel is multiplied by to balance the whites, keeping the luminance of white.
+    fn gains(&self) -> Color {
+        let [r, g, b] = white(NEUTRAL_TEMPERATURE).0;
+        let [r_light, g_light, b_light] = white(self.temperature).0;
+        let gains = Color::new(
+            r / r_light,
+            g / g_light * 2f64.powf(-self.tint),
+            b / b_light,
+        );
+        gains / luminance(gains)
+    }
+}
+
+impl Default for Grade {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            temperature: NEUTRAL_TEMPERATURE,
+            tint: 0.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+/// The luminance of a linear sRGB color.
+fn luminance(color: Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+/// The linear sRGB color of a blackbody at `kelvin`, with a luminance of 1, from the fit of the
+/// Planckian locus by Kim et al., "Design of advanced color temperature control system for HDTV
+/// applications". It's accurate enough for white balance and far cheaper than
+/// [`blackbody`](crate::collision::volumes::blackbody), which matters for every pixel.
+fn white(kelvin: f64) -> Color {
+    let (t, t2, t3) = (1e3 / kelvin, 1e6 / kelvin.powi(2), 1e9 / kelvin.powi(3));
+    let x = if kelvin <= 4000.0 {
+        -0.266_123_9 * t3 - 0.234_358_0 * t2 + 0.877_695_6 * t + 0.179_910
+    } else {
+        -3.025_846_9 * t3 + 2.107_037_9 * t2 + 0.222_634_7 * t + 0.240_390
+    };
+    let y = if kelvin <= 2222.0 {
+        -1.106_381_4 * x.powi(3) - 1.348_110_20 * x.powi(2) + 2.185_558_32 * x - 0.202_196_83
+    } else if kelvin <= 4000.0 {
+        -0.954_947_6 * x.powi(3) - 1.374_185_93 * x.powi(2) + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x.powi(3) - 5.873_386_70 * x.powi(2) + 3.751_129_97 * x - 0.370_014_83
+    };
+
+    // XYZ to linear sRGB, with a Y of 1
+    let (big_x, big_z) = (x / y, (1.0 - x - y) / y);
+    Color::new(
+        3.2406 * big_x - 1.5372 - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 + 1.0570 * big_z,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Color, b: Color) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn the_default_changes_nothing() {
+        let color = Color::new(0.2, 1.5, 0.7);
+        assert!(Grade::default().is_identity());
+        assert_eq!(Grade::default().apply(color), color);
+        let neutral = Grade::new(0.0, NEUTRAL_TEMPERATURE, 0.0, 1.0).unwrap();
+        assert!(close(neutral.gains(), Color::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn exposure_and_saturation_work_in_linear_light() {
+        let color = Color::new(0.2, 0.4, 0.1);
+        let brighter = Grade::new(1.0, NEUTRAL_TEMPERATURE, 0.0, 1.0).unwrap();
+        assert!(close(brighter.apply(color), 2.0 * color));
+
+        let gray = Grade::new(0.0, NEUTRAL_TEMPERATURE, 0.0, 0.0).unwrap();
+        let [r, g, b] = gray.apply(color).0;
+        assert!((r - g).abs() < 1e-12 && (g - b).abs() < 1e-12);
+        assert!((r - luminance(color)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn white_balance_neutralizes_the_light() {
+        // tungsten light, which white balancing for it turns into daylight
+        let tungsten = white(3200.0);
+        assert!(tungsten.x() > 1.5 * tungsten.z());
+        let balanced = Grade::new(0.0, 3200.0, 0.0, 1.0).unwrap().apply(tungsten);
+        let daylight = white(NEUTRAL_TEMPERATURE);
+        assert!(close(balanced / balanced.x(), daylight / daylight.x()));
+        assert!((daylight - Color::new(1.0, 1.0, 1.0)).length() < 0.1);
+
+        let magenta = Grade::new(0.0, NEUTRAL_TEMPERATURE, 1.0, 1.0).unwrap();
+        let [r, g, _] = magenta.apply(Color::new(1.0, 1.0, 1.0)).0;
+        assert!((r / g - 2.0).abs() < 1e-9);
+
+        assert!(Grade::new(0.0, 1000.0, 0.0, 1.0).is_err());
+        assert!(Grade::new(0.0, 6500.0, 2.0, 1.0).is_err());
+        assert!(Grade::new(0.0, 6500.0, 0.0, -1.0).is_err());
+    }
+}
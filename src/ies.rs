@@ -0,0 +1,272 @@
+//! Light distributions of real fixtures, from IES photometric files.
+//!
+//! Manufacturers measure how bright a fixture is in every direction and publish it in the
+//! IESNA LM-63 format: a grid of intensities over vertical angles, from straight down at 0° to
+//! straight up at 180°, and horizontal angles around the fixture. Lights with a profile are
+//! dimmed in each direction by it, which gives downlights their cones and wall washers their
+//! scallops. Only type C photometry is read, which is what nearly every architectural fixture is
+//! measured with.
+
+use crate::{Error, Result, Vec3};
+
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IesProfile {
+    /// The vertical angles of the grid, in degrees, increasing.
+    vertical: Vec<f64>,
+    /// The horizontal angles of the grid, in degrees, increasing.
+    horizontal: Vec<f64>,
+    /// The intensity at every pair of angles, with the vertical angle changing fastest, relative
+    /// to the brightest.
+    intensity: Vec<f64>,
+    /// The direction of a vertical angle of 0, and of horizontal angles of 0° and 90°.
+    frame: [Vec3; 3],
+}
+
+impl IesProfile {
+    /// Loads the `.ies` file at `path`, aimed straight down.
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(Error::file(path))?;
+        text.parse().map_err(|message| Error::Ies {
+            path: path.to_owned(),
+            message,
+        })
+    }
+
+    /// Turns the fixture so that it points along `nadir`, the direction of a vertical angle of
+    /// 0. A horizontal angle of 0 is then along x, or along z for fixtures pointing along x, and
+    /// horizontal angles go counterclockwise seen from behind the fixture. Fails if `nadir` is
+    /// zero.
+    pub fn aimed(mut self, nadir: Vec3) -> Result<Self> {
+        Error::check("aim length", nadir.length(), |length| length > 0.0)?;
+        self.frame = frame(nadir.normalize());
+        Ok(self)
+    }
+
+    /// How bright the fixture is toward `direction`, from 0 to 1 at its brightest.
+    pub fn intensity(&self, direction: Vec3) -> f64 {
+        let [nadir, zero, ninety] = self.frame;
+        let direction = direction.normalize();
+        let vertical = direction.dot(&nadir).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = direction
+            .dot(&ninety)
+            .atan2(direction.dot(&zero))
+            .to_degrees()
+            .rem_euclid(360.0);
+
+        // fixtures that are symmetric are only measured over a half or a quarter of the way
+        // around, and the rest is mirrored from it
+        let horizontal = match self.horizontal.last() {
+            Some(&last) if last <= 90.0 => {
+                let half = if horizontal > 180.0 {
+                    360.0 - horizontal
+                } else {
+                    horizontal
+                };
+                if half > 90.0 {
+                    180.0 - half
+                } else {
+                    half
+                }
+            }
+            Some(&last) if last <= 180.0 && horizontal > 180.0 => 360.0 - horizontal,
+            _ => horizontal,
+        };
+
+        let Some((v, v_fraction)) = locate(&self.vertical, vertical) else {
+            return 0.0;
+        };
+        let (h, h_fraction) = match self.horizontal.len() {
+            1 => (0, 0.0),
+            _ => locate(&self.horizontal, horizontal).unwrap_or((0, 0.0)),
+        };
+
+        let rows = self.vertical.len();
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal.len() - 1);
+            let v = v.min(rows - 1);
+            self.intensity[h * rows + v]
+        };
+        let below = (1.0 - v_fraction) * at(h, v) + v_fraction * at(h, v + 1);
+        let above = (1.0 - v_fraction) * at(h + 1, v) + v_fraction * at(h + 1, v + 1);
+        (1.0 - h_fraction) * below + h_fraction * above
+    }
+}
+
+/// The directions of a vertical angle of 0 and horizontal angles of 0° and 90°, for a fixture
+/// pointing along the unit vector `nadir`.
+fn frame(nadir: Vec3) -> [Vec3; 3] {
+    let reference = if nadir.x().abs() > 0.9 {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let zero = (reference - reference.dot(&nadir) * nadir).normalize();
+    [nadir, zero, zero.cross(&nadir)]
+}
+
+/// The index of the angle in `angles` just below `angle`, and how far it is toward the next one,
+/// or `None` if `angle` is outside of them.
+fn locate(angles: &[f64], angle: f64) -> Option<(usize, f64)> {
+    let (first, last) = (angles[0], angles[angles.len() - 1]);
+    if angle < first || angle > last {
+        return None;
+    }
+    if angles.len() == 1 {
+        return Some((0, 0.0));
+    }
+
+    let i = angles
+        .partition_point(|&a| a <= angle)
+        .clamp(1, angles.len() - 1)
+        - 1;
+    let span = angles[i + 1] - angles[i];
+    let fraction = if span > 0.0 {
+        (angle - angles[i]) / span
+    } else {
+        0.0
+    };
+    Some((i, fraction.clamp(0.0, 1.0)))
+}
+
+impl std::str::FromStr for IesProfile {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        // keywords, one per line, up to the tilt, then only numbers
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or("missing TILT")?
+            .trim()
+            .to_owned();
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                word.parse::<f64>()
+                    .map_err(|_| format!("{:?} isn't a number", word))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or(Err("the file ends early".to_owned()))
+        };
+
+        // how the lamp's output changes as it's tilted, which doesn't change the distribution
+        if tilt == "INCLUDE" {
+            let _geometry = next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let _multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        // the units and size of the fixture, and its ballast, lamp factor and wattage
+        for _ in 0..7 {
+            next()?;
+        }
+
+        if photometric_type != 1.0 {
+            return Err("only type C photometry is supported".to_owned());
+        }
+        if vertical_count < 2 || horizontal_count < 1 {
+            return Err("expected at least 2 vertical angles and 1 horizontal one".to_owned());
+        }
+
+        let mut read = |count: usize| (0..count).map(|_| next()).collect::<Result<Vec<_>, _>>();
+        let vertical = read(vertical_count)?;
+        let horizontal = read(horizontal_count)?;
+        let mut intensity = read(vertical_count * horizontal_count)?;
+
+        for angles in [&vertical, &horizontal] {
+            if angles.windows(2).any(|pair| pair[0] > pair[1]) {
+                return Err("the angles must be increasing".to_owned());
+            }
+        }
+        if intensity.iter().any(|&candela| candela < 0.0) {
+            return Err("the intensities can't be negative".to_owned());
+        }
+        let brightest = intensity.iter().fold(0.0, |a: f64, &b| a.max(b));
+        if brightest <= 0.0 {
+            return Err("the fixture gives off no light".to_owned());
+        }
+        for candela in &mut intensity {
+            *candela /= brightest;
+        }
+
+        Ok(Self {
+            vertical,
+            horizontal,
+            intensity,
+            frame: frame(Vec3::new(0.0, -1.0, 0.0)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A downlight with a 60° cone, brighter toward horizontal angles of 90°.
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[MANUFAC] Example
+TILT=NONE
+1 1000 1 4 3 1 2 0.1 0.1 0
+1 1 10
+0 30 60 90
+0 45 90
+1000 800 0 0
+1000 900 0 0
+1000 1000 0 0
+";
+
+    #[test]
+    fn reads_the_distribution() {
+        let profile: IesProfile = DOWNLIGHT.parse().unwrap();
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(profile.intensity(down), 1.0);
+        assert_eq!(profile.intensity(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+        assert_eq!(profile.intensity(Vec3::new(1.0, 0.0, 0.0)), 0.0);
+
+        // 30° from straight down, toward horizontal angles of 0°, 90°, and mirrored to 270°
+        let (sin, cos) = 30_f64.to_radians().sin_cos();
+        let toward_zero = profile.intensity(Vec3::new(sin, -cos, 0.0));
+        assert!((toward_zero - 0.8).abs() < 1e-9, "{}", toward_zero);
+        let toward_ninety = profile.intensity(Vec3::new(0.0, -cos, -sin));
+        assert!((toward_ninety - 1.0).abs() < 1e-9, "{}", toward_ninety);
+        let mirrored = profile.intensity(Vec3::new(0.0, -cos, sin));
+        assert!((mirrored - 1.0).abs() < 1e-9, "{}", mirrored);
+
+        // halfway to the edge of the cone
+        let (sin, cos) = 45_f64.to_radians().sin_cos();
+        let edge = profile.intensity(Vec3::new(sin, -cos, 0.0));
+        assert!((edge - 0.4).abs() < 1e-9, "{}", edge);
+
+        let sideways = profile.aimed(Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert_eq!(sideways.intensity(Vec3::new(1.0, 0.0, 0.0)), 1.0);
+        assert_eq!(sideways.intensity(down), 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!("1 2 3".parse::<IesProfile>().is_err());
+        let truncated = &DOWNLIGHT[..DOWNLIGHT.len() - 10];
+        assert!(truncated.parse::<IesProfile>().is_err());
+        let type_b = DOWNLIGHT.replace("4 3 1 2", "4 3 2 2");
+        assert!(type_b.parse::<IesProfile>().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Standard light sources, by the spectra they give off.
+//!
+//! The renderer works in RGB, so a spectrum is turned into the color the eye sees it as, through
+//! the CIE 1931 color matching functions. Daylight and tungsten follow the CIE's definitions,
+//! while the lamps with lines and bands in their spectra are modeled after typical ones, with
+//! colors within about 0.01 in xy of theirs.
+
+use crate::Color;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Illuminant {
+    /// Average daylight, CIE standard illuminant D65, which is the white of sRGB.
+    D65,
+    /// A tungsten filament bulb, CIE standard illuminant A: a blackbody at 2856 K.
+    Tungsten,
+    /// A cool white fluorescent tube, like CIE F2: two broad bands of halophosphate glowing
+    /// under the lines of mercury.
+    Fluorescent,
+    /// A tube with three narrow bands of rare earth phosphors, like CIE F11, the usual office
+    /// light.
+    TribandFluorescent,
+    /// A high pressure sodium street light, a deep orange.
+    SodiumVapor,
+    /// A cool white LED: a blue diode under a yellow phosphor.
+    Led,
+}
+
+impl Illuminant {
+    /// The relative power given off at `nm` nanometers.
+    pub fn spectrum(self, nm: f64) -> f64 {
+        match self {
+            Self::D65 => {
+                let position = ((nm - 380.0) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+                let i = (position as usize).min(D65.len() - 2);
+                let fraction = position - i as f64;
+                (1.0 - fraction) * D65[i] + fraction * D65[i + 1]
+            }
+            Self::Tungsten => planck(2856.0, nm),
+            Self::Fluorescent => {
+                0.6 * band(nm, 480.0, 90.0) + band(nm, 590.0, 70.0) + 0.3 * mercury(nm)
+            }
+            Self::TribandFluorescent => {
+                0.3 * band(nm, 435.8, 3.0)
+                    + 0.6 * band(nm, 544.0, 8.0)
+                    + band(nm, 611.0, 6.0)
+                    + 0.2 * band(nm, 404.7, 2.0)
+                    + 0.1 * band(nm, 480.0, 40.0)
+            }
+            // the sodium lines at 589 nm are so broadened that they absorb their own center
+            Self::SodiumVapor => {
+                0.8 * band(nm, 565.0, 15.0)
+                    + 0.6 * band(nm, 600.0, 25.0)
+                    + 0.3 * band(nm, 620.0, 30.0)
+                    + 0.1 * band(nm, 470.0, 30.0)
+            }
+            Self::Led => 1.5 * band(nm, 450.0, 22.0) + band(nm, 570.0, 100.0),
+        }
+    }
+
+    /// The color of the light, in linear sRGB with a luminance of 1.
+    pub fn color(self) -> Color {
+        spectrum_color(|nm| self.spectrum(nm))
+    }
+}
+
+/// CIE standard illuminant D65 from 380 to 780 nm, every 10 nm.
+#[rustfmt::skip]
+const D65: [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81,
+    109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60,
+    87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09,
+    63.59, 46.42, 66.81, 63.38,
+];
+
+/// Planck's law at `kelvin`, with the constants folded together and wavelengths in nanometers,
+/// which only changes the brightness.
+pub(crate) fn planck(kelvin: f64, nm: f64) -> f64 {
+    1.0 / (nm.powi(5) * ((1.438_777e7 / (nm * kelvin)).exp() - 1.0))
+}
+
+/// A band of light around `peak` nanometers, `width` nanometers wide at half its height.
+fn band(nm: f64, peak: f64, width: f64) -> f64 {
+    let sigma = width / (8.0 * 2.0_f64.ln()).sqrt();
+    (-0.5 * ((nm - peak) / sigma).powi(2)).exp()
+}
+
+/// The visible lines of mercury vapor, which fluorescent tubes glow with.
+fn mercury(nm: f64) -> f64 {
+    0.35 * band(nm, 404.7, 2.0)
+        + 0.9 * band(nm, 435.8, 2.0)
+        + band(nm, 546.1, 2.0)
+        + 0.5 * band(nm, 578.0, 3.0)
+}
+
+/// The color of the light with the `spectrum`, in linear sRGB with a luminance of 1. Colors
+/// outside of sRGB lose what they can't show.
+pub(crate) fn spectrum_color<F>(spectrum: F) -> Color
+where
+    F: Fn(f64) -> f64,
+{
+    // the CIE 1931 color matching functions, as fit by Wyman, Sloan and Shirley
+    let lobe = |nm: f64, mean: f64, below: f64, above: f64| {
+        let sigma = if nm < mean { below } else { above };
+        (-0.5 * ((nm - mean) / sigma).powi(2)).exp()
+    };
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    // every nanometer, so that narrow lines aren't missed
+    for nm in 380..=780 {
+        let nm = nm as f64;
+        let power = spectrum(nm);
+        x += power
+            * (1.056 * lobe(nm, 599.8, 37.9, 31.0) + 0.362 * lobe(nm, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(nm, 501.1, 20.4, 26.2));
+        y += power * (0.821 * lobe(nm, 568.8, 46.9, 40.5) + 0.286 * lobe(nm, 530.9, 16.3, 31.1));
+        z += power * (1.217 * lobe(nm, 437.0, 11.8, 36.0) + 0.681 * lobe(nm, 459.0, 26.0, 13.8));
+    }
+
+    // XYZ to linear sRGB, leaving out the colors sRGB can't show
+    let color = Color::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    );
+    color / y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daylight_is_white() {
+        let white = Illuminant::D65.color();
+        assert!(
+            (white - Color::new(1.0, 1.0, 1.0)).length() < 0.01,
+            "{}",
+            white
+        );
+    }
+
+    #[test]
+    fn lamps_have_their_colors() {
+        let tungsten = Illuminant::Tungsten.color();
+        assert!(tungsten.x() > tungsten.y() && tungsten.y() > tungsten.z());
+
+        let sodium = Illuminant::SodiumVapor.color();
+        assert!(
+            sodium.x() > 2.0 * sodium.y() && sodium.z() < 0.1,
+            "{}",
+            sodium
+        );
+
+        // close to white, but not quite
+        for illuminant in [
+            Illuminant::Fluorescent,
+            Illuminant::TribandFluorescent,
+            Illuminant::Led,
+        ] {
+            let color = illuminant.color();
+            assert!(color.x() > color.z(), "{:?} {}", illuminant, color);
+            assert!((color - Color::new(1.0, 1.0, 1.0)).length() < 0.7);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! The shared assets of a scene: its materials by name, and the images its textures are made of.
+//!
+//! Objects don't own their materials, they refer to one of the library's, so a material used by
+//! thousands of triangles is built once. Images are loaded once per file and color space, however
+//! many textures and meshes use them, which is most of the memory an imported scene takes.
+
+use crate::collision::Material;
+use crate::color_space::ColorSpace;
+use crate::texture::{ImageTexture, LevelCache};
+use crate::{Error, Result};
+
+use std::collections::HashMap;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Where a material is in its [`MaterialLibrary`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+impl MaterialId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Default)]
+pub struct MaterialLibrary {
+    materials: Vec<Arc<dyn Material>>,
+    names: Vec<String>,
+    ids: HashMap<String, MaterialId>,
+    textures: TextureCache,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty library, loading the images of its materials into `textures`.
+    pub fn with_textures(textures: TextureCache) -> Self {
+        Self {
+            textures,
+            ..Self::default()
+        }
+    }
+
+    /// Adds `material` under `name`, replacing the one that had it, which keeps its ID.
+    pub fn add(&mut self, name: impl Into<String>, material: Arc<dyn Material>) -> MaterialId {
+        let name = name.into();
+        if let Some(&id) = self.ids.get(&name) {
+            self.materials[id.0] = material;
+            return id;
+        }
+        let id = MaterialId(self.materials.len());
+        self.materials.push(material);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    pub fn id(&self, name: &str) -> Option<MaterialId> {
+        self.ids.get(name).copied()
+    }
+
+    /// The material called `name`, failing if there's none.
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Material>> {
+        self.id(name)
+            .map(|id| self[id].clone())
+            .ok_or_else(|| Error::UnknownMaterial(name.to_owned()))
+    }
+
+    /// The name of every material with the material, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<dyn Material>)> {
+        self.names.iter().map(String::as_str).zip(&self.materials)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    /// The images the library's materials use, for building more of them.
+    pub fn textures(&self) -> &TextureCache {
+        &self.textures
+    }
+}
+
+impl Index<MaterialId> for MaterialLibrary {
+    type Output = Arc<dyn Material>;
+
+    fn index(&self, id: MaterialId) -> &Self::Output {
+        &self.materials[id.0]
+    }
+}
+
+/// Images loaded for textures, by the file they came from and the color space they were decoded
+/// from. Clones share the images, loading them into the same cache.
+#[derive(Clone, Default)]
+pub struct TextureCache {
+    images: Arc<Mutex<Images>>,
+    /// Where the images are decoded into as they're looked up, for caches with a budget.
+    levels: Option<Arc<LevelCache>>,
+}
+
+type Images = HashMap<(PathBuf, ColorSpace), Arc<ImageTexture>>;
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache for images that are only decoded once they're looked up, and are kept along with
+    /// their mipmaps to `budget` bytes, dropping the least recently used. Scenes with many large
+    /// images then start rendering right away, and only take the memory of the ones they show.
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            levels: Some(Arc::new(LevelCache::new(budget))),
+            ..Self::default()
+        }
+    }
+
+    /// Where lazy images are decoded into, for caches with a budget. See
+    /// [`ImageTexture::lazy`].
+    pub fn levels(&self) -> Option<&Arc<LevelCache>> {
+        self.levels.as_ref()
+    }
+
+    /// The image at `path`, loaded with `load` unless it already was. Paths to the same file are
+    /// told apart only if it can't be found.
+    pub fn get_or_load<F>(
+        &self,
+        path: &Path,
+        color_space: ColorSpace,
+        load: F,
+    ) -> Result<Arc<ImageTexture>>
+    where
+        F: FnOnce(&Path, ColorSpace) -> Result<ImageTexture>,
+    {
+        let key = (
+            path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+            color_space,
+        );
+        if let Some(image) = self.lock().get(&key) {
+            return Ok(image.clone());
+        }
+
+        let image = Arc::new(load(path, color_space)?);
+        Ok(self.lock().entry(key).or_insert(image).clone())
+    }
+
+    /// How many images are loaded.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The bytes the loaded images take up. Lazy images count for as much as they would
+    /// decoded, up to the budget.
+    pub fn memory(&self) -> usize {
+        let (mut decoded, mut lazy) = (0, 0);
+        for image in self.lock().values() {
+            if image.is_lazy() {
+                lazy += image.memory();
+            } else {
+                decoded += image.memory();
+            }
+        }
+        let budget = self.levels.as_ref().map_or(0, |levels| levels.budget());
+
+        decoded + lazy.min(budget)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Images> {
+        // a panic while loading leaves the map as it was
+        self.images
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::Color;
+
+    use image::{DynamicImage, RgbImage};
+
+    #[test]
+    fn materials_keep_their_ids() {
+        let mut library = MaterialLibrary::new();
+        let gray = library.add(
+            "gray",
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap()),
+        );
+        let red = library.add(
+            "red",
+            Arc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1)).unwrap()),
+        );
+        assert_ne!(gray, red);
+        assert_eq!(library.id("red"), Some(red));
+        assert!(library.get("blue").is_err());
+
+        let darker = library.add(
+            "gray",
+            Arc::new(Lambertian::new(Color::new(0.2, 0.2, 0.2)).unwrap()),
+        );
+        assert_eq!(darker, gray);
+        assert_eq!(library.len(), 2);
+        assert!(Arc::ptr_eq(&library[gray], &library.get("gray").unwrap()));
+        let names: Vec<&str> = library.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["gray", "red"]);
+    }
+
+    #[test]
+    fn images_are_loaded_once() {
+        let cache = TextureCache::new();
+        let loads = std::cell::Cell::new(0);
+        let load = |_: &Path, color_space| {
+            loads.set(loads.get() + 1);
+            Ok(ImageTexture::new(
+                DynamicImage::ImageRgb8(RgbImage::new(2, 2)),
+                color_space,
+            ))
+        };
+
+        let path = Path::new("no such directory/bricks.png");
+        let first = cache.get_or_load(path, ColorSpace::Srgb, load).unwrap();
+        let again = cache.get_or_load(path, ColorSpace::Srgb, load).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        // the same file read as data is another texture
+        cache.get_or_load(path, ColorSpace::Linear, load).unwrap();
+        assert_eq!((loads.get(), cache.len()), (2, 2));
+
+        let failing = |path: &Path, _| {
+            Err(Error::File {
+                path: path.to_owned(),
+                source: std::io::ErrorKind::NotFound.into(),
+            })
+        };
+        let other = Path::new("no such directory/tiles.png");
+        assert!(cache.get_or_load(other, ColorSpace::Srgb, failing).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
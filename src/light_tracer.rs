@@ -0,0 +1,396 @@
+//! Light tracing: paths traced from the sun and the sky, and put onto the film wherever the camera
+//! sees them. Every surface the light bounces off is joined up with the camera, so this finds the
+//! same light as path tracing does the other way around. That makes it a check that materials
+//! scatter light the same both ways, and it shows caustics on surfaces seen straight from the
+//! camera without any help.
+
+use crate::collision::bvh::Aabb;
+use crate::collision::{Hit, Hittable};
+use crate::photons::{facing, is_specular};
+use crate::render::{self, Integrator, RenderSettings};
+use crate::{Camera, Color, Projection, Ray, RayCone, Vec3};
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use std::f64::consts::PI;
+use std::sync::RwLock;
+
+/// Light paths are traced in this many batches, each with its own random numbers.
+const BATCHES: usize = 64;
+/// How many rays across the film look for the part of the scene the camera sees.
+const VIEW_PROBES: usize = 32;
+
+/// Traces as many paths from the lights before every pass as the pass has camera samples, half
+/// of them from the sun if there is one. The camera rays only pick up what they see directly,
+/// through mirrors and glass: the lights themselves and the sky.
+///
+/// This is meant for checking the other integrators, not for final images: only perspective
+/// cameras are supported, as if through a pinhole, fog is left out, emissive objects don't light
+/// anything, and diffuse surfaces seen in mirrors or through glass stay dark.
+#[derive(Default)]
+pub struct LightTracer {
+    film: RwLock<Film>,
+}
+
+/// The light splatted onto each pixel during the current pass, starting from the bottom row.
+#[derive(Default)]
+struct Film {
+    width: usize,
+    pixels: Vec<Color>,
+}
+
+/// What paths from the lights need to know about the camera.
+struct Eye<'a> {
+    camera: &'a Camera,
+    width: usize,
+    height: usize,
+    /// The area of a pixel, one unit away from the pinhole.
+    pixel_area: f64,
+}
+
+impl Integrator for LightTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        _: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        let mut ray = *ray;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut seen = Color::default();
+        for _ in 0..settings.max_depth {
+            let hit = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(hit) => hit,
+                None => return seen + throughput * render::background_color(&ray, settings, false),
+            };
+
+            let material = render::shading_material(&ray, &hit, settings);
+            seen += throughput * material.emitted(&ray, &hit);
+            if !is_specular(&ray, &hit, settings) {
+                break;
+            }
+            match material.scatter(&ray, &hit, rng) {
+                Some((attenuation, scattered)) => {
+                    render::count_ray(false);
+                    throughput = throughput * attenuation;
+                    ray = scattered;
+                }
+                None => break,
+            }
+        }
+
+        seen
+    }
+
+    fn begin_pass(
+        &self,
+        world: &(dyn Hittable + Sync),
+        camera: &Camera,
+        settings: &RenderSettings,
+    ) {
+        let (width, height) = (settings.width as usize, settings.height as usize);
+        let mut film = Film {
+            width,
+            pixels: vec![Color::default(); width * height],
+        };
+        let bbox = match world.bounding_box() {
+            Some(bbox) if camera.projection() == Projection::Perspective => bbox,
+            _ => {
+                *self.film.write().unwrap() = film;
+                return;
+            }
+        };
+
+        let eye = Eye {
+            camera,
+            width,
+            height,
+            pixel_area: camera.film_area()
+                / ((width as f64 - 1.0).max(1.0) * (height as f64 - 1.0).max(1.0)),
+        };
+        let paths = width * height * settings.samples_per_pixel as usize;
+        let from_sun = match settings.background.sun() {
+            Some(_) => paths / 2,
+            None => 0,
+        };
+
+        let visible = visible_box(world, camera, settings);
+        let batch = |index: usize| {
+            let mut rng = render::row_rng(settings.seed, index);
+            let mut splats = Vec::new();
+
+            for path in (index..paths).step_by(BATCHES) {
+                let (direction, power) = match settings.background.sun() {
+                    Some(sun) if path < from_sun => {
+                        let power = sun.irradiance / from_sun as f64;
+                        (-sun.sample(&mut rng), power)
+                    }
+                    _ => {
+                        let direction = Vec3::random_unit_vec(&mut rng);
+                        let sky = settings.background.color(-direction);
+                        (direction, sky * (4.0 * PI / (paths - from_sun) as f64))
+                    }
+                };
+
+                // through a square in front of the whole scene, or half the time one in front
+                // of just what the camera sees, where most of the light that counts goes
+                let (corner, u, v, side) = facing(-direction, &bbox);
+                let seen = visible
+                    .map(|visible| facing(-direction, &visible))
+                    .filter(|&(_, _, _, small)| small > 0.0);
+                let across = |rng: &mut StdRng| rng.gen::<f64>() * u + rng.gen::<f64>() * v;
+                let origin = match seen {
+                    Some((corner, _, _, small)) if rng.gen::<bool>() => {
+                        corner + small * across(&mut rng) - 2.0 * side * direction
+                    }
+                    _ => corner + side * across(&mut rng),
+                };
+
+                let inside = |corner: Vec3, side: f64| {
+                    let offset = origin - corner;
+                    let range = 0.0..=side;
+                    range.contains(&offset.dot(&u)) && range.contains(&offset.dot(&v))
+                };
+                let density = match seen {
+                    Some((small_corner, _, _, small)) => {
+                        let whole = if inside(corner, side) {
+                            0.5 / (side * side)
+                        } else {
+                            0.0
+                        };
+                        let near = if inside(small_corner, small) {
+                            0.5 / (small * small)
+                        } else {
+                            0.0
+                        };
+                        whole + near
+                    }
+                    None => 1.0 / (side * side),
+                };
+                let ray = Ray { origin, direction };
+                let power = power / density;
+                trace_light(ray, power, world, &eye, settings, &mut rng, &mut splats);
+            }
+            splats
+        };
+
+        #[cfg(feature = "parallel")]
+        let batches = (0..BATCHES).into_par_iter().map(batch).collect::<Vec<_>>();
+        #[cfg(not(feature = "parallel"))]
+        let batches = (0..BATCHES).map(batch).collect::<Vec<_>>();
+        for (pixel, light) in batches.into_iter().flatten() {
+            film.pixels[pixel] += light;
+        }
+
+        *self.film.write().unwrap() = film;
+    }
+
+    fn splat(&self, row: usize, col: usize) -> Color {
+        let film = self.film.read().unwrap();
+        film.pixels
+            .get(row * film.width + col)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// A box around what the camera sees, from a grid of rays through the film.
+fn visible_box(
+    world: &(dyn Hittable + Sync),
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> Option<Aabb> {
+    let mut rng = render::row_rng(settings.seed, usize::MAX);
+    let mut visible: Option<Aabb> = None;
+    for i in 0..VIEW_PROBES * VIEW_PROBES {
+        let s = (i % VIEW_PROBES) as f64 / (VIEW_PROBES - 1) as f64;
+        let t = (i / VIEW_PROBES) as f64 / (VIEW_PROBES - 1) as f64;
+        if let Some(hit) = world.hit(&camera.get_ray(s, t, &mut rng), 0.001, f64::INFINITY) {
+            let point = Aabb::new(hit.point, hit.point);
+            visible = Some(visible.map_or(point, |visible| visible.union(&point)));
+        }
+    }
+
+    visible
+}
+
+/// Follows light with `power` from the ray's origin, joining up every diffuse surface it reaches
+/// with the camera.
+fn trace_light(
+    mut ray: Ray,
+    mut power: Color,
+    world: &(dyn Hittable + Sync),
+    eye: &Eye,
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+    splats: &mut Vec<(usize, Color)>,
+) {
+    for _ in 0..settings.max_depth {
+        render::count_ray(false);
+        let hit = match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return,
+        };
+
+        if !is_specular(&ray, &hit, settings) {
+            splats.extend(connect(&ray, &hit, power, world, eye, settings));
+        }
+        match render::shading_material(&ray, &hit, settings).scatter(&ray, &hit, rng) {
+            Some((attenuation, scattered)) => {
+                power = power * attenuation;
+                ray = scattered;
+            }
+            None => return,
+        }
+    }
+}
+
+/// The pixel the camera sees `hit` in, and the light that `power` arriving along the ray adds to
+/// it, if the camera can see it at all.
+fn connect(
+    ray: &Ray,
+    hit: &Hit,
+    power: Color,
+    world: &(dyn Hittable + Sync),
+    eye: &Eye,
+    settings: &RenderSettings,
+) -> Option<(usize, Color)> {
+    let (s, t, cos_view) = eye.camera.project(hit.point)?;
+    if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+        return None;
+    }
+    let col = (s * (eye.width as f64 - 1.0)) as usize;
+    let row = (t * (eye.height as f64 - 1.0)) as usize;
+    if col >= eye.width || row >= eye.height {
+        return None;
+    }
+
+    // the light can't get through to the other side of the surface
+    let to_camera = eye.camera.origin() - hit.point;
+    let distance = to_camera.length();
+    let toward = to_camera / distance;
+    let cos_out = hit.normal.dot(&toward);
+    if cos_out <= 0.0 {
+        return None;
+    }
+
+    let shadow_ray = Ray {
+        origin: hit.point,
+        direction: toward,
+    };
+    render::count_ray(false);
+    if world
+        .hit(&shadow_ray, 0.001, distance * (1.0 - 1e-9))
+        .is_some()
+    {
+        return None;
+    }
+
+    // the surface as the camera sees it
+    let view = Ray {
+        origin: eye.camera.origin(),
+        direction: -toward,
+    };
+    let light = -ray.direction.normalize();
+    let reflectance =
+        render::shading_material(&view, hit, settings).reflectance(&view, hit, light)?;
+    let cos_in = light.dot(&hit.normal).abs().max(1e-3);
+
+    // the pixel's share of the light leaving toward the pinhole, per unit of film area, which
+    // the lens spreads over more or fewer pixels
+    let lens = eye.camera.vignetting(s, t) * eye.camera.magnification(s, t);
+    let scale = lens * cos_out / (eye.pixel_area * distance * distance * cos_view.powi(3));
+    Some((
+        row * eye.width + col,
+        reflectance * power * (scale / cos_in),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lens, Point3};
+
+    use rand::SeedableRng;
+
+    #[test]
+    fn points_project_back_onto_their_rays() {
+        let camera = Camera::builder()
+            .look_from(Point3::new(1.0, 2.0, 3.0))
+            .look_at(Point3::new(0.0, 0.5, 0.0))
+            .vertical_fov(40.0)
+            .aspect_ratio(2.0)
+            .build()
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for (s, t) in [(0.5, 0.5), (0.1, 0.9), (0.75, 0.2)] {
+            let ray = camera.get_ray(s, t, &mut rng);
+            let (ps, pt, cos) = camera.project(ray.origin + 7.0 * ray.direction).unwrap();
+            assert!((ps - s).abs() < 1e-9 && (pt - t).abs() < 1e-9);
+
+            let (_, _, near) = camera.project(ray.origin + ray.direction).unwrap();
+            assert!((near - cos).abs() < 1e-9);
+        }
+        assert!(camera.project(Point3::new(2.0, 3.5, 6.0)).is_none());
+    }
+
+    #[test]
+    fn distorted_points_project_back_onto_their_rays() {
+        let camera = Camera::builder()
+            .look_from(Point3::new(1.0, 2.0, 3.0))
+            .look_at(Point3::new(0.0, 0.5, 0.0))
+            .vertical_fov(60.0)
+            .aspect_ratio(1.5)
+            .lens(Lens {
+                vignetting: true,
+                distortion: [-0.08, 0.01],
+                ..Lens::default()
+            })
+            .build()
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for (s, t) in [(0.1, 0.9), (0.75, 0.2), (0.0, 0.0)] {
+            let ray = camera.get_ray(s, t, &mut rng);
+            let (ps, pt, _) = camera.project(ray.origin + 7.0 * ray.direction).unwrap();
+            assert!((ps - s).abs() < 1e-6 && (pt - t).abs() < 1e-6);
+        }
+
+        // barrel distortion squeezes the edges of the view together, and they get darker
+        assert!((camera.vignetting(0.5, 0.5) - 1.0).abs() < 1e-12);
+        assert!(camera.vignetting(0.0, 0.0) < camera.vignetting(0.25, 0.25));
+        assert!((camera.magnification(0.5, 0.5) - 1.0).abs() < 1e-12);
+        assert!(camera.magnification(0.0, 0.0) < camera.magnification(0.25, 0.25));
+    }
+
+    #[test]
+    fn red_is_magnified_more_than_blue() {
+        let camera = Camera::builder()
+            .lens(Lens {
+                lateral_aberration: 0.1,
+                ..Lens::default()
+            })
+            .build()
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let red = camera.channel_ray(0.9, 0.5, 0, &mut rng);
+        let green = camera.channel_ray(0.9, 0.5, 1, &mut rng);
+        let blue = camera.channel_ray(0.9, 0.5, 2, &mut rng);
+        assert_eq!(
+            green.direction,
+            camera.get_ray(0.9, 0.5, &mut rng).direction
+        );
+        // so the red ray through a pixel points closer to the center of the view
+        assert!(red.direction.x() < green.direction.x());
+        assert!(green.direction.x() < blue.direction.x());
+        let (s, _, _) = camera.project(red.origin + red.direction).unwrap();
+        assert!((s - 0.5 - 0.4 / 1.1).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,207 @@
+//! 3D lookup tables in the `.cube` format of Adobe and Resolve, for baking looks like film
+//! emulations, or the display transforms of a studio pipeline, into the saved images.
+//!
+//! A table maps colors as they'd be shown on screen, so it's applied after the light is clamped
+//! and encoded with the curve of the output's color space. The colors in between its entries are interpolated trilinearly. Only 3D
+//! tables are read, since a 1D one only ever adjusts each channel on its own.
+
+use crate::color_space::ColorSpace;
+use crate::{Color, Error, Result};
+
+use std::path::{Path, PathBuf};
+
+/// The line a parse error is on, and what's wrong with it.
+type ParseError = (usize, String);
+
+/// The most entries along each side that a table can have, as in the specification.
+const MAX_SIZE: usize = 256;
+
+/// A cube of colors, with an entry for every combination of evenly spaced red, green and blue
+/// input values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    /// How many entries there are along each side.
+    size: usize,
+    /// The input colors the corners of the cube stand for.
+    domain: [Color; 2],
+    /// With red changing fastest, then green, then blue.
+    table: Vec<Color>,
+}
+
+impl Lut {
+    /// Loads the `.cube` file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(Error::file(path))?;
+        parse(&text).map_err(|(line, message)| Error::Lut {
+            path: PathBuf::from(path),
+            line,
+            message,
+        })
+    }
+
+    /// Looks up an sRGB color, clamped into the domain of the table.
+    pub fn apply(&self, color: Color) -> Color {
+        let last = (self.size - 1) as f64;
+        let [min, max] = self.domain;
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let position = (color[axis] - min[axis]) / (max[axis] - min[axis]);
+            let position = position.clamp(0.0, 1.0) * last;
+            // the last entry only ever gets interpolated towards, from the one before it
+            base[axis] = (position as usize).min(self.size - 2);
+            fraction[axis] = position - base[axis] as f64;
+        }
+
+        let mut result = Color::default();
+        for corner in 0..8 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for axis in (0..3).rev() {
+                let high = corner >> axis & 1;
+                index = index * self.size + base[axis] + high;
+                weight *= if high == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+            }
+            if weight > 0.0 {
+                result += weight * self.table[index];
+            }
+        }
+        result
+    }
+
+    /// Applies the table to a linear color of `space` the way it'd be saved: encoded with the
+    /// curve of the space, looked up, and decoded back into linear light.
+    pub fn apply_linear(&self, color: Color, space: ColorSpace) -> Color {
+        let [r, g, b] = color.0.map(|c| space.encode(c));
+        let [r, g, b] = self
+            .apply(Color::new(r, g, b))
+            .0
+            .map(|c| space.decode(c.max(0.0)));
+        Color::new(r, g, b)
+    }
+}
+
+fn parse(text: &str) -> Result<Lut, ParseError> {
+    let mut size = None;
+    let mut domain = [Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)];
+    let mut table = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| (index + 1, message.to_owned());
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(&keyword) = words.first() else {
+            continue;
+        };
+
+        match keyword {
+            comment if comment.starts_with('#') => {}
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err(error("1D tables aren't supported")),
+            "LUT_3D_SIZE" => match words.get(1).and_then(|word| word.parse().ok()) {
+                Some(entries @ 2..=MAX_SIZE) if table.is_empty() => size = Some(entries),
+                Some(_) => return Err(error("the size must be from 2 to 256, before the table")),
+                None => return Err(error("expected a size")),
+            },
+            "DOMAIN_MIN" => domain[0] = parse_color(&words[1..]).map_err(error)?,
+            "DOMAIN_MAX" => domain[1] = parse_color(&words[1..]).map_err(error)?,
+            _ => table.push(parse_color(&words).map_err(error)?),
+        }
+    }
+
+    let size = size.ok_or((0, "missing LUT_3D_SIZE".to_owned()))?;
+    if table.len() != size.pow(3) {
+        let message = format!("expected {} entries, found {}", size.pow(3), table.len());
+        return Err((0, message));
+    }
+    if (0..3).any(|axis| domain[0][axis] >= domain[1][axis]) {
+        return Err((
+            0,
+            "the domain must be larger than 0 along every axis".to_owned(),
+        ));
+    }
+
+    Ok(Lut {
+        size,
+        domain,
+        table,
+    })
+}
+
+fn parse_color(words: &[&str]) -> Result<Color, &'static str> {
+    let mut values = [0.0; 3];
+    if words.len() != 3 {
+        return Err("expected three numbers");
+    }
+    for (value, word) in values.iter_mut().zip(words) {
+        *value = word
+            .parse()
+            .ok()
+            .filter(|value: &f64| value.is_finite())
+            .ok_or("expected a number")?;
+    }
+
+    let [r, g, b] = values;
+    Ok(Color::new(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Swaps red and blue, and halves green, with two entries to a side.
+    const SWAP: &str = "\
+# a test table
+TITLE \"swap\"
+LUT_3D_SIZE 2
+
+0 0 0
+0 0 1
+0 0.5 0
+0 0.5 1
+1 0 0
+1 0 1
+1 0.5 0
+1 0.5 1
+";
+
+    #[test]
+    fn colors_interpolate_between_entries() {
+        let lut = parse(SWAP).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(
+            lut.apply(Color::new(1.0, 0.0, 0.0)),
+            Color::new(0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            lut.apply(Color::new(1.0, 1.0, 1.0)),
+            Color::new(1.0, 0.5, 1.0)
+        );
+        let mixed = lut.apply(Color::new(0.25, 0.5, 0.75));
+        assert!((mixed - Color::new(0.75, 0.25, 0.25)).length() < 1e-12);
+        // outside the domain, the edges carry on
+        assert_eq!(
+            lut.apply(Color::new(2.0, -1.0, 0.0)),
+            Color::new(0.0, 0.0, 1.0)
+        );
+
+        let linear = lut.apply_linear(Color::new(0.25, 0.0, 0.0), ColorSpace::Srgb);
+        assert!((linear - Color::new(0.0, 0.0, 0.25)).length() < 1e-12);
+    }
+
+    #[test]
+    fn malformed_tables_fail_on_their_line() {
+        assert_eq!(parse("LUT_1D_SIZE 16").unwrap_err().0, 1);
+        assert_eq!(parse("LUT_3D_SIZE 2\n0 0 0\n0 0").unwrap_err().0, 3);
+        assert_eq!(parse("LUT_3D_SIZE 1").unwrap_err().0, 1);
+        assert!(parse("LUT_3D_SIZE 2\n0 0 0")
+            .unwrap_err()
+            .1
+            .contains("8 entries"));
+        assert!(parse("0 0 0").is_err());
+    }
+}
@@ -1,32 +1,73 @@
+mod args;
+mod distributed;
+mod preview;
+mod report;
+mod serve;
+
+use args::Args;
+
+use ray_tracing::animation::*;
 use ray_tracing::collision::*;
-use ray_tracing::collision::{materials::*, objects::*};
+use ray_tracing::collision::{bvh::Bvh, bvh::TopLevelBvh, materials::*, objects::*};
+use ray_tracing::film::Film;
+use ray_tracing::library::{MaterialLibrary, TextureCache};
+use ray_tracing::lut::Lut;
+use ray_tracing::memory::MemoryUsage;
+use ray_tracing::output::{self, SaveOptions, VideoWriter};
+use ray_tracing::render::{self, CancellationToken, Pixel, RenderMode, RenderSettings};
+use ray_tracing::scene::{CameraDescription, Scene};
+use ray_tracing::transform::Transform;
 use ray_tracing::*;
 
-use image::ImageBuffer;
-use image::Rgb as GenericRgb;
+use notify::{RecursiveMode, Watcher};
 use rand::Rng;
-use rayon::iter::{IndexedParallelIterator, ParallelIterator};
-use rayon::slice::ParallelSliceMut;
 
 use std::io::{self, Write};
-use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{mpsc, Arc, LazyLock};
+use std::thread;
 
 pub const ASPECT_RATIO: f64 = 16.0 / 9.0;
 pub const IMG_WIDTH: u32 = 800;
 pub const IMG_HEIGHT: u32 = (IMG_WIDTH as f64 / ASPECT_RATIO) as u32;
 pub const SAMPLES_PER_PIXEL: u32 = 500;
 pub const MAX_DEPTH: i32 = 50;
+/// The most samples per pixel used by the previews in watch mode.
+pub const WATCH_SAMPLES: u32 = 16;
+/// Samples per pixel rendered between chances to stop the render.
+const PASS_SAMPLES: u32 = 10;
 
-type Rgb = GenericRgb<u8>;
+/// Set by the first Ctrl-C or when the time budget runs out, asking the render to stop and save
+/// what it has so far.
+static INTERRUPTED: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let threads = num_cpus::get() as u32;
-    eprintln!("Detected {} cores.", threads);
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    // World
+    if let Err(err) = run() {
+        log::error!("{}", err);
+        std::process::exit(1);
+    }
+}
 
-    let world = Arc::new(generate_world());
+fn run() -> ray_tracing::Result<()> {
+    let mut args = Args::parse()?;
+    match args.settings.threads.count {
+        Some(count) => log::info!("Rendering on {} of {} cores.", count, num_cpus::get()),
+        None => log::info!("Detected {} cores.", num_cpus::get()),
+    }
+    if let Some(addr) = &args.worker {
+        return distributed::serve(addr);
+    }
+    if let Some(path) = &args.lut {
+        args.save_options.lut = Some(Arc::new(Lut::load(path)?));
+    }
+    if let Some(addr) = args.serve.clone() {
+        return serve::serve(&addr, args);
+    }
+    if args.watch {
+        return watch(&args);
+    }
 
     // Camera
 
@@ -39,156 +80,635 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let aperture = 0.1;
     */
 
-    let camera = Camera::new(
-        Point3::new(4.0, 2.6, 2.2),
-        Point3::new(1.0, 0.0, -1.5),
-        Vec3::new(0.0, 1.0, 0.0),
-        20.0,
-        ASPECT_RATIO,
-        0.0,
-        1.0,
+    let scene = report::timed(
+        |stages| &mut stages.scene_load,
+        || args.scene.as_deref().map(Scene::load).transpose(),
+    )?;
+    if let Some(scene) = &scene {
+        warn_about(scene);
+        scene.apply(&mut args.settings)?;
+    }
+    let camera_builder = match &scene {
+        Some(scene) => scene.camera_builder(),
+        None => Camera::builder(),
+    }
+    .aspect_ratio(ASPECT_RATIO)
+    .view_height(2.2)
+    .projection(args.projection);
+    let camera_keys = match &scene {
+        Some(scene) => CameraKeyframes {
+            look_from: Track::constant(scene.camera.look_from),
+            look_at: Track::constant(scene.camera.look_at),
+            vertical_fov: Track::constant(scene.camera.vertical_fov),
+        },
+        None => CameraKeyframes {
+            look_from: Track::new(
+                Interpolation::Cubic,
+                vec![
+                    (0.0, Point3::new(4.0, 2.6, 2.2)),
+                    (2.0, Point3::new(2.2, 2.6, 4.0)),
+                ],
+            ),
+            look_at: Track::constant(Point3::new(1.0, 0.0, -1.5)),
+            vertical_fov: Track::constant(20.0),
+        },
+    };
+    // the objects are only built once, animating them just moves them around
+    let mut id_names = None;
+    let textures = match args.texture_cache {
+        Some(budget) => TextureCache::with_budget(budget),
+        None => TextureCache::new(),
+    };
+    let (still, moving) = report::timed(
+        |stages| &mut stages.scene_load,
+        || match &scene {
+            Some(scene) if args.cryptomatte || args.settings.diagnostics.is_on() => {
+                let (world, names) = scene.labeled_world_with(&textures)?;
+                // the logs name the objects and materials like the IDs do
+                if args.settings.diagnostics.is_on() {
+                    args.settings.diagnostics.names = Some(Arc::new(names.clone()));
+                }
+                if args.cryptomatte {
+                    id_names = Some(names);
+                }
+                Ok((world, Vec::new()))
+            }
+            Some(scene) => Ok((scene.world_with(&textures)?, Vec::new())),
+            None => generate_world(),
+        },
+    )?;
+    let mut world = report::timed(
+        |stages| &mut stages.bvh_build,
+        || {
+            let still = Transformed {
+                object: Arc::new(Bvh::new(still)),
+                transform: Transform::identity(),
+            };
+            let moving = moving.iter().map(|(object, motion)| Transformed {
+                object: object.clone(),
+                transform: motion(0.0),
+            });
+            TopLevelBvh::new(std::iter::once(still).chain(moving).collect())
+        },
     );
+    let camera_builder = match &scene {
+        Some(scene) => scene.focus(camera_builder, &world)?,
+        None => camera_builder,
+    };
+
+    let mut memory = MemoryUsage::new();
+    memory.add_object(&world);
+    memory.textures = textures.memory();
+    let (width, height) = frame_dimensions(&args.settings, &args);
+    memory.add_framebuffer(width, height);
+    log::info!("Estimated memory use: {}.", memory);
+    if let Some(budget) = args.memory_budget {
+        memory.check(budget)?;
+    }
+    report::record_memory(memory);
+    let move_to = |world: &mut TopLevelBvh, time: f64| {
+        report::timed(
+            |stages| &mut stages.bvh_build,
+            || {
+                let transforms = moving
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (_, motion))| (idx + 1, motion(time)));
+                world.set_transforms(transforms);
+            },
+        )
+    };
 
     // Render
 
-    //let mut buf = ImageBuffer::new(IMG_WIDTH, IMG_HEIGHT);
-    let mut buf: Vec<Rgb> = vec![Rgb::from([0, 0, 0]); (IMG_WIDTH * IMG_HEIGHT) as usize];
-
-    let chunk_size = ((IMG_HEIGHT * IMG_WIDTH) / threads as u32) as usize;
-
-    let linesleft = AtomicI32::new(IMG_HEIGHT as i32);
-    buf.par_chunks_mut(chunk_size).enumerate().for_each_init(
-        || (rand::thread_rng(), camera.clone(), world.clone()),
-        |(rng, camera, world), (num, chunk)| {
-            let offset = chunk_size * num;
-
-            let mut row = offset / IMG_WIDTH as usize;
-            let mut col = offset.rem_euclid(IMG_WIDTH as usize);
-            for pixel in chunk.iter_mut() {
-                // calculate
-                *pixel = calculate_pixel(row, col, camera, world, rng);
-                // update indices
-                col += 1;
-                if col == IMG_WIDTH as usize {
-                    col = 0;
-                    row += 1;
-                    let lines = linesleft.load(Ordering::SeqCst) - 1;
-                    linesleft.store(lines, Ordering::SeqCst);
-
-                    eprint!("\rScanlines remaining: {} ", lines);
-                    let _ = io::stderr().flush();
+    if args.preview {
+        let description = match &scene {
+            Some(scene) => scene.camera.clone(),
+            None => CameraDescription {
+                look_from: camera_keys.look_from.sample(0.0),
+                look_at: camera_keys.look_at.sample(0.0),
+                vup: Vec3::new(0.0, 1.0, 0.0),
+                vertical_fov: camera_keys.vertical_fov.sample(0.0),
+                aperture: 0.0,
+                focus_dist: None,
+                focus_on: None,
+                vignetting: false,
+                distortion: [0.0; 2],
+                lateral_aberration: 0.0,
+                longitudinal_aberration: 0.0,
+            },
+        };
+        let camera_builder = camera_builder.vertical_fov(description.vertical_fov);
+        return preview::run(&world, description, camera_builder, &args.settings);
+    }
+
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.is_cancelled() {
+            std::process::exit(130);
+        }
+        INTERRUPTED.cancel();
+        log::warn!(
+            "Interrupted, saving the samples so far. Press Ctrl-C again to quit right away."
+        );
+    })
+    .map_err(io::Error::other)?;
+
+    if let Some(max_time) = args.max_time {
+        thread::spawn(move || {
+            thread::sleep(max_time);
+            if !INTERRUPTED.is_cancelled() {
+                INTERRUPTED.cancel();
+                log::warn!("Out of time, saving the samples so far.");
+            }
+        });
+    }
+
+    if !args.workers.is_empty() {
+        // checked when parsing the arguments
+        let scene = scene.as_ref().unwrap();
+        let forwarded: Vec<String> = std::env::args().skip(1).collect();
+        let settings = &args.settings;
+        let pixels = distributed::render(&args.workers, &forwarded, scene, settings, &INTERRUPTED)?;
+        report::timed(
+            |stages| &mut stages.output,
+            || {
+                save_image(
+                    &args.output,
+                    &pixels,
+                    settings.width,
+                    settings.height,
+                    settings,
+                    &args,
+                )
+            },
+        )?;
+        log::info!("Saved {}.", args.output);
+
+        // the work was done elsewhere, so there are no statistics to show
+        return Ok(());
+    }
+
+    if args.turntable.is_some() || args.frames.is_some() {
+        // either one video file, or an image per frame
+        let mut video = if output::is_video(&args.output) {
+            let (width, height) = frame_dimensions(&args.settings, &args);
+            Some(VideoWriter::new(
+                &args.output,
+                width,
+                height,
+                args.fps,
+                &args.save_options,
+            )?)
+        } else {
+            None
+        };
+        let mut save_frame = |frame: u32,
+                              world: &TopLevelBvh,
+                              camera: &Camera,
+                              settings: &RenderSettings|
+         -> ray_tracing::Result<()> {
+            match &mut video {
+                Some(video) => {
+                    let film = render_frame(world, camera, settings, &args, None)?;
+                    report::timed(
+                        |stages| &mut stages.output,
+                        || video.write_frame(&film.pixels()),
+                    )?;
+                    log::info!("Encoded frame {}.", frame);
+                }
+                None => {
+                    let path = frame_path(&args.output, frame);
+                    render_to_file(world, camera, settings, &path, &args, None)?;
+                    log::info!("Saved {}.", path);
                 }
             }
-        },
-    );
 
-    let mut imgbuf = ImageBuffer::new(IMG_WIDTH, IMG_HEIGHT);
-    let mut idx = 0;
+            Ok(())
+        };
+
+        if let Some(frames) = args.turntable {
+            let turntable = Turntable::through(
+                camera_keys.look_from.sample(0.0),
+                camera_keys.look_at.sample(0.0),
+                frames,
+            )?;
+            let camera_builder = camera_builder.vertical_fov(camera_keys.vertical_fov.sample(0.0));
+
+            for frame in 0..frames {
+                let camera = turntable.camera_at(frame, camera_builder.clone())?;
+                save_frame(frame + 1, &world, &camera, &args.settings)?;
+                if INTERRUPTED.is_cancelled() {
+                    break;
+                }
+            }
+        } else if let Some((first, last)) = args.frames {
+            let mut settings = args.settings.clone();
+            for frame in first..=last {
+                let time = frame as f64 / args.fps;
+                move_to(&mut world, time);
+                let camera = camera_keys.camera_at(time, camera_builder.clone())?;
+                // the sun moves over the day
+                if let Some(sky) = scene.as_ref().and_then(|scene| scene.sky.as_ref()) {
+                    settings.background = sky.build_at(time)?;
+                }
+                save_frame(frame, &world, &camera, &settings)?;
+                if INTERRUPTED.is_cancelled() {
+                    break;
+                }
+            }
+        }
 
-    for row in (0..IMG_HEIGHT).rev() {
-        for col in 0..IMG_WIDTH {
-            imgbuf.put_pixel(col, row, buf[idx]);
-            idx += 1;
+        if let Some(video) = video {
+            video.finish()?;
+            log::info!("Saved {}.", args.output);
         }
+
+        return report::print(args.stats_json);
     }
-    imgbuf.save("output.png")?;
 
-    eprintln!("\nDone.");
-    Ok(())
+    let camera = camera_keys.camera_at(0.0, camera_builder)?;
+
+    if args.preview_ladder {
+        // quick low resolution passes to check the scene setup before the real thing
+        for divisor in [8, 4, 2].iter().copied() {
+            let settings = args.settings.scaled_down(divisor);
+            let path = with_suffix(&args.output, &format!("_div{}", divisor));
+            render_to_file(&world, &camera, &settings, &path, &args, None)?;
+            log::info!("Saved {}.", path);
+            if INTERRUPTED.is_cancelled() {
+                return Ok(());
+            }
+        }
+    }
+
+    let resume = args
+        .resume
+        .as_deref()
+        .map(output::load_checkpoint)
+        .transpose()?;
+    render_to_file(&world, &camera, &args.settings, &args.output, &args, resume)?;
+    log::info!("Saved {}.", args.output);
+
+    if let Some(names) = &id_names {
+        let settings = &args.settings;
+        let layers = cryptomatte::render(&world, &camera, settings, names);
+        let path = Path::new(&with_suffix(&args.output, "_cryptomatte")).with_extension("exr");
+        report::timed(
+            |stages| &mut stages.output,
+            || output::save_cryptomatte(&path, settings.width, settings.height, &layers),
+        )?;
+        log::info!("Saved {}.", path.display());
+    }
+    if args.deep {
+        let settings = &args.settings;
+        let pixels = deep::render(&world, &camera, settings);
+        let path = Path::new(&with_suffix(&args.output, "_deep")).with_extension("exr");
+        report::timed(
+            |stages| &mut stages.output,
+            || output::save_deep(&path, settings.width, settings.height, &pixels),
+        )?;
+        log::info!("Saved {}.", path.display());
+    }
+
+    report::print(args.stats_json)
 }
 
-fn calculate_pixel<T, R>(row: usize, col: usize, camera: &Camera, world: &T, rng: &mut R) -> Rgb
+/// Renders quick previews of the scene file whenever it changes, until interrupted.
+fn watch(args: &Args) -> ray_tracing::Result<()> {
+    let scene_path = Path::new(args.scene.as_deref().unwrap()).canonicalize()?;
+    let settings = RenderSettings {
+        samples_per_pixel: args.settings.samples_per_pixel.min(WATCH_SAMPLES),
+        ..args.settings.clone()
+    };
+
+    // editors often save by replacing the file, so watch its directory instead
+    let cancel = CancellationToken::new();
+    let (changed_tx, changed) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher({
+        let cancel = cancel.clone();
+        let scene_path = scene_path.clone();
+        move |event: notify::Result<notify::Event>| {
+            let touches_scene = event
+                .is_ok_and(|event| !event.kind.is_access() && event.paths.contains(&scene_path));
+            if touches_scene {
+                cancel.cancel();
+                let _ = changed_tx.send(());
+            }
+        }
+    })
+    .map_err(io::Error::other)?;
+    watcher
+        .watch(scene_path.parent().unwrap(), RecursiveMode::NonRecursive)
+        .map_err(io::Error::other)?;
+
+    loop {
+        cancel.reset();
+        while changed.try_recv().is_ok() {}
+
+        let rendered = Scene::load(&scene_path).and_then(|scene| {
+            warn_about(&scene);
+            let world = Bvh::new(scene.world()?);
+            let camera = scene_camera_builder(&scene, args.projection, &world)?.build()?;
+            let mut settings = settings.clone();
+            scene.apply(&mut settings)?;
+            Ok(render::render_cancellable(
+                &world, &camera, &settings, &cancel,
+            ))
+        });
+
+        match rendered {
+            Ok(Some(pixels)) => {
+                output::save(
+                    &args.output,
+                    settings.width,
+                    settings.height,
+                    &pixels,
+                    &args.save_options,
+                )?;
+                log::info!("Saved {}, waiting for changes.", args.output);
+            }
+            // the scene changed mid-render, start over right away
+            Ok(None) => continue,
+            Err(err) => log::error!("{}, waiting for changes.", err),
+        }
+
+        changed
+            .recv()
+            .expect("the watcher sends changes for as long as it's around");
+    }
+}
+
+/// Logs what the scene is made of, and warns about anything in it that looks like a mistake.
+fn warn_about(scene: &Scene) {
+    let validation = scene.validate();
+    log::info!("Objects in the scene: {}.", validation);
+    for issue in &validation.issues {
+        log::warn!("{}.", issue);
+    }
+}
+
+/// The camera of a scene file, the same way the command line sets it up, focused in `world`.
+pub fn scene_camera_builder<T: Hittable + ?Sized>(
+    scene: &Scene,
+    projection: Projection,
+    world: &T,
+) -> ray_tracing::Result<CameraBuilder> {
+    let builder = scene
+        .camera_builder()
+        .aspect_ratio(ASPECT_RATIO)
+        .view_height(2.2)
+        .projection(projection);
+    scene.focus(builder, world)
+}
+
+/// Renders a single image, or a stereo pair if requested, picking up from `resume` if given.
+///
+/// The samples are taken in passes, so that the render can be interrupted with Ctrl-C, which
+/// keeps whatever was accumulated up to the last finished pass.
+fn render_frame<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    args: &Args,
+    resume: Option<Film>,
+) -> ray_tracing::Result<Film>
 where
-    T: Hittable,
-    R: Rng,
+    T: Hittable + Sync + ?Sized,
 {
-    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-    for _ in 0..SAMPLES_PER_PIXEL {
-        let u = (col as f64 + rng.gen::<f64>()) / (IMG_WIDTH as f64 - 1.0);
-        let v = (row as f64 + rng.gen::<f64>()) / (IMG_HEIGHT as f64 - 1.0);
-
-        let ray = camera.get_ray(u, v);
+    let (width, height) = frame_dimensions(settings, args);
+    let mut film = resume.unwrap_or_else(|| Film::new(width, height));
+    if (film.width(), film.height()) != (width, height) {
+        return Err(ray_tracing::Error::SizeMismatch {
+            path: args.resume.clone().unwrap_or_default().into(),
+            expected: (width, height),
+            found: (film.width(), film.height()),
+        });
+    }
 
-        pixel_color += ray_color(&ray, world, MAX_DEPTH);
+    // the debug modes only ever take one sample
+    let total = match settings.mode {
+        RenderMode::Shaded => settings.samples_per_pixel,
+        _ => 1,
+    };
+    let mut progress_shown = false;
+    while film.samples() < total && !INTERRUPTED.is_cancelled() {
+        let samples = (total - film.samples()).min(PASS_SAMPLES);
+        let pass_settings = RenderSettings {
+            samples_per_pixel: samples,
+            show_progress: false,
+            // a different seed for every pass, or they'd all take the same samples
+            seed: settings
+                .seed
+                .map(|seed| seed.wrapping_add(film.samples() as u64)),
+            ..settings.clone()
+        };
+
+        let pixels = match args.stereo {
+            Some(layout) => render::render_stereo_cancellable(
+                world,
+                camera,
+                &pass_settings,
+                args.ipd,
+                layout,
+                &INTERRUPTED,
+            ),
+            None => render::render_cancellable(world, camera, &pass_settings, &INTERRUPTED),
+        };
+        if let Some(pixels) = pixels {
+            film.add_pass(&pixels, samples);
+            eprint!("\rSamples per pixel: {}/{} ", film.samples(), total);
+            io::stderr().flush()?;
+            progress_shown = true;
+        }
+    }
+    if progress_shown {
+        eprintln!();
     }
 
-    color_to_rgb(pixel_color, SAMPLES_PER_PIXEL)
+    Ok(film)
 }
 
-fn ray_color<T: Hittable>(ray: &Ray, world: &T, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+fn frame_dimensions(settings: &RenderSettings, args: &Args) -> (u32, u32) {
+    match args.stereo {
+        Some(layout) => layout.dimensions(settings.width, settings.height),
+        None => (settings.width, settings.height),
     }
+}
 
-    if let Some(hit) = world.hit(ray, 0.001, f64::INFINITY) {
-        let material = hit.material.clone();
-        if let Some((attenuation, scattered)) = material.scatter(ray, &hit) {
-            return attenuation * ray_color(&scattered, world, depth - 1);
+fn render_to_file<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    path: &str,
+    args: &Args,
+    resume: Option<Film>,
+) -> ray_tracing::Result<()>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let film = render_frame(world, camera, settings, args, resume)?;
+    let (pixels, width, height) = (film.pixels(), film.width(), film.height());
+    if INTERRUPTED.is_cancelled() {
+        let checkpoint = format!("{}.checkpoint", path);
+        output::save_checkpoint(&checkpoint, &film)?;
+        log::warn!(
+            "Stopped at {} samples per pixel, saved {} to resume from.",
+            film.samples(),
+            checkpoint
+        );
+    }
+
+    report::timed(
+        |stages| &mut stages.output,
+        || save_image(path, &pixels, width, height, settings, args),
+    )
+}
+
+fn save_image(
+    path: &str,
+    pixels: &[Pixel],
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+    args: &Args,
+) -> ray_tracing::Result<()> {
+    match settings.region {
+        // touch up a previous render of the whole frame
+        Some(region) if Path::new(path).exists() => {
+            output::composite(path, width, height, pixels, &region, &args.save_options)?
         }
-        return Color::new(0.0, 0.0, 0.0);
+        Some(region) => {
+            let cropped = region.crop(pixels, width);
+            let (width, height) = (region.width(), region.height());
+            output::save(path, width, height, &cropped, &args.save_options)?
+        }
+        None => output::save(path, width, height, pixels, &args.save_options)?,
+    }
+    if args.time_heatmap {
+        let heatmap = render::time_heatmap(pixels);
+        let path = with_suffix(path, "_time");
+        output::save(path, width, height, &heatmap, &SaveOptions::default())?;
+    }
+
+    Ok(())
+}
+
+/// The path of an animation frame, next to `output` and with the same extension.
+fn frame_path(output: &str, frame: u32) -> String {
+    let output = Path::new(output);
+    let mut name = format!("frame_{:04}", frame);
+    if let Some(ext) = output.extension().and_then(|s| s.to_str()) {
+        name = format!("{}.{}", name, ext);
     }
 
-    let direction = ray.direction.normalize();
-    let t = 0.5 * (direction.y() + 1.0);
+    output.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Inserts `suffix` between the file stem and the extension.
+fn with_suffix(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let mut name = format!("{}{}", stem, suffix);
+    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+        name = format!("{}.{}", name, ext);
+    }
 
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    path.with_file_name(name).to_string_lossy().into_owned()
 }
 
+/// How an object moves, as its transformation at each point in time.
+type Motion = Box<dyn Fn(f64) -> Transform>;
+
+/// The objects that stay put, and the ones that move.
+type World = (
+    Vec<Arc<dyn Hittable + Send + Sync>>,
+    Vec<(Arc<dyn Hittable + Send + Sync>, Motion)>,
+);
+
 #[allow(unused_variables)]
 #[allow(non_upper_case_globals)]
-fn generate_world() -> Vec<Arc<dyn Hittable + Send + Sync>> {
+fn generate_world() -> ray_tracing::Result<World> {
     let mut world: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
 
-    let yellow_diffuse = Arc::new(Lambertian {
-        albedo: Color::new(0.8, 0.8, 0.0),
-    });
-    let red_diffuse = Arc::new(Lambertian {
-        albedo: Color::new(0.8, 0.1, 0.1),
-    });
-    let blue_diffuse = Arc::new(Lambertian {
-        albedo: Color::new(0.1, 0.1, 0.8),
-    });
-    let glass = Arc::new(Dielectric { ri: 1.5 });
-    let anti_glass = Arc::new(Dielectric { ri: 1.0 / 1.3 });
-    let metal = Arc::new(Metal::new(Color::new(1.0, 1.0, 1.0), 0.1));
+    let mut materials = MaterialLibrary::new();
+    materials.add(
+        "yellow diffuse",
+        Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0))?),
+    );
+    materials.add(
+        "red diffuse",
+        Arc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1))?),
+    );
+    materials.add(
+        "blue diffuse",
+        Arc::new(Lambertian::new(Color::new(0.1, 0.1, 0.8))?),
+    );
+    materials.add("glass", Arc::new(Dielectric::new(1.5)?));
+    materials.add("anti glass", Arc::new(Dielectric::new(1.0 / 1.3)?));
+    materials.add(
+        "metal",
+        Arc::new(Metal::new(Color::new(1.0, 1.0, 1.0), 0.1)?),
+    );
 
-    world.push(Arc::new(Sphere {
-        // ground
-        center: Point3::new(0.0, -100.5, 0.0),
-        radius: 100.0,
-        material: yellow_diffuse,
-    }));
+    // ground
+    world.push(Arc::new(Sphere::new(
+        Point3::new(0.0, -100.5, 0.0),
+        100.0,
+        materials.get("yellow diffuse")?,
+    )?));
 
-    world.push(Arc::new(Sphere {
-        center: Point3::new(0.0, 0.0, -1.5),
-        radius: 0.5,
-        material: blue_diffuse,
-    }));
+    world.push(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, -1.5),
+        0.5,
+        materials.get("blue diffuse")?,
+    )?));
 
     const r: f64 = 0.2;
     const R: f64 = 0.6;
     const offset: [f64; 3] = [1.0, 0.0, -1.5];
 
-    world.push(Arc::new(ImplicitMarched {
+    let torus = Arc::new(ImplicitMarched {
         dist: |v| {
             let [x, y, z] = (v - Vec3(offset)).0;
 
             (((x * x + z * z).sqrt() - R * R).powi(2) + y * y).sqrt() - r
         },
         max_dist: |v| 2.0 * ((v - Vec3(offset)).length() + r + R),
-        material: metal,
-    }));
+        material: materials.get("metal")?,
+    });
 
-    world.push(Arc::new(Sphere {
-        center: Vec3(offset),
-        radius: 0.05,
-        material: red_diffuse,
-    }));
+    // rock the torus back and forth around its center
+    let rocking = TransformKeyframes {
+        rotation: Track::new(
+            Interpolation::Cubic,
+            vec![
+                (0.0, Vec3::new(0.0, 0.0, 0.0)),
+                (1.0, Vec3::new(0.0, 0.0, 30.0)),
+                (2.0, Vec3::new(0.0, 0.0, 0.0)),
+            ],
+        ),
+        ..Default::default()
+    };
+    let motion: Motion = Box::new(move |time| {
+        Transform::translation(Vec3(offset))
+            .after(&rocking.transform_at(time))
+            .after(&Transform::translation(-Vec3(offset)))
+    });
+    let moving: Vec<(Arc<dyn Hittable + Send + Sync>, Motion)> = vec![(torus, motion)];
+
+    world.push(Arc::new(Sphere::new(
+        Vec3(offset),
+        0.05,
+        materials.get("red diffuse")?,
+    )?));
 
     /*world.push(Arc::new(ImplicitMarched {
         dist: |v| v.length() - 0.3,
         max_dist: |v| v.length() + 0.6,
-        material: red_diffuse,
+        material: materials.get("red diffuse")?,
     }));*/
 
     /*world.push(Arc::new(Parallelogram::new(
@@ -196,13 +716,13 @@ fn generate_world() -> Vec<Arc<dyn Hittable + Send + Sync>> {
         Vec3::new(1.0, 0.0, 0.0),
         Vec3::new(0.0, 1.0, 0.0),
         Vec3::new(0.0, 0.0, -1.0),
-        glass,
+        materials.get("glass")?,
     )));
 
     world.push(Arc::new(Sphere {
         center: Point3::new(1.0, 0.0, -1.5),
         radius: 0.3,
-        material: red_diffuse,
+        material: materials.get("red diffuse")?,
     }));*/
 
     /*
@@ -211,26 +731,24 @@ fn generate_world() -> Vec<Arc<dyn Hittable + Send + Sync>> {
         Vec3::new(0.8, 0.0, 0.0),
         Vec3::new(0.0, 0.8, 0.0),
         Vec3::new(0.0, 0.0, -0.8),
-        anti_glass,
+        materials.get("anti glass")?,
     )));
     */
 
-    world
+    Ok((world, moving))
 }
 
 #[allow(dead_code)]
-fn generate_weekend_cover_world() -> Arc<Vec<Sphere>> {
+fn generate_weekend_cover_world() -> ray_tracing::Result<Arc<Vec<Sphere>>> {
     let mut rand = rand::thread_rng();
     let mut world = Vec::new();
 
-    let ground_material = Arc::new(Lambertian {
-        albedo: Color::new(0.5, 0.5, 0.5),
-    });
-    world.push(Sphere {
-        center: Point3::new(0.0, -1000.0, 0.0),
-        radius: 1000.0,
-        material: ground_material,
-    });
+    let ground_material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))?);
+    world.push(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )?);
 
     for a in (-11)..11 {
         let a = a as f64;
@@ -248,47 +766,29 @@ fn generate_weekend_cover_world() -> Arc<Vec<Sphere>> {
                 let material: Arc<dyn Material> = if choose_mat < 0.8 {
                     // diffuse
                     let albedo = rand.gen::<Color>() * rand.gen::<Color>();
-                    Arc::new(Lambertian { albedo })
+                    Arc::new(Lambertian::new(albedo)?)
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = rand.gen::<Color>() * 0.5 + Color::new(0.5, 0.5, 0.5);
                     let fuzz: f64 = rand.gen_range(0.0..0.5);
-                    Arc::new(Metal::new(albedo, fuzz))
+                    Arc::new(Metal::new(albedo, fuzz)?)
                 } else {
                     // glass
-                    Arc::new(Dielectric { ri: 1.5 })
+                    Arc::new(Dielectric::new(1.5)?)
                 };
 
-                world.push(Sphere {
-                    center,
-                    radius: 0.2,
-                    material,
-                })
+                world.push(Sphere::new(center, 0.2, material)?)
             }
         }
     }
 
-    let material1 = Arc::new(Dielectric { ri: 1.5 });
-    let material2 = Arc::new(Lambertian {
-        albedo: Color::new(0.4, 0.2, 0.1),
-    });
-    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    let material1 = Arc::new(Dielectric::new(1.5)?);
+    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))?);
+    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0)?);
 
-    world.push(Sphere {
-        center: Point3::new(0.0, 1.0, 0.0),
-        radius: 1.0,
-        material: material1,
-    });
-    world.push(Sphere {
-        center: Point3::new(-4.0, 1.0, 0.0),
-        radius: 1.0,
-        material: material2,
-    });
-    world.push(Sphere {
-        center: Point3::new(4.0, 1.0, 0.0),
-        radius: 1.0,
-        material: material3,
-    });
+    world.push(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, material1)?);
+    world.push(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, material2)?);
+    world.push(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, material3)?);
 
-    Arc::new(world)
+    Ok(Arc::new(world))
 }
@@ -0,0 +1,141 @@
+//! Estimates of how much memory a render takes, made before it starts, so that a scene too big
+//! for the machine fails right away instead of running out of memory halfway through.
+//!
+//! Objects report what they hold through [`Hittable::memory`]: their own size by default, and
+//! whatever they keep on the heap when they say so. Objects shared by several others, like the
+//! prototype of a thousand instances, are counted once. The estimate leaves out what the
+//! renderer allocates along the way, like the photon map, which is small next to big scenes.
+
+use crate::collision::Hittable;
+use crate::render::Pixel;
+use crate::{Error, Result};
+
+use serde::Serialize;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::size_of;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MemoryUsage {
+    /// The objects themselves: triangles, curves, voxels, volumes and everything else.
+    pub geometry: usize,
+    /// The nodes of BVHs.
+    pub bvh: usize,
+    /// The images of textures, with their mipmaps.
+    pub textures: usize,
+    /// The rendered pixels, and the film they're added to.
+    pub framebuffer: usize,
+    /// Objects counted already.
+    #[serde(skip)]
+    seen: HashSet<usize>,
+}
+
+impl MemoryUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn total(&self) -> usize {
+        self.geometry + self.bvh + self.textures + self.framebuffer
+    }
+
+    /// Adds everything `object` holds.
+    pub fn add_object<T: Hittable + ?Sized>(&mut self, object: &T) {
+        object.memory(self);
+    }
+
+    /// Adds the pixels of a `width` by `height` render, and the film they go onto.
+    pub fn add_framebuffer(&mut self, width: u32, height: u32) {
+        let pixels = width as usize * height as usize;
+        self.framebuffer += pixels * (2 * size_of::<Pixel>() + size_of::<u32>());
+    }
+
+    /// Whether the object at `address` hasn't been counted yet, marking it as counted. For
+    /// objects shared between others, which take up memory only once.
+    pub fn first_visit<T: ?Sized>(&mut self, address: *const T) -> bool {
+        self.seen.insert(address as *const () as usize)
+    }
+
+    /// Fails if the estimate is over `budget` bytes.
+    pub fn check(&self, budget: usize) -> Result<()> {
+        if self.total() > budget {
+            return Err(Error::MemoryBudget {
+                estimate: self.total(),
+                budget,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of geometry, {} of BVH nodes, {} of textures and {} of framebuffer",
+            format_bytes(self.total()),
+            format_bytes(self.geometry),
+            format_bytes(self.bvh),
+            format_bytes(self.textures),
+            format_bytes(self.framebuffer)
+        )
+    }
+}
+
+/// A number of bytes in the largest binary unit that keeps it at least 1, like `1.5 GiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::bvh::Bvh;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::objects::Sphere;
+    use crate::{Color, Point3};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn shared_objects_are_counted_once() {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap());
+        let sphere = || -> Arc<dyn Hittable + Send + Sync> {
+            Arc::new(Sphere::new(Point3::default(), 1.0, material.clone()).unwrap())
+        };
+        let shared = sphere();
+
+        let mut once = MemoryUsage::new();
+        once.add_object(&Bvh::new(vec![shared.clone(), shared]));
+        let mut twice = MemoryUsage::new();
+        twice.add_object(&Bvh::new(vec![sphere(), sphere()]));
+
+        // the second sphere, with its reference counts
+        let sphere = size_of::<Sphere>() + 2 * size_of::<usize>();
+        assert_eq!(twice.geometry - once.geometry, sphere);
+        assert_eq!(twice.bvh, once.bvh);
+    }
+
+    #[test]
+    fn budgets_fail_fast() {
+        let mut usage = MemoryUsage::new();
+        usage.add_framebuffer(1920, 1080);
+        assert!(usage.framebuffer > 1920 * 1080 * size_of::<Pixel>());
+        assert!(usage.check(usage.total()).is_ok());
+        assert!(usage.check(usage.total() - 1).is_err());
+
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(3 << 29), "1.5 GiB");
+    }
+}
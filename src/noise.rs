@@ -0,0 +1,193 @@
+//! Smooth pseudo-random functions of space, for procedural textures and volumes.
+
+use crate::{Point3, Vec3};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+const POINTS: usize = 256;
+
+/// Perlin gradient noise, the same for the same seed.
+#[derive(Clone)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINTS)
+            .map(|_| Vec3::random_unit_vec(&mut rng))
+            .collect();
+        let mut permutation = || {
+            let mut permutation = (0..POINTS).collect::<Vec<_>>();
+            permutation.shuffle(&mut rng);
+            permutation
+        };
+
+        Self {
+            gradients,
+            permutations: [permutation(), permutation(), permutation()],
+        }
+    }
+
+    /// Noise between -1 and 1, changing over distances of about 1.
+    pub fn noise(&self, point: Point3) -> f64 {
+        let floor = point.0.map(f64::floor);
+        let fraction = [0, 1, 2].map(|axis| point[axis] - floor[axis]);
+        // wrapping the lattice every POINTS units
+        let cell = floor.map(|f| f.rem_euclid(POINTS as f64) as usize);
+
+        let mut sum = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let hash = (0..3)
+                .map(|axis| self.permutations[axis][(cell[axis] + offset[axis]) % POINTS])
+                .fold(0, |hash, index| hash ^ index);
+
+            let mut weight = 1.0;
+            let mut to_point = Vec3::default();
+            for axis in 0..3 {
+                // hermite smoothing keeps the noise free of creases between cells
+                let t = fraction[axis];
+                let smooth = t * t * (3.0 - 2.0 * t);
+                weight *= if offset[axis] == 1 {
+                    smooth
+                } else {
+                    1.0 - smooth
+                };
+                to_point[axis] = t - offset[axis] as f64;
+            }
+            sum += weight * self.gradients[hash].dot(&to_point);
+        }
+
+        sum.clamp(-1.0, 1.0)
+    }
+
+    /// The sum of `octaves` layers of the absolute noise, each at twice the frequency and half
+    /// the weight of the last. Always between 0 and 2.
+    pub fn turbulence(&self, point: Point3, octaves: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut point = point;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(point).abs();
+            point *= 2.0;
+            weight *= 0.5;
+        }
+
+        sum
+    }
+}
+
+/// Worley's cellular noise: feature points scattered one to a unit cell, and the distances to
+/// the nearest ones. The same for the same seed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Worley {
+    seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// The distances from `point` to the nearest feature point and to the second nearest one.
+    pub fn distances(&self, point: Point3) -> (f64, f64) {
+        let cell = point.0.map(f64::floor);
+
+        let mut nearest = (f64::INFINITY, f64::INFINITY);
+        // the two nearest points are always within the neighbouring cells
+        for neighbour in 0..27 {
+            let offset = [neighbour % 3, neighbour / 3 % 3, neighbour / 9].map(|o| o as f64 - 1.0);
+            let corner = Point3::new(
+                cell[0] + offset[0],
+                cell[1] + offset[1],
+                cell[2] + offset[2],
+            );
+            let distance = (self.feature_point(corner) - point).length();
+
+            if distance < nearest.0 {
+                nearest = (distance, nearest.0);
+            } else if distance < nearest.1 {
+                nearest.1 = distance;
+            }
+        }
+
+        nearest
+    }
+
+    /// The feature point of the cell with the lowest corner at `corner`.
+    fn feature_point(&self, corner: Point3) -> Point3 {
+        let mut hash = self.seed;
+        for &c in &corner.0 {
+            hash = splitmix(hash ^ c as i64 as u64);
+        }
+
+        let mut jitter = Vec3::default();
+        for axis in 0..3 {
+            hash = splitmix(hash);
+            jitter[axis] = (hash >> 11) as f64 / (1u64 << 53) as f64;
+        }
+
+        corner + jitter
+    }
+}
+
+/// Scrambles the bits of `x`, from the SplitMix64 generator.
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_smooth_and_seeded() {
+        let perlin = Perlin::new(7);
+        let other = Perlin::new(8);
+
+        let mut differs = false;
+        for i in 0..1000 {
+            let point = Point3::new(i as f64 * 0.137, i as f64 * -0.071, i as f64 * 0.053);
+            let value = perlin.noise(point);
+            assert!((-1.0..=1.0).contains(&value));
+            assert_eq!(value, Perlin::new(7).noise(point));
+            differs |= value != other.noise(point);
+
+            let nearby = perlin.noise(point + Vec3::new(1e-4, 1e-4, 1e-4));
+            assert!((value - nearby).abs() < 1e-3);
+            assert!((0.0..2.0).contains(&perlin.turbulence(point, 6)));
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn vanishes_on_the_lattice() {
+        let perlin = Perlin::new(0);
+        assert_eq!(perlin.noise(Point3::new(3.0, -2.0, 17.0)), 0.0);
+    }
+
+    #[test]
+    fn worley_distances_are_ordered_and_continuous() {
+        let worley = Worley::new(3);
+        let mut previous = worley.distances(Point3::default());
+        for i in 1..2000 {
+            let point = Point3::new(i as f64 * 1e-3, i as f64 * 2e-3, -(i as f64) * 1e-3);
+            let (f1, f2) = worley.distances(point);
+            assert!(f1 <= f2 && f1 < 3.0f64.sqrt());
+            assert!((f1 - previous.0).abs() < 1e-2);
+            previous = (f1, f2);
+        }
+        assert_ne!(
+            Worley::new(4).distances(Point3::default()),
+            worley.distances(Point3::default())
+        );
+    }
+}
@@ -0,0 +1,610 @@
+//! Triangle meshes from Wavefront OBJ files, along with the materials of the MTL files they
+//! refer to.
+//!
+//! Only faces are read, so points, lines, groups and smoothing groups are skipped. Polygons are
+//! split into fans of triangles, unless they're subdivided first. Every MTL material becomes the
+//! closest one the renderer has:
+//!
+//! - with an emissive color `Ke`, a diffuse light,
+//! - with a dissolve `d` below 1, a dielectric with the index of refraction `Ni`,
+//! - with a diffuse texture `map_Kd`, a lambertian with that texture,
+//! - with a specular color `Ks` brighter than the diffuse color `Kd`, a metal that gets fuzzier as
+//!   the specular exponent `Ns` goes down,
+//! - and otherwise a lambertian with the diffuse color.
+
+use crate::collision::materials::Lambertian;
+use crate::collision::objects::Triangle;
+use crate::collision::Material;
+use crate::library::TextureCache;
+use crate::scene::{MaterialDescription, TextureDescription};
+use crate::subdivision::{Polygon, PolygonMesh};
+use crate::{Color, Error, Point3, Result, Vec3};
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+/// The line a parse error is on, and what's wrong with it.
+type ParseError = (usize, String);
+
+/// Loads the triangles of the OBJ file at `path`. They all get `material` if it's given, and the
+/// materials from the MTL files otherwise, with light gray for faces without one. Faces whose MTL
+/// file or material is missing are warned about and left light gray too.
+///
+/// With `subdivisions` above 0, the polygons are smoothed by that many steps of Catmull-Clark
+/// subdivision, and the normals of the file are replaced by the ones of the smooth surface. Each
+/// step makes four times as many triangles, so it fails above 6.
+///
+/// Images the materials use come from `textures` if they're already loaded, and are added to it
+/// otherwise, so meshes sharing them load them once.
+pub fn load(
+    path: &Path,
+    material: Option<Arc<dyn Material>>,
+    subdivisions: u32,
+    textures: &TextureCache,
+) -> Result<Vec<Triangle>> {
+    Error::check("subdivisions", subdivisions as f64, |levels| levels <= 6.0)?;
+    let obj = parse_obj(&read(path)?).map_err(at(path))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials = HashMap::new();
+    if material.is_none() {
+        for library in &obj.libraries {
+            let library = dir.join(library);
+            let library_dir = library.parent().unwrap_or(dir);
+            let text = match read(&library) {
+                Ok(text) => text,
+                Err(err) => {
+                    log::warn!("{}, leaving its materials light gray", err);
+                    continue;
+                }
+            };
+            for (name, description) in parse_mtl(&text, library_dir).map_err(at(&library))? {
+                materials.insert(name, description.build_with(textures)?);
+            }
+        }
+    }
+    let default: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))?);
+
+    // looked up once per name, so that a missing material is only warned about once
+    let used: Vec<_> = obj
+        .material_names
+        .iter()
+        .map(|name| {
+            materials.get(name).cloned().unwrap_or_else(|| {
+                if material.is_none() {
+                    log::warn!(
+                        "{} uses the unknown material {:?}, leaving it light gray",
+                        path.display(),
+                        name
+                    );
+                }
+                default.clone()
+            })
+        })
+        .collect();
+    let material_of = |face: &Face| match (&material, face.material) {
+        (Some(material), _) => material.clone(),
+        (None, Some(index)) => used[index].clone(),
+        (None, None) => default.clone(),
+    };
+
+    if subdivisions > 0 {
+        let mut mesh = obj.polygon_mesh(material_of);
+        for _ in 0..subdivisions {
+            mesh = mesh.subdivide();
+        }
+        return mesh.triangles();
+    }
+
+    let mut triangles = Vec::with_capacity(obj.faces.len());
+    for face in &obj.faces {
+        triangles.push(obj.triangle(face, material_of(face))?);
+    }
+
+    Ok(triangles)
+}
+
+/// How many triangles the OBJ file at `path` has, and how many of them have no area, without
+/// loading its materials or building anything.
+pub fn stats(path: &Path) -> Result<MeshStats> {
+    let obj = parse_obj(&read(path)?).map_err(at(path))?;
+    let degenerate = obj
+        .faces
+        .iter()
+        .filter(|face| {
+            let [a, b, c] = face.vertices.map(|vertex| obj.positions[vertex.position]);
+            let area = (b - a).cross(&(c - a)).length();
+            !area.is_finite() || area == 0.0
+        })
+        .count();
+
+    Ok(MeshStats {
+        triangles: obj.faces.len(),
+        degenerate,
+    })
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    pub triangles: usize,
+    /// Triangles whose corners are all on a line, or with coordinates that aren't finite.
+    pub degenerate: usize,
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(Error::file(path))
+}
+
+/// For use with `map_err`, turning a parse error into an [`Error::Mesh`] in the file at `path`.
+fn at(path: &Path) -> impl FnOnce(ParseError) -> Error + '_ {
+    move |(line, message)| Error::Mesh {
+        path: path.to_owned(),
+        line,
+        message,
+    }
+}
+
+#[derive(Default)]
+struct Obj {
+    positions: Vec<Point3>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    faces: Vec<Face>,
+    /// The faces each polygon was split into.
+    polygons: Vec<Range<usize>>,
+    /// The MTL files, relative to the OBJ file.
+    libraries: Vec<PathBuf>,
+    /// The materials faces refer to by index.
+    material_names: Vec<String>,
+}
+
+/// A triangle, with every index already checked to be in range.
+struct Face {
+    vertices: [Vertex; 3],
+    material: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+impl Obj {
+    fn triangle(&self, face: &Face, material: Arc<dyn Material>) -> Result<Triangle> {
+        let [a, b, c] = face.vertices;
+        let mut triangle = Triangle::new(
+            [
+                self.positions[a.position],
+                self.positions[b.position],
+                self.positions[c.position],
+            ],
+            material,
+        )?;
+
+        if let (Some(a), Some(b), Some(c)) = (a.normal, b.normal, c.normal) {
+            triangle = triangle.with_normals([self.normals[a], self.normals[b], self.normals[c]]);
+        }
+        if let (Some(a), Some(b), Some(c)) = (a.uv, b.uv, c.uv) {
+            triangle = triangle.with_uvs([self.uvs[a], self.uvs[b], self.uvs[c]]);
+        }
+
+        Ok(triangle)
+    }
+
+    /// The polygons of the file before they were split into triangles, for subdividing. Their
+    /// texture coordinates are kept if all of their corners have some.
+    fn polygon_mesh(&self, material: impl Fn(&Face) -> Arc<dyn Material>) -> PolygonMesh {
+        let mut polygons = Vec::with_capacity(self.polygons.len());
+        for range in &self.polygons {
+            // a fan shares the first corner, and adds one more with every triangle
+            let faces = &self.faces[range.clone()];
+            let mut vertices = faces[0].vertices.to_vec();
+            vertices.extend(faces[1..].iter().map(|face| face.vertices[2]));
+
+            let uvs = vertices
+                .iter()
+                .map(|vertex| vertex.uv.map(|uv| self.uvs[uv]))
+                .collect();
+            polygons.push(Polygon {
+                corners: vertices.iter().map(|vertex| vertex.position).collect(),
+                uvs,
+                material: material(&faces[0]),
+            });
+        }
+
+        PolygonMesh {
+            positions: self.positions.clone(),
+            polygons,
+        }
+    }
+
+    /// Parses a face vertex like `1`, `1/2`, `1//3` or `1/2/3`.
+    fn vertex(&self, word: &str) -> Result<Vertex, String> {
+        let mut indices = word.split('/');
+        let position = index(indices.next().unwrap_or(""), self.positions.len())?;
+        let mut optional = |count| match indices.next() {
+            None | Some("") => Ok(None),
+            Some(text) => index(text, count).map(Some),
+        };
+
+        Ok(Vertex {
+            position,
+            uv: optional(self.uvs.len())?,
+            normal: optional(self.normals.len())?,
+        })
+    }
+}
+
+fn parse_obj(text: &str) -> Result<Obj, ParseError> {
+    let mut obj = Obj::default();
+    let mut material_indices = HashMap::new();
+    let mut material = None;
+
+    for (number, line) in lines(text) {
+        let fail = |message: String| (number, message);
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or("");
+        let rest = line[keyword.len()..].trim();
+
+        match keyword {
+            "v" => obj.positions.push(vector(&mut words).map_err(fail)?),
+            "vn" => obj.normals.push(vector(&mut words).map_err(fail)?),
+            "vt" => {
+                let u = number_in(words.next()).map_err(fail)?;
+                let v = words.next().map_or(Ok(0.0), |v| number_in(Some(v)));
+                obj.uvs.push((u, v.map_err(fail)?));
+            }
+            "f" => {
+                let vertices = words
+                    .map(|word| obj.vertex(word))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(fail)?;
+                if vertices.len() < 3 {
+                    return Err(fail(String::from("a face needs at least 3 vertices")));
+                }
+
+                let first = obj.faces.len();
+                for i in 1..vertices.len() - 1 {
+                    obj.faces.push(Face {
+                        vertices: [vertices[0], vertices[i], vertices[i + 1]],
+                        material,
+                    });
+                }
+                obj.polygons.push(first..obj.faces.len());
+            }
+            "mtllib" => obj.libraries.extend(words.map(PathBuf::from)),
+            "usemtl" => {
+                let next = obj.material_names.len();
+                let index = *material_indices.entry(rest.to_owned()).or_insert(next);
+                if index == next {
+                    obj.material_names.push(rest.to_owned());
+                }
+                material = Some(index);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(obj)
+}
+
+/// An MTL material, with the defaults of the format for anything not given.
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    exponent: f64,
+    dissolve: f64,
+    ior: Option<f64>,
+    texture: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            emission: Color::new(0.0, 0.0, 0.0),
+            exponent: 0.0,
+            dissolve: 1.0,
+            ior: None,
+            texture: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn describe(self) -> MaterialDescription {
+        let brightest = |color: Color| color.x().max(color.y()).max(color.z());
+        let clamp = |color: Color| {
+            Color::new(
+                color.x().clamp(0.0, 1.0),
+                color.y().clamp(0.0, 1.0),
+                color.z().clamp(0.0, 1.0),
+            )
+        };
+
+        if brightest(self.emission) > 0.0 {
+            MaterialDescription::DiffuseLight {
+                emit: self.emission,
+                double_sided: true,
+                illuminant: None,
+                ies: None,
+                texture: None,
+            }
+        } else if self.dissolve < 1.0 {
+            MaterialDescription::Dielectric {
+                ri: self.ior.unwrap_or(1.5),
+            }
+        } else if let Some(texture) = self.texture {
+            MaterialDescription::Textured {
+                texture: TextureDescription::Image(texture),
+            }
+        } else if brightest(self.specular) > brightest(self.diffuse) {
+            // a sharp highlight has a high exponent, which makes for a near-perfect mirror
+            MaterialDescription::Metal {
+                albedo: clamp(self.specular),
+                fuzz: (2.0 / (self.exponent.max(0.0) + 2.0)).sqrt(),
+            }
+        } else {
+            MaterialDescription::Lambertian {
+                albedo: clamp(self.diffuse),
+            }
+        }
+    }
+}
+
+/// Parses the materials of an MTL file, with textures relative to `dir`.
+fn parse_mtl(text: &str, dir: &Path) -> Result<Vec<(String, MaterialDescription)>, ParseError> {
+    let mut materials = Vec::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (number, line) in lines(text) {
+        let fail = |message: String| (number, message);
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or("");
+        let rest = line[keyword.len()..].trim();
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.push((name, material.describe()));
+            }
+            current = Some((rest.to_owned(), MtlMaterial::default()));
+            continue;
+        }
+        let material = match &mut current {
+            Some((_, material)) => material,
+            // nothing to apply it to
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = color(&mut words).map_err(fail)?,
+            "Ks" => material.specular = color(&mut words).map_err(fail)?,
+            "Ke" => material.emission = color(&mut words).map_err(fail)?,
+            "Ns" => material.exponent = number_in(words.next()).map_err(fail)?,
+            "d" => material.dissolve = number_in(words.next()).map_err(fail)?,
+            "Tr" => material.dissolve = 1.0 - number_in(words.next()).map_err(fail)?,
+            "Ni" => material.ior = Some(number_in(words.next()).map_err(fail)?),
+            "map_Kd" => {
+                // options like `-s 2 2 1` come before the file name
+                let file = if rest.starts_with('-') {
+                    rest.split_whitespace().last().unwrap_or("")
+                } else {
+                    rest
+                };
+                material.texture = Some(dir.join(file));
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.push((name, material.describe()));
+    }
+
+    Ok(materials)
+}
+
+/// The non-empty lines of an OBJ or MTL file with their line numbers, without comments.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn number_in(word: Option<&str>) -> Result<f64, String> {
+    let word = word.ok_or("expected a number")?;
+    word.parse()
+        .map_err(|_| format!("expected a number, found {:?}", word))
+}
+
+fn vector(words: &mut SplitWhitespace) -> Result<Vec3, String> {
+    Ok(Vec3::new(
+        number_in(words.next())?,
+        number_in(words.next())?,
+        number_in(words.next())?,
+    ))
+}
+
+/// An RGB color, where a single number is a shade of gray.
+fn color(words: &mut SplitWhitespace) -> Result<Color, String> {
+    let r = number_in(words.next())?;
+    match words.next() {
+        Some(g) => Ok(Color::new(r, number_in(Some(g))?, number_in(words.next())?)),
+        None => Ok(Color::new(r, r, r)),
+    }
+}
+
+/// Resolves a 1-based index, or one counting back from the end if it's negative.
+fn index(text: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = text
+        .parse()
+        .map_err(|_| format!("expected an index, found {:?}", text))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        Err(format!("index {} is out of range", index))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "\
+# a unit square, split in two
+mtllib square.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl blue
+f -4//1 -3//1 -2//1
+";
+
+    #[test]
+    fn polygons_become_fans() {
+        let obj = parse_obj(QUAD).unwrap();
+
+        assert_eq!(obj.positions.len(), 4);
+        assert_eq!(obj.libraries, [PathBuf::from("square.mtl")]);
+        assert_eq!(obj.material_names, ["red", "blue"]);
+        assert_eq!(obj.faces.len(), 3);
+
+        let positions: Vec<_> = obj
+            .faces
+            .iter()
+            .map(|face| face.vertices.map(|vertex| vertex.position))
+            .collect();
+        assert_eq!(positions, [[0, 1, 2], [0, 2, 3], [0, 1, 2]]);
+        assert_eq!(obj.polygons, [0..2, 2..3]);
+        assert_eq!(obj.faces[1].material, Some(0));
+        assert_eq!(obj.faces[2].material, Some(1));
+    }
+
+    #[test]
+    fn missing_materials_are_light_gray() {
+        let dir = std::env::temp_dir().join(format!("obj-materials-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("square.obj");
+        std::fs::write(&path, QUAD).unwrap();
+        let load = || load(&path, None, 0, &TextureCache::default()).unwrap();
+
+        // square.mtl isn't there at all
+        let triangles = load();
+        assert_eq!(triangles.len(), 3);
+        assert!(triangles
+            .iter()
+            .all(|triangle| Arc::ptr_eq(&triangle.material, &triangles[0].material)));
+
+        // and then it only has red
+        std::fs::write(dir.join("square.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        let triangles = load();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(Arc::ptr_eq(&triangles[0].material, &triangles[1].material));
+        assert!(!Arc::ptr_eq(&triangles[0].material, &triangles[2].material));
+    }
+
+    #[test]
+    fn vertices_may_skip_uvs_and_normals() {
+        let obj = parse_obj(QUAD).unwrap();
+
+        let first = obj.faces[0].vertices[2];
+        assert_eq!((first.uv, first.normal), (Some(2), Some(0)));
+        let last = obj.faces[2].vertices[2];
+        assert_eq!((last.uv, last.normal), (None, Some(0)));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let missing = "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n";
+        assert_eq!(
+            parse_obj(missing).err(),
+            Some((4, String::from("index 3 is out of range")))
+        );
+
+        let zero = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n";
+        assert!(parse_obj(zero).is_err());
+        let line = "v 0 0 0\nv 1 0 0\nf 1 2\n";
+        assert!(parse_obj(line).is_err());
+        let garbage = "v 0 zero 0\n";
+        assert_eq!(parse_obj(garbage).err().map(|(line, _)| line), Some(1));
+    }
+
+    #[test]
+    fn materials_map_to_the_closest_kind() {
+        let mtl = "\
+Kd 1 1 1
+newmtl matte
+Kd 0.5 0.25 0
+newmtl mirror
+Kd 0.1
+Ks 0.9 0.9 0.9
+Ns 1000
+newmtl glass
+d 0.2
+Ni 1.33
+newmtl lamp
+Ke 4 4 4
+newmtl painted
+map_Kd -s 2 2 1 wood.png
+";
+        let materials: HashMap<_, _> = parse_mtl(mtl, Path::new("textures"))
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            materials["matte"],
+            MaterialDescription::Lambertian {
+                albedo: Color::new(0.5, 0.25, 0.0)
+            }
+        );
+        match materials["mirror"] {
+            MaterialDescription::Metal { albedo, fuzz } => {
+                assert_eq!(albedo, Color::new(0.9, 0.9, 0.9));
+                assert!(fuzz < 0.05);
+            }
+            ref other => panic!("expected a metal, got {:?}", other),
+        }
+        assert_eq!(
+            materials["glass"],
+            MaterialDescription::Dielectric { ri: 1.33 }
+        );
+        assert_eq!(
+            materials["lamp"],
+            MaterialDescription::DiffuseLight {
+                emit: Color::new(4.0, 4.0, 4.0),
+                double_sided: true,
+                illuminant: None,
+                ies: None,
+                texture: None,
+            }
+        );
+        assert_eq!(
+            materials["painted"],
+            MaterialDescription::Textured {
+                texture: TextureDescription::Image(Path::new("textures").join("wood.png"))
+            }
+        );
+    }
+}
@@ -0,0 +1,810 @@
+//! Writing rendered pixels to image files.
+
+use crate::bloom::Bloom;
+use crate::color_space::{ColorSpace, Conversion};
+use crate::cryptomatte::{self, Layer};
+use crate::deep::DeepSample;
+use crate::film::Film;
+use crate::lut::Lut;
+use crate::render::{Pixel, Region};
+use crate::{Color, Error, Result};
+
+use exr::meta::attribute::Chromaticities;
+use exr::prelude::{
+    AnyChannel, AnyChannels, AttributeValue, Encoding, FlatSamples, LayerAttributes, SmallVec,
+    SpecificChannels, Text, Vec2, WritableImage,
+};
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb, Rgba};
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SaveOptions {
+    /// Write an alpha channel, if the format supports one.
+    pub alpha: bool,
+    /// Use 16 bits per channel instead of 8 for PNG and PPM files.
+    pub sixteen_bit: bool,
+    /// Write PPM files in the plain-text variant (P3) rather than binary (P6).
+    pub plain: bool,
+    /// A look applied to the encoded colors of 8 and 16-bit images. EXR files are left
+    /// linear, for the table to be applied when they're graded.
+    pub lut: Option<Arc<Lut>>,
+    /// A glow around the brightest lights, in every format.
+    pub bloom: Option<Bloom>,
+    /// The space to save the colors in. EXR files are always linear, but take its primaries.
+    pub color_space: ColorSpace,
+}
+
+/// Saves the pixels returned by the renderer, picking the format from the file extension.
+///
+/// Supported formats are PNG, PPM and EXR. EXR files always store linear RGBA with premultiplied
+/// alpha, the rest are encoded with the curve of the color space, sRGB by default.
+pub fn save(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    options: &SaveOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    let pixels = look(width, height, pixels, options, !is_exr(path));
+    write(path, width, height, &pixels, options)
+}
+
+fn is_exr(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"))
+}
+
+/// Writes pixels that already have the [`look`] of the options, picking the format from the file
+/// extension.
+fn write(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    options: &SaveOptions,
+) -> Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("exr") => write_exr(File::create(path)?, width, height, pixels, options)?,
+        Some("ppm") => save_ppm(path, width, height, pixels, options).map_err(Error::file(path))?,
+        _ => to_image(width, height, pixels, options).save(path)?,
+    }
+
+    Ok(())
+}
+
+/// Reads back an image written by [`write`], as the pixels it was written from: in the linear
+/// light of the color space of the options, with premultiplied alpha. Images without alpha are
+/// opaque.
+fn read(path: &Path, options: &SaveOptions) -> Result<(u32, u32, Vec<Pixel>)> {
+    if is_exr(path) {
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |size, _| (size, vec![Pixel::default(); size.area()]),
+            |(size, pixels), Vec2(x, y), (r, g, b, a): (f32, f32, f32, f32)| {
+                // the rows are stored bottom to top, like in pixel_at
+                pixels[(size.height() - 1 - y) * size.width() + x] = Pixel {
+                    color: Color::new(r as f64, g as f64, b as f64),
+                    alpha: a as f64,
+                    time: Duration::ZERO,
+                };
+            },
+        )?;
+        let (size, pixels) = image.layer_data.channel_data.pixels;
+        return Ok((size.width() as u32, size.height() as u32, pixels));
+    }
+
+    let image = image::open(path)?.into_rgba16();
+    let (width, height) = image.dimensions();
+    let space = options.color_space;
+    let pixels = (0..height)
+        .rev()
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let [r, g, b, a] = image.get_pixel(x, y).0.map(|c| c as f64 / 65535.0);
+            Pixel {
+                color: a * Color::new(space.decode(r), space.decode(g), space.decode(b)),
+                alpha: a,
+                time: Duration::ZERO,
+            }
+        })
+        .collect();
+    Ok((width, height, pixels))
+}
+
+/// The formats [`encode`] supports.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    Exr,
+}
+
+/// Encodes the pixels into an image file in memory, the same way [`save`] writes them.
+pub fn encode(
+    format: Format,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    options: &SaveOptions,
+) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    match format {
+        Format::Png => to_image(
+            width,
+            height,
+            &look(width, height, pixels, options, true),
+            options,
+        )
+        .write_to(&mut bytes, ImageOutputFormat::Png)?,
+        Format::Exr => write_exr(
+            &mut bytes,
+            width,
+            height,
+            &look(width, height, pixels, options, false),
+            options,
+        )?,
+    }
+
+    Ok(bytes.into_inner())
+}
+
+fn write_exr(
+    out: impl Write + Seek,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    options: &SaveOptions,
+) -> exr::error::UnitResult {
+    let channels = SpecificChannels::rgba(|Vec2(x, y)| {
+        let pixel = pixel_at(pixels, width, height, x as u32, y as u32);
+        let [r, g, b] = pixel.color.0;
+        (r as f32, g as f32, b as f32, pixel.alpha as f32)
+    });
+
+    let mut image = exr::prelude::Image::from_channels((width as usize, height as usize), channels);
+    let [red, green, blue, white] = options
+        .color_space
+        .chromaticities()
+        .map(|[x, y]| Vec2(x as f32, y as f32));
+    image.attributes.chromaticities = Some(Chromaticities {
+        red,
+        green,
+        blue,
+        white,
+    });
+    image.write().to_buffered(out)
+}
+
+/// Saves Cryptomatte ID layers to an EXR file, with their manifests in the header. Each layer
+/// takes a set of RGBA channels for every two ranks of IDs, like `CryptoObject00.R`.
+pub fn save_cryptomatte(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    layers: &[Layer],
+) -> Result<()> {
+    let mut channels = Vec::new();
+    let mut attributes = LayerAttributes::default();
+    for layer in layers {
+        for rank in 0..cryptomatte::RANKS {
+            let samples = |value: fn(&(f32, f32)) -> f32| {
+                let samples = (0..width * height)
+                    .map(|i| {
+                        value(&pixel_at(&layer.pixels, width, height, i % width, i / width)[rank])
+                    })
+                    .collect();
+                FlatSamples::F32(samples)
+            };
+            let (id, coverage) = if rank % 2 == 0 {
+                ("R", "G")
+            } else {
+                ("B", "A")
+            };
+            let set = format!("{}{:02}", layer.name, rank / 2);
+            channels.push(AnyChannel::new(
+                format!("{}.{}", set, id).as_str(),
+                samples(|p| p.0),
+            ));
+            channels.push(AnyChannel::new(
+                format!("{}.{}", set, coverage).as_str(),
+                samples(|p| p.1),
+            ));
+        }
+
+        let key = layer.key();
+        for (field, value) in [
+            ("name", layer.name.clone()),
+            ("hash", "MurmurHash3_32".to_owned()),
+            ("conversion", "uint32_to_float32".to_owned()),
+            ("manifest", layer.manifest_json()),
+        ] {
+            // Cryptomatte wants UTF-8, where EXR only knows bytes
+            attributes.other.insert(
+                Text::from(format!("cryptomatte/{}/{}", key, field).as_str()),
+                AttributeValue::Text(Text::from_slice_unchecked(value.as_bytes())),
+            );
+        }
+    }
+
+    // the IDs are hashes, which only survive lossless compression at full precision
+    let layer = exr::prelude::Layer::new(
+        (width as usize, height as usize),
+        attributes,
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+    exr::prelude::Image::from_layer(layer)
+        .write()
+        .to_buffered(BufWriter::new(File::create(path)?))?;
+
+    Ok(())
+}
+
+/// Saves deep pixels, as returned by [`deep::render`](crate::deep::render), to a deep scanline
+/// EXR file with premultiplied RGBA and Z channels.
+pub fn save_deep(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[Vec<DeepSample>],
+) -> Result<()> {
+    let path = path.as_ref();
+    write_deep_exr(path, width, height, pixels).map_err(Error::file(path))
+}
+
+/// The exr crate can't write deep images yet, so this writes the file by hand: uncompressed, with
+/// one scanline to a chunk, which everything reading deep EXR files understands.
+fn write_deep_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[Vec<DeepSample>],
+) -> std::io::Result<()> {
+    // in the alphabetical order the samples are stored in
+    type Channel = fn(&DeepSample) -> f32;
+    let channels: [(&str, Channel); 5] = [
+        ("A", |sample| sample.alpha as f32),
+        ("B", |sample| sample.color.z() as f32),
+        ("G", |sample| sample.color.y() as f32),
+        ("R", |sample| sample.color.x() as f32),
+        ("Z", |sample| sample.depth as f32),
+    ];
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        for text in [name, kind] {
+            header.extend_from_slice(text.as_bytes());
+            header.push(0);
+        }
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // 32-bit floats, not perceptually linear, sampled at every pixel
+        for value in [2, 0, 1, 1] {
+            list.extend_from_slice(&i32::to_le_bytes(value));
+        }
+    }
+    list.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|coordinate| coordinate.to_le_bytes())
+        .collect();
+    let max_samples = pixels.iter().map(Vec::len).max().unwrap_or(0) as i32;
+    attribute("channels", "chlist", &list);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    attribute("type", "string", b"deepscanline");
+    attribute("version", "int", &1i32.to_le_bytes());
+    attribute("maxSamplesPerPixel", "int", &max_samples.to_le_bytes());
+    attribute("chunkCount", "int", &(height as i32).to_le_bytes());
+    header.push(0);
+
+    let chunks: Vec<Vec<u8>> = (0..height)
+        .map(|y| {
+            let line: Vec<&[DeepSample]> = (0..width)
+                .map(|x| pixel_at(pixels, width, height, x, y).as_slice())
+                .collect();
+
+            // how many samples there are up to and including each pixel
+            let mut counts = Vec::with_capacity(4 * width as usize);
+            let mut total = 0;
+            for samples in &line {
+                total += samples.len() as i32;
+                counts.extend_from_slice(&total.to_le_bytes());
+            }
+            let mut data = Vec::with_capacity(4 * channels.len() * total as usize);
+            for (_, value) in &channels {
+                for sample in line.iter().flat_map(|samples| samples.iter()) {
+                    data.extend_from_slice(&value(sample).to_le_bytes());
+                }
+            }
+
+            let mut chunk = Vec::with_capacity(28 + counts.len() + data.len());
+            chunk.extend_from_slice(&(y as i32).to_le_bytes());
+            chunk.extend_from_slice(&(counts.len() as u64).to_le_bytes());
+            // uncompressed, so the packed and unpacked data are the same size
+            for _ in 0..2 {
+                chunk.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            }
+            chunk.extend_from_slice(&counts);
+            chunk.extend_from_slice(&data);
+            chunk
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    // the magic number, and version 2 with the flag for deep data
+    out.write_all(&20000630i32.to_le_bytes())?;
+    out.write_all(&(2u32 | 1 << 11).to_le_bytes())?;
+    out.write_all(&header)?;
+    let mut offset = (8 + header.len() + 8 * chunks.len()) as u64;
+    for chunk in &chunks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += chunk.len() as u64;
+    }
+    for chunk in &chunks {
+        out.write_all(chunk)?;
+    }
+
+    out.flush()
+}
+
+/// Converts the pixels to an 8 or 16-bit image, encoded with the curve of the color space and
+/// with straight alpha.
+fn to_image(width: u32, height: u32, pixels: &[Pixel], options: &SaveOptions) -> DynamicImage {
+    let pixel = |x, y| pixel_at(pixels, width, height, x, y);
+    let space = options.color_space;
+
+    match (options.sixteen_bit, options.alpha) {
+        (true, true) => DynamicImage::ImageRgba16(ImageBuffer::from_fn(width, height, |x, y| {
+            let (color, alpha) = straight(pixel(x, y));
+            let [r, g, b] = to_u16(color, space);
+            Rgba([r, g, b, (65536.0 * alpha.clamp(0.0, 0.99999)) as u16])
+        })),
+        (true, false) => DynamicImage::ImageRgb16(ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb(to_u16(pixel(x, y).color, space))
+        })),
+        (false, true) => DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+            let (color, alpha) = straight(pixel(x, y));
+            let [r, g, b] = to_u8(color, space);
+            Rgba([r, g, b, (256.0 * alpha.clamp(0.0, 0.999)) as u8])
+        })),
+        (false, false) => DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb(to_u8(pixel(x, y).color, space))
+        })),
+    }
+}
+
+/// Writes the pixels inside `region` over an existing image of the same size, saved with the same
+/// options. The rest of the image is read back into linear light and written again as it was.
+pub fn composite(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    region: &Region,
+    options: &SaveOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    let (found_width, found_height, mut image) = read(path, options)?;
+    if (found_width, found_height) != (width, height) {
+        return Err(Error::SizeMismatch {
+            path: path.to_owned(),
+            expected: (width, height),
+            found: (found_width, found_height),
+        });
+    }
+
+    // the bloom spreads light out of the region, so it's applied to the whole render first
+    let pixels = look(width, height, pixels, options, !is_exr(path));
+    for y in region.y0..region.y1 {
+        let row = (height - 1 - y) * width;
+        let range = (row + region.x0) as usize..(row + region.x1) as usize;
+        image[range.clone()].copy_from_slice(&pixels[range]);
+    }
+
+    write(path, width, height, &image, options)
+}
+
+/// Whether `path` names a video rather than a single image, judging by its extension.
+pub fn is_video(path: impl AsRef<Path>) -> bool {
+    let extension = path
+        .as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    matches!(extension.as_deref(), Some("mp4" | "webm" | "mkv" | "mov"))
+}
+
+/// Encodes frames into a video by streaming them to an `ffmpeg` process, which needs to be
+/// installed. The container and codec are picked by ffmpeg from the file extension.
+pub struct VideoWriter {
+    ffmpeg: Child,
+    stdin: BufWriter<ChildStdin>,
+    width: u32,
+    height: u32,
+    options: SaveOptions,
+}
+
+impl VideoWriter {
+    /// Only the bloom and lookup table of the options apply, since videos have no alpha and 8
+    /// bits. Fails if the width or height is odd, which the pixel format can't store.
+    pub fn new(
+        path: impl AsRef<Path>,
+        width: u32,
+        height: u32,
+        fps: f64,
+        options: &SaveOptions,
+    ) -> Result<Self> {
+        if !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+            return Err(Error::OddVideoSize(width, height));
+        }
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-video_size")
+            .arg(format!("{}x{}", width, height))
+            .arg("-framerate")
+            .arg(fps.to_string())
+            .args(["-i", "-"])
+            // the most widely supported pixel format, rather than ffmpeg's guess
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(path.as_ref())
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(Error::Ffmpeg)?;
+        let stdin = BufWriter::new(ffmpeg.stdin.take().unwrap());
+
+        Ok(Self {
+            ffmpeg,
+            stdin,
+            width,
+            height,
+            options: options.clone(),
+        })
+    }
+
+    pub fn write_frame(&mut self, pixels: &[Pixel]) -> Result<()> {
+        let (width, height) = (self.width, self.height);
+        let pixels = look(width, height, pixels, &self.options, true);
+        for y in 0..height {
+            for x in 0..width {
+                let color = pixel_at(&pixels, width, height, x, y).color;
+                let rgb = to_u8(color, self.options.color_space);
+                self.stdin.write_all(&rgb).map_err(Error::Ffmpeg)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes the stream and waits for ffmpeg to finish writing the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.stdin.flush().map_err(Error::Ffmpeg)?;
+        drop(self.stdin);
+
+        let status = self.ffmpeg.wait().map_err(Error::Ffmpeg)?;
+        if !status.success() {
+            return Err(Error::FfmpegFailed(status));
+        }
+
+        Ok(())
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT02";
+/// Checkpoints from before pixels had sample counts of their own, all with the same one.
+const CHECKPOINT_MAGIC_V1: &[u8; 8] = b"RTCKPT01";
+
+/// Saves an unfinished progressive render, so it can be picked up later by [`load_checkpoint`].
+///
+/// The file starts with a magic number, followed by the width and height as little-endian
+/// `u32`s, and then for every pixel the red, green, blue, alpha and time sums as little-endian
+/// `f64`s and the number of samples as a little-endian `u32`.
+pub fn save_checkpoint(path: impl AsRef<Path>, film: &Film) -> Result<()> {
+    let path = path.as_ref();
+    write_checkpoint(path, film).map_err(Error::file(path))
+}
+
+fn write_checkpoint(path: &Path, film: &Film) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    out.write_all(CHECKPOINT_MAGIC)?;
+    for n in [film.width(), film.height()] {
+        out.write_all(&n.to_le_bytes())?;
+    }
+    for (sum, samples) in film.sums() {
+        let [r, g, b] = sum.color.0;
+        for x in [r, g, b, sum.alpha, sum.time.as_secs_f64()] {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        out.write_all(&samples.to_le_bytes())?;
+    }
+
+    out.flush()
+}
+
+pub fn load_checkpoint(path: impl AsRef<Path>) -> Result<Film> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(Error::file(path))?;
+    let file_len = file.metadata().map_err(Error::file(path))?.len();
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic).map_err(Error::file(path))?;
+    let version = match &magic {
+        CHECKPOINT_MAGIC_V1 => 1,
+        CHECKPOINT_MAGIC => 2,
+        _ => return Err(Error::NotACheckpoint(path.to_owned())),
+    };
+
+    read_checkpoint(input, version, file_len - magic.len() as u64).map_err(|err| match err.kind() {
+        std::io::ErrorKind::InvalidData => Error::Checkpoint {
+            path: path.to_owned(),
+            message: err.to_string(),
+        },
+        _ => Error::file(path)(err),
+    })
+}
+
+/// Reads everything after the magic number, which is `len` bytes. Fails without allocating
+/// anything if that isn't exactly what the size of the image calls for.
+fn read_checkpoint(mut input: impl Read, version: u32, len: u64) -> std::io::Result<Film> {
+    fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+        let mut bytes = [0; 4];
+        input.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+    let (width, height) = (read_u32(&mut input)?, read_u32(&mut input)?);
+    // the first version has one count for the whole image, before the pixels
+    let shared_samples = match version {
+        1 => Some(read_u32(&mut input)?),
+        _ => None,
+    };
+
+    // five f64 sums, and a u32 count unless it's shared
+    let (header, pixel_size) = match shared_samples {
+        Some(_) => (12, 5 * 8),
+        None => (8, 5 * 8 + 4),
+    };
+    let expected = (width as u64)
+        .checked_mul(height as u64)
+        .and_then(|pixels| pixels.checked_mul(pixel_size));
+    if expected != len.checked_sub(header) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the checkpoint is {} bytes long, which doesn't fit {}x{} pixels",
+                len + 8,
+                width,
+                height
+            ),
+        ));
+    }
+
+    let len = width as usize * height as usize;
+    let (mut sums, mut samples) = (Vec::with_capacity(len), Vec::with_capacity(len));
+    for _ in 0..len {
+        let mut values = [0.0; 5];
+        for value in values.iter_mut() {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            *value = f64::from_le_bytes(bytes);
+        }
+
+        let [r, g, b, alpha, time] = values;
+        sums.push(Pixel {
+            color: Color::new(r, g, b),
+            alpha,
+            time: Duration::from_secs_f64(time),
+        });
+        samples.push(match shared_samples {
+            Some(count) => count,
+            None => read_u32(&mut input)?,
+        });
+    }
+
+    Ok(Film::from_sums(width, height, sums, samples))
+}
+
+fn save_ppm(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[Pixel],
+    options: &SaveOptions,
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    let magic = if options.plain { "P3" } else { "P6" };
+    let max = if options.sixteen_bit { 65535 } else { 255 };
+    write!(out, "{}\n{} {}\n{}\n", magic, width, height, max)?;
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = pixel_at(pixels, width, height, x, y).color;
+            let channels: [u16; 3] = if options.sixteen_bit {
+                to_u16(color, options.color_space)
+            } else {
+                to_u8(color, options.color_space).map(u16::from)
+            };
+
+            if options.plain {
+                writeln!(out, "{} {} {}", channels[0], channels[1], channels[2])?;
+            } else if options.sixteen_bit {
+                for channel in channels.iter() {
+                    out.write_all(&channel.to_be_bytes())?;
+                }
+            } else {
+                for channel in channels.iter() {
+                    out.write_all(&[*channel as u8])?;
+                }
+            }
+        }
+    }
+
+    out.flush()
+}
+
+/// Looks up the pixel at image coordinates, where the renderer's rows start at the bottom.
+fn pixel_at<P>(pixels: &[P], width: u32, height: u32, x: u32, y: u32) -> &P {
+    let row = height - 1 - y;
+    &pixels[(row * width + x) as usize]
+}
+
+/// The pixels with the post-processing of the options applied, in the linear light of their
+/// color space: the bloom, and for `display` formats, which are encoded rather than linear, the
+/// lookup table.
+fn look<'a>(
+    width: u32,
+    height: u32,
+    pixels: &'a [Pixel],
+    options: &SaveOptions,
+    display: bool,
+) -> Cow<'a, [Pixel]> {
+    let lut = options.lut.as_ref().filter(|_| display);
+    let conversion = Conversion::from_working(options.color_space);
+    if options.bloom.is_none() && lut.is_none() && conversion.is_identity() {
+        return Cow::Borrowed(pixels);
+    }
+
+    // the glow is light like any other, so it's blurred premultiplied, and shows over
+    // transparent pixels too
+    let mut colors: Vec<Color> = pixels.iter().map(|pixel| pixel.color).collect();
+    if let Some(bloom) = &options.bloom {
+        bloom.apply(width as usize, height as usize, &mut colors);
+    }
+
+    let pixels = pixels
+        .iter()
+        .zip(colors)
+        .map(|(pixel, color)| {
+            let mut pixel = Pixel {
+                color: conversion.apply(color),
+                ..*pixel
+            };
+            // the table is meant for the colors of opaque pixels, so it's applied to straight
+            // alpha
+            if let Some(lut) = lut {
+                let (color, alpha) = straight(&pixel);
+                pixel.color = alpha * lut.apply_linear(color, options.color_space);
+            }
+            pixel
+        })
+        .collect();
+    Cow::Owned(pixels)
+}
+
+/// Undoes the premultiplication of the renderer's colors by coverage.
+fn straight(pixel: &Pixel) -> (Color, f64) {
+    let color = if pixel.alpha > 0.0 {
+        pixel.color / pixel.alpha
+    } else {
+        Color::default()
+    };
+
+    (color, pixel.alpha)
+}
+
+/// Encodes a color of the color space with its curve, in 8 bits per channel.
+fn to_u8(color: Color, space: ColorSpace) -> [u8; 3] {
+    color
+        .0
+        .map(|c| (256.0 * space.encode(c).clamp(0.0, 0.999)) as u8)
+}
+
+/// Same as [`to_u8`], but with 16 bits per channel.
+fn to_u16(color: Color, space: ColorSpace) -> [u16; 3] {
+    color
+        .0
+        .map(|c| (65536.0 * space.encode(c).clamp(0.0, 0.99999)) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn composites_match_whole_renders() {
+        let (width, height) = (4, 3);
+        let frame = |shade: f64| -> Vec<Pixel> {
+            (0..width * height)
+                .map(|i| Pixel {
+                    color: Color::new(shade, 0.1 * i as f64, 0.5) * 0.5,
+                    alpha: 0.5,
+                    time: Duration::ZERO,
+                })
+                .collect()
+        };
+        let (before, after) = (frame(0.2), frame(0.9));
+        let region = Region {
+            x0: 1,
+            y0: 0,
+            x1: 3,
+            y1: 2,
+        };
+        let mut expected = before.clone();
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                let index = ((height - 1 - y) * width + x) as usize;
+                expected[index] = after[index];
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("composite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sixteen_bit = SaveOptions {
+            alpha: true,
+            sixteen_bit: true,
+            ..SaveOptions::default()
+        };
+        for (name, options) in [
+            ("8.png", SaveOptions::default()),
+            ("16.png", sixteen_bit),
+            ("linear.exr", SaveOptions::default()),
+        ] {
+            let (touched, whole) = (dir.join(name), dir.join(format!("whole-{}", name)));
+            save(&touched, width, height, &before, &options).unwrap();
+            composite(&touched, width, height, &after, &region, &options).unwrap();
+            save(&whole, width, height, &expected, &options).unwrap();
+
+            assert_eq!(
+                read(&touched, &options).unwrap(),
+                read(&whole, &options).unwrap(),
+                "{}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn videos_need_even_sizes() {
+        let video = VideoWriter::new("odd.mp4", 801, 450, 24.0, &SaveOptions::default());
+        assert!(matches!(video.err(), Some(Error::OddVideoSize(801, 450))));
+    }
+}
@@ -0,0 +1,668 @@
+//! Caustics by progressive photon mapping. Photons are traced from the sun and glowing objects
+//! through mirrors and glass, and stored where they land on the surfaces behind them, which
+//! camera rays then gather light from. The sun and small lights are far too small for paths
+//! traced from the camera to find them through a glass sphere by chance, so without this the
+//! bright spot under the sphere takes millions of samples to show up.
+
+use crate::collision::bvh::Aabb;
+use crate::collision::lights::{self, AreaLight};
+use crate::collision::{Hit, Hittable, Material};
+use crate::render::{self, Integrator, RenderSettings};
+use crate::sky::Sun;
+use crate::{Camera, Color, Error, Point3, Ray, RayCone, Result, Vec3};
+
+use rand::{Rng, RngCore};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use std::f64::consts::PI;
+use std::sync::{Arc, RwLock};
+
+/// How fast the gather radius shrinks from one pass to the next, between 0 and 1. Lower values
+/// shrink it faster, trading noise for blur.
+const ALPHA: f64 = 2.0 / 3.0;
+/// How many cells across the projection map has.
+const PROJECTION_CELLS: usize = 512;
+/// Photons are traced in this many batches, each with its own random numbers.
+const BATCHES: usize = 64;
+
+/// Path tracing, with caustics gathered from a photon map instead.
+///
+/// Before every pass a new map is made with `photons` photons, and the radius they're gathered
+/// from shrinks a little, so that the blur of the first passes averages out over the later ones.
+/// The photons are shared between the sun and the [lights](Hittable::lights) of the world by how
+/// much light each gives off. The caustics of anything else that glows are still path traced.
+pub struct PhotonMapper {
+    photons: usize,
+    radius: f64,
+    pass: RwLock<Pass>,
+}
+
+/// The photon map of the current pass.
+struct Pass {
+    number: u32,
+    radius: f64,
+    map: Arc<PhotonMap>,
+    /// The lights photons were sent from, whose caustics are in the map.
+    lights: Vec<AreaLight>,
+}
+
+/// Where a camera path has been, which tells whether the photon map already has the light of
+/// the lights it finds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Path {
+    /// Only through mirrors and glass, if anything, since the camera.
+    Camera,
+    /// Straight from a diffuse surface.
+    Diffuse,
+    /// From a diffuse surface, and then through mirrors or glass.
+    Caustic,
+}
+
+impl PhotonMapper {
+    /// Fails unless there are some photons and the radius, in scene units, is positive.
+    pub fn new(photons: usize, radius: f64) -> Result<Self> {
+        Error::check("photon count", photons as f64, |count| count >= 1.0)?;
+        let radius = Error::check("photon radius", radius, |radius| radius > 0.0)?;
+
+        Ok(Self {
+            photons,
+            radius,
+            pass: RwLock::new(Pass {
+                number: 0,
+                radius,
+                map: Arc::default(),
+                lights: Vec::new(),
+            }),
+        })
+    }
+
+    /// The camera path, like the path tracer's except that the sun and the lights seen through
+    /// mirrors and glass from a diffuse surface are left out, since the photon map already has
+    /// that light.
+    #[allow(clippy::too_many_arguments)]
+    fn trace(
+        &self,
+        pass: &Pass,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        depth: i32,
+        sun_sampled: bool,
+        path: Path,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        if depth <= 0 {
+            return Color::default();
+        }
+        if depth < settings.max_depth {
+            render::count_ray(false);
+        }
+
+        let (color, t) = match render::surface_hit(ray, cone, world) {
+            Some((hit, cone)) => {
+                let t = hit.t;
+                let material = render::shading_material(ray, &hit, settings);
+                let mapped = path == Path::Caustic
+                    && pass
+                        .lights
+                        .iter()
+                        .any(|light| light.is_made_of(&hit.material));
+                let emitted = if mapped {
+                    Color::default()
+                } else {
+                    material.emitted(ray, &hit)
+                };
+                let specular = material.reflectance(ray, &hit, hit.normal).is_none();
+                let path = match (specular, path) {
+                    (false, _) => Path::Diffuse,
+                    (true, Path::Camera) => Path::Camera,
+                    (true, _) => Path::Caustic,
+                };
+                let (direct, sun_sampled) = if specular {
+                    (Color::default(), sun_sampled)
+                } else {
+                    let sunlight = match settings.background.sun() {
+                        Some(sun) => render::direct_sunlight(
+                            sun,
+                            ray,
+                            &hit,
+                            material.as_ref(),
+                            world,
+                            settings,
+                            rng,
+                        ),
+                        None => (Color::default(), false),
+                    };
+                    let caustics = pass.map.radiance(ray, &hit, material.as_ref(), pass.radius);
+                    (sunlight.0 + caustics, sunlight.1)
+                };
+
+                let indirect = material
+                    .scatter(ray, &hit, rng)
+                    .map(|(attenuation, scattered)| {
+                        let seen = self.trace(
+                            pass,
+                            &scattered,
+                            cone,
+                            world,
+                            settings,
+                            depth - 1,
+                            sun_sampled,
+                            path,
+                            rng,
+                        );
+                        attenuation * seen
+                    });
+                (emitted + direct + indirect.unwrap_or_default(), t)
+            }
+            None => (
+                render::background_color(ray, settings, sun_sampled),
+                f64::INFINITY,
+            ),
+        };
+
+        render::with_fog((color, t), ray, settings)
+    }
+}
+
+impl Default for PhotonMapper {
+    fn default() -> Self {
+        Self::new(100_000, 0.05).unwrap()
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        let pass = self.pass.read().unwrap();
+        self.trace(
+            &pass,
+            ray,
+            cone,
+            world,
+            settings,
+            settings.max_depth,
+            false,
+            Path::Camera,
+            rng,
+        )
+    }
+
+    fn begin_pass(&self, world: &(dyn Hittable + Sync), _: &Camera, settings: &RenderSettings) {
+        let (number, radius) = {
+            let pass = self.pass.read().unwrap();
+            match pass.number {
+                0 => (1, self.radius),
+                n => {
+                    let shrink = (n as f64 + ALPHA) / (n as f64 + 1.0);
+                    (n + 1, pass.radius * shrink.sqrt())
+                }
+            }
+        };
+
+        let lights = lights::lights(world);
+        let photons = shoot(
+            settings.background.sun(),
+            &lights,
+            self.photons,
+            world,
+            settings,
+        );
+        *self.pass.write().unwrap() = Pass {
+            number,
+            radius,
+            map: Arc::new(PhotonMap::new(photons)),
+            lights,
+        };
+    }
+}
+
+/// Light carried from the sun or a light onto a diffuse surface, by way of at least one mirror or
+/// glass.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Photon {
+    position: Point3,
+    /// Which way it was going, as a unit vector.
+    direction: Vec3,
+    /// The normal of the surface it landed on, facing where it came from.
+    normal: Vec3,
+    power: Color,
+}
+
+/// Photons in a kd-tree, each the median of its subtree along the axis stored with it.
+#[derive(Default)]
+struct PhotonMap {
+    photons: Vec<(Photon, usize)>,
+}
+
+impl PhotonMap {
+    fn new(photons: Vec<Photon>) -> Self {
+        let mut photons: Vec<_> = photons.into_iter().map(|photon| (photon, 0)).collect();
+        Self::build(&mut photons);
+
+        Self { photons }
+    }
+
+    fn build(photons: &mut [(Photon, usize)]) {
+        if photons.is_empty() {
+            return;
+        }
+
+        let (lo, hi) = photons.iter().fold(
+            (Vec3([f64::INFINITY; 3]), Vec3([f64::NEG_INFINITY; 3])),
+            |(lo, hi), (photon, _)| {
+                let p = photon.position;
+                (
+                    Vec3(std::array::from_fn(|axis| lo[axis].min(p[axis]))),
+                    Vec3(std::array::from_fn(|axis| hi[axis].max(p[axis]))),
+                )
+            },
+        );
+        let extent = hi - lo;
+        let axis = (0..3)
+            .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+            .unwrap();
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+            a.position[axis].total_cmp(&b.position[axis])
+        });
+        photons[mid].1 = axis;
+
+        let (below, rest) = photons.split_at_mut(mid);
+        Self::build(below);
+        Self::build(&mut rest[1..]);
+    }
+
+    /// Calls `f` with every photon within `radius` of `center`.
+    fn gather<F>(&self, center: Point3, radius: f64, f: &mut F)
+    where
+        F: FnMut(&Photon),
+    {
+        Self::gather_in(&self.photons, center, radius * radius, f);
+    }
+
+    fn gather_in<F>(photons: &[(Photon, usize)], center: Point3, radius_sq: f64, f: &mut F)
+    where
+        F: FnMut(&Photon),
+    {
+        if photons.is_empty() {
+            return;
+        }
+
+        let mid = photons.len() / 2;
+        let (photon, axis) = &photons[mid];
+        let offset = center[*axis] - photon.position[*axis];
+        let (near, far) = if offset < 0.0 {
+            (&photons[..mid], &photons[mid + 1..])
+        } else {
+            (&photons[mid + 1..], &photons[..mid])
+        };
+
+        if (center - photon.position).length_squared() <= radius_sq {
+            f(photon);
+        }
+        Self::gather_in(near, center, radius_sq, f);
+        if offset * offset <= radius_sq {
+            Self::gather_in(far, center, radius_sq, f);
+        }
+    }
+
+    /// The light of the photons near `hit` scattered back along the ray.
+    fn radiance(&self, ray: &Ray, hit: &Hit, material: &dyn Material, radius: f64) -> Color {
+        let mut flux = Color::default();
+        self.gather(hit.point, radius, &mut |photon| {
+            // photons on the other side of a thin wall don't count
+            if photon.normal.dot(&hit.normal) <= 0.0 {
+                return;
+            }
+
+            let light = -photon.direction;
+            if let Some(reflectance) = material.reflectance(ray, hit, light) {
+                // the reflectance is per unit of irradiance facing the light, not the surface
+                let cos = light.dot(&hit.normal).abs().max(1e-3);
+                flux += reflectance * photon.power / cos;
+            }
+        });
+
+        flux / (PI * radius * radius)
+    }
+}
+
+/// Traces `count` photons from the sun and `lights`, keeping the ones which made it through a
+/// mirror or glass onto something else. Each gets a share of the photons as big as its share of
+/// the light.
+fn shoot(
+    sun: Option<&Sun>,
+    lights: &[AreaLight],
+    count: usize,
+    world: &(dyn Hittable + Sync),
+    settings: &RenderSettings,
+) -> Vec<Photon> {
+    let sun = sun.and_then(|sun| SunPhotons::new(sun, world, settings));
+    let sun_power = sun.as_ref().map_or(0.0, |sun| brightness(sun.power()));
+    // running totals, to pick lights by with a single random number
+    let light_powers: Vec<f64> = lights
+        .iter()
+        .scan(0.0, |total, light| {
+            *total += brightness(light.power());
+            Some(*total)
+        })
+        .collect();
+    let lights_power = light_powers.last().copied().unwrap_or(0.0);
+    if sun_power + lights_power <= 0.0 {
+        return Vec::new();
+    }
+    let from_sun = (count as f64 * sun_power / (sun_power + lights_power)).round() as usize;
+    let from_lights = count - from_sun;
+
+    let batch = |index: usize| {
+        let mut rng = render::row_rng(settings.seed, index);
+        let mut photons = Vec::new();
+        for photon in (index..count).step_by(BATCHES) {
+            let (ray, power) = match &sun {
+                Some(sun) if photon < from_sun => {
+                    (sun.emit(&mut rng), sun.power() / from_sun as f64)
+                }
+                _ => {
+                    let picked = rng.gen::<f64>() * lights_power;
+                    let index = light_powers
+                        .partition_point(|&total| total <= picked)
+                        .min(lights.len() - 1);
+                    let light = &lights[index];
+                    let below = index.checked_sub(1).map_or(0.0, |i| light_powers[i]);
+                    let chance = (light_powers[index] - below) / lights_power;
+                    (
+                        light.emit(&mut rng),
+                        light.power() / (chance * from_lights as f64),
+                    )
+                }
+            };
+            photons.extend(trace_photon(ray, power, world, settings, &mut rng));
+        }
+        photons
+    };
+
+    #[cfg(feature = "parallel")]
+    let batches = (0..BATCHES).into_par_iter().map(batch).collect::<Vec<_>>();
+    #[cfg(not(feature = "parallel"))]
+    let batches = (0..BATCHES).map(batch).collect::<Vec<_>>();
+    batches.concat()
+}
+
+/// A single number for how bright a color is, to share out photons by.
+fn brightness(color: Color) -> f64 {
+    color.0.iter().sum::<f64>() / 3.0
+}
+
+/// Where photons from the sun start.
+///
+/// Only the parts of the sky where the sun's light hits a mirror or glass first, found with a
+/// projection map, send out any photons at all. Most of the light lands on diffuse surfaces
+/// straight away, and that's already taken care of by sampling the sun.
+struct SunPhotons<'a> {
+    sun: &'a Sun,
+    /// The corner of the square the photons cross, and the unit vectors along its sides.
+    corner: Point3,
+    u: Vec3,
+    v: Vec3,
+    /// The width of a cell of the projection map.
+    cell: f64,
+    /// The cells with a mirror or glass in them, or next to one.
+    cells: Vec<(usize, usize)>,
+}
+
+impl<'a> SunPhotons<'a> {
+    /// `None` if the sun's light can't reach a mirror or glass.
+    fn new(sun: &'a Sun, world: &(dyn Hittable + Sync), settings: &RenderSettings) -> Option<Self> {
+        let bbox = world.bounding_box()?;
+        let (corner, u, v, side) = facing(sun.direction, &bbox);
+        let cell = side / PROJECTION_CELLS as f64;
+        let at = |x: f64, y: f64| corner + x * cell * u + y * cell * v;
+
+        let mut specular = vec![false; PROJECTION_CELLS * PROJECTION_CELLS];
+        for y in 0..PROJECTION_CELLS {
+            for x in 0..PROJECTION_CELLS {
+                let probe = Ray {
+                    origin: at(x as f64 + 0.5, y as f64 + 0.5),
+                    direction: -sun.direction,
+                };
+                specular[y * PROJECTION_CELLS + x] = world
+                    .hit(&probe, 0.001, f64::INFINITY)
+                    .is_some_and(|hit| is_specular(&probe, &hit, settings));
+            }
+        }
+
+        // the neighbors too, for anything between the probes
+        let mut cells = Vec::new();
+        for y in 0..PROJECTION_CELLS {
+            for x in 0..PROJECTION_CELLS {
+                let near = |(dx, dy): (usize, usize)| {
+                    let (nx, ny) = ((x + dx).checked_sub(1), (y + dy).checked_sub(1));
+                    matches!((nx, ny), (Some(nx), Some(ny))
+                        if nx < PROJECTION_CELLS && ny < PROJECTION_CELLS
+                            && specular[ny * PROJECTION_CELLS + nx])
+                };
+                if (0..9).any(|i| near((i % 3, i / 3))) {
+                    cells.push((x, y));
+                }
+            }
+        }
+
+        (!cells.is_empty()).then_some(Self {
+            sun,
+            corner,
+            u,
+            v,
+            cell,
+            cells,
+        })
+    }
+
+    /// The sunlight going through the cells.
+    fn power(&self) -> Color {
+        self.sun.irradiance * (self.cell * self.cell * self.cells.len() as f64)
+    }
+
+    fn emit(&self, rng: &mut dyn RngCore) -> Ray {
+        let (x, y) = self.cells[rng.gen_range(0..self.cells.len())];
+        let (x, y) = (x as f64 + rng.gen::<f64>(), y as f64 + rng.gen::<f64>());
+        Ray {
+            origin: self.corner + x * self.cell * self.u + y * self.cell * self.v,
+            direction: -self.sun.sample(rng),
+        }
+    }
+}
+
+/// A square seen from `direction` outside of `bbox`, which all the light coming from there onto
+/// the box passes through: its corner, the unit vectors along two sides, and how long they are.
+pub(crate) fn facing(direction: Vec3, bbox: &Aabb) -> (Point3, Vec3, Vec3, f64) {
+    let half = 0.5 * (bbox.max - bbox.min).length();
+    let helper = if direction.x().abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let u = direction.cross(&helper).normalize();
+    let v = direction.cross(&u);
+    let corner = bbox.centroid() + 2.0 * half * direction - half * (u + v);
+
+    (corner, u, v, 2.0 * half)
+}
+
+/// Follows a photon through mirrors and glass, returning where it lands if it went through any.
+fn trace_photon(
+    mut ray: Ray,
+    mut power: Color,
+    world: &(dyn Hittable + Sync),
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> Option<Photon> {
+    for bounce in 0..settings.max_depth {
+        let hit = world.hit(&ray, 0.001, f64::INFINITY)?;
+        render::count_ray(false);
+
+        if !is_specular(&ray, &hit, settings) {
+            return (bounce > 0).then(|| Photon {
+                position: hit.point,
+                direction: ray.direction.normalize(),
+                normal: hit.normal,
+                power,
+            });
+        }
+
+        let (attenuation, scattered) =
+            render::shading_material(&ray, &hit, settings).scatter(&ray, &hit, rng)?;
+        power = power * attenuation;
+        ray = scattered;
+    }
+
+    None
+}
+
+/// Whether the surface only scatters into a few directions, like mirrors and glass do.
+pub(crate) fn is_specular(ray: &Ray, hit: &Hit, settings: &RenderSettings) -> bool {
+    render::shading_material(ray, hit, settings)
+        .reflectance(ray, hit, hit.normal)
+        .is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::{Dielectric, DiffuseLight, Lambertian};
+    use crate::collision::objects::{Parallelogram, Sphere};
+    use crate::sky::Preetham;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn gathering_finds_the_photons_in_range() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let photons: Vec<_> = (0..2000)
+            .map(|_| Photon {
+                position: Vec3(rng.gen()),
+                direction: Vec3::new(0.0, -1.0, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                power: Color::new(1.0, 1.0, 1.0),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+
+        for _ in 0..50 {
+            let center = Vec3(rng.gen());
+            let mut found = 0;
+            map.gather(center, 0.1, &mut |_| found += 1);
+            let expected = photons
+                .iter()
+                .filter(|photon| (photon.position - center).length() <= 0.1)
+                .count();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn glass_focuses_the_sun() {
+        let floor = Parallelogram::new(
+            Point3::new(-2.0, -1.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 4.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap()),
+        );
+        let ball = Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            0.5,
+            Arc::new(Dielectric::new(1.5).unwrap()),
+        )
+        .unwrap();
+        let world: Vec<Arc<dyn Hittable + Send + Sync>> = vec![Arc::new(floor), Arc::new(ball)];
+        let settings = RenderSettings {
+            seed: Some(1),
+            background: Arc::new(Preetham::new(Vec3::new(0.0, 1.0, 0.0), 2.5, 0.53, 1.0).unwrap()),
+            ..Default::default()
+        };
+
+        let sun = settings.background.sun();
+        let photons = shoot(sun, &[], 10_000, &world, &settings);
+        assert!(photons.len() > 5000);
+
+        // everything lands on the floor, and a quarter of the ball's shadow gets much of the light
+        for photon in &photons {
+            assert!(photon.position.y().abs() < 1e-9);
+        }
+        let focused = photons
+            .iter()
+            .filter(|photon| photon.position.length() < 0.25)
+            .count();
+        assert!(focused > photons.len() * 2 / 5);
+    }
+
+    #[test]
+    fn lights_send_out_photons() {
+        // a closed room lit only by a lamp under its ceiling, with a glass ball below that
+        let room = Parallelogram::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7)).unwrap()),
+        );
+        let lamp = Sphere::new(
+            Point3::new(0.0, 0.8, 0.0),
+            0.1,
+            Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        )
+        .unwrap();
+        let ball = Sphere::new(
+            Point3::new(0.0, 0.2, 0.0),
+            0.4,
+            Arc::new(Dielectric::new(1.5).unwrap()),
+        )
+        .unwrap();
+        let world: Vec<Arc<dyn Hittable + Send + Sync>> =
+            vec![Arc::new(room), Arc::new(lamp), Arc::new(ball)];
+        let settings = RenderSettings {
+            seed: Some(2),
+            ..Default::default()
+        };
+
+        let lights = lights::lights(&world);
+        assert_eq!(lights.len(), 1);
+        let photons = shoot(None, &lights, 10_000, &world, &settings);
+        assert!(photons.len() > 1000, "{}", photons.len());
+
+        let mut power = 0.0;
+        for photon in &photons {
+            assert!(photon.position.0.iter().all(|x| x.abs() < 1.0 + 1e-9));
+            power += brightness(photon.power);
+        }
+        // what the glass lets through, out of everything the lamp gives off
+        let total = brightness(lights[0].power());
+        assert!(
+            power > 0.05 * total && power < total,
+            "{} of {}",
+            power,
+            total
+        );
+
+        let floor: Vec<_> = photons
+            .iter()
+            .filter(|photon| photon.position.y() < -1.0 + 1e-9)
+            .collect();
+        let focused = floor
+            .iter()
+            .filter(|photon| photon.position.x().hypot(photon.position.z()) < 0.4)
+            .count();
+        assert!(focused > floor.len() / 2, "{} of {}", focused, floor.len());
+    }
+}
@@ -0,0 +1,282 @@
+//! An interactive preview window, refining the image one sample per pixel at a time.
+//!
+//! Passes are rendered on another thread, so the window keeps up with the controls, and a pass
+//! is cancelled as soon as the camera moves rather than finished for nothing.
+//!
+//! Controls:
+//! - left mouse drag: orbit around the target
+//! - right mouse drag: pan
+//! - scroll wheel: zoom
+//! - WASD, Q and E: move the target
+//! - P: print the camera, ready to paste into a scene file
+//! - I: switch between the quick preview lighting and the integrator of the final render
+//! - Escape: close the window
+
+use ray_tracing::collision::Hittable;
+use ray_tracing::film::Film;
+use ray_tracing::render::{
+    render_cancellable, CancellationToken, Integrator, Preview, RenderSettings,
+};
+use ray_tracing::scene::CameraDescription;
+use ray_tracing::*;
+
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use std::io;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// A camera circling a target point, which is what the mouse controls move around.
+struct Orbit {
+    target: Point3,
+    radius: f64,
+    /// Radians around the vertical axis, starting at +z.
+    yaw: f64,
+    /// Radians above the horizontal plane.
+    pitch: f64,
+}
+
+impl Orbit {
+    fn new(look_from: Point3, look_at: Point3) -> Self {
+        let [x, y, z] = (look_from - look_at).0;
+        let radius = (look_from - look_at).length();
+
+        Self {
+            target: look_at,
+            radius,
+            yaw: x.atan2(z),
+            pitch: (y / radius).asin(),
+        }
+    }
+
+    /// From the target towards the camera.
+    fn backward(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        )
+    }
+
+    fn look_from(&self) -> Point3 {
+        self.target + self.radius * self.backward()
+    }
+
+    /// The camera's right and up directions.
+    fn right_up(&self) -> (Vec3, Vec3) {
+        let right = Vec3::new(self.yaw.cos(), 0.0, -self.yaw.sin());
+        let up = self.backward().cross(&right);
+        (right, up)
+    }
+}
+
+pub fn run<T>(
+    world: &T,
+    description: CameraDescription,
+    builder: CameraBuilder,
+    settings: &RenderSettings,
+) -> ray_tracing::Result<()>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    // ambient occlusion instead of bounced light, which settles in a few samples
+    let quick: Arc<dyn Integrator> = Arc::new(Preview::default());
+    let mut pass_settings = RenderSettings {
+        samples_per_pixel: 1,
+        show_progress: false,
+        integrator: quick.clone(),
+        // a few samples look far cleaner with their noise spread out evenly
+        blue_noise: true,
+        ..settings.clone()
+    };
+
+    let mut window = Window::new(
+        "Ray tracing preview",
+        width,
+        height,
+        WindowOptions::default(),
+    )
+    .map_err(io::Error::other)?;
+    window.set_target_fps(60);
+
+    let mut orbit = Orbit::new(description.look_from, description.look_at);
+    let mut film = Film::new(settings.width, settings.height);
+    let mut framebuffer = vec![0u32; width * height];
+    let mut last_mouse = None;
+    let mut last_frame = Instant::now();
+
+    let cancel = CancellationToken::new();
+    thread::scope(|scope| {
+        let (passes_tx, passes) = mpsc::channel::<(Camera, RenderSettings)>();
+        let (rendered_tx, rendered) = mpsc::channel();
+        scope.spawn({
+            let cancel = cancel.clone();
+            move || {
+                for (camera, settings) in passes {
+                    let pixels = render_cancellable(world, &camera, &settings, &cancel);
+                    if rendered_tx.send(pixels).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        // whether a pass is being rendered, and whether it's of a view that's since changed
+        let (mut rendering, mut stale) = (false, false);
+        let result = loop {
+            if !window.is_open() || window.is_key_down(Key::Escape) {
+                break Ok(());
+            }
+            let elapsed = last_frame.elapsed().as_secs_f64();
+            last_frame = Instant::now();
+
+            let mut changed = handle_input(&window, &mut orbit, &mut last_mouse, elapsed);
+            if window.is_key_pressed(Key::P, KeyRepeat::No) {
+                if let Err(err) = print_camera(&description, &orbit) {
+                    break Err(err);
+                }
+            }
+            if window.is_key_pressed(Key::I, KeyRepeat::No) {
+                pass_settings.integrator = if Arc::ptr_eq(&pass_settings.integrator, &quick) {
+                    settings.integrator.clone()
+                } else {
+                    quick.clone()
+                };
+                changed = true;
+            }
+            if changed {
+                film = Film::new(settings.width, settings.height);
+                if rendering {
+                    cancel.cancel();
+                    stale = true;
+                }
+            }
+
+            let mut updated = false;
+            if rendering {
+                match rendered.try_recv() {
+                    Ok(pixels) => {
+                        rendering = false;
+                        // only taken back once the pass has seen it
+                        cancel.reset();
+                        if let (Some(pixels), false) = (pixels, stale) {
+                            film.add_pass(&pixels, 1);
+                            updated = true;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Disconnected) => {
+                        break Err(io::Error::other("the render stopped").into())
+                    }
+                }
+            }
+
+            if !rendering && film.samples() < settings.samples_per_pixel {
+                let camera = builder
+                    .clone()
+                    .look_from(orbit.look_from())
+                    .look_at(orbit.target)
+                    .build();
+                let camera = match camera {
+                    Ok(camera) => camera,
+                    Err(err) => break Err(err.into()),
+                };
+                pass_settings.seed = settings
+                    .seed
+                    .map(|seed| seed.wrapping_add(film.samples() as u64));
+                let _ = passes_tx.send((camera, pass_settings.clone()));
+                (rendering, stale) = (true, false);
+            }
+
+            if !updated {
+                window.update();
+                continue;
+            }
+            for (i, out) in framebuffer.iter_mut().enumerate() {
+                let (x, y) = ((i % width) as u32, (i / width) as u32);
+                let [r, g, b] = color_to_rgb(film.color(x, y)).0;
+                *out = u32::from_be_bytes([0, r, g, b]);
+            }
+            if let Err(err) = window.update_with_buffer(&framebuffer, width, height) {
+                break Err(io::Error::other(err).into());
+            }
+        };
+
+        // stops the pass being rendered, and the thread with it once the channel is dropped
+        cancel.cancel();
+        result
+    })?;
+
+    print_camera(&description, &orbit)
+}
+
+/// Moves the camera according to the mouse and keyboard, returning whether it moved.
+fn handle_input(
+    window: &Window,
+    orbit: &mut Orbit,
+    last_mouse: &mut Option<(f32, f32)>,
+    elapsed: f64,
+) -> bool {
+    let mut moved = false;
+
+    let mouse = window.get_mouse_pos(MouseMode::Pass);
+    if let (Some((x, y)), Some((last_x, last_y))) = (mouse, *last_mouse) {
+        let (dx, dy) = ((x - last_x) as f64, (y - last_y) as f64);
+        if dx != 0.0 || dy != 0.0 {
+            if window.get_mouse_down(MouseButton::Left) {
+                orbit.yaw -= dx * 0.01;
+                // stop just short of the poles, where the view direction and up would coincide
+                orbit.pitch = (orbit.pitch + dy * 0.01).clamp(-1.55, 1.55);
+                moved = true;
+            } else if window.get_mouse_down(MouseButton::Right) {
+                let (right, up) = orbit.right_up();
+                let scale = orbit.radius * 0.002;
+                orbit.target += (-dx * right + dy * up) * scale;
+                moved = true;
+            }
+        }
+    }
+    *last_mouse = mouse;
+
+    if let Some((_, scroll)) = window.get_scroll_wheel() {
+        orbit.radius = (orbit.radius * 0.9f64.powf(scroll as f64)).max(1e-3);
+        moved = true;
+    }
+
+    let (right, _) = orbit.right_up();
+    let forward = Vec3::new(-orbit.yaw.sin(), 0.0, -orbit.yaw.cos());
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let speed = orbit.radius * elapsed;
+    for (key, direction) in [
+        (Key::W, forward),
+        (Key::S, -forward),
+        (Key::D, right),
+        (Key::A, -right),
+        (Key::E, up),
+        (Key::Q, -up),
+    ] {
+        if window.is_key_down(key) {
+            orbit.target += direction * speed;
+            moved = true;
+        }
+    }
+
+    moved
+}
+
+fn print_camera(description: &CameraDescription, orbit: &Orbit) -> ray_tracing::Result<()> {
+    let camera = CameraDescription {
+        look_from: orbit.look_from(),
+        look_at: orbit.target,
+        ..description.clone()
+    };
+    println!(
+        "\"camera\": {}",
+        serde_json::to_string_pretty(&camera).map_err(io::Error::from)?
+    );
+
+    Ok(())
+}
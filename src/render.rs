@@ -0,0 +1,2055 @@
+use crate::blue_noise::Sequence;
+use crate::camera;
+use crate::collision::bvh;
+use crate::collision::materials::Lambertian;
+use crate::collision::{self, Hit, Hittable, Material};
+use crate::diagnostics::{self, Diagnostics};
+use crate::filter::{BoxFilter, FilterSample, PixelFilter, MIN_TOTAL_WEIGHT};
+use crate::framebuffer::Framebuffer;
+use crate::grade::Grade;
+use crate::scratch::{self, Scratch};
+use crate::sky::{self, Background, Gradient, Portal, Sun};
+use crate::{Camera, Color, Error, Point3, Ray, RayCone, Result, Vec3};
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+#[cfg(feature = "parallel")]
+use rayon::slice::ParallelSliceMut;
+use serde::{Deserialize, Serialize};
+use web_time::Instant;
+
+use std::cell::Cell;
+use std::f64::consts::PI;
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What the renderer computes for every pixel.
+///
+/// Every mode other than `Shaded` traces a single primary ray through the center of each pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RenderMode {
+    /// Full path tracing.
+    #[default]
+    Shaded,
+    /// The shading normal, mapped from [-1, 1] to [0, 1].
+    Normals,
+    /// Distance to the closest hit, bright when near and fading to black.
+    Depth,
+    /// Surface coordinates, u in red and v in green.
+    Uv,
+    /// Front faces in green, back faces in red.
+    FrontFace,
+    /// Number of BVH nodes and primitives tested by the primary ray, from blue (none) to red
+    /// (`max_tests` or more).
+    Heatmap { max_tests: u32 },
+}
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    pub mode: RenderMode,
+    /// When set, every object is shaded with this material instead of its own.
+    pub material_override: Option<Arc<dyn Material>>,
+    /// Whether emissive materials are replaced by the override as well.
+    pub override_emitters: bool,
+    pub filter: Arc<dyn PixelFilter>,
+    /// How the light along each camera ray is worked out, in the `Shaded` mode.
+    pub integrator: Arc<dyn Integrator>,
+    /// Once a path has bounced off a rough surface, mirrors and glass further along it blur what
+    /// they reflect and refract over a cone this many degrees across. Small, bright lights seen
+    /// through them then light the rough surface smoothly, instead of as rare fireflies, at the
+    /// cost of blurring their caustics. 0 leaves them sharp.
+    pub roughening: f64,
+    /// How many directions the sun, and the sky through the portals, are sampled in wherever
+    /// they light a surface directly. Their shadow rays are traced together, as one packet, so
+    /// more of them cost less than as many more samples per pixel, which trace everything else
+    /// again too. From 1 to [`PACKET_SIZE`](collision::PACKET_SIZE), and clamped to that.
+    pub light_samples: u32,
+    /// Spread the noise out evenly over the image with blue noise, instead of leaving it in
+    /// random clumps. It's no less noise, but far less distracting at a few samples per pixel.
+    pub blue_noise: bool,
+    /// The exposure, white balance and saturation of the `Shaded` mode's light.
+    pub grade: Grade,
+    /// Makes the background transparent to camera rays, while still lighting the scene.
+    pub transparent_background: bool,
+    /// Whether the scene has any materials that are shadow catchers, which camera rays have to
+    /// look out for. See [`Material::is_shadow_catcher`].
+    pub shadow_catchers: bool,
+    /// Render only this part of the frame, leaving the rest of the pixels empty.
+    pub region: Option<Region>,
+    /// Print the number of scanlines left to stderr while rendering.
+    pub show_progress: bool,
+    /// Makes the render reproducible, as long as the seed and everything else stay the same.
+    /// Without one, the random numbers are seeded from the system.
+    pub seed: Option<u64>,
+    /// Haze filling the whole scene, which distant objects fade into.
+    pub fog: Option<Fog>,
+    /// The sky, lighting the scene along with any sun it has.
+    pub background: Arc<dyn Background>,
+    /// Openings the sky is sampled through, see [`Portal`].
+    pub portals: Vec<Portal>,
+    /// Checks and logs for tracking down where bad light comes from.
+    pub diagnostics: Diagnostics,
+    /// The threads the render runs on.
+    pub threads: Threads,
+    /// The order the pixels are rendered in.
+    pub pixel_order: PixelOrder,
+}
+
+/// How many threads a render runs on, and how it shares the machine with everything else on it.
+/// Only has an effect with the `parallel` feature, and the default renders on every core, like
+/// everything else using rayon's global pool.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Threads {
+    /// How many threads to render on, one per core if not set.
+    pub count: Option<usize>,
+    /// The nice level of the threads, from -20 to 19, like the `nice` command's. Higher levels
+    /// give way to everything else running, so that a render can go on in the background of a
+    /// workstation without freezing it. Lowering it usually takes special permission. Only
+    /// supported on Unix.
+    pub nice: i32,
+    /// Pins every thread to a core of its own, in the order of the cores the process is allowed
+    /// to run on. Only supported on Linux.
+    pub pin: bool,
+}
+
+/// The order the pixels of the image are rendered in. Every row of pixels draws its random
+/// numbers in the same order either way, so seeded renders come out the same.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PixelOrder {
+    /// One row after the other, from left to right.
+    #[default]
+    Scanline,
+    /// Square tiles 16 pixels across, one row of tiles after the other, with the pixels of each
+    /// tile along a Morton curve. The camera rays of neighbouring pixels go through much the
+    /// same BVH nodes, which are then more likely to still be in the cache.
+    Morton,
+}
+
+/// How many pixels across the tiles of [`PixelOrder::Morton`] are.
+const TILE_SIZE: usize = 16;
+
+impl PixelOrder {
+    /// How many rows of pixels are rendered together, and the order of the pixels within them,
+    /// as their column and their row counting from the first of them.
+    fn band(self, width: usize) -> (usize, Vec<(usize, usize)>) {
+        match self {
+            PixelOrder::Scanline => (1, (0..width).map(|col| (col, 0)).collect()),
+            PixelOrder::Morton => {
+                let mut tile: Vec<_> = (0..TILE_SIZE)
+                    .flat_map(|row| (0..TILE_SIZE).map(move |col| (col, row)))
+                    .collect();
+                tile.sort_by_key(|&(col, row)| morton_code(col, row));
+
+                let order = (0..width)
+                    .step_by(TILE_SIZE)
+                    .flat_map(|start| tile.iter().map(move |&(col, row)| (start + col, row)))
+                    .filter(|&(col, _)| col < width)
+                    .collect();
+                (TILE_SIZE, order)
+            }
+        }
+    }
+}
+
+/// The bits of `x` and `y` interleaved, starting from the lowest bit of `x`.
+fn morton_code(x: usize, y: usize) -> usize {
+    (0..usize::BITS as usize / 2)
+        .map(|bit| (x >> bit & 1) << (2 * bit) | (y >> bit & 1) << (2 * bit + 1))
+        .fold(0, |code, bits| code | bits)
+}
+
+impl RenderSettings {
+    /// The same settings at a fraction of the resolution, for previewing.
+    /// A region keeps covering the same part of the image, and at least a pixel of it.
+    pub fn scaled_down(&self, divisor: u32) -> Self {
+        let scale = |x: u32| (x / divisor).max(1);
+        let (width, height) = (scale(self.width), scale(self.height));
+        // the start rounded down and the end up, each kept inside the smaller image
+        let range = |start: u32, end: u32, len: u32| {
+            let start = (start / divisor).min(len - 1);
+            (start, end.div_ceil(divisor).clamp(start + 1, len))
+        };
+
+        Self {
+            width,
+            height,
+            region: self.region.map(|region| {
+                let (x0, x1) = range(region.x0, region.x1, width);
+                let (y0, y1) = range(region.y0, region.y1, height);
+                Region { x0, y0, x1, y1 }
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Shade everything with a plain diffuse gray, keeping emitters intact.
+    pub fn clay(mut self) -> Self {
+        self.material_override = Some(Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        }));
+        self.override_emitters = false;
+        self
+    }
+
+    /// [`RenderSettings::light_samples`], but at least one, or the light would be divided by
+    /// zero, and at most as many as the shadow ray packet holds.
+    fn light_samples(&self) -> u32 {
+        self.light_samples.clamp(1, collision::PACKET_SIZE as u32)
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 450,
+            samples_per_pixel: 500,
+            max_depth: 50,
+            mode: RenderMode::Shaded,
+            material_override: None,
+            override_emitters: false,
+            filter: Arc::new(BoxFilter { radius: 0.5 }),
+            integrator: Arc::new(PathTracer),
+            roughening: 0.0,
+            light_samples: 1,
+            blue_noise: false,
+            grade: Grade::default(),
+            transparent_background: false,
+            shadow_catchers: false,
+            region: None,
+            show_progress: true,
+            seed: None,
+            fog: None,
+            background: Arc::new(Gradient::default()),
+            portals: Vec::new(),
+            diagnostics: Diagnostics::default(),
+            threads: Threads::default(),
+            pixel_order: PixelOrder::default(),
+        }
+    }
+}
+
+/// A homogeneous haze between the camera and everything it sees, thinning out with height.
+///
+/// Rather than scattering rays at random, like a volume does, every ray segment blends toward
+/// the fog's color by how much of the light along it the fog absorbs. That's noise-free and
+/// costs nothing, and leaves the sky visible above the fog as long as it thins out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    /// The density at a height of 0, per unit of distance.
+    pub density: f64,
+    /// The light the fog scatters toward the camera.
+    pub color: Color,
+    /// How quickly the density falls off with height, with `density * e^(-falloff * y)` at
+    /// height `y`. 0 makes it the same everywhere, hiding the sky completely.
+    pub falloff: f64,
+}
+
+impl Fog {
+    /// Fails if the density, falloff or any component of the color is negative or not finite.
+    pub fn new(density: f64, color: Color, falloff: f64) -> Result<Self> {
+        let density = Error::check("fog density", density, |density| density >= 0.0)?;
+        let falloff = Error::check("fog falloff", falloff, |falloff| falloff >= 0.0)?;
+        for component in color.0 {
+            Error::check("fog color", component, |c| c >= 0.0)?;
+        }
+
+        Ok(Self {
+            density,
+            color,
+            falloff,
+        })
+    }
+
+    /// The fraction of the light that makes it through the fog along `ray`, over `t` times the
+    /// ray's direction.
+    pub fn transmittance(&self, ray: &Ray, t: f64) -> f64 {
+        let distance = t * ray.direction.length();
+        let rise = ray.direction.normalize().y();
+        let density = self.density * (-self.falloff * ray.origin.y()).exp();
+
+        // the integral of the density along the ray
+        let k = self.falloff * rise;
+        let optical_depth = if k.abs() < 1e-9 {
+            density * distance
+        } else {
+            density * (1.0 - (-k * distance).exp()) / k
+        };
+        if optical_depth.is_nan() {
+            // no fog at all, times an infinite distance
+            return 1.0;
+        }
+
+        (-optical_depth).exp()
+    }
+
+    /// Fogs the light `color` coming from `t` along `ray`.
+    pub fn apply(&self, color: Color, ray: &Ray, t: f64) -> Color {
+        let transmittance = self.transmittance(ray, t);
+        transmittance * color + (1.0 - transmittance) * self.color
+    }
+}
+
+/// A rectangle of pixels in image coordinates, with the origin at the top left corner.
+///
+/// The start coordinates are inclusive and the end coordinates exclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Region {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    /// Extracts the pixels inside the region out of a full frame of `width` pixels per row.
+    ///
+    /// Just like the frame, the result is ordered starting from the bottom row.
+    pub fn crop(&self, pixels: &[Pixel], width: u32) -> Vec<Pixel> {
+        let height = pixels.len() as u32 / width;
+
+        (self.y0..self.y1)
+            .rev()
+            .flat_map(|y| {
+                let row = (height - 1 - y) * width;
+                &pixels[(row + self.x0) as usize..(row + self.x1) as usize]
+            })
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Pixel {
+    /// Averaged linear color, premultiplied by `alpha`.
+    pub color: Color,
+    /// Fraction of the pixel covered by geometry, or 1 if the background is opaque.
+    pub alpha: f64,
+    /// Wall-clock time spent rendering this pixel.
+    pub time: Duration,
+}
+
+/// Counts of the work done by the renderer, summed over all threads.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Rays starting at the camera.
+    pub camera_rays: u64,
+    /// Every ray traced, both from the camera and scattered off surfaces.
+    pub rays_traced: u64,
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+    /// Wall-clock time spent rendering.
+    pub render_time: Duration,
+}
+
+impl RenderStats {
+    /// 0 if no time was spent rendering.
+    pub fn rays_per_second(&self) -> f64 {
+        ratio(self.rays_traced as f64, self.render_time.as_secs_f64())
+    }
+
+    /// The average number of rays making up a path, including the camera ray, or 0 if there
+    /// were no paths.
+    pub fn average_path_depth(&self) -> f64 {
+        ratio(self.rays_traced as f64, self.camera_rays as f64)
+    }
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.camera_rays += other.camera_rays;
+        self.rays_traced += other.rays_traced;
+        self.nodes_visited += other.nodes_visited;
+        self.primitives_tested += other.primitives_tested;
+        self.render_time += other.render_time;
+    }
+}
+
+thread_local! {
+    /// Camera rays and rays traced on the current thread, since the end of its last scanline.
+    static RAYS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+static TOTAL_STATS: Mutex<RenderStats> = Mutex::new(RenderStats {
+    camera_rays: 0,
+    rays_traced: 0,
+    nodes_visited: 0,
+    primitives_tested: 0,
+    render_time: Duration::ZERO,
+});
+
+/// Returns the statistics of every render since the last call, across the whole process, and
+/// resets them to zero.
+pub fn take_render_stats() -> RenderStats {
+    std::mem::take(&mut *TOTAL_STATS.lock().unwrap())
+}
+
+pub(crate) fn count_ray(from_camera: bool) {
+    RAYS.with(|rays| {
+        let (camera, total) = rays.get();
+        rays.set((camera + from_camera as u64, total + 1));
+    });
+}
+
+/// Counts `count` rays from the camera at once, like as many calls to [`count_ray`].
+fn count_camera_rays(count: u32) {
+    RAYS.with(|rays| {
+        let (camera, total) = rays.get();
+        rays.set((camera + count as u64, total + count as u64));
+    });
+}
+
+/// A world seen through a camera, for rendering straight into memory.
+///
+/// ```
+/// use ray_tracing::collision::{materials::Lambertian, objects::Sphere};
+/// use ray_tracing::render::{RenderSettings, Renderer};
+/// use ray_tracing::{Camera, Color, Point3};
+/// use std::sync::Arc;
+///
+/// let gray = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))?);
+/// let world = vec![Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, gray)?];
+/// let renderer = Renderer::new(world, Camera::builder().aspect_ratio(2.0).build()?);
+///
+/// let settings = RenderSettings {
+///     width: 8,
+///     height: 4,
+///     samples_per_pixel: 4,
+///     show_progress: false,
+///     ..Default::default()
+/// };
+/// let mut buffer = vec![0.0; 8 * 4 * 4];
+/// renderer.render_into(&mut buffer, &settings)?;
+/// # Ok::<(), ray_tracing::Error>(())
+/// ```
+pub struct Renderer<W> {
+    pub world: W,
+    pub camera: Camera,
+}
+
+impl<W> Renderer<W>
+where
+    W: Hittable + Sync,
+{
+    pub fn new(world: W, camera: Camera) -> Self {
+        Self { world, camera }
+    }
+
+    /// Renders into `buffer`, laid out like a [`Framebuffer`]: linear RGBA starting from the top
+    /// row. Fails if the buffer doesn't hold exactly four floats per pixel.
+    pub fn render_into(&self, buffer: &mut [f32], settings: &RenderSettings) -> Result<()> {
+        self.render_into_cancellable(buffer, settings, &CancellationToken::new())
+    }
+
+    /// Same as [`Renderer::render_into`], but stops early with [`Error::Cancelled`] once `cancel`
+    /// is cancelled, leaving the buffer as it was.
+    pub fn render_into_cancellable(
+        &self,
+        buffer: &mut [f32],
+        settings: &RenderSettings,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let expected = Framebuffer::len(settings.width, settings.height);
+        if buffer.len() != expected {
+            return Err(Error::BufferSize {
+                expected,
+                found: buffer.len(),
+            });
+        }
+
+        let pixels = render_cancellable(&self.world, &self.camera, settings, cancel)
+            .ok_or(Error::Cancelled)?;
+        Framebuffer::write_pixels(buffer, &pixels, settings.width);
+
+        Ok(())
+    }
+
+    pub fn render(&self, settings: &RenderSettings) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(settings.width, settings.height);
+        self.render_into(framebuffer.as_mut_slice(), settings)
+            .expect("the framebuffer has the right size");
+
+        framebuffer
+    }
+}
+
+/// Renders the world, returning every pixel row by row, starting from the bottom row of the
+/// image.
+pub fn render<T>(world: &T, camera: &Camera, settings: &RenderSettings) -> Vec<Pixel>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    render_cancellable(world, camera, settings, &CancellationToken::new()).unwrap()
+}
+
+/// Asks renders to stop early, from any thread. Clones share the same request, so that one can
+/// be handed to the render and another kept to cancel it with.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Takes the request back, for reusing the token with the next render.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Same as [`render`], but gives up and returns `None` once `cancel` is cancelled. The token is
+/// checked before every pixel, so the render stops within a pixel's samples on every thread.
+pub fn render_cancellable<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    cancel: &CancellationToken,
+) -> Option<Vec<Pixel>>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let mut buf = vec![Pixel::default(); settings.width as usize * settings.height as usize];
+    render_rows(world, camera, settings, cancel, &mut buf, 0)?;
+
+    Some(buf)
+}
+
+/// Same as [`render_cancellable`], but renders only `region`, overriding that of `settings`,
+/// and returns just its pixels, in the order of [`Region::crop`]. Only takes as much memory as
+/// the rows of the region need. Fails with [`Error::RegionOutside`] unless the region is inside
+/// the image and not empty, and with [`Error::Cancelled`] once `cancel` is cancelled.
+pub fn render_region_cancellable<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    region: Region,
+    cancel: &CancellationToken,
+) -> Result<Vec<Pixel>>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    if !(region.x0 < region.x1
+        && region.x1 <= settings.width
+        && region.y0 < region.y1
+        && region.y1 <= settings.height)
+    {
+        return Err(Error::RegionOutside {
+            region,
+            width: settings.width,
+            height: settings.height,
+        });
+    }
+
+    let settings = RenderSettings {
+        region: Some(region),
+        ..settings.clone()
+    };
+    let mut buf = vec![Pixel::default(); settings.width as usize * region.height() as usize];
+    let first_row = (settings.height - region.y1) as usize;
+    render_rows(world, camera, &settings, cancel, &mut buf, first_row).ok_or(Error::Cancelled)?;
+
+    let rows = Region {
+        y0: 0,
+        y1: region.height(),
+        ..region
+    };
+    Ok(rows.crop(&buf, settings.width))
+}
+
+/// Renders the region of `settings` into `buf`, which holds whole rows of the frame starting
+/// from `first_row`, counted from the top. Returns `None` if `cancel` was cancelled.
+fn render_rows<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    cancel: &CancellationToken,
+    buf: &mut [Pixel],
+    first_row: usize,
+) -> Option<()>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let region = settings.region.unwrap_or(Region {
+        x0: 0,
+        y0: 0,
+        x1: settings.width,
+        y1: settings.height,
+    });
+
+    let start = Instant::now();
+    let stats = Mutex::new(RenderStats::default());
+    // the row past the top one has its random numbers to itself
+    let sequence = settings
+        .blue_noise
+        .then(|| Sequence::new(&mut row_rng(settings.seed, height)));
+
+    let (band_height, order) = settings.pixel_order.band(width);
+    let linesleft = AtomicI32::new(region.height() as i32);
+    with_threads(&settings.threads, || {
+        if settings.mode == RenderMode::Shaded {
+            settings.integrator.begin_pass(&world, camera, settings);
+        }
+        #[cfg(feature = "parallel")]
+        let bands = buf.par_chunks_mut(width * band_height);
+        #[cfg(not(feature = "parallel"))]
+        let bands = buf.chunks_mut(width * band_height);
+        bands.enumerate().for_each(|(band, pixels)| {
+            let first_row = first_row + band * band_height;
+            // rows outside the region have no random numbers, and nothing rendered
+            let mut rngs: Vec<_> = (first_row..first_row + pixels.len() / width)
+                .map(|row| {
+                    let y = (height - 1 - row) as u32;
+                    (region.y0..region.y1)
+                        .contains(&y)
+                        .then(|| row_rng(settings.seed, row))
+                })
+                .collect();
+            let rows_rendered = rngs.iter().flatten().count() as i32;
+            if rows_rendered == 0 || cancel.is_cancelled() {
+                return;
+            }
+
+            let (x0, x1) = (region.x0 as usize, region.x1 as usize);
+            for &(col, offset) in &order {
+                let Some(rng) = rngs.get_mut(offset).and_then(Option::as_mut) else {
+                    continue;
+                };
+                if !(x0..x1).contains(&col) {
+                    continue;
+                }
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let start = Instant::now();
+                let (color, alpha) = calculate_pixel(
+                    first_row + offset,
+                    col,
+                    camera,
+                    world,
+                    settings,
+                    sequence.as_ref(),
+                    rng,
+                );
+                pixels[offset * width + col] = Pixel {
+                    color,
+                    alpha,
+                    time: start.elapsed(),
+                };
+                scratch::reset();
+            }
+
+            let traversal = bvh::take_traversal_stats();
+            let (camera_rays, rays_traced) = RAYS.with(|rays| rays.take());
+            *stats.lock().unwrap() += RenderStats {
+                camera_rays,
+                rays_traced,
+                nodes_visited: traversal.nodes_visited,
+                primitives_tested: traversal.primitives_tested,
+                render_time: Duration::ZERO,
+            };
+
+            let lines = linesleft.fetch_sub(rows_rendered, Ordering::SeqCst) - rows_rendered;
+            if settings.show_progress {
+                eprint!("\rScanlines remaining: {} ", lines);
+                let _ = io::stderr().flush();
+            }
+        });
+    });
+
+    if settings.show_progress {
+        eprintln!();
+    }
+
+    let mut stats = stats.into_inner().unwrap();
+    stats.render_time = start.elapsed();
+    *TOTAL_STATS.lock().unwrap() += stats;
+
+    (!cancel.is_cancelled()).then_some(())
+}
+
+/// The pool of threads set up the last time something asked for them, along with how. It's kept
+/// for the next render, which usually asks for the same ones.
+#[cfg(feature = "parallel")]
+static POOL: Mutex<Option<(Threads, Arc<rayon::ThreadPool>)>> = Mutex::new(None);
+
+/// Runs `f` on threads set up as `threads` asks for, or on rayon's global pool if it asks for
+/// nothing in particular. Without the `parallel` feature, just runs it.
+pub(crate) fn with_threads<R: Send>(threads: &Threads, f: impl FnOnce() -> R + Send) -> R {
+    #[cfg(feature = "parallel")]
+    if *threads != Threads::default() {
+        match pool(threads) {
+            Ok(pool) => return pool.install(f),
+            Err(err) => log::warn!(
+                "Couldn't start the render threads, using the usual ones: {}",
+                err
+            ),
+        }
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = threads;
+    f()
+}
+
+/// The pool set up as `threads` asks for, started unless the last one was set up the same way.
+#[cfg(feature = "parallel")]
+fn pool(threads: &Threads) -> Result<Arc<rayon::ThreadPool>, rayon::ThreadPoolBuildError> {
+    let mut cached = POOL.lock().unwrap();
+    if let Some((cached_threads, pool)) = &*cached {
+        if cached_threads == threads {
+            return Ok(pool.clone());
+        }
+    }
+
+    let (nice, pin) = (threads.nice, threads.pin);
+    let cores = if pin { allowed_cores() } else { Vec::new() };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.count.unwrap_or(0))
+        .thread_name(|index| format!("render-{}", index))
+        .start_handler(move |index| set_up_thread(index, nice, pin, &cores))
+        .build()?;
+    let pool = Arc::new(pool);
+    *cached = Some((threads.clone(), pool.clone()));
+    Ok(pool)
+}
+
+/// The cores the process is allowed to run on, in order, which may be fewer than the machine has
+/// under `taskset` or in a container.
+#[cfg(feature = "parallel")]
+fn allowed_cores() -> Vec<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: the set is plain data, zeroed is empty, and the size passed is its own
+        let set = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                log::warn!(
+                    "Couldn't find the cores to pin the render threads to: {}",
+                    io::Error::last_os_error()
+                );
+                return Vec::new();
+            }
+            set
+        };
+        (0..libc::CPU_SETSIZE as usize)
+            // SAFETY: the index is within the set
+            .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    Vec::new()
+}
+
+/// Sets the nice level of the calling thread, and pins it to the `index`th of `cores`, wrapping
+/// around if there are more threads than cores.
+#[cfg(feature = "parallel")]
+fn set_up_thread(index: usize, nice: i32, pin: bool, cores: &[usize]) {
+    #[cfg(unix)]
+    // SAFETY: only takes plain values. On Linux it changes the calling thread alone, and on other
+    // Unixes the whole process.
+    if nice != 0 && unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        log::warn!(
+            "Couldn't set the nice level of the render threads to {}: {}",
+            nice,
+            io::Error::last_os_error()
+        );
+    }
+    #[cfg(not(unix))]
+    if nice != 0 {
+        log::warn!("Nice levels are only supported on Unix.");
+    }
+
+    #[cfg(target_os = "linux")]
+    if pin && !cores.is_empty() {
+        // SAFETY: the set is plain data, zeroed is empty, and the size passed is its own
+        let pinned = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cores[index % cores.len()], &mut set);
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+        };
+        if pinned != 0 {
+            log::warn!(
+                "Couldn't pin render thread {} to a core: {}",
+                index,
+                io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = cores;
+    #[cfg(not(target_os = "linux"))]
+    if pin && index == 0 {
+        log::warn!("Pinning threads to cores is only supported on Linux.");
+    }
+}
+
+/// How the two views of a stereo pair are arranged in one image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left, right eye on the right.
+    SideBySide,
+    /// Left eye on top, right eye on the bottom.
+    OverUnder,
+}
+
+impl StereoLayout {
+    /// The size of the combined image for views of the given size.
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            StereoLayout::SideBySide => (2 * width, height),
+            StereoLayout::OverUnder => (width, 2 * height),
+        }
+    }
+}
+
+/// Renders both eyes of a stereo pair with the given interpupillary distance, and combines them
+/// into a single frame with the size given by [`StereoLayout::dimensions`].
+pub fn render_stereo<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    ipd: f64,
+    layout: StereoLayout,
+) -> Vec<Pixel>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let cancel = CancellationToken::new();
+    render_stereo_cancellable(world, camera, settings, ipd, layout, &cancel).unwrap()
+}
+
+/// Same as [`render_stereo`], but gives up and returns `None` once `cancel` is cancelled.
+pub fn render_stereo_cancellable<T>(
+    world: &T,
+    camera: &Camera,
+    settings: &RenderSettings,
+    ipd: f64,
+    layout: StereoLayout,
+    cancel: &CancellationToken,
+) -> Option<Vec<Pixel>>
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let left = render_cancellable(world, &camera.eye(-ipd / 2.0), settings, cancel)?;
+    let right = render_cancellable(world, &camera.eye(ipd / 2.0), settings, cancel)?;
+
+    let pixels = match layout {
+        StereoLayout::SideBySide => left
+            .chunks(settings.width as usize)
+            .zip(right.chunks(settings.width as usize))
+            .flat_map(|(left, right)| left.iter().chain(right))
+            .copied()
+            .collect(),
+        // rows start from the bottom, so the right eye goes first
+        StereoLayout::OverUnder => right.into_iter().chain(left).collect(),
+    };
+
+    Some(pixels)
+}
+
+/// The random number generator for one row, which only depends on the seed and the row so that
+/// the order the rows are rendered in doesn't matter.
+pub(crate) fn row_rng(seed: Option<u64>, row: usize) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add((row as u64) << 32)),
+        None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+    }
+}
+
+/// How many samples of a pixel have their camera rays drawn at once.
+const SAMPLE_BATCH: usize = 16;
+
+/// The random numbers that place one sample of a pixel: where on the pixel and the lens its ray
+/// goes through, and how much it counts.
+#[derive(Copy, Clone, Default)]
+struct CameraSample {
+    offset: (f64, f64),
+    weight: f64,
+    lens: Vec3,
+}
+
+impl CameraSample {
+    fn draw(camera: &Camera, settings: &RenderSettings, rng: &mut dyn RngCore) -> Self {
+        let FilterSample { offset, weight } = settings.filter.sample(rng);
+        Self {
+            offset,
+            weight,
+            lens: camera.lens_sample(rng),
+        }
+    }
+}
+
+fn calculate_pixel<T, R>(
+    row: usize,
+    col: usize,
+    camera: &Camera,
+    world: &T,
+    settings: &RenderSettings,
+    sequence: Option<&Sequence>,
+    rng: &mut R,
+) -> (Color, f64)
+where
+    T: Hittable + Sync + ?Sized,
+    R: RngCore,
+{
+    if settings.mode != RenderMode::Shaded {
+        let u = col as f64 / (settings.width as f64 - 1.0);
+        let v = row as f64 / (settings.height as f64 - 1.0);
+
+        count_ray(true);
+        return (
+            debug_color(&camera.get_ray(u, v, rng), world, settings.mode),
+            1.0,
+        );
+    }
+
+    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
+    let mut coverage = 0.0;
+    let mut total_weight = 0.0;
+    // the same without the weights, for when they cancel out
+    let mut unweighted = (Color::new(0.0, 0.0, 0.0), 0.0);
+    let cone = camera.pixel_cone(settings.height);
+    // with chromatic aberration, the samples take turns tracing red, green and blue
+    let first_channel = camera.lens().has_aberration().then(|| rng.gen_range(0..3));
+    let (x, y) = (col as u32, settings.height - 1 - row as u32);
+    let watched = settings.diagnostics.watches(x, y);
+    // the center of the pixel on the film, and how far it is across a pixel
+    let step = (
+        1.0 / (settings.width as f64 - 1.0),
+        1.0 / (settings.height as f64 - 1.0),
+    );
+    let center = ((col as f64 + 0.5) * step.0, (row as f64 + 0.5) * step.1);
+    count_camera_rays(settings.samples_per_pixel);
+
+    let mut batch = [CameraSample::default(); SAMPLE_BATCH];
+    for first in (0..settings.samples_per_pixel).step_by(SAMPLE_BATCH) {
+        let count = (settings.samples_per_pixel - first).min(SAMPLE_BATCH as u32) as usize;
+        // blue noise gives every sample numbers of its own, so those can't be drawn ahead
+        if sequence.is_none() {
+            for sample in &mut batch[..count] {
+                *sample = CameraSample::draw(camera, settings, rng);
+            }
+        }
+
+        for (index, &sample) in (first..).zip(&batch[..count]) {
+            let mut blue_noise;
+            let (rng, sample): (&mut dyn RngCore, _) = match sequence {
+                Some(sequence) => {
+                    blue_noise = sequence.sample(col, row, index, rng);
+                    let sample = CameraSample::draw(camera, settings, &mut blue_noise);
+                    (&mut blue_noise, sample)
+                }
+                None => (rng, sample),
+            };
+
+            let CameraSample {
+                offset,
+                weight,
+                lens,
+            } = sample;
+            let u = center.0 + offset.0 * step.0;
+            let v = center.1 + offset.1 * step.1;
+
+            let (ray, channels) = match first_channel {
+                Some(first) => {
+                    let channel = (first + index as usize) % 3;
+                    (
+                        camera.ray_through(u, v, Some(channel), lens),
+                        camera::channel_weights(channel),
+                    )
+                }
+                None => (
+                    camera.ray_through(u, v, None, lens),
+                    Color::new(1.0, 1.0, 1.0),
+                ),
+            };
+            total_weight += weight;
+
+            if settings.transparent_background || settings.shadow_catchers {
+                match world.hit(&ray, 0.001, f64::INFINITY) {
+                    // rays escaping straight to a transparent background contribute nothing
+                    None if settings.transparent_background => continue,
+                    Some(hit) if hit.material.is_shadow_catcher() => {
+                        let (mut color, mut alpha) =
+                            catch_shadow(&ray, &hit, cone, &world, settings, rng);
+                        if !settings.transparent_background {
+                            color += (1.0 - alpha) * background_color(&ray, settings, false);
+                            alpha = 1.0;
+                        }
+                        let color = camera.vignetting(u, v) * channels * color;
+                        pixel_color += weight * color;
+                        coverage += weight * alpha;
+                        unweighted.0 += color;
+                        unweighted.1 += alpha;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            if watched {
+                diagnostics::start();
+            }
+            let radiance = settings
+                .integrator
+                .radiance(&ray, cone, &world, settings, rng);
+            if watched && !diagnostics::finish(&settings.diagnostics, x, y, index, radiance) {
+                coverage += weight;
+                unweighted.1 += 1.0;
+                continue;
+            }
+            let color = camera.vignetting(u, v) * channels * radiance;
+            pixel_color += weight * color;
+            coverage += weight;
+            unweighted.0 += color;
+            unweighted.1 += 1.0;
+        }
+    }
+
+    // the negative lobes of filters like Mitchell's can cancel out the rest of a few samples,
+    // leaving nothing to divide by
+    let (color, alpha) = if total_weight.abs() < MIN_TOTAL_WEIGHT {
+        let samples = settings.samples_per_pixel as f64;
+        (unweighted.0 / samples, unweighted.1 / samples)
+    } else {
+        (pixel_color / total_weight, coverage / total_weight)
+    };
+    let splatted = settings.integrator.splat(row, col);
+    (settings.grade.apply(color + splatted), alpha)
+}
+
+/// What the camera `ray` sees of the shadow catcher at `hit`: the light the rest of the scene
+/// reflects onto it, premultiplied by how much of the photograph underneath that takes the place
+/// of, which is returned along with it.
+///
+/// A single ray is scattered off the catcher. If it reaches the sky, the photograph is left as it
+/// is, since it already shows the ground lit by it. If it hits anything else, that blocks the
+/// light of the sky, and replaces it with its own, so the photograph is covered. Averaged over
+/// many samples, that darkens the photograph by the fraction of its light that's blocked, which
+/// assumes the sky is about as bright in every direction. Small suns cast noisier shadows.
+fn catch_shadow(
+    ray: &Ray,
+    hit: &Hit,
+    cone: RayCone,
+    world: &(dyn Hittable + Sync),
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> (Color, f64) {
+    let (attenuation, scattered) = match hit.material.scatter(ray, hit, rng) {
+        Some(scattered) => scattered,
+        None => return (Color::default(), 0.0),
+    };
+
+    count_ray(false);
+    let mut t_min = 0.001;
+    loop {
+        match world.hit(&scattered, t_min, f64::INFINITY) {
+            // catchers don't cast shadows onto each other, they're all part of the photograph
+            Some(next) if next.material.is_shadow_catcher() => {
+                t_min = next.t + 1e-9 * next.t.abs().max(1.0)
+            }
+            Some(_) => {
+                let light = settings
+                    .integrator
+                    .radiance(&scattered, cone, world, settings, rng);
+                return (attenuation * light, 1.0);
+            }
+            None => return (Color::default(), 0.0),
+        }
+    }
+}
+
+/// How the light arriving along each camera ray is worked out. The more accurate ones are slower,
+/// which previews can trade for speed.
+pub trait Integrator: Send + Sync {
+    /// The light arriving along the camera `ray`, with textures filtered over its `cone`.
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color;
+
+    /// Called before every pass of samples over the image, for integrators that need to look at
+    /// the world first.
+    fn begin_pass(
+        &self,
+        _world: &(dyn Hittable + Sync),
+        _camera: &Camera,
+        _settings: &RenderSettings,
+    ) {
+    }
+
+    /// The light that paths traced from the lights during this pass put on the pixel at `row`,
+    /// counting from the bottom, and `col`, on top of what the camera rays find.
+    fn splat(&self, _row: usize, _col: usize) -> Color {
+        Color::default()
+    }
+}
+
+/// Full path tracing, following rays from surface to surface up to the maximum depth.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        trace(
+            ray,
+            cone,
+            world,
+            settings,
+            settings.max_depth,
+            Sampled::default(),
+            false,
+            rng,
+        )
+    }
+}
+
+/// Only light reaching the first surface straight from the sun, the sky or an emitter, with no
+/// bounces in between. Much faster and less noisy than path tracing, but the shadows are black
+/// and mirrors and glass only show the lights.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        let (color, t) = match surface_hit(ray, cone, world) {
+            Some((hit, _)) => {
+                let t = hit.t;
+                let material = shading_material(ray, &hit, settings);
+                let emitted = material.emitted(ray, &hit);
+                let (sunlight, sun_sampled) = match settings.background.sun() {
+                    Some(sun) => {
+                        direct_sunlight(sun, ray, &hit, material.as_ref(), world, settings, rng)
+                    }
+                    None => (Color::default(), false),
+                };
+                let (skylight, portals_sampled) =
+                    direct_skylight(ray, &hit, material.as_ref(), world, settings, rng);
+                let sampled = Sampled {
+                    sun: sun_sampled,
+                    portals: portals_sampled,
+                };
+
+                // whatever the scattered ray sees first is taken as a light, lit or not
+                let lights = material
+                    .scatter(ray, &hit, rng)
+                    .map(|(attenuation, scattered)| {
+                        count_ray(false);
+                        let seen = match world.hit(&scattered, 0.001, f64::INFINITY) {
+                            Some(hit) => (
+                                shading_material(&scattered, &hit, settings)
+                                    .emitted(&scattered, &hit),
+                                hit.t,
+                            ),
+                            None => (escaped_color(&scattered, settings, sampled), f64::INFINITY),
+                        };
+                        attenuation * with_fog(seen, &scattered, settings)
+                    });
+                (
+                    emitted + sunlight + skylight + lights.unwrap_or_default(),
+                    t,
+                )
+            }
+            None => (background_color(ray, settings, false), f64::INFINITY),
+        };
+
+        with_fog((color, t), ray, settings)
+    }
+}
+
+/// Classic Whitted ray tracing: mirrors and glass are followed up to the maximum depth, while
+/// everything else is only lit by the sun, and by the sky above it without any shadows.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Whitted;
+
+impl Whitted {
+    fn trace(
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        depth: i32,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        if depth <= 0 {
+            return Color::default();
+        }
+        if depth < settings.max_depth {
+            count_ray(false);
+        }
+
+        let (color, t) = match surface_hit(ray, cone, world) {
+            Some((hit, cone)) => {
+                let t = hit.t;
+                let material = shading_material(ray, &hit, settings);
+                let emitted = material.emitted(ray, &hit);
+                let specular = material.reflectance(ray, &hit, hit.normal).is_none();
+                let sunlight = match settings.background.sun() {
+                    Some(sun) => {
+                        direct_sunlight(sun, ray, &hit, material.as_ref(), world, settings, rng).0
+                    }
+                    None => Color::default(),
+                };
+
+                let reflected = match material.scatter(ray, &hit, rng) {
+                    Some((attenuation, scattered)) if specular => {
+                        let seen = Self::trace(&scattered, cone, world, settings, depth - 1, rng);
+                        attenuation * seen
+                    }
+                    Some((attenuation, _)) => {
+                        attenuation * settings.background.color(hit.normal.normalize())
+                    }
+                    None => Color::default(),
+                };
+                (emitted + sunlight + reflected, t)
+            }
+            None => (background_color(ray, settings, false), f64::INFINITY),
+        };
+
+        with_fog((color, t), ray, settings)
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        Self::trace(ray, cone, world, settings, settings.max_depth, rng)
+    }
+}
+
+/// A quick look at the scene that settles down after a handful of samples, for previews. Mirrors
+/// and glass are followed, and everything else is lit by the sun and emitters like in
+/// [`DirectLighting`], plus the sky in place of all the light bouncing around. The sky is
+/// only blocked by what's closer than `occlusion_distance`, which darkens corners and creases
+/// without the long shadows that make path tracing noisy.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Preview {
+    /// In scene units.
+    pub occlusion_distance: f64,
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self {
+            occlusion_distance: 1.0,
+        }
+    }
+}
+
+impl Preview {
+    fn trace(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        depth: i32,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        if depth <= 0 {
+            return Color::default();
+        }
+        if depth < settings.max_depth {
+            count_ray(false);
+        }
+
+        let (color, t) = match surface_hit(ray, cone, world) {
+            Some((hit, cone)) => {
+                let t = hit.t;
+                let material = shading_material(ray, &hit, settings);
+                let emitted = material.emitted(ray, &hit);
+                let specular = material.reflectance(ray, &hit, hit.normal).is_none();
+                let (sunlight, sun_sampled) = match settings.background.sun() {
+                    Some(sun) if !specular => {
+                        direct_sunlight(sun, ray, &hit, material.as_ref(), world, settings, rng)
+                    }
+                    _ => (Color::default(), false),
+                };
+
+                let scattered = material.scatter(ray, &hit, rng);
+                let indirect = match scattered {
+                    Some((attenuation, scattered)) if specular => {
+                        let seen = self.trace(&scattered, cone, world, settings, depth - 1, rng);
+                        attenuation * seen
+                    }
+                    Some((attenuation, scattered)) => {
+                        count_ray(false);
+                        let length = scattered.direction.length();
+                        let near = self.occlusion_distance / length;
+                        let ambient = match world.hit(&scattered, 0.001, near) {
+                            Some(hit) => shading_material(&scattered, &hit, settings)
+                                .emitted(&scattered, &hit),
+                            None => background_color(&scattered, settings, sun_sampled),
+                        };
+                        attenuation * ambient
+                    }
+                    None => Color::default(),
+                };
+                (emitted + sunlight + indirect, t)
+            }
+            None => (background_color(ray, settings, false), f64::INFINITY),
+        };
+
+        with_fog((color, t), ray, settings)
+    }
+}
+
+impl Integrator for Preview {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        cone: RayCone,
+        world: &(dyn Hittable + Sync),
+        settings: &RenderSettings,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        self.trace(ray, cone, world, settings, settings.max_depth, rng)
+    }
+}
+
+pub fn ray_color<T>(
+    ray: &Ray,
+    world: &T,
+    settings: &RenderSettings,
+    depth: i32,
+    rng: &mut dyn RngCore,
+) -> Color
+where
+    T: Hittable + ?Sized,
+{
+    trace(
+        ray,
+        RayCone::default(),
+        world,
+        settings,
+        depth,
+        Sampled::default(),
+        false,
+        rng,
+    )
+}
+
+/// The lights that were already sampled where a ray starts, which it leaves out if it reaches
+/// them, so they aren't counted twice.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Sampled {
+    sun: bool,
+    /// The sky seen through portals.
+    portals: bool,
+}
+
+/// Like [`ray_color`], with textures filtered over the ray's `cone` and leaving out the lights
+/// that were already `sampled` where the ray starts. Past a `rough` surface, mirrors and glass
+/// are roughened by the settings.
+#[allow(clippy::too_many_arguments)]
+fn trace<T>(
+    ray: &Ray,
+    cone: RayCone,
+    world: &T,
+    settings: &RenderSettings,
+    depth: i32,
+    sampled: Sampled,
+    rough: bool,
+    rng: &mut dyn RngCore,
+) -> Color
+where
+    T: Hittable + ?Sized,
+{
+    if depth <= 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // camera rays are counted by the caller
+    if depth < settings.max_depth {
+        count_ray(false);
+    }
+
+    let recording = diagnostics::recording();
+    let mut surface = None;
+    let (color, t) = match surface_hit(ray, cone, world) {
+        Some((hit, cone)) => {
+            let t = hit.t;
+            if recording {
+                let material = shading_material(ray, &hit, settings);
+                surface = Some(diagnostics::Surface::new(
+                    &settings.diagnostics,
+                    &hit,
+                    material.as_ref(),
+                ));
+            }
+            (shade(ray, hit, cone, world, settings, depth, rough, rng), t)
+        }
+        None => (escaped_color(ray, settings, sampled), f64::INFINITY),
+    };
+
+    let color = with_fog((color, t), ray, settings);
+    if recording {
+        diagnostics::record(settings.max_depth - depth, surface, color);
+    }
+    color
+}
+
+/// The closest surface along the ray, with the footprint of the ray's `cone` on it, and the cone
+/// where it reaches the surface.
+pub(crate) fn surface_hit<T>(ray: &Ray, cone: RayCone, world: &T) -> Option<(Hit, RayCone)>
+where
+    T: Hittable + ?Sized,
+{
+    let mut hit = world.hit(ray, 0.001, f64::INFINITY)?;
+    let length = ray.direction.length();
+    let cone = cone.advance(hit.t * length);
+    // surfaces seen at a grazing angle stretch the cone over more of themselves
+    let cos = (ray.direction.dot(&hit.normal) / length).abs().max(1e-3);
+    hit.footprint = cone.width * hit.uv_density / cos;
+
+    Some((hit, cone))
+}
+
+/// The `color` seen at `t` along the ray, through any fog in between.
+pub(crate) fn with_fog((color, t): (Color, f64), ray: &Ray, settings: &RenderSettings) -> Color {
+    match &settings.fog {
+        Some(fog) => fog.apply(color, ray, t),
+        None => color,
+    }
+}
+
+/// The material `hit` is shaded with, which is the override if there is one.
+pub(crate) fn shading_material(
+    ray: &Ray,
+    hit: &Hit,
+    settings: &RenderSettings,
+) -> Arc<dyn Material> {
+    match &settings.material_override {
+        Some(material)
+            if settings.override_emitters || hit.material.emitted(ray, hit) == Color::default() =>
+        {
+            material.clone()
+        }
+        _ => hit.material.clone(),
+    }
+}
+
+/// The light leaving the surface `hit` toward the ray's origin.
+#[allow(clippy::too_many_arguments)]
+fn shade<T>(
+    ray: &Ray,
+    hit: Hit,
+    cone: RayCone,
+    world: &T,
+    settings: &RenderSettings,
+    depth: i32,
+    rough: bool,
+    rng: &mut dyn RngCore,
+) -> Color
+where
+    T: Hittable + ?Sized,
+{
+    let material = shading_material(ray, &hit, settings);
+    let emitted = material.emitted(ray, &hit);
+    let (mut sunlight, mut sun_sampled) = match settings.background.sun() {
+        Some(sun) => direct_sunlight(sun, ray, &hit, material.as_ref(), world, settings, rng),
+        None => (Color::default(), false),
+    };
+    let (skylight, portals_sampled) =
+        direct_skylight(ray, &hit, material.as_ref(), world, settings, rng);
+    let specular = material.reflectance(ray, &hit, hit.normal).is_none();
+    if let Some((attenuation, mut scattered)) = material.scatter(ray, &hit, rng) {
+        if rough && specular && settings.roughening > 0.0 {
+            let cos_radius = (0.5 * settings.roughening.to_radians()).cos();
+            let axis = scattered.direction.normalize();
+            // blurred directions crossing the surface the other way are absorbed
+            let side = axis.dot(&hit.normal).signum();
+            let within = |direction: Vec3| {
+                direction.dot(&axis) >= cos_radius && direction.dot(&hit.normal) * side > 0.0
+            };
+
+            // the blur is wide enough to sample the sun through, like a rough surface
+            if let Some(sun) = settings.background.sun() {
+                let light = sun.sample(rng);
+                if within(light) {
+                    let density = 1.0 / (2.0 * PI * (1.0 - cos_radius));
+                    let visibility = sun_visibility(&hit, light, world, settings);
+                    sunlight += attenuation * sun.irradiance * density * visibility;
+                }
+                sun_sampled = true;
+            }
+
+            scattered.direction = sky::sample_cone(axis, cos_radius, rng);
+            if !within(scattered.direction) {
+                return emitted + sunlight + skylight;
+            }
+        }
+
+        let sampled = Sampled {
+            sun: sun_sampled,
+            portals: portals_sampled,
+        };
+        let indirect = trace(
+            &scattered,
+            cone,
+            world,
+            settings,
+            depth - 1,
+            sampled,
+            rough || !specular,
+            rng,
+        );
+        return emitted + sunlight + skylight + attenuation * indirect;
+    }
+    emitted + sunlight + skylight
+}
+
+/// The light of the sun reaching `hit` directly, and whether the material could be lit that way.
+/// The sun is too small for scattered rays to find it often, so it's sampled on its own instead.
+pub(crate) fn direct_sunlight<T>(
+    sun: &Sun,
+    ray: &Ray,
+    hit: &Hit,
+    material: &dyn Material,
+    world: &T,
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> (Color, bool)
+where
+    T: Hittable + ?Sized,
+{
+    scratch::with(|scratch| {
+        let count = settings.light_samples();
+        let mut lights = scratch.vec(count as usize);
+        for _ in 0..count {
+            let light = sun.sample(rng);
+            match material.reflectance(ray, hit, light) {
+                Some(reflectance) => lights.push((light, reflectance)),
+                None => return (Color::default(), false),
+            }
+        }
+
+        let light = shadowed(scratch, hit, &lights, world, settings)
+            .iter()
+            .zip(lights.iter())
+            .map(|(visibility, (_, reflectance))| *reflectance * sun.irradiance * *visibility)
+            .fold(Color::default(), |sum, light| sum + light);
+        (light / count as f64, true)
+    })
+}
+
+/// The light of the sky reaching `hit` directly through one of the portals, and whether the
+/// material could be lit that way.
+pub(crate) fn direct_skylight<T>(
+    ray: &Ray,
+    hit: &Hit,
+    material: &dyn Material,
+    world: &T,
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> (Color, bool)
+where
+    T: Hittable + ?Sized,
+{
+    if settings.portals.is_empty() {
+        return (Color::default(), false);
+    }
+
+    scratch::with(|scratch| {
+        let count = settings.light_samples();
+        let mut lights = scratch.vec(count as usize);
+        for _ in 0..count {
+            let portal = settings.portals[rng.gen_range(0..settings.portals.len())];
+            // seen edge on, the portal lets no light through
+            let Some((light, _)) = portal.sample(hit.point, rng) else {
+                continue;
+            };
+            match material.reflectance(ray, hit, light) {
+                Some(reflectance) => lights.push((light, reflectance)),
+                None => return (Color::default(), false),
+            }
+        }
+        if lights.is_empty() {
+            return (Color::default(), false);
+        }
+
+        let light = shadowed(scratch, hit, &lights, world, settings)
+            .iter()
+            .zip(lights.iter())
+            .map(|(visibility, &(light, reflectance))| {
+                // portals in line with each other could each have picked the same direction
+                let pdf = portal_pdf(hit.point, light, settings);
+                reflectance * settings.background.color(light) * *visibility / pdf
+            })
+            .fold(Color::default(), |sum, light| sum + light);
+        (light / count as f64, true)
+    })
+}
+
+/// The density of [`direct_skylight`] picking the unit vector `direction` from `origin`, over
+/// all the portals.
+fn portal_pdf(origin: Point3, direction: Vec3, settings: &RenderSettings) -> f64 {
+    let total: f64 = settings
+        .portals
+        .iter()
+        .map(|portal| portal.pdf(origin, direction))
+        .sum();
+    total / settings.portals.len() as f64
+}
+
+/// How much of the sunlight arriving from `light` reaches `hit`: none if something is in the
+/// way, and otherwise what the fog lets through.
+fn sun_visibility<T>(hit: &Hit, light: Vec3, world: &T, settings: &RenderSettings) -> f64
+where
+    T: Hittable + ?Sized,
+{
+    let shadow_ray = Ray {
+        origin: hit.point,
+        direction: light,
+    };
+    if world.occluded(&shadow_ray, 0.001, f64::INFINITY) {
+        return 0.0;
+    }
+
+    settings
+        .fog
+        .map_or(1.0, |fog| fog.transmittance(&shadow_ray, f64::INFINITY))
+}
+
+/// Like [`sun_visibility`] for each of the directions of `lights` from `hit`, with the shadow
+/// rays traced together. Lights the surface doesn't reflect are left dark without tracing them.
+fn shadowed<'a, T>(
+    scratch: &'a Scratch,
+    hit: &Hit,
+    lights: &[(Vec3, Color)],
+    world: &T,
+    settings: &RenderSettings,
+) -> &'a [f64]
+where
+    T: Hittable + ?Sized,
+{
+    let mut packet = scratch.vec(lights.len());
+    packet.extend(lights.iter().map(|&(light, _)| {
+        let shadow_ray = Ray {
+            origin: hit.point,
+            direction: light,
+        };
+        (shadow_ray, f64::INFINITY)
+    }));
+    let reflected = collision::rays_in(collision::first_rays(lights.len()))
+        .filter(|&i| lights[i].1 != Color::default())
+        .fold(0, |reflected, i| reflected | 1 << i);
+    let occluded = world.occluded_packet(&packet, 0.001, reflected);
+
+    let visibility = scratch.slice(lights.len(), 0.0);
+    for i in collision::rays_in(reflected & !occluded) {
+        visibility[i] = settings
+            .fog
+            .map_or(1.0, |fog| fog.transmittance(&packet[i].0, f64::INFINITY));
+    }
+    visibility
+}
+
+pub(crate) fn background_color(ray: &Ray, settings: &RenderSettings, sun_sampled: bool) -> Color {
+    let direction = ray.direction.normalize();
+    settings.background.color(direction) + sun_seen(direction, settings, sun_sampled)
+}
+
+/// Like [`background_color`], but leaving out the sky seen through the portals too if it was
+/// sampled where the ray starts.
+fn escaped_color(ray: &Ray, settings: &RenderSettings, sampled: Sampled) -> Color {
+    let direction = ray.direction.normalize();
+    if sampled.portals && portal_pdf(ray.origin, direction, settings) > 0.0 {
+        sun_seen(direction, settings, sampled.sun)
+    } else {
+        background_color(ray, settings, sampled.sun)
+    }
+}
+
+/// The light of the sun along the unit vector `direction`, unless it was already sampled.
+fn sun_seen(direction: Vec3, settings: &RenderSettings, sun_sampled: bool) -> Color {
+    match settings.background.sun() {
+        Some(sun) if !sun_sampled && sun.contains(direction) => sun.radiance(),
+        _ => Color::default(),
+    }
+}
+
+fn debug_color<T>(ray: &Ray, world: &T, mode: RenderMode) -> Color
+where
+    T: Hittable + ?Sized,
+{
+    if let RenderMode::Heatmap { max_tests } = mode {
+        let before = bvh::traversal_stats();
+        world.hit(ray, 0.001, f64::INFINITY);
+        let after = bvh::traversal_stats();
+
+        let tests = (after.nodes_visited - before.nodes_visited)
+            + (after.primitives_tested - before.primitives_tested);
+        return false_color(tests as f64 / max_tests as f64);
+    }
+
+    let hit = match world.hit(ray, 0.001, f64::INFINITY) {
+        Some(hit) => hit,
+        None => return Color::new(0.0, 0.0, 0.0),
+    };
+
+    match mode {
+        RenderMode::Shaded | RenderMode::Heatmap { .. } => unreachable!(),
+        RenderMode::Normals => 0.5 * (hit.normal + Color::new(1.0, 1.0, 1.0)),
+        RenderMode::Depth => {
+            let t = hit.t * ray.direction.length();
+            let shade = 1.0 / (1.0 + t);
+            Color::new(shade, shade, shade)
+        }
+        RenderMode::Uv => Color::new(hit.u, hit.v, 0.0),
+        RenderMode::FrontFace => {
+            if hit.front_face {
+                Color::new(0.0, 1.0, 0.0)
+            } else {
+                Color::new(1.0, 0.0, 0.0)
+            }
+        }
+    }
+}
+
+/// Maps `t` in [0, 1] onto a blue-cyan-green-yellow-red ramp. Values outside are clamped.
+pub fn false_color(t: f64) -> Color {
+    let t = 4.0 * t.clamp(0.0, 1.0);
+    match t {
+        t if t < 1.0 => Color::new(0.0, t, 1.0),
+        t if t < 2.0 => Color::new(0.0, 1.0, 2.0 - t),
+        t if t < 3.0 => Color::new(t - 2.0, 1.0, 0.0),
+        t => Color::new(1.0, 4.0 - t, 0.0),
+    }
+}
+
+/// `numerator / denominator`, or 0 if there's nothing to divide by.
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+/// False-color visualization of per-pixel render times, relative to the slowest pixel. If no
+/// time was measured at all, every pixel gets the coldest color.
+pub fn time_heatmap(pixels: &[Pixel]) -> Vec<Pixel> {
+    let max = pixels
+        .iter()
+        .map(|pixel| pixel.time)
+        .max()
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    pixels
+        .iter()
+        .map(|pixel| Pixel {
+            color: false_color(ratio(pixel.time.as_secs_f64(), max)),
+            alpha: 1.0,
+            time: pixel.time,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::objects::Sphere;
+    use crate::filter::MitchellFilter;
+    use crate::sky::Preetham;
+
+    #[test]
+    #[cfg(all(feature = "parallel", target_os = "linux"))]
+    fn threads_are_kept_and_pinned_to_allowed_cores() {
+        let threads = Threads {
+            count: Some(2),
+            pin: true,
+            ..Threads::default()
+        };
+        let first = pool(&threads).unwrap();
+        assert!(Arc::ptr_eq(&first, &pool(&threads).unwrap()));
+
+        let allowed = allowed_cores();
+        let pinned = with_threads(&threads, allowed_cores);
+        assert_eq!(pinned.len(), 1);
+        assert!(allowed.contains(&pinned[0]));
+    }
+
+    #[test]
+    fn empty_stats_have_no_rates() {
+        let stats = RenderStats::default();
+        assert_eq!(stats.rays_per_second(), 0.0);
+        assert_eq!(stats.average_path_depth(), 0.0);
+
+        let stats = RenderStats {
+            camera_rays: 2,
+            rays_traced: 6,
+            render_time: Duration::from_secs(2),
+            ..stats
+        };
+        assert_eq!(stats.rays_per_second(), 3.0);
+        assert_eq!(stats.average_path_depth(), 3.0);
+    }
+
+    #[test]
+    fn untimed_heatmaps_are_cold() {
+        let pixels = [Pixel::default(); 3];
+        for pixel in time_heatmap(&pixels) {
+            assert_eq!(pixel.color, false_color(0.0));
+        }
+    }
+
+    #[test]
+    fn morton_order_visits_every_pixel_once() {
+        let (band_height, order) = PixelOrder::Morton.band(37);
+        assert_eq!(band_height, TILE_SIZE);
+        let mut visited = order.clone();
+        visited.sort_unstable();
+        visited.dedup();
+        assert_eq!(visited.len(), 37 * TILE_SIZE);
+        assert_eq!(order.len(), visited.len());
+
+        assert_eq!(order[..4], [(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(order[TILE_SIZE * TILE_SIZE], (TILE_SIZE, 0));
+    }
+
+    #[test]
+    fn pixel_order_leaves_the_image_the_same() {
+        let gray = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let world = vec![
+            Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, gray.clone()).unwrap(),
+            Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, gray).unwrap(),
+        ];
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+        let settings = RenderSettings {
+            width: 40,
+            height: 20,
+            samples_per_pixel: 4,
+            show_progress: false,
+            seed: Some(3),
+            region: Some(Region {
+                x0: 3,
+                y0: 2,
+                x1: 37,
+                y1: 19,
+            }),
+            ..Default::default()
+        };
+        let morton = RenderSettings {
+            pixel_order: PixelOrder::Morton,
+            ..settings.clone()
+        };
+
+        let scanline = render(&world, &camera, &settings);
+        let morton = render(&world, &camera, &morton);
+        for (scanline, morton) in scanline.iter().zip(&morton) {
+            assert_eq!(scanline.color, morton.color);
+            assert_eq!(scanline.alpha, morton.alpha);
+        }
+    }
+
+    #[test]
+    fn cancelled_out_filter_weights_average_the_samples() {
+        let gray = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let world = vec![Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, gray).unwrap()];
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+
+        for samples_per_pixel in [1, 2] {
+            let settings = RenderSettings {
+                width: 16,
+                height: 8,
+                samples_per_pixel,
+                show_progress: false,
+                seed: Some(9),
+                filter: Arc::new(MitchellFilter::default()),
+                ..Default::default()
+            };
+            for pixel in render(&world, &camera, &settings) {
+                assert!(pixel.color.0.iter().all(|c| c.is_finite()));
+                assert!(pixel.alpha.is_finite());
+            }
+        }
+    }
+
+    #[test]
+    fn light_samples_out_of_range_are_clamped() {
+        let gray = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let world = vec![Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, gray).unwrap()];
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+        let sky = Preetham::new(Vec3::new(0.0, 1.0, 1.0), 3.0, 0.53, 1.0).unwrap();
+
+        for light_samples in [0, collision::PACKET_SIZE as u32 + 1] {
+            let settings = RenderSettings {
+                width: 16,
+                height: 8,
+                samples_per_pixel: 1,
+                show_progress: false,
+                seed: Some(4),
+                background: Arc::new(sky),
+                light_samples,
+                ..Default::default()
+            };
+            for pixel in render(&world, &camera, &settings) {
+                assert!(pixel.color.0.iter().all(|c| c.is_finite()));
+            }
+        }
+    }
+
+    #[test]
+    fn scaled_down_regions_stay_inside_the_image() {
+        let settings = RenderSettings {
+            width: 800,
+            height: 450,
+            region: Some(Region {
+                x0: 797,
+                y0: 449,
+                x1: 800,
+                y1: 450,
+            }),
+            ..Default::default()
+        };
+
+        let scaled = settings.scaled_down(8);
+        assert_eq!((scaled.width, scaled.height), (100, 56));
+        assert_eq!(
+            scaled.region,
+            Some(Region {
+                x0: 99,
+                y0: 55,
+                x1: 100,
+                y1: 56,
+            })
+        );
+    }
+
+    #[test]
+    fn regions_render_like_they_do_in_the_whole_frame() {
+        let gray = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let world = vec![Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, gray).unwrap()];
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+        let settings = RenderSettings {
+            width: 40,
+            height: 20,
+            samples_per_pixel: 2,
+            show_progress: false,
+            seed: Some(5),
+            pixel_order: PixelOrder::Morton,
+            ..Default::default()
+        };
+        let region = Region {
+            x0: 5,
+            y0: 2,
+            x1: 30,
+            y1: 17,
+        };
+
+        let whole = RenderSettings {
+            region: Some(region),
+            ..settings.clone()
+        };
+        let frame = region.crop(&render(&world, &camera, &whole), settings.width);
+        let tile = render_region_cancellable(
+            &world,
+            &camera,
+            &settings,
+            region,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(tile.len(), frame.len());
+        for (tile, frame) in tile.iter().zip(&frame) {
+            assert_eq!(tile.color, frame.color);
+        }
+    }
+
+    #[test]
+    fn regions_outside_the_frame_fail() {
+        let world: Vec<Sphere> = Vec::new();
+        let camera = Camera::builder().aspect_ratio(2.0).build().unwrap();
+        let settings = RenderSettings {
+            width: 8,
+            height: 4,
+            show_progress: false,
+            ..Default::default()
+        };
+        let inside = Region {
+            x0: 2,
+            y0: 1,
+            x1: 6,
+            y1: 3,
+        };
+        let outside = [
+            Region { y1: 5, ..inside },
+            Region { x1: 9, ..inside },
+            Region { x1: 2, ..inside },
+        ];
+
+        let tile = |region| {
+            render_region_cancellable(
+                &world,
+                &camera,
+                &settings,
+                region,
+                &CancellationToken::new(),
+            )
+        };
+        assert_eq!(tile(inside).unwrap().len(), 8);
+        for region in outside {
+            assert!(matches!(tile(region), Err(Error::RegionOutside { .. })));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! The statistics summary printed at the end of a run.
+
+use ray_tracing::memory::MemoryUsage;
+use ray_tracing::render;
+
+use serde::Serialize;
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Wall-clock time spent in each stage outside of the renderer itself, in seconds.
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct StageTimes {
+    pub scene_load: f64,
+    pub bvh_build: f64,
+    pub output: f64,
+}
+
+static STAGE_TIMES: Mutex<StageTimes> = Mutex::new(StageTimes {
+    scene_load: 0.0,
+    bvh_build: 0.0,
+    output: 0.0,
+});
+
+/// The memory the render was estimated to take before it started.
+static MEMORY: Mutex<Option<MemoryUsage>> = Mutex::new(None);
+
+/// Keeps the estimate of the memory the render takes, for the summary.
+pub fn record_memory(memory: MemoryUsage) {
+    *MEMORY.lock().unwrap() = Some(memory);
+}
+
+/// Runs `f`, adding the time it takes to the stage picked by `stage`.
+pub fn timed<T>(stage: fn(&mut StageTimes) -> &mut f64, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *stage(&mut STAGE_TIMES.lock().unwrap()) += start.elapsed().as_secs_f64();
+
+    result
+}
+
+#[derive(Serialize)]
+struct Report {
+    camera_rays: u64,
+    rays_traced: u64,
+    rays_per_second: f64,
+    average_path_depth: f64,
+    bvh_nodes_visited: u64,
+    primitives_tested: u64,
+    render_time: f64,
+    #[serde(flatten)]
+    stages: StageTimes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryUsage>,
+}
+
+/// Logs a summary of everything rendered so far, or prints it to stdout as JSON.
+pub fn print(json: bool) -> ray_tracing::Result<()> {
+    let stats = render::take_render_stats();
+    let report = Report {
+        camera_rays: stats.camera_rays,
+        rays_traced: stats.rays_traced,
+        rays_per_second: stats.rays_per_second(),
+        average_path_depth: stats.average_path_depth(),
+        bvh_nodes_visited: stats.nodes_visited,
+        primitives_tested: stats.primitives_tested,
+        render_time: stats.render_time.as_secs_f64(),
+        stages: *STAGE_TIMES.lock().unwrap(),
+        memory: MEMORY.lock().unwrap().clone(),
+    };
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(io::Error::from)?
+        );
+        return Ok(());
+    }
+
+    log::info!(
+        "Traced {} rays ({} from the camera) at {:.0} rays/s, {:.2} per path on average.",
+        report.rays_traced,
+        report.camera_rays,
+        report.rays_per_second,
+        report.average_path_depth
+    );
+    log::info!(
+        "Visited {} BVH nodes and tested {} primitives.",
+        report.bvh_nodes_visited,
+        report.primitives_tested
+    );
+    log::info!(
+        "Spent {:.2}s loading the scene, {:.2}s building the BVH, {:.2}s rendering and {:.2}s \
+         writing the output.",
+        report.stages.scene_load,
+        report.stages.bvh_build,
+        report.render_time,
+        report.stages.output
+    );
+
+    Ok(())
+}
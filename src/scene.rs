@@ -0,0 +1,1872 @@
+//! Scenes described in JSON files.
+//!
+//! A scene file holds the camera, a set of named materials and the objects using them:
+//!
+//! ```json
+//! {
+//!     "camera": { "look_from": [4, 2.6, 2.2], "look_at": [1, 0, -1.5], "vertical_fov": 20 },
+//!     "materials": {
+//!         "ground": { "type": "lambertian", "albedo": [0.8, 0.8, 0] }
+//!     },
+//!     "objects": [
+//!         { "type": "sphere", "center": [0, -100.5, 0], "radius": 100, "material": "ground" }
+//!     ]
+//! }
+//! ```
+//!
+//! Relative paths to meshes, voxel models, volumes and textures are relative to the scene file.
+
+use crate::collision::bvh::{Aabb, Bvh, TopLevelBvh};
+use crate::collision::graph::{Bsdf, MathOp, Node};
+use crate::collision::instancer::{Distribution, Instancer, Jitter};
+use crate::collision::lod::{Lod, LodSwitch};
+use crate::collision::materials::*;
+use crate::collision::objects::*;
+use crate::collision::phase::HenyeyGreenstein;
+use crate::collision::volumes::{Blackbody, DensityField, HeterogeneousMedium, NoiseDensity};
+use crate::collision::voxels::VoxelGrid;
+use crate::collision::{Hittable, Material};
+use crate::color_space::ColorSpace;
+use crate::cryptomatte::Names;
+use crate::ies::IesProfile;
+use crate::illuminant::Illuminant;
+use crate::library::{MaterialLibrary, TextureCache};
+use crate::noise::{Perlin, Worley};
+use crate::render::{Fog, RenderSettings};
+use crate::scene_graph::{PlacedNode, SceneGraph};
+use crate::sky::{Background, Daylight, Gradient, Portal, Preetham};
+use crate::texture::{
+    CellFeature, Cells, Fbm, ImageTexture, Pattern, PatternTexture, SolidColor, Texture,
+    TextureTransform, Wrap,
+};
+use crate::transform::Transform;
+use crate::{CameraBuilder, Color, Error, Lens, Point3, Result, Vec3};
+
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+#[cfg(not(feature = "vdb"))]
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    /// Can be left out when `camera_node` picks a camera from the graph instead.
+    #[serde(default)]
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDescription>,
+    pub objects: Vec<ObjectDescription>,
+    /// Objects placed many times by [`ObjectDescription::Instances`], by name.
+    #[serde(default)]
+    pub prototypes: HashMap<String, Vec<ObjectDescription>>,
+    /// Haze over the whole scene, see [`Fog`].
+    #[serde(default)]
+    pub fog: Option<FogDescription>,
+    /// The default is the gradient of the book.
+    #[serde(default)]
+    pub sky: Option<SkyDescription>,
+    /// Windows and other openings the sky lights the scene through, see [`Portal`].
+    #[serde(default)]
+    pub portals: Vec<PortalDescription>,
+    /// More objects, and cameras, in a hierarchy of named nodes, see [`SceneGraph`].
+    #[serde(default)]
+    pub graph: SceneGraph,
+    /// The node of the graph whose camera replaces `camera` when the scene is parsed.
+    #[serde(default)]
+    pub camera_node: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    #[serde(default = "default_vup")]
+    pub vup: Vec3,
+    /// In degrees.
+    pub vertical_fov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    /// Defaults to the distance between `look_from` and `look_at`.
+    #[serde(default)]
+    pub focus_dist: Option<f64>,
+    /// Measures `focus_dist` to this instead, see [`Scene::focus`].
+    #[serde(default)]
+    pub focus_on: Option<FocusTarget>,
+    /// See [`Lens`].
+    #[serde(default)]
+    pub vignetting: bool,
+    /// The Brown-Conrady coefficients `k1` and `k2`, see [`Lens`].
+    #[serde(default)]
+    pub distortion: [f64; 2],
+    /// See [`Lens`].
+    #[serde(default)]
+    pub lateral_aberration: f64,
+    /// See [`Lens`].
+    #[serde(default)]
+    pub longitudinal_aberration: f64,
+}
+
+impl Default for CameraDescription {
+    /// At the origin, looking down the negative z axis.
+    fn default() -> Self {
+        Self {
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            vup: default_vup(),
+            vertical_fov: 90.0,
+            aperture: 0.0,
+            focus_dist: None,
+            focus_on: None,
+            vignetting: false,
+            distortion: [0.0; 2],
+            lateral_aberration: 0.0,
+            longitudinal_aberration: 0.0,
+        }
+    }
+}
+
+/// What a camera can focus on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FocusTarget {
+    /// Whatever is first seen on the way to this point.
+    Point(Point3),
+    /// The center of an object, named after its type and place in the list like `sphere 3`, as
+    /// in [`Scene::labeled_world`]. Objects of the graph are named after their node too, like
+    /// `lamp/sphere 0`.
+    Object(String),
+}
+
+fn default_vup() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: Color,
+    },
+    Metal {
+        albedo: Color,
+        fuzz: f64,
+    },
+    Dielectric {
+        ri: f64,
+    },
+    DiffuseLight {
+        emit: Color,
+        /// Whether the back gives off light too, which it does unless it's turned off.
+        #[serde(default = "default_double_sided")]
+        double_sided: bool,
+        /// A standard light source whose color `emit` is tinted with, so that `emit` only needs
+        /// to give its brightness.
+        #[serde(default)]
+        illuminant: Option<Illuminant>,
+        /// The measured distribution of a real fixture, which dims the light in the directions
+        /// the fixture doesn't shine.
+        #[serde(default)]
+        ies: Option<IesDescription>,
+        /// A pattern `emit` is multiplied by over the surface, like an image for a screen.
+        #[serde(default)]
+        texture: Option<TextureDescription>,
+    },
+    /// A lambertian with its albedo taken from a texture, usually an image.
+    Textured {
+        texture: TextureDescription,
+    },
+    /// Scatters in every direction, for volumes.
+    Isotropic {
+        albedo: Color,
+    },
+    /// Scatters mostly forward for a positive `g` and back for a negative one, for volumes.
+    HenyeyGreenstein {
+        albedo: Color,
+        g: f64,
+    },
+    /// Metal where `metallic` is 1 and lambertian where it's 0, as fuzzy as `roughness`.
+    MetallicRoughness {
+        base_color: TextureDescription,
+        metallic: TextureDescription,
+        roughness: TextureDescription,
+    },
+    /// Hair and fur, for curves. `roughness` from 0 to 1 makes the highlights duller.
+    Hair {
+        color: Color,
+        #[serde(default = "default_hair_roughness")]
+        roughness: f64,
+    },
+    /// Put together from nodes evaluated wherever it's hit, see [`Bsdf`].
+    Graph {
+        bsdf: BsdfDescription,
+    },
+    /// The ground of a photograph to composite the render onto, which only shows the shadows and
+    /// reflections cast onto it, see [`ShadowCatcher`]. Everything else sees it as `surface`.
+    ShadowCatcher {
+        surface: Box<MaterialDescription>,
+    },
+}
+
+fn default_double_sided() -> bool {
+    true
+}
+
+fn default_hair_roughness() -> f64 {
+    0.3
+}
+
+/// How a [`MaterialDescription::Graph`] scatters light, see [`Bsdf`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BsdfDescription {
+    Diffuse {
+        color: NodeDescription,
+    },
+    Metal {
+        color: NodeDescription,
+        #[serde(default = "default_roughness")]
+        roughness: NodeDescription,
+    },
+    Glass {
+        ior: f64,
+    },
+    Emission {
+        color: NodeDescription,
+    },
+    Mix {
+        factor: NodeDescription,
+        a: Box<BsdfDescription>,
+        b: Box<BsdfDescription>,
+    },
+}
+
+fn default_roughness() -> NodeDescription {
+    NodeDescription::Value(0.0)
+}
+
+/// A node of a [`MaterialDescription::Graph`], given as a single number, a color, an operation
+/// on other nodes or anything a texture can be.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NodeDescription {
+    Value(f64),
+    Color(Color),
+    Operation(NodeOperation),
+    Texture(TextureDescription),
+}
+
+/// See [`Node`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeOperation {
+    Math {
+        op: MathOp,
+        a: Box<NodeDescription>,
+        b: Box<NodeDescription>,
+    },
+    Mix {
+        factor: Box<NodeDescription>,
+        a: Box<NodeDescription>,
+        b: Box<NodeDescription>,
+    },
+    Facing,
+    Fresnel {
+        ior: f64,
+    },
+}
+
+/// A texture given as a single number, a color, the path to an image or a typed object.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TextureDescription {
+    Value(f64),
+    Color(Color),
+    Image(PathBuf),
+    Procedural(ProceduralTexture),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProceduralTexture {
+    /// An image, like a plain path, but with its color space given. Data like normal and
+    /// roughness maps is linear rather than sRGB, and wide-gamut images are converted into the
+    /// space the renderer works in.
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        color_space: ColorSpace,
+    },
+    /// Another texture with its surface coordinates scaled, turned and moved, see
+    /// [`TextureTransform`].
+    Transformed {
+        texture: Box<TextureDescription>,
+        #[serde(default = "default_tiling")]
+        scale: [f64; 2],
+        #[serde(default)]
+        offset: [f64; 2],
+        /// In degrees.
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default)]
+        wrap: Wrap,
+    },
+    /// Perlin noise, blending between two colors.
+    Perlin {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+    },
+    /// Worley's cellular noise, blending between two colors.
+    Worley {
+        #[serde(default)]
+        feature: CellFeature,
+        #[serde(flatten)]
+        noise: NoiseDescription,
+    },
+}
+
+/// What noise textures have in common.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseDescription {
+    /// How many features of the noise fit in a unit of distance.
+    #[serde(default = "default_scale")]
+    pub frequency: f64,
+    #[serde(default)]
+    pub seed: u64,
+    /// More than 1 layers finer and finer noise on top, see [`Fbm`].
+    #[serde(default = "default_noise_octaves")]
+    pub octaves: u32,
+    #[serde(default = "default_lacunarity")]
+    pub lacunarity: f64,
+    #[serde(default = "default_gain")]
+    pub gain: f64,
+    /// The colors at 0 and at 1, black and white by default.
+    #[serde(default = "default_noise_colors")]
+    pub colors: [Color; 2],
+}
+
+impl NoiseDescription {
+    fn build(&self, pattern: Arc<dyn Pattern>) -> Result<Arc<dyn Texture>> {
+        let pattern = if self.octaves == 1 {
+            pattern
+        } else {
+            Arc::new(Fbm::new(pattern, self.octaves, self.lacunarity, self.gain)?)
+        };
+
+        Ok(Arc::new(PatternTexture::new(
+            pattern,
+            self.frequency,
+            self.colors,
+        )?))
+    }
+}
+
+/// An IES photometric file, with the fixture pointing along `aim`, straight down by default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IesDescription {
+    pub path: PathBuf,
+    #[serde(default = "default_aim")]
+    pub aim: Vec3,
+}
+
+/// The parallelogram spanned by `u` and `v` from `corner`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PortalDescription {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FogDescription {
+    pub density: f64,
+    pub color: Color,
+    #[serde(default)]
+    pub falloff: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SkyDescription {
+    Gradient {
+        horizon: Color,
+        zenith: Color,
+    },
+    /// The same color in every direction, like black for a night scene lit only by its lights.
+    Solid {
+        color: Color,
+    },
+    /// A clear sky with a sun, see [`Preetham`].
+    Preetham {
+        sun_direction: Vec3,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        /// In degrees.
+        #[serde(default = "default_sun_diameter")]
+        sun_angular_diameter: f64,
+        #[serde(default = "default_scale")]
+        intensity: f64,
+    },
+    /// A [`Preetham`] sky with the sun where it is at a place and time, see [`Daylight`].
+    Daylight {
+        /// In degrees, north of the equator and east of Greenwich.
+        latitude: f64,
+        longitude: f64,
+        /// The local date, as `[year, month, day]`.
+        date: [i32; 3],
+        /// The local clock time, in hours since midnight.
+        hour: f64,
+        /// How many hours the local time zone is ahead of UTC.
+        #[serde(default)]
+        utc_offset: f64,
+        /// Which way is north, along the ground.
+        #[serde(default = "default_north")]
+        north: Vec3,
+        /// How many hours of the day pass in a second of animation, for time-lapses.
+        #[serde(default)]
+        hours_per_second: f64,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        /// In degrees.
+        #[serde(default = "default_sun_diameter")]
+        sun_angular_diameter: f64,
+        #[serde(default = "default_scale")]
+        intensity: f64,
+    },
+}
+
+fn default_north() -> Vec3 {
+    Vec3::new(0.0, 0.0, -1.0)
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_sun_diameter() -> f64 {
+    0.53
+}
+
+/// The glow of a volume, with the color of a blackbody at its temperature.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmissionDescription {
+    /// Kelvin per unit of the field the temperature is read from.
+    pub temperature: f64,
+    /// Scales the brightness, which is 1 at 1000 K and grows with the fourth power of the
+    /// temperature.
+    #[serde(default = "default_scale")]
+    pub intensity: f64,
+}
+
+/// Objects refer to materials by their name in [`Scene::materials`]. Surfaces with
+/// `cull_backfaces` set can only be seen from the front, see [`Culled`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectDescription {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: String,
+        #[serde(default)]
+        cull_backfaces: bool,
+    },
+    Parallelogram {
+        corner: Point3,
+        u: Vec3,
+        v: Vec3,
+        w: Vec3,
+        material: String,
+        #[serde(default)]
+        cull_backfaces: bool,
+    },
+    /// A surface stretched between four corners, see [`BilinearPatch`].
+    BilinearPatch {
+        corners: [Point3; 4],
+        material: String,
+        #[serde(default)]
+        cull_backfaces: bool,
+    },
+    /// A curved surface bent by 4 rows of 4 control points, see [`BezierPatch`].
+    BezierPatch {
+        control_points: Box<[[Point3; 4]; 4]>,
+        material: String,
+        #[serde(default)]
+        cull_backfaces: bool,
+    },
+    /// Hairs or blades of grass, each a Bézier curve of 4 control points, see [`Curve`]. They
+    /// are `radius[0]` thick at the root and `radius[1]` at the tip.
+    Curves {
+        curves: Vec<[Point3; 4]>,
+        radius: [f64; 2],
+        material: String,
+    },
+    /// Blobs that melt into each other, see [`Metaballs`].
+    Metaballs {
+        balls: Vec<MetaballDescription>,
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+        #[serde(default)]
+        falloff: Falloff,
+        material: String,
+    },
+    /// Copies of the objects of `prototype`, which share them and their BVH. See [`Instancer`].
+    Instancer {
+        prototype: Vec<ObjectDescription>,
+        distribution: DistributionDescription,
+        #[serde(default)]
+        jitter: JitterDescription,
+        #[serde(default)]
+        seed: u64,
+    },
+    /// Copies of a prototype of the scene, one for each placement. They share its objects and
+    /// BVH, so each only takes a transformation of its own, and a [`TopLevelBvh`] over them finds
+    /// the ones a ray passes by. Prototypes can hold instances of other prototypes.
+    Instances {
+        prototype: String,
+        placements: Vec<PlacementDescription>,
+    },
+    /// Several versions of the same objects, from the most detailed to the roughest, each one
+    /// made of a list of objects, switched between by how far away they are. See [`Lod`].
+    Lod {
+        levels: Vec<Vec<ObjectDescription>>,
+        switch: LodSwitch,
+    },
+    /// The triangles of an OBJ file. They use the materials of the MTL files it refers to, unless
+    /// `material` names one for the whole mesh.
+    Mesh {
+        path: PathBuf,
+        #[serde(default)]
+        material: Option<String>,
+        /// Steps of Catmull-Clark subdivision to smooth the mesh with.
+        #[serde(default)]
+        subdivisions: u32,
+        #[serde(default)]
+        cull_backfaces: bool,
+    },
+    /// The first model of a MagicaVoxel file, standing on `corner`. Palette indices can be given
+    /// materials from the scene, the rest get one matching the palette.
+    Voxels {
+        path: PathBuf,
+        #[serde(default)]
+        corner: Point3,
+        voxel_size: f64,
+        #[serde(default)]
+        materials: HashMap<u8, String>,
+    },
+    /// A volume with the density of a grid in a NanoVDB file, which needs the `vdb` feature. The
+    /// grid is placed where the file puts it, then scaled and moved by `scale` and `translation`.
+    Vdb {
+        path: PathBuf,
+        /// The name of the grid, or the first float grid in the file.
+        #[serde(default)]
+        grid: Option<String>,
+        #[serde(default = "default_scale")]
+        density_scale: f64,
+        #[serde(default)]
+        translation: Vec3,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        /// Usually an isotropic material.
+        material: String,
+        /// Makes the volume glow, with the temperature read from the grid named
+        /// `temperature_grid`, or the density grid if there's none.
+        #[serde(default)]
+        emission: Option<EmissionDescription>,
+        #[serde(default)]
+        temperature_grid: Option<String>,
+    },
+    /// A box of wispy Perlin noise density, up to `density` per unit of distance.
+    Cloud {
+        min: Point3,
+        max: Point3,
+        #[serde(default = "default_scale")]
+        density: f64,
+        /// How many noise features fit in a unit of distance.
+        #[serde(default = "default_scale")]
+        frequency: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default)]
+        seed: u64,
+        /// Usually an isotropic material.
+        material: String,
+        /// Makes the cloud glow, hotter where it's denser.
+        #[serde(default)]
+        emission: Option<EmissionDescription>,
+    },
+}
+
+impl ObjectDescription {
+    /// The type of the object, as scene files give it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Sphere { .. } => "sphere",
+            Self::Parallelogram { .. } => "parallelogram",
+            Self::BilinearPatch { .. } => "bilinear_patch",
+            Self::BezierPatch { .. } => "bezier_patch",
+            Self::Curves { .. } => "curves",
+            Self::Metaballs { .. } => "metaballs",
+            Self::Instancer { .. } => "instancer",
+            Self::Instances { .. } => "instances",
+            Self::Lod { .. } => "lod",
+            Self::Mesh { .. } => "mesh",
+            Self::Voxels { .. } => "voxels",
+            Self::Vdb { .. } => "vdb",
+            Self::Cloud { .. } => "cloud",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetaballDescription {
+    pub center: Point3,
+    pub radius: f64,
+    /// Negative for carving into the other balls.
+    #[serde(default = "default_scale")]
+    pub strength: f64,
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+/// Where the copies of an [`ObjectDescription::Instancer`] go, see [`Distribution`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DistributionDescription {
+    Grid {
+        min: Point3,
+        max: Point3,
+        counts: [usize; 3],
+    },
+    Random {
+        min: Point3,
+        max: Point3,
+        count: usize,
+    },
+    /// On top of the objects of `surface`, which are only used for placing the copies.
+    Surface {
+        surface: Vec<ObjectDescription>,
+        count: usize,
+        #[serde(default)]
+        spacing: f64,
+        #[serde(default)]
+        align: bool,
+    },
+}
+
+/// Where a copy of an [`ObjectDescription::Instances`] goes: scaled, then rotated by the angles in
+/// degrees around the x, y and z axes, then moved. See [`Transform::new`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlacementDescription {
+    #[serde(default)]
+    pub translation: Vec3,
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default = "default_scale_vector")]
+    pub scale: Vec3,
+}
+
+fn default_scale_vector() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+impl PlacementDescription {
+    /// Fails if the scale flattens the copy.
+    pub fn transform(&self) -> Result<Transform> {
+        for &component in self.translation.0.iter().chain(&self.rotation.0) {
+            Error::check("instance placement", component, |_| true)?;
+        }
+        for &component in self.scale.0.iter() {
+            Error::check("instance scale", component, |s| s != 0.0)?;
+        }
+        Ok(Transform::new(self.translation, self.rotation, self.scale))
+    }
+}
+
+/// The most each copy of an [`ObjectDescription::Instancer`] is changed by, see [`Jitter`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JitterDescription {
+    /// In degrees, around each axis.
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default = "default_scale_range")]
+    pub scale: [f64; 2],
+    #[serde(default)]
+    pub offset: Vec3,
+}
+
+impl Default for JitterDescription {
+    fn default() -> Self {
+        Self {
+            rotation: Vec3::default(),
+            scale: default_scale_range(),
+            offset: Vec3::default(),
+        }
+    }
+}
+
+fn default_scale_range() -> [f64; 2] {
+    [1.0, 1.0]
+}
+
+/// How many segments each of the [`ObjectDescription::Curves`] is split into, for the BVH to
+/// have tighter boxes around them.
+const CURVE_SEGMENTS: usize = 4;
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_tiling() -> [f64; 2] {
+    [1.0, 1.0]
+}
+
+fn default_noise_octaves() -> u32 {
+    1
+}
+
+fn default_lacunarity() -> f64 {
+    2.0
+}
+
+fn default_gain() -> f64 {
+    0.5
+}
+
+fn default_aim() -> Vec3 {
+    Vec3::new(0.0, -1.0, 0.0)
+}
+
+fn default_noise_colors() -> [Color; 2] {
+    [Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)]
+}
+
+fn default_octaves() -> u32 {
+    5
+}
+
+impl Scene {
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(Error::file(path))?;
+        let mut scene: Self = text.parse().map_err(|source| Error::SceneParse {
+            path: path.to_owned(),
+            source,
+        })?;
+        if let Some(dir) = path.parent() {
+            scene.resolve_paths(dir);
+        }
+
+        Ok(scene)
+    }
+
+    /// Makes relative paths to meshes, voxel models, volumes and textures relative to `dir`
+    /// instead of the working directory.
+    #[cfg(feature = "fs")]
+    fn resolve_paths(&mut self, dir: &Path) {
+        self.visit_paths(&mut |path| {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        });
+    }
+
+    /// Fails if a path to a mesh, voxel model, volume or texture is absolute or has a `..` in
+    /// it, so that the scene can only load files from under the working directory. For scenes
+    /// sent by someone else, like those rendered by `serve`.
+    #[cfg(feature = "fs")]
+    pub fn check_paths(&mut self) -> Result<()> {
+        use std::path::Component;
+
+        let mut outside = None;
+        self.visit_paths(&mut |path| {
+            let escapes = path
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+            if escapes && outside.is_none() {
+                outside = Some(path.clone());
+            }
+        });
+        match outside {
+            Some(path) => Err(Error::OutsidePath(path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `f` with every path to a mesh, voxel model, volume or texture.
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        for material in self.materials.values_mut() {
+            material.visit_paths(f);
+        }
+        for object in self
+            .objects
+            .iter_mut()
+            .chain(self.prototypes.values_mut().flatten())
+        {
+            object.visit_paths(f);
+        }
+        self.graph.visit_mut(&mut |node| {
+            for object in &mut node.objects {
+                object.visit_paths(f);
+            }
+        });
+    }
+
+    /// How many copies the instancers of the scene place in all, saturating at `usize::MAX`.
+    /// Instancers on a surface count as many as they ask for, even if fewer fit.
+    pub fn instance_count(&self) -> usize {
+        let mut total = 0usize;
+        self.visit_objects(&mut |object| {
+            if let ObjectDescription::Instancer { distribution, .. } = object {
+                let count = match distribution {
+                    DistributionDescription::Grid { counts, .. } => counts
+                        .iter()
+                        .fold(1usize, |n, &count| n.saturating_mul(count)),
+                    DistributionDescription::Random { count, .. }
+                    | DistributionDescription::Surface { count, .. } => *count,
+                };
+                total = total.saturating_add(count);
+            }
+        });
+        total
+    }
+
+    /// The most steps of subdivision any mesh of the scene is smoothed with.
+    pub fn max_subdivisions(&self) -> u32 {
+        let mut most = 0;
+        self.visit_objects(&mut |object| {
+            if let ObjectDescription::Mesh { subdivisions, .. } = object {
+                most = most.max(*subdivisions);
+            }
+        });
+        most
+    }
+
+    /// Calls `f` with every object description, including those inside instancers and levels
+    /// of detail.
+    fn visit_objects(&self, f: &mut dyn FnMut(&ObjectDescription)) {
+        let mut nodes: Vec<_> = self.graph.roots.iter().collect();
+        let mut objects: Vec<_> = self.objects.iter().collect();
+        objects.extend(self.prototypes.values().flatten());
+        while let Some(node) = nodes.pop() {
+            objects.extend(&node.objects);
+            nodes.extend(&node.children);
+        }
+        for object in objects {
+            object.visit(f);
+        }
+    }
+
+    /// A camera builder with everything the scene specifies already set.
+    pub fn camera_builder(&self) -> CameraBuilder {
+        let camera = &self.camera;
+        let builder = CameraBuilder::default()
+            .look_from(camera.look_from)
+            .look_at(camera.look_at)
+            .vup(camera.vup)
+            .vertical_fov(camera.vertical_fov)
+            .aperture(camera.aperture)
+            .lens(Lens {
+                vignetting: camera.vignetting,
+                distortion: camera.distortion,
+                lateral_aberration: camera.lateral_aberration,
+                longitudinal_aberration: camera.longitudinal_aberration,
+            });
+
+        match camera.focus_dist {
+            Some(focus_dist) => builder.focus_dist(focus_dist),
+            None => builder,
+        }
+    }
+
+    /// Sets the focus distance of `builder` to what the camera focuses on, if anything, in
+    /// `world`, which has to be the world of this scene. Animated cameras keep the distance from
+    /// their first position.
+    pub fn focus<T: Hittable + ?Sized>(
+        &self,
+        builder: CameraBuilder,
+        world: &T,
+    ) -> Result<CameraBuilder> {
+        let point = match &self.camera.focus_on {
+            None => return Ok(builder),
+            Some(FocusTarget::Point(point)) => *point,
+            Some(FocusTarget::Object(name)) => {
+                let assets = self.assets(&TextureCache::new())?;
+                let mut bbox = (0..self.objects.len())
+                    .find(|&index| object_name(index, &self.objects[index]) == *name)
+                    .map(|index| build_objects(&self.objects[index..=index], &assets))
+                    .transpose()?
+                    .and_then(|objects| objects.bounding_box());
+                for placed in self.graph.flatten()? {
+                    for (index, description) in placed.node.objects.iter().enumerate() {
+                        if bbox.is_none() && graph_object_name(&placed, index, description) == *name
+                        {
+                            let objects =
+                                build_objects(std::slice::from_ref(description), &assets)?;
+                            bbox = place(objects, placed.transform).bounding_box();
+                        }
+                    }
+                }
+                bbox.ok_or_else(|| Error::UnknownObject(name.clone()))?
+                    .centroid()
+            }
+        };
+
+        Ok(builder.focus_on(world, point))
+    }
+
+    /// Sets the parts of `settings` that the scene decides, its fog, sky and portals, failing if
+    /// their parameters are invalid.
+    pub fn apply(&self, settings: &mut RenderSettings) -> Result<()> {
+        settings.fog = self
+            .fog
+            .map(|fog| Fog::new(fog.density, fog.color, fog.falloff))
+            .transpose()?;
+        settings.background = match &self.sky {
+            Some(sky) => sky.build()?,
+            None => Arc::new(Gradient::default()),
+        };
+        settings.portals = self
+            .portals
+            .iter()
+            .map(|portal| Portal::new(portal.corner, portal.u, portal.v))
+            .collect::<Result<_>>()?;
+        settings.shadow_catchers = self
+            .materials
+            .values()
+            .any(|material| matches!(material, MaterialDescription::ShadowCatcher { .. }));
+
+        Ok(())
+    }
+
+    /// Builds the materials of the scene, in the order of their names, sharing the images they
+    /// have in common.
+    pub fn material_library(&self) -> Result<MaterialLibrary> {
+        self.material_library_with(&TextureCache::new())
+    }
+
+    /// Like [`Scene::material_library`], loading images into `textures`.
+    pub fn material_library_with(&self, textures: &TextureCache) -> Result<MaterialLibrary> {
+        let mut names: Vec<&String> = self.materials.keys().collect();
+        names.sort();
+        let mut library = MaterialLibrary::with_textures(textures.clone());
+        for name in names {
+            let material = self.materials[name].build_with(library.textures())?;
+            library.add(name, material);
+        }
+
+        Ok(library)
+    }
+
+    /// The materials of the scene, and its prototypes, which are built when they're first used.
+    fn assets(&self, textures: &TextureCache) -> Result<Assets<'_>> {
+        Ok(Assets {
+            materials: self.material_library_with(textures)?,
+            prototypes: &self.prototypes,
+            built: RefCell::new(HashMap::new()),
+            building: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Builds the objects of the scene, failing if any refer to a material that doesn't exist or
+    /// have invalid parameters.
+    pub fn world(&self) -> Result<Vec<Arc<dyn Hittable + Send + Sync>>> {
+        self.world_with(&TextureCache::new())
+    }
+
+    /// Like [`Scene::world`], loading images into `textures`, which then tells how much memory
+    /// they take.
+    pub fn world_with(
+        &self,
+        textures: &TextureCache,
+    ) -> Result<Vec<Arc<dyn Hittable + Send + Sync>>> {
+        let assets = self.assets(textures)?;
+        let mut world = build_objects(&self.objects, &assets)?;
+        for placed in self.graph.flatten()? {
+            let objects = build_objects(&placed.node.objects, &assets)?;
+            world.extend(place(objects, placed.transform));
+        }
+
+        Ok(world)
+    }
+
+    /// Like [`Scene::world`], but with every object labeled for ID passes, and the names to give
+    /// the IDs. Objects are named after their type and where they are in the list, like
+    /// `sphere 3`, and materials by their own names.
+    pub fn labeled_world(&self) -> Result<(Vec<Arc<dyn Hittable + Send + Sync>>, Names)> {
+        self.labeled_world_with(&TextureCache::new())
+    }
+
+    /// Like [`Scene::labeled_world`], loading images into `textures`.
+    pub fn labeled_world_with(
+        &self,
+        textures: &TextureCache,
+    ) -> Result<(Vec<Arc<dyn Hittable + Send + Sync>>, Names)> {
+        let mut names = Names::new();
+        let assets = self.assets(textures)?;
+        for (name, material) in assets.materials.iter() {
+            names.add_material(material, name);
+        }
+
+        let mut world: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
+        let mut add = |parts: Vec<Arc<dyn Hittable + Send + Sync>>, name: String| {
+            world.push(Arc::new(Labeled {
+                object: single(parts),
+                label: names.add_object(name),
+            }));
+        };
+        for (index, description) in self.objects.iter().enumerate() {
+            let parts = build_objects(std::slice::from_ref(description), &assets)?;
+            add(parts, object_name(index, description));
+        }
+        for placed in self.graph.flatten()? {
+            for (index, description) in placed.node.objects.iter().enumerate() {
+                let parts = build_objects(std::slice::from_ref(description), &assets)?;
+                add(
+                    place(parts, placed.transform),
+                    graph_object_name(&placed, index, description),
+                );
+            }
+        }
+
+        Ok((world, names))
+    }
+}
+
+/// The name of the object at `index` in the list, after its type, like `sphere 3`.
+pub(crate) fn object_name(index: usize, description: &ObjectDescription) -> String {
+    format!("{} {}", description.kind(), index)
+}
+
+/// Like [`object_name`], for the objects of a graph node, with the node's name in front, like
+/// `lamp/sphere 0`.
+fn graph_object_name(placed: &PlacedNode, index: usize, description: &ObjectDescription) -> String {
+    format!("{}/{}", placed.node.name, object_name(index, description))
+}
+
+/// One object standing for all of `parts`.
+fn single(mut parts: Vec<Arc<dyn Hittable + Send + Sync>>) -> Arc<dyn Hittable + Send + Sync> {
+    // meshes and curves are many objects as far as building them goes
+    match parts.len() {
+        1 => parts.remove(0),
+        _ => Arc::new(Bvh::new(parts)),
+    }
+}
+
+/// Moves `objects` into the world with `transform`, leaving them as they are if it does nothing.
+fn place(
+    objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    transform: Transform,
+) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+    if transform == Transform::identity() || objects.is_empty() {
+        return objects;
+    }
+    vec![Arc::new(Transformed {
+        object: single(objects),
+        transform,
+    })]
+}
+
+/// What the objects of a scene are built from.
+struct Assets<'a> {
+    materials: MaterialLibrary,
+    prototypes: &'a HashMap<String, Vec<ObjectDescription>>,
+    /// The prototypes built so far, each into a BVH all of its instances share.
+    built: RefCell<HashMap<&'a str, Arc<dyn Hittable + Send + Sync>>>,
+    /// The prototypes being built, to catch ones holding instances of themselves.
+    building: RefCell<Vec<&'a str>>,
+}
+
+impl Assets<'_> {
+    /// The prototype called `name`, built if it wasn't already.
+    fn prototype(&self, name: &str) -> Result<Arc<dyn Hittable + Send + Sync>> {
+        let (name, objects) = self
+            .prototypes
+            .get_key_value(name)
+            .ok_or_else(|| Error::UnknownPrototype(name.to_owned()))?;
+        if let Some(prototype) = self.built.borrow().get(name.as_str()) {
+            return Ok(prototype.clone());
+        }
+        if self.building.borrow().contains(&name.as_str()) {
+            return Err(Error::RecursivePrototype(name.clone()));
+        }
+
+        self.building.borrow_mut().push(name);
+        let objects = build_objects(objects, self);
+        self.building.borrow_mut().pop();
+        let prototype: Arc<dyn Hittable + Send + Sync> = Arc::new(Bvh::new(objects?));
+        self.built.borrow_mut().insert(name, prototype.clone());
+        Ok(prototype)
+    }
+}
+
+/// Builds `objects` with the materials and prototypes of the scene.
+fn build_objects(
+    objects: &[ObjectDescription],
+    assets: &Assets,
+) -> Result<Vec<Arc<dyn Hittable + Send + Sync>>> {
+    let material = |name: &str| assets.materials.get(name);
+    let cull = |object: Arc<dyn Hittable + Send + Sync>, cull_backfaces: bool| {
+        if cull_backfaces {
+            Arc::new(Culled { object })
+        } else {
+            object
+        }
+    };
+
+    let mut world: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
+    for object in objects {
+        match object {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material: name,
+                cull_backfaces,
+            } => world.push(cull(
+                Arc::new(Sphere::new(*center, *radius, material(name)?)?),
+                *cull_backfaces,
+            )),
+            ObjectDescription::Parallelogram {
+                corner,
+                u,
+                v,
+                w,
+                material: name,
+                cull_backfaces,
+            } => world.push(cull(
+                Arc::new(Parallelogram::new(*corner, *u, *v, *w, material(name)?)),
+                *cull_backfaces,
+            )),
+            ObjectDescription::BilinearPatch {
+                corners,
+                material: name,
+                cull_backfaces,
+            } => world.push(cull(
+                Arc::new(BilinearPatch::new(*corners, material(name)?)?),
+                *cull_backfaces,
+            )),
+            ObjectDescription::BezierPatch {
+                control_points,
+                material: name,
+                cull_backfaces,
+            } => world.push(cull(
+                Arc::new(BezierPatch::new(**control_points, material(name)?)?),
+                *cull_backfaces,
+            )),
+            ObjectDescription::Curves {
+                curves,
+                radius,
+                material: name,
+            } => {
+                let material = material(name)?;
+                for &control_points in curves {
+                    let curve = Curve::new(control_points, *radius, material.clone())?;
+                    for segment in curve.segments(CURVE_SEGMENTS) {
+                        world.push(Arc::new(segment));
+                    }
+                }
+            }
+            ObjectDescription::Metaballs {
+                balls,
+                threshold,
+                falloff,
+                material: name,
+            } => {
+                let balls = balls
+                    .iter()
+                    .map(|ball| Metaball {
+                        center: ball.center,
+                        radius: ball.radius,
+                        strength: ball.strength,
+                    })
+                    .collect();
+                world.push(Arc::new(Metaballs::new(
+                    balls,
+                    *threshold,
+                    *falloff,
+                    material(name)?,
+                )?));
+            }
+            ObjectDescription::Instancer {
+                prototype,
+                distribution,
+                jitter,
+                seed,
+            } => {
+                let prototype = Arc::new(Bvh::new(build_objects(prototype, assets)?));
+                let distribution = match distribution {
+                    DistributionDescription::Grid { min, max, counts } => Distribution::Grid {
+                        min: *min,
+                        max: *max,
+                        counts: *counts,
+                    },
+                    DistributionDescription::Random { min, max, count } => Distribution::Random {
+                        min: *min,
+                        max: *max,
+                        count: *count,
+                    },
+                    DistributionDescription::Surface {
+                        surface,
+                        count,
+                        spacing,
+                        align,
+                    } => Distribution::Surface {
+                        surface: Arc::new(Bvh::new(build_objects(surface, assets)?)),
+                        count: *count,
+                        spacing: *spacing,
+                        align: *align,
+                    },
+                };
+                let jitter = Jitter {
+                    rotation: jitter.rotation,
+                    scale: (jitter.scale[0], jitter.scale[1]),
+                    offset: jitter.offset,
+                };
+                world.push(Arc::new(Instancer::new(
+                    prototype,
+                    &distribution,
+                    jitter,
+                    *seed,
+                )?));
+            }
+            ObjectDescription::Instances {
+                prototype,
+                placements,
+            } => {
+                let prototype = assets.prototype(prototype)?;
+                let instances = placements
+                    .iter()
+                    .map(|placement| {
+                        Ok(Transformed {
+                            object: prototype.clone(),
+                            transform: placement.transform()?,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                world.push(Arc::new(TopLevelBvh::new(instances)));
+            }
+            ObjectDescription::Lod { levels, switch } => {
+                let levels = levels
+                    .iter()
+                    .map(|level| Ok(single(build_objects(level, assets)?)))
+                    .collect::<Result<_>>()?;
+                world.push(Arc::new(Lod::new(levels, switch)?));
+            }
+            ObjectDescription::Mesh {
+                path,
+                material: name,
+                subdivisions,
+                cull_backfaces,
+            } => {
+                let material = name.as_deref().map(&material).transpose()?;
+                world.extend(load_mesh(
+                    path,
+                    material,
+                    *subdivisions,
+                    *cull_backfaces,
+                    assets.materials.textures(),
+                )?);
+            }
+            ObjectDescription::Voxels {
+                path,
+                corner,
+                voxel_size,
+                materials: names,
+            } => {
+                let materials = names
+                    .iter()
+                    .map(|(&index, name)| Ok((index, material(name)?)))
+                    .collect::<Result<_>>()?;
+                world.push(Arc::new(load_voxels(
+                    path,
+                    *corner,
+                    *voxel_size,
+                    &materials,
+                )?));
+            }
+            ObjectDescription::Vdb {
+                path,
+                grid,
+                density_scale,
+                translation,
+                scale,
+                material: name,
+                emission,
+                temperature_grid,
+            } => {
+                let scale = Error::check("vdb scale", *scale, |scale| scale > 0.0)?;
+                let transform = Transform::new(
+                    *translation,
+                    Vec3::default(),
+                    Vec3::new(scale, scale, scale),
+                );
+                let field = load_vdb(path, grid.as_deref(), &transform)?;
+                let mut material = material(name)?;
+                if let Some(emission) = emission {
+                    let temperature = match temperature_grid {
+                        Some(grid) => load_vdb(path, Some(grid), &transform)?,
+                        None => field.clone(),
+                    };
+                    material = emission.build(material, temperature)?;
+                }
+                world.push(Arc::new(HeterogeneousMedium::new(
+                    field,
+                    *density_scale,
+                    material,
+                )?));
+            }
+            ObjectDescription::Cloud {
+                min,
+                max,
+                density,
+                frequency,
+                octaves,
+                seed,
+                material: name,
+                emission,
+            } => {
+                let bounds = Aabb::new(*min, *max);
+                let field: Arc<dyn DensityField> =
+                    Arc::new(NoiseDensity::new(bounds, *frequency, *octaves, *seed)?);
+                let mut material = material(name)?;
+                if let Some(emission) = emission {
+                    material = emission.build(material, field.clone())?;
+                }
+                world.push(Arc::new(HeterogeneousMedium::new(
+                    field, *density, material,
+                )?));
+            }
+        }
+    }
+
+    Ok(world)
+}
+
+/// Parses the contents of a scene file, for when it doesn't come from the file system.
+impl FromStr for Scene {
+    type Err = serde_json::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut scene: Self = serde_json::from_str(text)?;
+        if let Some(name) = &scene.camera_node {
+            scene.camera = scene.graph.camera(name).map_err(serde::de::Error::custom)?;
+        }
+
+        Ok(scene)
+    }
+}
+
+impl MaterialDescription {
+    /// Fails if any of the parameters is out of range.
+    pub fn build(&self) -> Result<Arc<dyn Material>> {
+        self.build_with(&TextureCache::new())
+    }
+
+    /// Like [`MaterialDescription::build`], taking images from `textures` when they're already
+    /// loaded.
+    pub fn build_with(&self, textures: &TextureCache) -> Result<Arc<dyn Material>> {
+        Ok(match *self {
+            Self::Lambertian { albedo } => Arc::new(Lambertian::new(albedo)?),
+            Self::Metal { albedo, fuzz } => Arc::new(Metal::new(albedo, fuzz)?),
+            Self::Dielectric { ri } => Arc::new(Dielectric::new(ri)?),
+            Self::DiffuseLight {
+                emit,
+                double_sided,
+                illuminant,
+                ref ies,
+                ref texture,
+            } => {
+                let emit = match illuminant {
+                    Some(illuminant) => emit * illuminant.color(),
+                    None => emit,
+                };
+                let mut light = DiffuseLight::new(emit).double_sided(double_sided);
+                if let Some(ies) = ies {
+                    light = light.profile(Arc::new(load_ies(&ies.path)?.aimed(ies.aim)?));
+                }
+                if let Some(texture) = texture {
+                    light = light.texture(texture.build_with("emission", textures)?);
+                }
+                Arc::new(light)
+            }
+            Self::Textured { ref texture } => Arc::new(TexturedLambertian {
+                texture: texture.build_with("albedo", textures)?,
+            }),
+            Self::Isotropic { albedo } => Arc::new(Isotropic::new(albedo)?),
+            Self::HenyeyGreenstein { albedo, g } => Arc::new(Scattering::new(
+                albedo,
+                Arc::new(HenyeyGreenstein::new(g)?),
+            )?),
+            Self::MetallicRoughness {
+                ref base_color,
+                ref metallic,
+                ref roughness,
+            } => Arc::new(MetallicRoughness {
+                base_color: base_color.build_with("base color", textures)?,
+                metallic: metallic.build_with("metallic", textures)?,
+                roughness: roughness.build_with("roughness", textures)?,
+            }),
+            Self::Hair { color, roughness } => Arc::new(Hair::new(color, roughness)?),
+            Self::Graph { ref bsdf } => Arc::new(bsdf.build_with(textures)?),
+            Self::ShadowCatcher { ref surface } => Arc::new(ShadowCatcher {
+                surface: surface.build_with(textures)?,
+            }),
+        })
+    }
+
+    /// Calls `f` with every path to an image, see [`Scene::visit_paths`].
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        match self {
+            Self::Textured { texture } => texture.visit_paths(f),
+            Self::MetallicRoughness {
+                base_color,
+                metallic,
+                roughness,
+            } => {
+                for texture in [base_color, metallic, roughness] {
+                    texture.visit_paths(f);
+                }
+            }
+            Self::Graph { bsdf } => bsdf.visit_paths(f),
+            Self::DiffuseLight { ies, texture, .. } => {
+                if let Some(ies) = ies {
+                    f(&mut ies.path);
+                }
+                if let Some(texture) = texture {
+                    texture.visit_paths(f);
+                }
+            }
+            Self::ShadowCatcher { surface } => surface.visit_paths(f),
+            _ => {}
+        }
+    }
+}
+
+impl BsdfDescription {
+    /// Fails if a refraction index isn't positive, or a texture can't be built.
+    pub fn build(&self) -> Result<Bsdf> {
+        self.build_with(&TextureCache::new())
+    }
+
+    /// Like [`BsdfDescription::build`], with the images of `textures`.
+    pub fn build_with(&self, textures: &TextureCache) -> Result<Bsdf> {
+        Ok(match self {
+            Self::Diffuse { color } => Bsdf::Diffuse {
+                color: color.build_with("diffuse color", textures)?,
+            },
+            Self::Metal { color, roughness } => Bsdf::Metal {
+                color: color.build_with("metal color", textures)?,
+                roughness: roughness.build_with("roughness", textures)?,
+            },
+            Self::Glass { ior } => Bsdf::Glass {
+                ior: Error::check("refraction index", *ior, |ior| ior > 0.0)?,
+            },
+            Self::Emission { color } => Bsdf::Emission {
+                color: color.build_with("emission color", textures)?,
+            },
+            Self::Mix { factor, a, b } => Bsdf::Mix {
+                factor: factor.build_with("mix factor", textures)?,
+                a: Arc::new(a.build_with(textures)?),
+                b: Arc::new(b.build_with(textures)?),
+            },
+        })
+    }
+
+    /// Calls `f` with every path to an image, see [`Scene::visit_paths`].
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        match self {
+            Self::Diffuse { color } | Self::Emission { color } => color.visit_paths(f),
+            Self::Metal { color, roughness } => {
+                color.visit_paths(f);
+                roughness.visit_paths(f);
+            }
+            Self::Mix { factor, a, b } => {
+                factor.visit_paths(f);
+                a.visit_paths(f);
+                b.visit_paths(f);
+            }
+            Self::Glass { .. } => {}
+        }
+    }
+}
+
+impl NodeDescription {
+    /// Numbers and colors aren't limited to [0, 1] like those of textures, since nodes can feed
+    /// math on other nodes, but textures still are.
+    pub fn build(&self, name: &'static str) -> Result<Arc<Node>> {
+        self.build_with(name, &TextureCache::new())
+    }
+
+    /// Like [`NodeDescription::build`], with the images of `textures`.
+    pub fn build_with(&self, name: &'static str, textures: &TextureCache) -> Result<Arc<Node>> {
+        Ok(Arc::new(match self {
+            Self::Value(value) => Node::value(*value),
+            Self::Color(color) => Node::Constant(*color),
+            Self::Operation(NodeOperation::Math { op, a, b }) => Node::Math {
+                op: *op,
+                a: a.build_with(name, textures)?,
+                b: b.build_with(name, textures)?,
+            },
+            Self::Operation(NodeOperation::Mix { factor, a, b }) => Node::Mix {
+                factor: factor.build_with(name, textures)?,
+                a: a.build_with(name, textures)?,
+                b: b.build_with(name, textures)?,
+            },
+            Self::Operation(NodeOperation::Facing) => Node::Facing,
+            Self::Operation(NodeOperation::Fresnel { ior }) => Node::Fresnel {
+                ior: Error::check("refraction index", *ior, |ior| ior > 0.0)?,
+            },
+            Self::Texture(texture) => Node::Texture(texture.build_with(name, textures)?),
+        }))
+    }
+
+    /// Calls `f` with every path to an image, see [`Scene::visit_paths`].
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        match self {
+            Self::Operation(NodeOperation::Math { a, b, .. }) => {
+                a.visit_paths(f);
+                b.visit_paths(f);
+            }
+            Self::Operation(NodeOperation::Mix { factor, a, b }) => {
+                factor.visit_paths(f);
+                a.visit_paths(f);
+                b.visit_paths(f);
+            }
+            Self::Texture(texture) => texture.visit_paths(f),
+            _ => {}
+        }
+    }
+}
+
+impl TextureDescription {
+    /// Fails if a number or color isn't between 0 and 1, or the image can't be loaded.
+    pub fn build(&self, name: &'static str) -> Result<Arc<dyn Texture>> {
+        self.build_with(name, &TextureCache::new())
+    }
+
+    /// Like [`TextureDescription::build`], taking images from `textures` when they're already
+    /// loaded.
+    pub fn build_with(
+        &self,
+        name: &'static str,
+        textures: &TextureCache,
+    ) -> Result<Arc<dyn Texture>> {
+        let unit = |c: f64| (0.0..=1.0).contains(&c);
+        Ok(match *self {
+            Self::Value(value) => {
+                let value = Error::check(name, value, unit)?;
+                Arc::new(SolidColor(Color::new(value, value, value)))
+            }
+            Self::Color(color) => {
+                for &component in &color.0 {
+                    Error::check(name, component, unit)?;
+                }
+                Arc::new(SolidColor(color))
+            }
+            Self::Image(ref path) => load_texture(path, ColorSpace::Srgb, textures)?,
+            Self::Procedural(ProceduralTexture::Image {
+                ref path,
+                color_space,
+            }) => load_texture(path, color_space, textures)?,
+            Self::Procedural(ProceduralTexture::Transformed {
+                ref texture,
+                scale,
+                offset,
+                rotation,
+                wrap,
+            }) => Arc::new(TextureTransform::new(
+                texture.build_with(name, textures)?,
+                scale,
+                offset,
+                rotation,
+                wrap,
+            )?),
+            Self::Procedural(ProceduralTexture::Perlin { ref noise }) => {
+                noise.build(Arc::new(Perlin::new(noise.seed)))?
+            }
+            Self::Procedural(ProceduralTexture::Worley { feature, ref noise }) => {
+                noise.build(Arc::new(Cells {
+                    worley: Worley::new(noise.seed),
+                    feature,
+                }))?
+            }
+        })
+    }
+
+    /// Calls `f` with every path to an image, see [`Scene::visit_paths`].
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        match self {
+            Self::Image(path) | Self::Procedural(ProceduralTexture::Image { path, .. }) => f(path),
+            Self::Procedural(ProceduralTexture::Transformed { texture, .. }) => {
+                texture.visit_paths(f)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ObjectDescription {
+    /// Calls `f` with the object and every object inside it, see [`Scene::visit_objects`].
+    fn visit(&self, f: &mut dyn FnMut(&ObjectDescription)) {
+        f(self);
+        match self {
+            Self::Instancer {
+                prototype,
+                distribution,
+                ..
+            } => {
+                let surface = match distribution {
+                    DistributionDescription::Surface { surface, .. } => &surface[..],
+                    _ => &[],
+                };
+                for object in prototype.iter().chain(surface) {
+                    object.visit(f);
+                }
+            }
+            Self::Lod { levels, .. } => {
+                for object in levels.iter().flatten() {
+                    object.visit(f);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Calls `f` with every path to a mesh, voxel model, volume or image, see
+    /// [`Scene::visit_paths`].
+    #[cfg(feature = "fs")]
+    fn visit_paths(&mut self, f: &mut dyn FnMut(&mut PathBuf)) {
+        match self {
+            Self::Mesh { path, .. } | Self::Voxels { path, .. } | Self::Vdb { path, .. } => f(path),
+            Self::Instancer {
+                prototype,
+                distribution,
+                ..
+            } => {
+                let surface = match distribution {
+                    DistributionDescription::Surface { surface, .. } => &mut surface[..],
+                    _ => &mut [],
+                };
+                for object in prototype.iter_mut().chain(surface) {
+                    object.visit_paths(f);
+                }
+            }
+            Self::Lod { levels, .. } => {
+                for object in levels.iter_mut().flatten() {
+                    object.visit_paths(f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SkyDescription {
+    /// Fails if any of the parameters is out of range.
+    pub fn build(&self) -> Result<Arc<dyn Background>> {
+        self.build_at(0.0)
+    }
+
+    /// The sky `time` seconds into an animation, which only a daylight sky changes over.
+    pub fn build_at(&self, time: f64) -> Result<Arc<dyn Background>> {
+        Ok(match *self {
+            Self::Gradient { horizon, zenith } => {
+                for &component in horizon.0.iter().chain(&zenith.0) {
+                    Error::check("sky color", component, |c| c >= 0.0)?;
+                }
+                Arc::new(Gradient { horizon, zenith })
+            }
+            Self::Solid { color } => {
+                for &component in color.0.iter() {
+                    Error::check("sky color", component, |c| c >= 0.0)?;
+                }
+                Arc::new(Gradient {
+                    horizon: color,
+                    zenith: color,
+                })
+            }
+            Self::Preetham {
+                sun_direction,
+                turbidity,
+                sun_angular_diameter,
+                intensity,
+            } => Arc::new(Preetham::new(
+                sun_direction,
+                turbidity,
+                sun_angular_diameter,
+                intensity,
+            )?),
+            Self::Daylight {
+                latitude,
+                longitude,
+                date: [year, month, day],
+                hour,
+                utc_offset,
+                north,
+                hours_per_second,
+                turbidity,
+                sun_angular_diameter,
+                intensity,
+            } => {
+                // anything out of range stays out of range, for the checks to catch
+                let daylight = Daylight {
+                    latitude,
+                    longitude,
+                    year,
+                    month: month.clamp(0, 13) as u32,
+                    day: day.clamp(0, 32) as u32,
+                    hour,
+                    utc_offset,
+                };
+                let sun_direction = daylight
+                    .after(hours_per_second * time)
+                    .sun_direction(north)?;
+                Arc::new(Preetham::new(
+                    sun_direction,
+                    turbidity,
+                    sun_angular_diameter,
+                    intensity,
+                )?)
+            }
+        })
+    }
+}
+
+impl EmissionDescription {
+    /// Makes `material` glow with the temperature in `field`.
+    pub fn build(
+        &self,
+        material: Arc<dyn Material>,
+        field: Arc<dyn DensityField>,
+    ) -> Result<Arc<dyn Material>> {
+        Ok(Arc::new(Blackbody::new(
+            material,
+            field,
+            self.temperature,
+            self.intensity,
+        )?))
+    }
+}
+
+#[cfg(feature = "fs")]
+fn load_mesh(
+    path: &Path,
+    material: Option<Arc<dyn Material>>,
+    subdivisions: u32,
+    cull_backfaces: bool,
+    textures: &TextureCache,
+) -> Result<Vec<Arc<dyn Hittable + Send + Sync>>> {
+    let triangles = crate::obj::load(path, material, subdivisions, textures)?;
+    let triangles = triangles.into_iter().map(|triangle| match cull_backfaces {
+        true => triangle.culling_backfaces(),
+        false => triangle,
+    });
+    #[cfg(feature = "embree")]
+    {
+        let mesh = crate::collision::embree::EmbreeMesh::new(triangles.collect())?;
+        Ok(match mesh.triangles().is_empty() {
+            true => Vec::new(),
+            false => vec![Arc::new(mesh)],
+        })
+    }
+    #[cfg(not(feature = "embree"))]
+    Ok(triangles
+        .map(|triangle| Arc::new(triangle) as Arc<dyn Hittable + Send + Sync>)
+        .collect())
+}
+
+#[cfg(feature = "fs")]
+fn load_voxels(
+    path: &Path,
+    corner: Point3,
+    voxel_size: f64,
+    materials: &HashMap<u8, Arc<dyn Material>>,
+) -> Result<VoxelGrid> {
+    crate::vox::load(path, corner, voxel_size, materials)
+}
+
+#[cfg(feature = "vdb")]
+fn load_vdb(
+    path: &Path,
+    grid: Option<&str>,
+    transform: &Transform,
+) -> Result<Arc<dyn DensityField>> {
+    let grid = crate::vdb::VdbGrid::load(path, grid)?;
+    Ok(Arc::new(grid.transformed(transform)))
+}
+
+#[cfg(not(feature = "vdb"))]
+fn load_vdb(path: &Path, _: Option<&str>, _: &Transform) -> Result<Arc<dyn DensityField>> {
+    Err(unsupported(path, "built without the vdb feature"))
+}
+
+#[cfg(feature = "fs")]
+fn load_ies(path: &Path) -> Result<IesProfile> {
+    IesProfile::load(path)
+}
+
+/// The image at `path`, from `textures` if it's loaded already, and decoded once it's looked up if
+/// they have a budget.
+#[cfg(feature = "fs")]
+fn load_texture(
+    path: &Path,
+    color_space: ColorSpace,
+    textures: &TextureCache,
+) -> Result<Arc<ImageTexture>> {
+    textures.get_or_load(path, color_space, |path, color_space| {
+        match textures.levels() {
+            Some(levels) => ImageTexture::load_lazily(path, color_space, levels),
+            None => ImageTexture::load(path, color_space),
+        }
+    })
+}
+
+#[cfg(not(feature = "fs"))]
+fn load_mesh(
+    path: &Path,
+    _: Option<Arc<dyn Material>>,
+    _: u32,
+    _: bool,
+    _: &TextureCache,
+) -> Result<Vec<Arc<dyn Hittable + Send + Sync>>> {
+    Err(unsupported(path, "built without file access"))
+}
+
+#[cfg(not(feature = "fs"))]
+fn load_voxels(
+    path: &Path,
+    _: Point3,
+    _: f64,
+    _: &HashMap<u8, Arc<dyn Material>>,
+) -> Result<VoxelGrid> {
+    Err(unsupported(path, "built without file access"))
+}
+
+#[cfg(not(feature = "fs"))]
+fn load_ies(path: &Path) -> Result<IesProfile> {
+    Err(unsupported(path, "built without file access"))
+}
+
+#[cfg(not(feature = "fs"))]
+fn load_texture(path: &Path, _: ColorSpace, _: &TextureCache) -> Result<Arc<ImageTexture>> {
+    Err(unsupported(path, "built without file access"))
+}
+
+/// What loading a file fails with when the feature it needs is turned off.
+#[cfg(not(feature = "vdb"))]
+fn unsupported(path: &Path, reason: &str) -> Error {
+    Error::File {
+        path: path.to_owned(),
+        source: io::Error::new(io::ErrorKind::Unsupported, reason),
+    }
+}
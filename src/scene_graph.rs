@@ -0,0 +1,217 @@
+//! A hierarchy of named nodes, each placed relative to its parent, for scenes that are built out
+//! of parts: a lamp with its shade and bulb, a car with its wheels, a camera on a rig.
+//!
+//! Moving a node moves everything below it. Before rendering the graph is flattened, composing
+//! the transformations of every node with those of its ancestors, and the objects and cameras
+//! of the nodes end up in the world like any others. Names are unique across the graph, so
+//! nodes can be found by them, the way importers and animations refer to them.
+
+use crate::scene::{CameraDescription, FocusTarget, ObjectDescription};
+use crate::transform::Transform;
+use crate::{Error, Result, Vec3};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+/// The nodes at the top of the hierarchy, which are placed in the world directly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SceneGraph {
+    pub roots: Vec<GraphNode>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub name: String,
+    /// The node's place in its parent: scaled, then rotated by these angles in degrees around
+    /// the x, y and z axes, then moved by the translation. See [`Transform::new`].
+    #[serde(default)]
+    pub translation: Vec3,
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+    /// Objects, lights among them, in the node's own space.
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    /// A camera in the node's own space. Only its position and orientation follow the node:
+    /// the field of view, focus distance and lens stay the same.
+    #[serde(default)]
+    pub camera: Option<CameraDescription>,
+    #[serde(default)]
+    pub children: Vec<GraphNode>,
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+/// A node of a flattened graph.
+#[derive(Copy, Clone, Debug)]
+pub struct PlacedNode<'a> {
+    pub node: &'a GraphNode,
+    /// From the node's space to the world, with the transformations of its ancestors composed
+    /// into its own.
+    pub transform: Transform,
+}
+
+impl GraphNode {
+    /// An empty node at the origin of its parent.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            translation: Vec3::default(),
+            rotation: Vec3::default(),
+            scale: default_scale(),
+            objects: Vec::new(),
+            camera: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// From the node's space to its parent's. Fails if the scale flattens it.
+    pub fn transform(&self) -> Result<Transform> {
+        for &component in self.translation.0.iter().chain(&self.rotation.0) {
+            Error::check("node transformation", component, |_| true)?;
+        }
+        for &component in self.scale.0.iter() {
+            Error::check("node scale", component, |s| s != 0.0)?;
+        }
+        Ok(Transform::new(self.translation, self.rotation, self.scale))
+    }
+}
+
+impl SceneGraph {
+    /// Every node, parents before their children, placed in the world. Fails if two nodes have
+    /// the same name, or a node's transformation is invalid.
+    pub fn flatten(&self) -> Result<Vec<PlacedNode<'_>>> {
+        let mut placed = Vec::new();
+        let mut names = HashSet::new();
+        let mut stack: Vec<(&GraphNode, Transform)> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|node| (node, Transform::identity()))
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            if !names.insert(node.name.as_str()) {
+                return Err(Error::DuplicateNode(node.name.clone()));
+            }
+            let transform = parent.after(&node.transform()?);
+            placed.push(PlacedNode { node, transform });
+            stack.extend(node.children.iter().rev().map(|child| (child, transform)));
+        }
+
+        Ok(placed)
+    }
+
+    /// The node called `name`, placed in the world.
+    pub fn find(&self, name: &str) -> Result<Option<PlacedNode<'_>>> {
+        Ok(self
+            .flatten()?
+            .into_iter()
+            .find(|placed| placed.node.name == name))
+    }
+
+    /// The camera of the node called `name`, moved into the world.
+    pub fn camera(&self, name: &str) -> Result<CameraDescription> {
+        let unknown = || Error::UnknownCamera(name.to_owned());
+        let placed = self.find(name)?.ok_or_else(unknown)?;
+        let camera = placed.node.camera.as_ref().ok_or_else(unknown)?;
+        let transform = placed.transform;
+
+        Ok(CameraDescription {
+            look_from: transform.transform_point(camera.look_from),
+            look_at: transform.transform_point(camera.look_at),
+            vup: transform.transform_vector(camera.vup),
+            focus_on: match &camera.focus_on {
+                Some(FocusTarget::Point(point)) => {
+                    Some(FocusTarget::Point(transform.transform_point(*point)))
+                }
+                target => target.clone(),
+            },
+            ..camera.clone()
+        })
+    }
+
+    /// Calls `visit` on every node, parents before their children.
+    #[cfg(feature = "fs")]
+    pub(crate) fn visit_mut(&mut self, visit: &mut impl FnMut(&mut GraphNode)) {
+        fn walk(node: &mut GraphNode, visit: &mut impl FnMut(&mut GraphNode)) {
+            visit(node);
+            for child in &mut node.children {
+                walk(child, visit);
+            }
+        }
+        for root in &mut self.roots {
+            walk(root, visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point3;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    fn lamp() -> SceneGraph {
+        let mut bulb = GraphNode::new("bulb");
+        bulb.translation = Vec3::new(0.0, 1.0, 0.0);
+        let mut camera = GraphNode::new("camera");
+        camera.translation = Vec3::new(0.0, 0.0, 2.0);
+        camera.camera = Some(CameraDescription {
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            focus_on: Some(FocusTarget::Point(Point3::new(0.0, 0.0, -2.0))),
+            ..CameraDescription::default()
+        });
+
+        let mut lamp = GraphNode::new("lamp");
+        lamp.translation = Vec3::new(5.0, 0.0, 0.0);
+        lamp.rotation = Vec3::new(0.0, 90.0, 0.0);
+        lamp.scale = Vec3::new(2.0, 2.0, 2.0);
+        lamp.children = vec![bulb, camera];
+        SceneGraph { roots: vec![lamp] }
+    }
+
+    #[test]
+    fn transformations_compose_down_the_tree() {
+        let graph = lamp();
+        let placed = graph.flatten().unwrap();
+        let names: Vec<&str> = placed.iter().map(|p| p.node.name.as_str()).collect();
+        assert_eq!(names, ["lamp", "bulb", "camera"]);
+
+        // scaled by the lamp, then moved along with it
+        let bulb = graph.find("bulb").unwrap().unwrap();
+        let origin = bulb.transform.transform_point(Point3::default());
+        assert!(close(origin, Point3::new(5.0, 2.0, 0.0)), "{}", origin);
+
+        // turned a quarter around y, so looking along -x, at the origin of the lamp
+        let camera = graph.camera("camera").unwrap();
+        assert!(close(camera.look_from, Point3::new(9.0, 0.0, 0.0)));
+        assert!(close(camera.look_at, Point3::new(7.0, 0.0, 0.0)));
+        match camera.focus_on {
+            Some(FocusTarget::Point(point)) => assert!(close(point, Point3::new(5.0, 0.0, 0.0))),
+            target => panic!("{:?}", target),
+        }
+        assert!(graph.camera("bulb").is_err());
+        assert!(graph.camera("nothing").is_err());
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut graph = lamp();
+        graph.roots.push(GraphNode::new("bulb"));
+        assert!(matches!(graph.flatten(), Err(Error::DuplicateNode(name)) if name == "bulb"));
+
+        let mut flat = GraphNode::new("flat");
+        flat.scale = Vec3::new(1.0, 0.0, 1.0);
+        let graph = SceneGraph { roots: vec![flat] };
+        assert!(graph.flatten().is_err());
+    }
+}
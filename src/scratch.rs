@@ -0,0 +1,261 @@
+//! Scratch memory for the data a path needs while it's traced, like the lights and shadow rays of
+//! a bounce, bump allocated from a block kept by every thread instead of taken from the heap.
+//!
+//! Memory is handed out by [`with`], and taken back as soon as the closure it's given returns,
+//! which nothing allocated can outlive. Scopes nest, but only the innermost one can allocate,
+//! since the memory it hands out is taken back before that of the scopes around it. The renderer
+//! calls [`reset`] after every pixel, with no scopes open.
+
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::mem::MaybeUninit;
+use std::ptr::NonNull;
+
+/// The most any type allocated needs its address aligned to.
+const ALIGN: usize = 16;
+
+/// How big the first block of a thread is.
+const FIRST_BLOCK: usize = 16 << 10;
+
+thread_local! {
+    static ARENA: RefCell<Arena> = RefCell::new(Arena::default());
+}
+
+/// Blocks of memory that are bump allocated from, one after the other.
+#[derive(Default)]
+struct Arena {
+    /// Every block, with its size in bytes. Those after the one being allocated from are empty.
+    blocks: RefCell<Vec<(NonNull<u8>, usize)>>,
+    /// The block being allocated from, and how many bytes of it are taken.
+    top: Cell<(usize, usize)>,
+    /// How many scopes are open, the innermost of which is the only one that can allocate.
+    depth: Cell<usize>,
+}
+
+impl Arena {
+    /// `size` bytes aligned to `align`, which live as long as the innermost scope.
+    fn bump(&self, size: usize, align: usize) -> NonNull<u8> {
+        assert!(
+            align <= ALIGN,
+            "scratch memory is aligned to {} bytes at most",
+            ALIGN
+        );
+        let mut blocks = self.blocks.borrow_mut();
+        let (mut block, taken) = self.top.get();
+        let mut start = (taken + align - 1) & !(align - 1);
+
+        if blocks.get(block).is_none_or(|&(_, len)| start + size > len) {
+            if !blocks.is_empty() {
+                block += 1;
+            }
+            start = 0;
+            if blocks.get(block).is_none_or(|&(_, len)| size > len) {
+                // the blocks past this one are empty, and too small to be worth keeping
+                for (ptr, len) in blocks.drain(block..) {
+                    // SAFETY: allocated below with this layout, and nothing is left in it
+                    unsafe { alloc::dealloc(ptr.as_ptr(), block_layout(len)) };
+                }
+                let last = blocks.last().map_or(FIRST_BLOCK / 2, |&(_, len)| len);
+                let len = size.max(2 * last);
+                // SAFETY: the layout is never zero-sized
+                let ptr = unsafe { alloc::alloc(block_layout(len)) };
+                let ptr = NonNull::new(ptr)
+                    .unwrap_or_else(|| alloc::handle_alloc_error(block_layout(len)));
+                blocks.push((ptr, len));
+            }
+        }
+
+        self.top.set((block, start + size));
+        // SAFETY: start + size is within the block
+        unsafe { NonNull::new_unchecked(blocks[block].0.as_ptr().add(start)) }
+    }
+
+    /// Frees every block but one as big as all of them together, so that what the last pixel
+    /// needed fits in a single block from then on.
+    fn reset(&mut self) {
+        let blocks = self.blocks.get_mut();
+        if blocks.len() > 1 {
+            let len = blocks.iter().map(|&(_, len)| len).sum();
+            for (ptr, len) in blocks.drain(..) {
+                // SAFETY: allocated in bump with this layout, and there are no scopes left to
+                // use it
+                unsafe { alloc::dealloc(ptr.as_ptr(), block_layout(len)) };
+            }
+            // SAFETY: the layout is never zero-sized
+            let ptr = unsafe { alloc::alloc(block_layout(len)) };
+            let ptr =
+                NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(block_layout(len)));
+            blocks.push((ptr, len));
+        }
+        self.top.set((0, 0));
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for &(ptr, len) in self.blocks.get_mut().iter() {
+            // SAFETY: allocated in bump with this layout, and nothing borrows the arena anymore
+            unsafe { alloc::dealloc(ptr.as_ptr(), block_layout(len)) };
+        }
+    }
+}
+
+fn block_layout(len: usize) -> Layout {
+    Layout::from_size_align(len.max(1), ALIGN).unwrap()
+}
+
+/// Runs `f` with a scope of the calling thread's scratch memory, which everything allocated from
+/// it goes back to once `f` returns.
+pub fn with<R>(f: impl FnOnce(&Scratch) -> R) -> R {
+    ARENA.with(|arena| {
+        let arena = arena.borrow();
+        let top = arena.top.get();
+        let depth = arena.depth.get() + 1;
+        arena.depth.set(depth);
+
+        /// Closes the scope even if `f` panics, so that a thread that catches it can go on.
+        struct Close<'a>(&'a Arena, (usize, usize), usize);
+        impl Drop for Close<'_> {
+            fn drop(&mut self) {
+                self.0.top.set(self.1);
+                self.0.depth.set(self.2 - 1);
+            }
+        }
+        let _close = Close(&arena, top, depth);
+
+        f(&Scratch {
+            arena: &arena,
+            depth,
+        })
+    })
+}
+
+/// Trims the calling thread's scratch memory down to one block, for the next pixel. Panics if
+/// it's called inside [`with`].
+pub fn reset() {
+    ARENA.with(|arena| {
+        arena
+            .try_borrow_mut()
+            .expect("scratch memory can only be reset with no scopes open")
+            .reset()
+    });
+}
+
+/// A scope of scratch memory, see [`with`].
+pub struct Scratch<'a> {
+    arena: &'a Arena,
+    depth: usize,
+}
+
+impl Scratch<'_> {
+    /// `len` uninitialized values.
+    #[allow(clippy::mut_from_ref)]
+    fn uninit<T: Copy>(&self, len: usize) -> &mut [MaybeUninit<T>] {
+        assert_eq!(
+            self.arena.depth.get(),
+            self.depth,
+            "scratch memory can only be allocated from the innermost scope"
+        );
+        let layout = Layout::array::<T>(len).expect("too much scratch memory");
+        if layout.size() == 0 {
+            return &mut [];
+        }
+        let ptr = self.arena.bump(layout.size(), layout.align());
+        // SAFETY: the memory is aligned for T, big enough for len of them, and nothing else uses
+        // it until this scope closes, which the borrow of self can't outlive
+        unsafe { std::slice::from_raw_parts_mut(ptr.as_ptr().cast(), len) }
+    }
+
+    /// `len` copies of `value`.
+    #[allow(clippy::mut_from_ref)]
+    pub fn slice<T: Copy>(&self, len: usize, value: T) -> &mut [T] {
+        let slice = self.uninit(len);
+        slice.fill(MaybeUninit::new(value));
+        // SAFETY: every value was just initialized
+        unsafe { &mut *(slice as *mut [MaybeUninit<T>] as *mut [T]) }
+    }
+
+    /// An empty vector with room for `capacity` values before it has to grow.
+    pub fn vec<T: Copy>(&self, capacity: usize) -> ScratchVec<'_, T> {
+        ScratchVec {
+            scratch: self,
+            buf: self.uninit(capacity),
+            len: 0,
+        }
+    }
+}
+
+/// A vector in scratch memory. Growing it leaves the old values behind until the scope closes.
+pub struct ScratchVec<'a, T> {
+    scratch: &'a Scratch<'a>,
+    buf: &'a mut [MaybeUninit<T>],
+    len: usize,
+}
+
+impl<T: Copy> ScratchVec<'_, T> {
+    pub fn push(&mut self, value: T) {
+        if self.len == self.buf.len() {
+            let buf = self.scratch.uninit((2 * self.len).max(4));
+            buf[..self.len].copy_from_slice(&self.buf[..self.len]);
+            self.buf = buf;
+        }
+        self.buf[self.len] = MaybeUninit::new(value);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        // SAFETY: the values up to len are initialized
+        Some(unsafe { self.buf[self.len].assume_init() })
+    }
+
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) {
+        for value in values {
+            self.push(value);
+        }
+    }
+}
+
+impl<T> std::ops::Deref for ScratchVec<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the values up to len are initialized
+        unsafe { &*(&self.buf[..self.len] as *const [MaybeUninit<T>] as *const [T]) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_take_their_memory_back() {
+        with(|outer| {
+            let first = outer.slice(4, 1u64);
+            let inner_start = with(|inner| inner.slice(1, 2u8).as_ptr() as usize);
+            // the inner scope's memory is handed out again
+            let again = outer.slice(1, 3u8);
+            assert_eq!(again.as_ptr() as usize, inner_start);
+            assert_eq!(first, [1; 4]);
+        });
+    }
+
+    #[test]
+    fn vectors_grow_past_their_blocks() {
+        with(|scratch| {
+            let mut values = scratch.vec(1);
+            values.extend(0..10_000u64);
+            assert!(values.iter().copied().eq(0..10_000));
+            assert_eq!(values.pop(), Some(9_999));
+        });
+        reset();
+        ARENA.with(|arena| assert_eq!(arena.borrow().blocks.borrow().len(), 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "innermost")]
+    fn outer_scopes_cant_allocate() {
+        with(|outer| with(|_| outer.slice(1, 0u8).len()));
+    }
+}
@@ -0,0 +1,477 @@
+//! A small HTTP API for rendering scene files, started with `ray_tracing serve`.
+//!
+//! - `POST /render` with a scene file as the body starts rendering it, answering `202 Accepted`
+//!   with the id of the render. The query string can set the `width`, `height`, `samples` per
+//!   pixel, `seed` and `format`, which is either `png` (the default) or `exr`. Everything else
+//!   comes from the options `serve` was started with. Scenes can only load files from under the
+//!   working directory, and can't place too many instances or subdivide meshes too often, see
+//!   [`check_scene`]. Only a few renders run at once, with `503 Service Unavailable` answering
+//!   any more.
+//! - `GET /render/<id>/progress` streams the number of samples per pixel done so far as
+//!   server-sent events, ending with a `done` or `error` event.
+//! - `GET /render/<id>` answers with the image once it's done, and with the progress as
+//!   `202 Accepted` until then.
+//! - `DELETE /render/<id>` stops the render if it's still going, within a pixel, and forgets it.
+//!   Its progress streams end with an `error` event.
+
+use crate::args::Args;
+use crate::{scene_camera_builder, PASS_SAMPLES};
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::film::Film;
+use ray_tracing::output::{self, Format, SaveOptions};
+use ray_tracing::render::{self, CancellationToken, RenderMode, RenderSettings};
+use ray_tracing::scene::Scene;
+use ray_tracing::{Camera, Projection};
+
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// The most renders remembered at once. Beyond that, the oldest finished ones are forgotten.
+const MAX_JOBS: usize = 64;
+/// The largest scene file accepted, in bytes.
+const MAX_SCENE_SIZE: u64 = 16 << 20;
+/// The most renders going at once. Past that, new ones are turned away until one finishes.
+const MAX_RUNNING: usize = 4;
+/// The most pixels an image can have.
+const MAX_PIXELS: u64 = 8192 * 8192;
+/// The most samples per pixel a render can take.
+const MAX_SAMPLES: u32 = 1 << 16;
+/// The most copies the instancers of a scene can place in all.
+const MAX_INSTANCES: usize = 1 << 20;
+/// The most steps of subdivision a mesh can be smoothed with. Each one quadruples its faces.
+const MAX_SUBDIVISIONS: u32 = 4;
+
+/// Why a render wasn't started, when it's no fault of the request.
+#[derive(Debug)]
+struct Busy;
+
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} renders are already running", MAX_RUNNING)
+    }
+}
+
+impl Error for Busy {}
+
+struct Job {
+    format: Format,
+    cancel: CancellationToken,
+    progress: Mutex<Progress>,
+    changed: Condvar,
+}
+
+#[derive(Clone)]
+struct Progress {
+    samples: u32,
+    total: u32,
+    /// The encoded image, or what went wrong, once the render is over.
+    result: Option<Result<Arc<Vec<u8>>, String>>,
+}
+
+impl Job {
+    fn update(&self, f: impl FnOnce(&mut Progress)) {
+        f(&mut self.progress.lock().unwrap());
+        self.changed.notify_all();
+    }
+}
+
+struct Service {
+    args: Args,
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    next_id: AtomicU64,
+}
+
+/// Serves requests until the process is killed, rendering with the settings in `args` unless
+/// a request overrides them.
+pub fn serve(addr: &str, args: Args) -> ray_tracing::Result<()> {
+    let server = Server::http(addr).map_err(io::Error::other)?;
+    log::info!("Listening on http://{}.", server.server_addr());
+
+    let service = Arc::new(Service {
+        args,
+        jobs: Mutex::new(BTreeMap::new()),
+        next_id: AtomicU64::new(1),
+    });
+    for request in server.incoming_requests() {
+        let service = Arc::clone(&service);
+        // progress streams last as long as their render, so every request gets its own thread
+        thread::spawn(move || {
+            if let Err(err) = service.handle(request) {
+                log::warn!("Couldn't answer a request: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+impl Service {
+    fn handle(&self, mut request: Request) -> Result<(), Box<dyn Error>> {
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Post, ["render"]) => {
+                let mut body = String::new();
+                request
+                    .as_reader()
+                    .take(MAX_SCENE_SIZE)
+                    .read_to_string(&mut body)?;
+                match self.start(&body, query) {
+                    Ok(id) => {
+                        let location = format!("/render/{}", id);
+                        let response = json_response(202, json!({ "id": id }))
+                            .with_header(header("Location", &location));
+                        request.respond(response)?;
+                    }
+                    Err(err) if err.is::<Busy>() => request.respond(
+                        error_response(503, &err.to_string())
+                            .with_header(header("Retry-After", "10")),
+                    )?,
+                    Err(err) => request.respond(error_response(400, &err.to_string()))?,
+                }
+            }
+            (Method::Get, ["render", id]) => match self.job(id) {
+                Some(job) => {
+                    let progress = job.progress.lock().unwrap().clone();
+                    let response = match progress.result {
+                        Some(Ok(image)) => {
+                            let content_type = match job.format {
+                                Format::Png => "image/png",
+                                Format::Exr => "image/x-exr",
+                            };
+                            Response::from_data(image.as_slice())
+                                .with_header(header("Content-Type", content_type))
+                        }
+                        Some(Err(err)) => error_response(500, &err),
+                        None => json_response(202, progress_json(&progress)),
+                    };
+                    request.respond(response)?;
+                }
+                None => request.respond(error_response(404, "no such render"))?,
+            },
+            (Method::Delete, ["render", id]) => match self.forget(id) {
+                Some(job) => {
+                    job.cancel.cancel();
+                    request.respond(Response::empty(204))?;
+                }
+                None => request.respond(error_response(404, "no such render"))?,
+            },
+            (Method::Get, ["render", id, "progress"]) => match self.job(id) {
+                Some(job) => stream_progress(request, &job)?,
+                None => request.respond(error_response(404, "no such render"))?,
+            },
+            _ => request.respond(error_response(404, "not found"))?,
+        }
+
+        Ok(())
+    }
+
+    /// Checks the scene and options and starts rendering on another thread, returning the id of
+    /// the new render.
+    fn start(&self, scene: &str, query: &str) -> Result<u64, Box<dyn Error>> {
+        let mut settings = RenderSettings {
+            show_progress: false,
+            ..self.args.settings.clone()
+        };
+        let mut format = Format::Png;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "width" => settings.width = value.parse()?,
+                "height" => settings.height = value.parse()?,
+                "samples" => settings.samples_per_pixel = value.parse()?,
+                "seed" => settings.seed = Some(value.parse()?),
+                "format" => {
+                    format = match value {
+                        "png" => Format::Png,
+                        "exr" => Format::Exr,
+                        _ => return Err("the format must be png or exr".into()),
+                    }
+                }
+                _ => return Err(format!("unknown parameter {:?}", key).into()),
+            }
+        }
+        if settings.width == 0 || settings.height == 0 || settings.samples_per_pixel == 0 {
+            return Err("the width, height and samples must be at least 1".into());
+        }
+        if settings.width as u64 * settings.height as u64 > MAX_PIXELS {
+            return Err(format!("the image can have at most {} pixels", MAX_PIXELS).into());
+        }
+        if settings.samples_per_pixel > MAX_SAMPLES {
+            return Err(format!("there can be at most {} samples per pixel", MAX_SAMPLES).into());
+        }
+
+        let mut scene: Scene = scene.parse()?;
+        check_scene(&mut scene)?;
+        scene.apply(&mut settings)?;
+
+        let total = match settings.mode {
+            RenderMode::Shaded => settings.samples_per_pixel,
+            // the debug modes only ever take one sample
+            _ => 1,
+        };
+        let job = Arc::new(Job {
+            format,
+            cancel: CancellationToken::new(),
+            progress: Mutex::new(Progress {
+                samples: 0,
+                total,
+                result: None,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.remember(id, Arc::clone(&job))?;
+        log::info!(
+            "Started render {} at {}x{}.",
+            id,
+            settings.width,
+            settings.height
+        );
+
+        // building the world can take a while, so it happens on the job's thread, which holds
+        // its place among the running renders until it's done or has failed
+        let projection = self.args.projection;
+        let options = self.args.save_options.clone();
+        thread::spawn(move || {
+            let result = build(&scene, projection, &settings)
+                .map_err(|err| err.to_string())
+                .and_then(|(world, camera)| render_job(&job, &world, &camera, &settings, &options));
+            match &result {
+                Ok(_) => log::info!("Finished render {}.", id),
+                Err(_) if job.cancel.is_cancelled() => log::info!("Cancelled render {}.", id),
+                Err(err) => log::error!("Render {} failed: {}", id, err),
+            }
+            job.update(|progress| progress.result = Some(result));
+        });
+
+        Ok(id)
+    }
+
+    /// Fails with [`Busy`] if too many renders are already running.
+    fn remember(&self, id: u64, job: Arc<Job>) -> Result<(), Busy> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let running = jobs
+            .values()
+            .filter(|job| job.progress.lock().unwrap().result.is_none())
+            .count();
+        if running >= MAX_RUNNING {
+            return Err(Busy);
+        }
+        jobs.insert(id, job);
+
+        while jobs.len() > MAX_JOBS {
+            let finished = jobs
+                .iter()
+                .find(|(_, job)| job.progress.lock().unwrap().result.is_some())
+                .map(|(&id, _)| id);
+            match finished {
+                Some(id) => jobs.remove(&id),
+                // everything is still rendering, so nothing can go yet
+                None => break,
+            };
+        }
+
+        Ok(())
+    }
+
+    fn job(&self, id: &str) -> Option<Arc<Job>> {
+        let id = id.parse().ok()?;
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    fn forget(&self, id: &str) -> Option<Arc<Job>> {
+        let id = id.parse().ok()?;
+        self.jobs.lock().unwrap().remove(&id)
+    }
+}
+
+/// Fails if a scene sent by someone else could read files that aren't meant to be served, or
+/// would take too much to build.
+pub(crate) fn check_scene(scene: &mut Scene) -> Result<(), Box<dyn Error>> {
+    scene.check_paths()?;
+    if scene.instance_count() > MAX_INSTANCES {
+        return Err(format!("there can be at most {} instances", MAX_INSTANCES).into());
+    }
+    if scene.max_subdivisions() > MAX_SUBDIVISIONS {
+        return Err(format!(
+            "meshes can be subdivided at most {} times",
+            MAX_SUBDIVISIONS
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The world and camera of a scene checked by [`check_scene`].
+fn build(
+    scene: &Scene,
+    projection: Projection,
+    settings: &RenderSettings,
+) -> ray_tracing::Result<(Bvh, Camera)> {
+    let world = Bvh::new(scene.world()?);
+    let camera = scene_camera_builder(scene, projection, &world)?
+        .aspect_ratio(settings.width as f64 / settings.height as f64)
+        .build()?;
+    Ok((world, camera))
+}
+
+/// Renders in passes, updating the progress after each one, and returns the encoded image.
+/// Gives up as soon as the job is cancelled.
+fn render_job(
+    job: &Job,
+    world: &Bvh,
+    camera: &Camera,
+    settings: &RenderSettings,
+    options: &SaveOptions,
+) -> Result<Arc<Vec<u8>>, String> {
+    let total = job.progress.lock().unwrap().total;
+    let mut film = Film::new(settings.width, settings.height);
+    while film.samples() < total {
+        let samples = (total - film.samples()).min(PASS_SAMPLES);
+        let pass_settings = RenderSettings {
+            samples_per_pixel: samples,
+            // a different seed for every pass, or they'd all take the same samples
+            seed: settings
+                .seed
+                .map(|seed| seed.wrapping_add(film.samples() as u64)),
+            ..settings.clone()
+        };
+
+        let pixels = render::render_cancellable(world, camera, &pass_settings, &job.cancel)
+            .ok_or_else(|| ray_tracing::Error::Cancelled.to_string())?;
+        film.add_pass(&pixels, samples);
+        job.update(|progress| progress.samples = film.samples());
+    }
+
+    let pixels = film.pixels();
+    output::encode(
+        job.format,
+        settings.width,
+        settings.height,
+        &pixels,
+        options,
+    )
+    .map(Arc::new)
+    .map_err(|err| err.to_string())
+}
+
+/// Sends an event for every finished pass, until the render is over.
+fn stream_progress(request: Request, job: &Job) -> Result<(), Box<dyn Error>> {
+    // tiny_http buffers response bodies, so the events are written straight to the connection,
+    // one chunk each
+    let mut out = request.into_writer();
+    write!(
+        out,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\n\
+         Transfer-Encoding: chunked\r\n\
+         Connection: close\r\n\r\n"
+    )?;
+    let mut send = |event: &str, data: serde_json::Value| -> std::io::Result<()> {
+        let event = format!("event: {}\ndata: {}\n\n", event, data);
+        write!(out, "{:x}\r\n{}\r\n", event.len(), event)?;
+        out.flush()
+    };
+
+    let mut last = None;
+    loop {
+        // a copy, so that a slow client doesn't hold up the render
+        let progress = {
+            let mut progress = job.progress.lock().unwrap();
+            while last == Some(progress.samples) && progress.result.is_none() {
+                progress = job.changed.wait(progress).unwrap();
+            }
+            progress.clone()
+        };
+
+        if last != Some(progress.samples) {
+            last = Some(progress.samples);
+            send("progress", progress_json(&progress))?;
+        }
+        match &progress.result {
+            Some(Ok(_)) => {
+                send("done", progress_json(&progress))?;
+                break;
+            }
+            Some(Err(err)) => {
+                send("error", json!({ "error": err }))?;
+                break;
+            }
+            None => {}
+        }
+    }
+
+    write!(out, "0\r\n\r\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn progress_json(progress: &Progress) -> serde_json::Value {
+    json!({ "samples": progress.samples, "total": progress.total })
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+fn json_response(status: u16, value: serde_json::Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(value.to_string())
+        .with_status_code(StatusCode(status))
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenes_too_big_to_build_are_turned_away() {
+        let scene = |object: &str| {
+            let mut scene: Scene = format!(
+                r#"{{ "objects": [], "graph": [{{ "name": "a", "children": [
+                    {{ "name": "b", "objects": [{}] }}
+                ] }}] }}"#,
+                object
+            )
+            .parse()
+            .unwrap();
+            check_scene(&mut scene)
+        };
+        let grid = |count: usize| {
+            format!(
+                r#"{{ "type": "instancer", "prototype": [], "distribution": {{
+                    "type": "grid", "min": [0, 0, 0], "max": [1, 1, 1], "counts": [{0}, {0}, {0}]
+                }} }}"#,
+                count
+            )
+        };
+        let mesh = |subdivisions: u32| {
+            format!(
+                r#"{{ "type": "mesh", "path": "mesh.obj", "subdivisions": {} }}"#,
+                subdivisions
+            )
+        };
+
+        assert!(scene(&grid(100)).is_ok());
+        assert!(scene(&grid(1 << 30)).is_err());
+        assert!(scene(&mesh(MAX_SUBDIVISIONS)).is_ok());
+        assert!(scene(&mesh(MAX_SUBDIVISIONS + 1)).is_err());
+    }
+}
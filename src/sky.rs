@@ -0,0 +1,578 @@
+//! What rays see when they leave the scene: a sky, and maybe a sun lighting the scene.
+
+use crate::collision::volumes::blackbody;
+use crate::{Color, Error, Point3, Result, Vec3};
+
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+
+pub trait Background: Send + Sync {
+    /// The light coming from `direction`, a unit vector, leaving out the sun.
+    fn color(&self, direction: Vec3) -> Color;
+
+    /// The sun, which the renderer samples directly rather than waiting for rays to hit it.
+    fn sun(&self) -> Option<&Sun> {
+        None
+    }
+}
+
+/// A blend from white at the horizon to blue straight up, the sky of the book.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gradient {
+    /// The color below and at the horizon.
+    pub horizon: Color,
+    pub zenith: Color,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self {
+            horizon: Color::new(1.0, 1.0, 1.0),
+            zenith: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, direction: Vec3) -> Color {
+        let t = 0.5 * (direction.y() + 1.0);
+
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+/// A disk of light in the sky, far enough away for all of its light to arrive in parallel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sun {
+    /// Toward the sun, as a unit vector.
+    pub direction: Vec3,
+    /// The cosine of the angle between the center and the edge of the disk.
+    pub cos_radius: f64,
+    /// The light of the sun on a surface facing it, over the whole disk.
+    pub irradiance: Color,
+}
+
+impl Sun {
+    /// A sun `angular_diameter` degrees across, failing unless that's between 0 and 90.
+    pub fn new(direction: Vec3, angular_diameter: f64, irradiance: Color) -> Result<Self> {
+        let angular_diameter = Error::check("sun angular diameter", angular_diameter, |d| {
+            d > 0.0 && d < 90.0
+        })?;
+        if direction.near_zero() || direction.0.iter().any(|c| !c.is_finite()) {
+            return Err(Error::InvalidParameter {
+                name: "sun direction",
+                value: direction.length(),
+            });
+        }
+
+        Ok(Self {
+            direction: direction.normalize(),
+            cos_radius: (0.5 * angular_diameter.to_radians()).cos(),
+            irradiance,
+        })
+    }
+
+    /// The solid angle of the disk.
+    pub fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_radius)
+    }
+
+    /// The light coming from any direction within the disk.
+    pub fn radiance(&self) -> Color {
+        self.irradiance / self.solid_angle()
+    }
+
+    /// Whether the unit vector `direction` points at the disk.
+    pub fn contains(&self, direction: Vec3) -> bool {
+        direction.dot(&self.direction) >= self.cos_radius
+    }
+
+    /// A uniformly random direction toward the disk.
+    pub fn sample(&self, rng: &mut dyn RngCore) -> Vec3 {
+        sample_cone(self.direction, self.cos_radius, rng)
+    }
+}
+
+/// A uniformly random direction within `cos_radius` of the unit vector `axis`.
+pub(crate) fn sample_cone(axis: Vec3, cos_radius: f64, rng: &mut dyn RngCore) -> Vec3 {
+    let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - cos_radius);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f64>();
+
+    // any two axes perpendicular to the axis will do
+    let w = axis;
+    let helper = if w.x().abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let u = w.cross(&helper).normalize();
+    let v = w.cross(&u);
+
+    sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+}
+
+/// An opening, like a window, that the sky lights the inside of a room through. Paths bouncing
+/// around a room only rarely escape through a small window by chance, so the sky seen through
+/// portals is sampled directly instead, the way the sun is. A portal doesn't block any light
+/// itself: it's only a hint, which has to cover the opening to light all of the room.
+///
+/// It's the parallelogram spanned by `u` and `v` from `corner`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Portal {
+    corner: Point3,
+    u: Vec3,
+    v: Vec3,
+    /// `u × v`, as long as the area of the portal.
+    normal: Vec3,
+}
+
+impl Portal {
+    /// Fails unless `u` and `v` span an area, and every coordinate is finite.
+    pub fn new(corner: Point3, u: Vec3, v: Vec3) -> Result<Self> {
+        for &coordinate in corner.0.iter().chain(&u.0).chain(&v.0) {
+            Error::check("portal coordinate", coordinate, f64::is_finite)?;
+        }
+        let normal = u.cross(&v);
+        Error::check("portal area", normal.length(), |area| area > 1e-12)?;
+
+        Ok(Self {
+            corner,
+            u,
+            v,
+            normal,
+        })
+    }
+
+    /// A random direction from `origin` through the portal, to a point uniformly distributed
+    /// over it, and the density of that direction over solid angle. `None` if `origin` is in
+    /// the plane of the portal, where it can't be seen.
+    pub fn sample(&self, origin: Point3, rng: &mut dyn RngCore) -> Option<(Vec3, f64)> {
+        let point = self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        let to_point = point - origin;
+        let distance_squared = to_point.length_squared();
+        let direction = to_point / distance_squared.sqrt();
+        let cos_area = direction.dot(&self.normal).abs();
+        if cos_area < 1e-12 {
+            return None;
+        }
+
+        Some((direction, distance_squared / cos_area))
+    }
+
+    /// The density of [`Portal::sample`] picking the unit vector `direction` from `origin`, or 0
+    /// if it doesn't go through the portal.
+    pub fn pdf(&self, origin: Point3, direction: Vec3) -> f64 {
+        let cos_area = direction.dot(&self.normal);
+        if cos_area.abs() < 1e-12 {
+            return 0.0;
+        }
+        let t = (self.corner - origin).dot(&self.normal) / cos_area;
+        if t <= 0.0 {
+            return 0.0;
+        }
+
+        // the coordinates of the crossing along u and v
+        let offset = origin + t * direction - self.corner;
+        let w = self.normal / self.normal.length_squared();
+        let a = w.dot(&offset.cross(&self.v));
+        let b = w.dot(&self.u.cross(&offset));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return 0.0;
+        }
+
+        t * t / cos_area.abs()
+    }
+}
+
+/// The clear sky model of Preetham, Shirley and Smits, "A Practical Analytic Model for
+/// Daylight", with the sun at the top of the sky.
+///
+/// Brightness is scaled so that the sky is about as bright as the default gradient with the sun
+/// high up. Below the horizon the sky keeps the colors of the horizon.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Preetham {
+    sun: Option<Sun>,
+    /// Toward the sun, even when it's below the horizon and gives no light.
+    sun_direction: Vec3,
+    /// The Perez coefficients of luminance and the x and y chromaticities.
+    coefficients: [[f64; 5]; 3],
+    /// Luminance and chromaticities at the zenith, divided by the Perez function there.
+    zenith: [f64; 3],
+    intensity: f64,
+}
+
+/// Turns the model's kilocandelas per square meter into the renderer's units.
+const SKY_SCALE: f64 = 1.0 / 30.0;
+/// The light of the sun on a surface facing it, above the atmosphere.
+const SUN_IRRADIANCE: f64 = 5.0;
+
+impl Preetham {
+    /// `turbidity` is how hazy the air is, from 2 for a clear day to 10 for a hazy one. The sun is
+    /// `sun_angular_diameter` degrees across, 0.53 for the real one, and bigger suns give softer
+    /// shadows. `intensity` scales both the sky and the sun.
+    pub fn new(
+        sun_direction: Vec3,
+        turbidity: f64,
+        sun_angular_diameter: f64,
+        intensity: f64,
+    ) -> Result<Self> {
+        let t = Error::check("turbidity", turbidity, |t| (1.7..=10.0).contains(&t))?;
+        let intensity = Error::check("sky intensity", intensity, |i| i >= 0.0)?;
+        // checks the direction and diameter, even if the sun ends up below the horizon
+        let sun = Sun::new(sun_direction, sun_angular_diameter, Color::default())?;
+        let sun_direction = sun.direction;
+
+        // the model only holds up to the sun setting
+        let theta_s = sun_direction
+            .y()
+            .clamp(0.0, 1.0)
+            .acos()
+            .min(0.5 * PI - 1e-3);
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |c: [[f64; 4]; 3]| {
+            let polynomial =
+                |c: [f64; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+            t * t * polynomial(c[0]) + t * polynomial(c[1]) + polynomial(c[2])
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let mut sky = Self {
+            sun: None,
+            sun_direction,
+            coefficients,
+            zenith: [luminance, x, y],
+            intensity,
+        };
+        for (i, zenith) in sky.zenith.iter_mut().enumerate() {
+            *zenith /= perez(coefficients[i], 1.0, theta_s.cos());
+        }
+
+        if sun_direction.y() > 0.0 {
+            let irradiance = SUN_IRRADIANCE * intensity * sun_transmittance(theta_s, t);
+            sky.sun = Some(Sun::new(sun_direction, sun_angular_diameter, irradiance)?);
+        }
+
+        Ok(sky)
+    }
+}
+
+impl Background for Preetham {
+    fn color(&self, direction: Vec3) -> Color {
+        // the sky below the horizon mirrors the horizon
+        let cos_theta = direction.y().max(0.01);
+        let cos_gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0);
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * perez(self.coefficients[i], cos_theta, cos_gamma));
+
+        // xyY to XYZ to linear sRGB
+        let luminance = luminance.max(0.0) * SKY_SCALE * self.intensity;
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        Color::new(
+            (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+            (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+            (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+        )
+    }
+
+    fn sun(&self) -> Option<&Sun> {
+        self.sun.as_ref()
+    }
+}
+
+/// The Perez sky distribution, for the angle `theta` from the zenith and `gamma` from the sun.
+fn perez([a, b, c, d, e]: [f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let gamma = cos_gamma.acos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// The sunlight that makes it through the air, by Rayleigh scattering off of molecules and Mie
+/// scattering off of haze, in the colors of a sun at 5778 K.
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+    // the relative amount of air the light crosses, longer toward the horizon
+    let degrees = theta_s.to_degrees();
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let mut color = blackbody(5778.0);
+    // the wavelengths of red, green and blue, in micrometers
+    for (channel, &micrometers) in [0.65, 0.55, 0.45_f64].iter().enumerate() {
+        let rayleigh = 0.008735 * micrometers.powf(-4.08);
+        let mie = beta * micrometers.powf(-1.3);
+        color[channel] *= (-(rayleigh + mie) * air_mass).exp();
+    }
+
+    color
+}
+
+/// A place on Earth and a local time there, which say where the sun is in the sky. Outdoor
+/// lighting studies pick a site and step through the day with [`Daylight::after`].
+///
+/// The sun is placed with the low precision formulas of the Astronomical Almanac, which are
+/// within about a hundredth of a degree for the years 1950 to 2050, and refraction near the
+/// horizon is left out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Daylight {
+    /// In degrees, north of the equator.
+    pub latitude: f64,
+    /// In degrees, east of Greenwich.
+    pub longitude: f64,
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    /// The local clock time, in hours since midnight. It can run past 24 into the next days.
+    pub hour: f64,
+    /// How many hours the local time zone is ahead of UTC, including any daylight saving.
+    pub utc_offset: f64,
+}
+
+impl Daylight {
+    /// The same place, `hours` later.
+    pub fn after(self, hours: f64) -> Self {
+        Self {
+            hour: self.hour + hours,
+            ..self
+        }
+    }
+
+    /// The unit vector toward the sun, which is below the horizon at night, with y up and
+    /// `north` pointing north. Fails if the latitude or date are out of range, or `north` points
+    /// straight up or down.
+    pub fn sun_direction(&self, north: Vec3) -> Result<Vec3> {
+        let latitude = Error::check("latitude", self.latitude, |l| l.abs() <= 90.0)?;
+        let longitude = Error::check("longitude", self.longitude, |l| l.abs() <= 180.0)?;
+        Error::check("month", self.month as f64, |m| (1.0..=12.0).contains(&m))?;
+        Error::check("day", self.day as f64, |d| (1.0..=31.0).contains(&d))?;
+        let hour = Error::check("hour", self.hour, |_| true)?;
+        let utc_offset = Error::check("UTC offset", self.utc_offset, |o| o.abs() <= 14.0)?;
+
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let north = north - north.dot(&up) * up;
+        Error::check("north length", north.length(), |length| length > 1e-9)?;
+        let north = north.normalize();
+        let east = north.cross(&up);
+
+        // days since noon UTC on the 1st of January 2000
+        let n = days_from_civil(self.year, self.month, self.day) as f64 - 10957.5
+            + (hour - utc_offset) / 24.0;
+
+        // where the sun is along the ecliptic, and the tilt of the ecliptic
+        let mean_longitude = 280.460 + 0.985_647_4 * n;
+        let anomaly = (357.528 + 0.985_600_3 * n).to_radians();
+        let ecliptic_longitude =
+            (mean_longitude + 1.915 * anomaly.sin() + 0.020 * (2.0 * anomaly).sin()).to_radians();
+        let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+        let right_ascension =
+            (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+        let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+        // how far the sun has moved west of the meridian
+        let sidereal = (280.460_618_37 + 360.985_647_366_29 * n + longitude).to_radians();
+        let hour_angle = sidereal - right_ascension;
+
+        let latitude = latitude.to_radians();
+        let altitude = (latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0)
+        .asin();
+        // from north toward east
+        let azimuth = (-hour_angle.sin())
+            .atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+
+        let horizontal = azimuth.cos() * north + azimuth.sin() * east;
+        Ok(altitude.cos() * horizontal + altitude.sin() * up)
+    }
+}
+
+/// The number of days from the 1st of January 1970 to the date, on the Gregorian calendar.
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    // years starting in March, so that leap days come last
+    let year = year as i64 - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sun_samples_stay_on_the_disk() {
+        let sun = Sun::new(Vec3::new(1.0, 1.0, 0.0), 5.0, Color::new(1.0, 1.0, 1.0)).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let direction = sun.sample(&mut rng);
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!(sun.contains(direction));
+        }
+        assert!(!sun.contains(Vec3::new(0.0, 1.0, 0.0)));
+        assert!(Sun::new(Vec3::default(), 5.0, Color::default()).is_err());
+    }
+
+    #[test]
+    fn portal_samples_go_through_it() {
+        let window = Portal::new(
+            Point3::new(-1.0, 1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // the solid angle of the portal, as the average of one over the density
+        let mut solid_angle = 0.0;
+        for _ in 0..1000 {
+            let (direction, pdf) = window.sample(origin, &mut rng).unwrap();
+            assert!((window.pdf(origin, direction) - pdf).abs() < 1e-9 * pdf);
+            solid_angle += 1.0 / pdf / 1000.0;
+        }
+        // the solid angle of a rectangle 2 away, from the point facing its corner to (x, y)
+        let rectangle = |x: f64, y: f64| (x * y / ((4.0 + x * x) * (4.0 + y * y)).sqrt()).asin();
+        let expected = 2.0 * (rectangle(1.0, 2.0) - rectangle(1.0, 1.0));
+        assert!(
+            (solid_angle - expected).abs() < 0.05 * expected,
+            "{}",
+            solid_angle
+        );
+
+        assert_eq!(window.pdf(origin, Vec3::new(0.0, 0.0, -1.0)), 0.0);
+        assert_eq!(
+            window.pdf(origin, Vec3::new(0.0, -0.5, 1.0).normalize()),
+            0.0
+        );
+        assert!(Portal::new(origin, Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn clear_skies_are_blue_and_brightest_near_the_sun() {
+        let sky = Preetham::new(Vec3::new(0.0, 1.0, 1.0), 2.5, 0.53, 1.0).unwrap();
+        let up = sky.color(Vec3::new(0.0, 1.0, 0.0));
+        assert!(up.z() > up.x(), "{}", up);
+
+        let toward_sun = sky.color(Vec3::new(0.0, 0.5, 1.0).normalize());
+        let away = sky.color(Vec3::new(0.0, 0.5, -1.0).normalize());
+        assert!(toward_sun.y() > away.y());
+
+        let sun = sky.sun().unwrap();
+        assert!(sun.irradiance.x() > sun.irradiance.z());
+    }
+
+    #[test]
+    fn days_are_counted_from_1970() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 1, 1), 10957);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn the_sun_follows_the_clock() {
+        let greenwich = Daylight {
+            latitude: 51.4769,
+            longitude: 0.0,
+            year: 2024,
+            month: 6,
+            day: 20,
+            hour: 12.0,
+            utc_offset: 0.0,
+        };
+        let north = Vec3::new(0.0, 0.0, -1.0);
+        let altitude = |daylight: Daylight| {
+            let sun = daylight.sun_direction(north).unwrap();
+            assert!((sun.length() - 1.0).abs() < 1e-9);
+            sun.y().asin().to_degrees()
+        };
+
+        // near the solstice the sun is about as high as it gets, due south
+        let noon = greenwich.sun_direction(north).unwrap();
+        assert!(
+            (altitude(greenwich) - 61.9).abs() < 0.3,
+            "{}",
+            altitude(greenwich)
+        );
+        assert!(noon.z() > 0.0 && noon.x().abs() < 0.02, "{}", noon);
+
+        // rising in the northeast and setting in the northwest, and gone at midnight
+        let morning = greenwich.after(-8.0).sun_direction(north).unwrap();
+        assert!(morning.x() > 0.0 && morning.z() < 0.0, "{}", morning);
+        let evening = greenwich.after(8.0).sun_direction(north).unwrap();
+        assert!(evening.x() < 0.0 && evening.z() < 0.0, "{}", evening);
+        assert!(altitude(greenwich.after(12.0)) < -10.0);
+
+        // the same moment, told in another time zone
+        let summer_time = Daylight {
+            hour: 13.0,
+            utc_offset: 1.0,
+            ..greenwich
+        };
+        assert!((summer_time.sun_direction(north).unwrap() - noon).length() < 1e-9);
+
+        // on the equator at an equinox, the sun passes overhead
+        let equator = Daylight {
+            latitude: 0.0,
+            month: 3,
+            ..greenwich
+        };
+        assert!(altitude(equator) > 85.0);
+
+        assert!(greenwich.sun_direction(Vec3::new(0.0, 1.0, 0.0)).is_err());
+        assert!(Daylight {
+            month: 13,
+            ..greenwich
+        }
+        .sun_direction(north)
+        .is_err());
+    }
+
+    #[test]
+    fn suns_below_the_horizon_give_no_light() {
+        let night = Preetham::new(Vec3::new(0.0, -1.0, 1.0), 3.0, 0.53, 1.0).unwrap();
+        assert!(night.sun().is_none());
+        assert!(Preetham::new(Vec3::new(0.0, 1.0, 0.0), 30.0, 0.53, 1.0).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! The sRGB transfer function, which 8 and 16-bit images are stored with.
+//!
+//! Light is added up and multiplied linearly, but the eye is far more sensitive to differences
+//! between dark shades than bright ones, so images spend more of their levels on the dark end.
+//! Everything the renderer works with is linear: textures are decoded when they're loaded, and
+//! only the saved images and the preview window are encoded.
+
+use crate::Color;
+
+/// Encodes a linear value in [0, 1] to sRGB. The curve is a straight line near black and a
+/// power of 1/2.4 above it, which together are close to, but not quite, a gamma of 2.2.
+pub fn encode(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decodes an sRGB value in [0, 1] to linear, undoing [`encode`].
+pub fn decode(encoded: f64) -> f64 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// [`encode`] on every channel, clamping them into [0, 1] first.
+pub fn encode_color(color: Color) -> Color {
+    let encode = |c: f64| encode(c.clamp(0.0, 1.0));
+    Color::new(encode(color.x()), encode(color.y()), encode(color.z()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding_undoes_encoding() {
+        for i in 0..=100 {
+            let linear = i as f64 / 100.0;
+            assert!((decode(encode(linear)) - linear).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn curve_is_continuous() {
+        let knee = 0.003_130_8;
+        assert!((encode(knee) - encode(knee + 1e-12)).abs() < 1e-6);
+        assert!((decode(0.040_45) - decode(0.040_45 + 1e-12)).abs() < 1e-6);
+        // middle gray, which a gamma of 2 would put at 0.707
+        assert!((encode(0.5) - 0.7354).abs() < 1e-4);
+    }
+}
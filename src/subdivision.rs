@@ -0,0 +1,402 @@
+//! Catmull-Clark subdivision, which smooths a coarse mesh of polygons into a curved surface.
+//!
+//! Every step splits each polygon into quads around its center, moving the old vertices toward
+//! the average of their neighbours. Repeating it forever would give the limit surface, which
+//! [`PolygonMesh::triangles`] places the final vertices and their normals on.
+
+use crate::collision::objects::Triangle;
+use crate::collision::Material;
+use crate::{Point3, Result, Vec3};
+
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+/// Polygons sharing their vertices.
+#[derive(Clone)]
+pub struct PolygonMesh {
+    pub positions: Vec<Point3>,
+    pub polygons: Vec<Polygon>,
+}
+
+#[derive(Clone)]
+pub struct Polygon {
+    /// Indices into the positions, going counterclockwise around the front of the polygon.
+    pub corners: Vec<usize>,
+    /// Texture coordinates at the corners, which are interpolated linearly when subdividing.
+    pub uvs: Option<Vec<(f64, f64)>>,
+    /// Kept by the polygons subdivided from this one.
+    pub material: Arc<dyn Material>,
+}
+
+/// An edge between two vertices and the polygons on either side of it, only one on the
+/// boundary of the mesh.
+struct Edge {
+    ends: (usize, usize),
+    polygons: Vec<usize>,
+}
+
+impl PolygonMesh {
+    /// One step of Catmull-Clark subdivision. Edges with a single polygon are kept sharp, as a
+    /// boundary curve.
+    pub fn subdivide(&self) -> Self {
+        let (edges, edge_indices) = self.edges();
+        let vertex_count = self.positions.len();
+
+        let centers: Vec<Point3> = self
+            .polygons
+            .iter()
+            .map(|polygon| self.average(&polygon.corners))
+            .collect();
+
+        let edge_points = edges.iter().map(|edge| {
+            let (a, b) = edge.ends;
+            let middle = 0.5 * (self.positions[a] + self.positions[b]);
+            match edge.polygons[..] {
+                [left, right] => 0.5 * middle + 0.25 * (centers[left] + centers[right]),
+                _ => middle,
+            }
+        });
+
+        // the polygons and edges around each vertex
+        let mut vertex_polygons = vec![Vec::new(); vertex_count];
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            for &corner in &polygon.corners {
+                vertex_polygons[corner].push(index);
+            }
+        }
+        let mut vertex_edges = vec![Vec::new(); vertex_count];
+        for (index, edge) in edges.iter().enumerate() {
+            vertex_edges[edge.ends.0].push(index);
+            vertex_edges[edge.ends.1].push(index);
+        }
+
+        let vertex_points = (0..vertex_count).map(|vertex| {
+            let position = self.positions[vertex];
+            let other = |edge: usize| {
+                let (a, b) = edges[edge].ends;
+                self.positions[if a == vertex { b } else { a }]
+            };
+            let boundary: Vec<usize> = vertex_edges[vertex]
+                .iter()
+                .copied()
+                .filter(|&edge| edges[edge].polygons.len() == 1)
+                .collect();
+
+            match boundary[..] {
+                [] if !vertex_polygons[vertex].is_empty() => {
+                    let n = vertex_polygons[vertex].len() as f64;
+                    let center = vertex_polygons[vertex]
+                        .iter()
+                        .map(|&polygon| centers[polygon])
+                        .fold(Vec3::default(), |sum, center| sum + center)
+                        / n;
+                    let middle = vertex_edges[vertex]
+                        .iter()
+                        .map(|&edge| 0.5 * (position + other(edge)))
+                        .fold(Vec3::default(), |sum, middle| sum + middle)
+                        / vertex_edges[vertex].len() as f64;
+
+                    (center + 2.0 * middle + (n - 3.0) * position) / n
+                }
+                // along the boundary curve, with the corners of a single polygon kept sharp
+                [a, b] if vertex_polygons[vertex].len() > 1 => {
+                    0.75 * position + 0.125 * (other(a) + other(b))
+                }
+                // as do unconnected vertices and ones where the boundary meets itself
+                _ => position,
+            }
+        });
+
+        let mut positions = Vec::with_capacity(vertex_count + edges.len() + centers.len());
+        positions.extend(vertex_points);
+        positions.extend(edge_points);
+        positions.extend(centers.iter().copied());
+
+        let mut polygons = Vec::new();
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            let corners = &polygon.corners;
+            let k = corners.len();
+            let edge_point =
+                |i: usize| vertex_count + edge_indices[&key(corners[i % k], corners[(i + 1) % k])];
+            let center = vertex_count + edges.len() + index;
+
+            for i in 0..k {
+                let previous = (i + k - 1) % k;
+                let uvs = polygon.uvs.as_ref().map(|uvs| {
+                    let middle =
+                        |a: (f64, f64), b: (f64, f64)| (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1));
+                    let n = k as f64;
+                    let center = uvs
+                        .iter()
+                        .fold((0.0, 0.0), |sum, uv| (sum.0 + uv.0 / n, sum.1 + uv.1 / n));
+                    vec![
+                        uvs[i],
+                        middle(uvs[i], uvs[(i + 1) % k]),
+                        center,
+                        middle(uvs[previous], uvs[i]),
+                    ]
+                });
+
+                polygons.push(Polygon {
+                    corners: vec![corners[i], edge_point(i), center, edge_point(previous)],
+                    uvs,
+                    material: polygon.material.clone(),
+                });
+            }
+        }
+
+        Self {
+            positions,
+            polygons,
+        }
+    }
+
+    /// Splits the polygons into triangles, with the vertices moved onto the limit surface and
+    /// given its normals there. Vertices on the boundary or next to polygons that aren't quads,
+    /// which are only left before the first subdivision, get the average normal of their
+    /// polygons instead.
+    pub fn triangles(&self) -> Result<Vec<Triangle>> {
+        let mut rings: Vec<Vec<(usize, usize)>> = vec![Vec::new(); self.positions.len()];
+        let mut face_normals = vec![Vec3::default(); self.positions.len()];
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            let corners = &polygon.corners;
+            let normal = polygon_normal(corners.iter().map(|&corner| self.positions[corner]));
+            for (position, &corner) in corners.iter().enumerate() {
+                rings[corner].push((index, position));
+                face_normals[corner] += normal;
+            }
+        }
+
+        let limits: Vec<(Point3, Vec3)> = (0..self.positions.len())
+            .map(|vertex| {
+                let normal = face_normals[vertex];
+                self.limit(vertex, &rings[vertex], normal)
+                    .unwrap_or((self.positions[vertex], normal.normalize()))
+            })
+            .collect();
+
+        let mut triangles = Vec::new();
+        for polygon in &self.polygons {
+            let corners = &polygon.corners;
+            for i in 1..corners.len().saturating_sub(1) {
+                let fan = [0, i, i + 1];
+                let mut triangle =
+                    Triangle::new(fan.map(|j| limits[corners[j]].0), polygon.material.clone())?
+                        .with_normals(fan.map(|j| limits[corners[j]].1));
+                if let Some(uvs) = &polygon.uvs {
+                    triangle = triangle.with_uvs(fan.map(|j| uvs[j]));
+                }
+                triangles.push(triangle);
+            }
+        }
+
+        Ok(triangles)
+    }
+
+    /// The point of the limit surface a vertex ends up at and the normal there, for vertices
+    /// inside of a mesh of quads. `ring` holds the polygons around it, with the vertex's place in
+    /// each, and `facing` is roughly the way the normal should face.
+    ///
+    /// These are the formulas of Halstead, Kass and DeRose, "Efficient, Fair Interpolation using
+    /// Catmull-Clark Surfaces", for the neighbours in order around the vertex.
+    fn limit(
+        &self,
+        vertex: usize,
+        ring: &[(usize, usize)],
+        facing: Vec3,
+    ) -> Option<(Point3, Vec3)> {
+        if ring.len() < 3 {
+            return None;
+        }
+
+        // each quad goes from the vertex to `next`, across to `diagonal` and back from `previous`
+        let mut quads = HashMap::new();
+        for &(polygon, position) in ring {
+            let corners = &self.polygons[polygon].corners;
+            if corners.len() != 4 {
+                return None;
+            }
+            let at = |offset: usize| corners[(position + offset) % 4];
+            quads.insert(at(3), (at(1), at(2)));
+        }
+
+        // walking around the vertex, from the edge to `previous` to the one to `next`
+        let n = ring.len();
+        let first = *quads.keys().next()?;
+        let mut edges = Vec::with_capacity(n);
+        let mut diagonals = Vec::with_capacity(n);
+        let mut previous = first;
+        for _ in 0..n {
+            let &(next, diagonal) = quads.get(&previous)?;
+            edges.push(self.positions[previous]);
+            diagonals.push(self.positions[diagonal]);
+            previous = next;
+        }
+        if previous != first {
+            return None;
+        }
+
+        let position = self.positions[vertex];
+        let sum = |points: &[Point3]| points.iter().fold(Vec3::default(), |sum, &p| sum + p);
+        let nf = n as f64;
+        let limit = (nf * nf * position + 4.0 * sum(&edges) + sum(&diagonals)) / (nf * (nf + 5.0));
+
+        let a = 1.0
+            + (TAU / nf).cos()
+            + (TAU / (2.0 * nf)).cos() * (2.0 * (9.0 + (TAU / nf).cos())).sqrt();
+        let mut tangent_u = Vec3::default();
+        let mut tangent_v = Vec3::default();
+        for i in 0..n {
+            let (angle, following) = (TAU * i as f64 / nf, TAU * (i + 1) as f64 / nf);
+            tangent_u +=
+                a * angle.cos() * edges[i] + (angle.cos() + following.cos()) * diagonals[i];
+            tangent_v +=
+                a * angle.sin() * edges[i] + (angle.sin() + following.sin()) * diagonals[i];
+        }
+
+        let normal = tangent_u.cross(&tangent_v).normalize();
+        if normal.0.iter().any(|c| !c.is_finite()) {
+            return None;
+        }
+        // the tangents only span the surface, the normal could be either side of it
+        let normal = if normal.dot(&facing) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        Some((limit, normal))
+    }
+
+    /// The edges of the mesh, and their indices by their ends.
+    fn edges(&self) -> (Vec<Edge>, HashMap<(usize, usize), usize>) {
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut indices = HashMap::new();
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            let corners = &polygon.corners;
+            for i in 0..corners.len() {
+                let ends = key(corners[i], corners[(i + 1) % corners.len()]);
+                let edge = *indices.entry(ends).or_insert_with(|| {
+                    edges.push(Edge {
+                        ends,
+                        polygons: Vec::new(),
+                    });
+                    edges.len() - 1
+                });
+                edges[edge].polygons.push(index);
+            }
+        }
+
+        (edges, indices)
+    }
+
+    fn average(&self, corners: &[usize]) -> Point3 {
+        corners
+            .iter()
+            .fold(Vec3::default(), |sum, &corner| sum + self.positions[corner])
+            / corners.len() as f64
+    }
+}
+
+/// Edges are the same either way around.
+fn key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// The normal of a polygon, as long as its area, facing the side its corners go counterclockwise
+/// around.
+fn polygon_normal(corners: impl Iterator<Item = Point3> + Clone) -> Vec3 {
+    let first = corners.clone().next().unwrap_or_default();
+    corners
+        .clone()
+        .zip(corners.skip(1))
+        .fold(Vec3::default(), |sum, (a, b)| {
+            sum + 0.5 * (a - first).cross(&(b - first))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::materials::Lambertian;
+    use crate::collision::{Hittable, Ray};
+    use crate::Color;
+
+    fn mesh(positions: Vec<Point3>, polygons: &[&[usize]]) -> PolygonMesh {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        PolygonMesh {
+            positions,
+            polygons: polygons
+                .iter()
+                .map(|corners| Polygon {
+                    corners: corners.to_vec(),
+                    uvs: None,
+                    material: material.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn cubes_round_off() {
+        let corners = (0..8)
+            .map(|i| {
+                let side = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Point3::new(side(1), side(2), side(4))
+            })
+            .collect();
+        let faces: &[&[usize]] = &[
+            &[0, 2, 3, 1],
+            &[4, 5, 7, 6],
+            &[0, 1, 5, 4],
+            &[2, 6, 7, 3],
+            &[0, 4, 6, 2],
+            &[1, 3, 7, 5],
+        ];
+        let cube = mesh(corners, faces).subdivide().subdivide();
+        assert_eq!(cube.polygons.len(), 6 * 4 * 4);
+        assert_eq!(cube.triangles().unwrap().len(), 2 * 6 * 4 * 4);
+
+        let mut rings = vec![Vec::new(); cube.positions.len()];
+        for (index, polygon) in cube.polygons.iter().enumerate() {
+            for (position, &corner) in polygon.corners.iter().enumerate() {
+                rings[corner].push((index, position));
+            }
+        }
+        for (vertex, ring) in rings.iter().enumerate() {
+            let outward = cube.positions[vertex];
+            let (point, normal) = cube.limit(vertex, ring, outward).unwrap();
+            assert!(point.0.iter().all(|c| c.abs() < 1.0), "{:?}", point);
+            assert!(point.length() > 0.5, "{:?}", point);
+            assert!(normal.dot(&point) > 0.0, "{:?} at {:?}", normal, point);
+        }
+    }
+
+    #[test]
+    fn flat_grids_stay_flat() {
+        let positions = (0..9)
+            .map(|i| Point3::new((i % 3) as f64, (i / 3) as f64, 0.0))
+            .collect();
+        let grid = mesh(
+            positions,
+            &[&[0, 1, 4, 3], &[1, 2, 5, 4], &[3, 4, 7, 6], &[4, 5, 8, 7]],
+        );
+        let triangles = grid.subdivide().subdivide().triangles().unwrap();
+
+        for (x, y) in [(0.3, 0.4), (1.0, 1.0), (1.7, 0.2), (1.9, 1.9)] {
+            let ray = Ray {
+                origin: Point3::new(x, y, 1.0),
+                direction: Vec3::new(0.0, 0.0, -1.0),
+            };
+            let hit = triangles
+                .iter()
+                .find_map(|triangle| triangle.hit(&ray, 0.0, f64::INFINITY))
+                .unwrap();
+            assert!(hit.point.z().abs() < 1e-9, "{:?}", hit.point);
+            assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,766 @@
+//! Colors that vary over the surface of an object.
+
+use crate::color_space::{ColorSpace, Conversion};
+use crate::noise::{Perlin, Worley};
+use crate::{Color, Error, Point3, Result, Vec3};
+
+use image::{DynamicImage, GenericImageView, ImageBuffer};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub trait Texture: Send + Sync {
+    /// The color at surface coordinates `u`, `v`, which is at `point` in space.
+    fn value(&self, u: f64, v: f64, point: &Point3) -> Color;
+
+    /// The average color over a patch `footprint` wide in surface coordinates around `u`, `v`,
+    /// which keeps fine detail from flickering in the distance. Only the color at the center by
+    /// default.
+    fn filtered(&self, u: f64, v: f64, point: &Point3, footprint: f64) -> Color {
+        let _ = footprint;
+        self.value(u, v, point)
+    }
+}
+
+/// The same color everywhere.
+pub struct SolidColor(pub Color);
+
+impl Texture for SolidColor {
+    fn value(&self, _: f64, _: f64, _: &Point3) -> Color {
+        self.0
+    }
+}
+
+type Rgb32FImage = ImageBuffer<image::Rgb<f32>, Vec<f32>>;
+
+/// An image wrapped around the object, repeating outside of [0, 1].
+///
+/// Filtered lookups blend between mipmaps, copies of the image halving in size down to a single
+/// pixel, picking the ones with pixels about as big as the footprint.
+pub struct ImageTexture {
+    /// The size of the image, followed by those of the mipmaps.
+    sizes: Vec<(u32, u32)>,
+    levels: Levels,
+}
+
+enum Levels {
+    /// The image itself in linear values, followed by the mipmaps.
+    Decoded(Vec<Rgb32FImage>),
+    /// Decoded when first looked up, see [`ImageTexture::lazy`].
+    Lazy {
+        /// What tells the levels of this image apart from those of the others in `cache`.
+        id: usize,
+        decode: Box<dyn Fn() -> Result<DynamicImage> + Send + Sync>,
+        color_space: ColorSpace,
+        cache: Arc<LevelCache>,
+        /// Set once decoding fails, which leaves the image black rather than trying again at
+        /// every lookup.
+        failed: AtomicBool,
+    },
+}
+
+impl ImageTexture {
+    /// Decodes `image` from `color_space` into the linear light the renderer works in.
+    pub fn new(image: DynamicImage, color_space: ColorSpace) -> Self {
+        let mut levels = vec![linear(&image, color_space)];
+        loop {
+            let last = &levels[levels.len() - 1];
+            let (width, height) = last.dimensions();
+            if width <= 1 && height <= 1 {
+                break;
+            }
+            levels.push(half(last));
+        }
+
+        Self {
+            sizes: levels.iter().map(|level| level.dimensions()).collect(),
+            levels: Levels::Decoded(levels),
+        }
+    }
+
+    /// An image `width` by `height` pixels that's only decoded by `decode` once it's first looked
+    /// up, with each mipmap made the first time it's needed. Until then the image takes no
+    /// memory, and afterwards `cache` keeps it and its mipmaps within its budget along with those
+    /// of the rest of the images in it, making the least recently used ones again if they're
+    /// needed after being dropped. If decoding fails, the failure is logged and the image stays
+    /// black.
+    pub fn lazy<F>(
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+        decode: F,
+        cache: &Arc<LevelCache>,
+    ) -> Self
+    where
+        F: Fn() -> Result<DynamicImage> + Send + Sync + 'static,
+    {
+        let mut sizes = vec![(width, height)];
+        while let Some(&(width, height)) = sizes.last().filter(|&&(w, h)| w > 1 || h > 1) {
+            sizes.push(((width / 2).max(1), (height / 2).max(1)));
+        }
+
+        Self {
+            sizes,
+            levels: Levels::Lazy {
+                id: cache.next_id.fetch_add(1, Ordering::Relaxed),
+                decode: Box::new(decode),
+                color_space,
+                cache: cache.clone(),
+                failed: AtomicBool::new(false),
+            },
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|source| Error::Texture {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        Ok(Self::new(image, color_space))
+    }
+
+    /// Like [`load`](Self::load), but only reading the size of the image from its file, and
+    /// decoding it once it's first looked up, see [`lazy`](Self::lazy).
+    #[cfg(feature = "fs")]
+    pub fn load_lazily(
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        cache: &Arc<LevelCache>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let error = |path: &Path| {
+            let path = path.to_owned();
+            move |source| Error::Texture { path, source }
+        };
+        let (width, height) = image::image_dimensions(&path).map_err(error(&path))?;
+
+        let decode = move || image::open(&path).map_err(error(&path));
+        Ok(Self::lazy(width, height, color_space, decode, cache))
+    }
+
+    /// The bytes the image and its mipmaps take up, once they're decoded.
+    pub fn memory(&self) -> usize {
+        let values: usize = self
+            .sizes
+            .iter()
+            .map(|&(w, h)| 3 * w as usize * h as usize)
+            .sum();
+        std::mem::size_of::<Self>() + values * std::mem::size_of::<f32>()
+    }
+
+    /// Whether the image is only decoded once it's looked up, see [`lazy`](Self::lazy).
+    pub fn is_lazy(&self) -> bool {
+        matches!(self.levels, Levels::Lazy { .. })
+    }
+}
+
+impl ImageTexture {
+    /// `f` of the image, or of one of its mipmaps, or `None` if it couldn't be decoded.
+    fn with_level<R>(&self, level: usize, f: impl FnOnce(&Rgb32FImage) -> R) -> Option<R> {
+        match &self.levels {
+            Levels::Decoded(levels) => Some(f(&levels[level])),
+            Levels::Lazy { .. } => self.lazy_level(level).map(|image| f(&image)),
+        }
+    }
+
+    /// A level of a lazy image, decoding or making it if it isn't in the cache.
+    fn lazy_level(&self, level: usize) -> Option<Arc<Rgb32FImage>> {
+        let Levels::Lazy {
+            id,
+            decode,
+            color_space,
+            cache,
+            failed,
+        } = &self.levels
+        else {
+            unreachable!("only lazy images have levels in the cache");
+        };
+        if failed.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        cache.get_or_make((*id, level), || match level {
+            0 => match decode() {
+                Ok(image) => Some(linear(&image, *color_space)),
+                Err(err) => {
+                    if !failed.swap(true, Ordering::Relaxed) {
+                        log::warn!("Couldn't decode a texture, leaving it black: {}", err);
+                    }
+                    None
+                }
+            },
+            _ => self.lazy_level(level - 1).map(|above| half(&above)),
+        })
+    }
+
+    /// The color between the four pixels of a mipmap nearest to `u`, `v`.
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Color {
+        self.with_level(level, |image| {
+            let (width, height) = image.dimensions();
+
+            // pixel centers are at half coordinates, and v goes up but rows go down
+            let x = u.rem_euclid(1.0) * width as f64 - 0.5;
+            let y = (1.0 - v.rem_euclid(1.0)) * height as f64 - 0.5;
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+
+            let texel = |dx: f64, dy: f64| {
+                let x = (x0 + dx).rem_euclid(width as f64) as u32;
+                let y = (y0 + dy).rem_euclid(height as f64) as u32;
+                to_color(image.get_pixel(x.min(width - 1), y.min(height - 1)))
+            };
+            let top = (1.0 - fx) * texel(0.0, 0.0) + fx * texel(1.0, 0.0);
+            let bottom = (1.0 - fx) * texel(0.0, 1.0) + fx * texel(1.0, 1.0);
+
+            (1.0 - fy) * top + fy * bottom
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _: &Point3) -> Color {
+        let (width, height) = self.sizes[0];
+        if width == 0 || height == 0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        self.with_level(0, |image| {
+            let (width, height) = image.dimensions();
+            // v goes up, but rows go down
+            let x = (u.rem_euclid(1.0) * width as f64) as u32;
+            let y = ((1.0 - v.rem_euclid(1.0)) * height as f64) as u32;
+
+            to_color(image.get_pixel(x.min(width - 1), y.min(height - 1)))
+        })
+        .unwrap_or_default()
+    }
+
+    fn filtered(&self, u: f64, v: f64, point: &Point3, footprint: f64) -> Color {
+        let (width, height) = self.sizes[0];
+        // the mipmap with pixels as big as the footprint
+        let level = (footprint * width.max(height) as f64).log2();
+        // magnified images keep their crisp pixels
+        if width == 0 || height == 0 || level.is_nan() || level <= 0.0 {
+            return self.value(u, v, point);
+        }
+
+        let level = level.min((self.sizes.len() - 1) as f64);
+        let lower = level.floor() as usize;
+        let fraction = level - lower as f64;
+        let color = self.bilinear(lower, u, v);
+        if fraction == 0.0 {
+            return color;
+        }
+
+        (1.0 - fraction) * color + fraction * self.bilinear(lower + 1, u, v)
+    }
+}
+
+/// `image` decoded from `color_space` into linear values.
+fn linear(image: &DynamicImage, color_space: ColorSpace) -> Rgb32FImage {
+    let color = image.color();
+    let values: Vec<f64> = if color.bytes_per_pixel() > color.channel_count() {
+        let values = image.to_rgb16().into_raw().into_iter();
+        values.map(|c| f64::from(c) / 65535.0).collect()
+    } else {
+        let values = image.to_rgb8().into_raw().into_iter();
+        values.map(|c| f64::from(c) / 255.0).collect()
+    };
+    let conversion = Conversion::to_working(color_space);
+    let linear = values
+        .chunks(3)
+        .flat_map(|rgb| {
+            let decode = |i: usize| color_space.decode(rgb[i]);
+            let color = conversion.apply(Color::new(decode(0), decode(1), decode(2)));
+            // colors outside of the working space's gamut are clipped to its edge
+            color.0.map(|c| c.max(0.0) as f32)
+        })
+        .collect();
+    let (width, height) = image.dimensions();
+    Rgb32FImage::from_raw(width, height, linear).expect("there's a value for every channel")
+}
+
+/// The mipmap below `image`, half its size.
+fn half(image: &Rgb32FImage) -> Rgb32FImage {
+    let (width, height) = image.dimensions();
+    Rgb32FImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        // averaging the 2x2 block below
+        let mut sum = Color::default();
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+            sum += to_color(pixel);
+        }
+        image::Rgb((sum / 4.0).0.map(|c| c as f32))
+    })
+}
+
+/// The decoded images and mipmaps of lazy [`ImageTexture`]s, kept to a budget of bytes by
+/// dropping the least recently used ones whenever another is added.
+pub struct LevelCache {
+    budget: usize,
+    /// The levels by image and level, with when they were last used.
+    levels: RwLock<CachedLevels>,
+    /// Goes up with every level added. Lookups only mark the levels they use with it, rather
+    /// than each moving it on, so that they don't all write to the same place.
+    clock: AtomicU64,
+    next_id: AtomicUsize,
+}
+
+impl LevelCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            levels: RwLock::default(),
+            clock: AtomicU64::new(0),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many bytes of levels the cache keeps at most. A single level bigger than that is
+    /// kept on its own.
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The bytes the levels in the cache take up.
+    pub fn memory(&self) -> usize {
+        let levels = self
+            .levels
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        levels.values().map(|(level, _)| level_memory(level)).sum()
+    }
+
+    /// The level under `key`, made by `make` if it isn't in the cache. Threads needing the same
+    /// level at once might each make it, and keep the first.
+    fn get_or_make<F>(&self, key: (usize, usize), make: F) -> Option<Arc<Rgb32FImage>>
+    where
+        F: FnOnce() -> Option<Rgb32FImage>,
+    {
+        let now = self.clock.load(Ordering::Relaxed);
+        {
+            let levels = self
+                .levels
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some((level, last_used)) = levels.get(&key) {
+                if last_used.load(Ordering::Relaxed) != now {
+                    last_used.store(now, Ordering::Relaxed);
+                }
+                return Some(level.clone());
+            }
+        }
+
+        let level = Arc::new(make()?);
+        let mut levels = self
+            .levels
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let (level, last_used) = levels
+            .entry(key)
+            .or_insert_with(|| (level, AtomicU64::default()));
+        last_used.store(now, Ordering::Relaxed);
+        let level = level.clone();
+
+        let mut memory: usize = levels.values().map(|(level, _)| level_memory(level)).sum();
+        while memory > self.budget {
+            let Some(oldest) = levels
+                .iter()
+                .filter(|&(&other, _)| other != key)
+                .min_by_key(|(_, (_, last_used))| last_used.load(Ordering::Relaxed))
+                .map(|(&oldest, _)| oldest)
+            else {
+                break;
+            };
+            let (dropped, _) = levels.remove(&oldest).expect("it was just found");
+            memory -= level_memory(&dropped);
+        }
+
+        Some(level)
+    }
+}
+
+type CachedLevels = HashMap<(usize, usize), (Arc<Rgb32FImage>, AtomicU64)>;
+
+fn level_memory(level: &Rgb32FImage) -> usize {
+    level.as_raw().len() * std::mem::size_of::<f32>()
+}
+
+fn to_color(pixel: &image::Rgb<f32>) -> Color {
+    let [r, g, b] = pixel.0;
+    Color::new(r.into(), g.into(), b.into())
+}
+
+/// How surface coordinates outside of [0, 1] are brought back into it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    /// Tiles the texture.
+    #[default]
+    Repeat,
+    /// Stretches the edges of the texture outward.
+    Clamp,
+    /// Tiles the texture, flipping every other tile so that neighbouring edges match.
+    Mirror,
+}
+
+impl Wrap {
+    fn apply(self, t: f64) -> f64 {
+        // just below 1, which repeating textures would otherwise read as 0
+        let last = 1.0 - f64::EPSILON;
+        match self {
+            Self::Repeat => t.rem_euclid(1.0),
+            Self::Clamp => t.clamp(0.0, last),
+            Self::Mirror => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t.min(last)
+                }
+            }
+        }
+    }
+}
+
+/// Scales, turns and moves the surface coordinates before looking them up in another texture,
+/// like tiling an image of a few bricks over a whole wall. Points in space are passed through
+/// unchanged.
+pub struct TextureTransform {
+    texture: Arc<dyn Texture>,
+    scale: [f64; 2],
+    offset: [f64; 2],
+    /// The sine and cosine of the rotation.
+    rotation: (f64, f64),
+    wrap: Wrap,
+}
+
+impl TextureTransform {
+    /// `scale` is how many times the texture repeats along u and v, and the scaled coordinates
+    /// are turned counterclockwise by `rotation` degrees before `offset` is added. Fails if the
+    /// scale is 0 or anything isn't finite.
+    pub fn new(
+        texture: Arc<dyn Texture>,
+        scale: [f64; 2],
+        offset: [f64; 2],
+        rotation: f64,
+        wrap: Wrap,
+    ) -> Result<Self> {
+        for &s in &scale {
+            Error::check("texture scale", s, |s| s.is_finite() && s != 0.0)?;
+        }
+        for &o in &offset {
+            Error::check("texture offset", o, f64::is_finite)?;
+        }
+        let rotation = Error::check("texture rotation", rotation, f64::is_finite)?;
+
+        Ok(Self {
+            texture,
+            scale,
+            offset,
+            rotation: rotation.to_radians().sin_cos(),
+            wrap,
+        })
+    }
+
+    /// The coordinates in the inner texture of `u`, `v`.
+    pub fn transform(&self, u: f64, v: f64) -> (f64, f64) {
+        let (sin, cos) = self.rotation;
+        let (u, v) = (u * self.scale[0], v * self.scale[1]);
+        let (u, v) = (cos * u - sin * v, sin * u + cos * v);
+
+        (
+            self.wrap.apply(u + self.offset[0]),
+            self.wrap.apply(v + self.offset[1]),
+        )
+    }
+}
+
+impl Texture for TextureTransform {
+    fn value(&self, u: f64, v: f64, point: &Point3) -> Color {
+        let (u, v) = self.transform(u, v);
+        self.texture.value(u, v, point)
+    }
+
+    fn filtered(&self, u: f64, v: f64, point: &Point3, footprint: f64) -> Color {
+        let stretch = self.scale[0].abs().max(self.scale[1].abs());
+        let (u, v) = self.transform(u, v);
+        self.texture.filtered(u, v, point, footprint * stretch)
+    }
+}
+
+/// A pattern in space between 0 and 1, for [`PatternTexture`] to color.
+pub trait Pattern: Send + Sync {
+    fn value(&self, point: Point3) -> f64;
+}
+
+/// Smooth blobs, changing over distances of about 1.
+impl Pattern for Perlin {
+    fn value(&self, point: Point3) -> f64 {
+        // the noise hardly goes past 0.6 either way, so this stretches it over most of [0, 1]
+        (0.5 + 0.75 * self.noise(point)).clamp(0.0, 1.0)
+    }
+}
+
+/// Which distance of [`Worley`] noise a [`Cells`] pattern shows.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellFeature {
+    /// The distance to the nearest feature point, dark spots in light cells.
+    #[default]
+    Distance,
+    /// How much nearer the nearest feature point is than the second nearest, dark cracks
+    /// between light cells.
+    Edges,
+}
+
+/// A cellular pattern, for cracked mud, scales or the look of caustics.
+pub struct Cells {
+    pub worley: Worley,
+    pub feature: CellFeature,
+}
+
+impl Pattern for Cells {
+    fn value(&self, point: Point3) -> f64 {
+        let (f1, f2) = self.worley.distances(point);
+        match self.feature {
+            CellFeature::Distance => f1.min(1.0),
+            CellFeature::Edges => (f2 - f1).min(1.0),
+        }
+    }
+}
+
+/// Fractal Brownian motion: `octaves` layers of a pattern, each at `lacunarity` times the
+/// frequency of the last and `gain` times its weight, for the fine detail of clouds and rock.
+pub struct Fbm {
+    pattern: Arc<dyn Pattern>,
+    octaves: u32,
+    lacunarity: f64,
+    gain: f64,
+}
+
+impl Fbm {
+    /// Fails unless there are 1 to 16 octaves, the lacunarity is above 1 and the gain is above 0
+    /// and at most 1.
+    pub fn new(
+        pattern: Arc<dyn Pattern>,
+        octaves: u32,
+        lacunarity: f64,
+        gain: f64,
+    ) -> Result<Self> {
+        Error::check("octaves", octaves as f64, |o| (1.0..=16.0).contains(&o))?;
+        let lacunarity = Error::check("lacunarity", lacunarity, |l| l > 1.0 && l.is_finite())?;
+        let gain = Error::check("gain", gain, |g| g > 0.0 && g <= 1.0)?;
+
+        Ok(Self {
+            pattern,
+            octaves,
+            lacunarity,
+            gain,
+        })
+    }
+}
+
+impl Pattern for Fbm {
+    fn value(&self, point: Point3) -> f64 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut weight = 1.0;
+        for octave in 0..self.octaves {
+            // shifting each octave keeps their lattices from lining up at the origin
+            let shift = octave as f64 * Vec3::new(19.1, 7.3, 13.7);
+            sum += weight * self.pattern.value(frequency * point + shift);
+            total += weight;
+            frequency *= self.lacunarity;
+            weight *= self.gain;
+        }
+
+        sum / total
+    }
+}
+
+/// Colors a pattern, blending from the first color where it's 0 to the second where it's 1.
+pub struct PatternTexture {
+    pattern: Arc<dyn Pattern>,
+    frequency: f64,
+    colors: [Color; 2],
+}
+
+impl PatternTexture {
+    /// `frequency` scales the pattern, fitting that many of its features in a unit of distance.
+    /// Fails unless it's positive.
+    pub fn new(pattern: Arc<dyn Pattern>, frequency: f64, colors: [Color; 2]) -> Result<Self> {
+        let frequency = Error::check("frequency", frequency, |f| f > 0.0 && f.is_finite())?;
+
+        Ok(Self {
+            pattern,
+            frequency,
+            colors,
+        })
+    }
+}
+
+impl Texture for PatternTexture {
+    fn value(&self, _: f64, _: f64, point: &Point3) -> Color {
+        let t = self.pattern.value(self.frequency * *point).clamp(0.0, 1.0);
+        (1.0 - t) * self.colors[0] + t * self.colors[1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(scale: [f64; 2], offset: [f64; 2], rotation: f64, wrap: Wrap) -> TextureTransform {
+        let inner = Arc::new(SolidColor(Color::default()));
+        TextureTransform::new(inner, scale, offset, rotation, wrap).unwrap()
+    }
+
+    fn assert_near((u, v): (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (u - expected.0).abs() < 1e-9 && (v - expected.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            (u, v),
+            expected
+        );
+    }
+
+    #[test]
+    fn tiles_and_turns_coordinates() {
+        let tiled = transform([4.0, 2.0], [0.1, 0.0], 0.0, Wrap::Repeat);
+        assert_near(tiled.transform(0.3, 0.8), (0.3, 0.6));
+
+        let turned = transform([1.0, 1.0], [0.0, 0.0], 90.0, Wrap::Repeat);
+        assert_near(turned.transform(0.25, 0.0), (0.0, 0.25));
+
+        assert!(TextureTransform::new(
+            Arc::new(SolidColor(Color::default())),
+            [0.0, 1.0],
+            [0.0, 0.0],
+            0.0,
+            Wrap::Repeat
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wraps_the_edges() {
+        let clamped = transform([3.0, 3.0], [0.0, 0.0], 0.0, Wrap::Clamp);
+        let (u, v) = clamped.transform(0.5, -0.5);
+        assert!(u < 1.0 && u > 0.999);
+        assert_eq!(v, 0.0);
+
+        let mirrored = transform([3.0, 3.0], [0.0, 0.0], 0.0, Wrap::Mirror);
+        assert_near(mirrored.transform(0.4, -0.1), (0.8, 0.3));
+    }
+
+    #[test]
+    fn distant_images_blur_into_their_average() {
+        let checkers = image::RgbImage::from_fn(64, 32, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let texture = ImageTexture::new(DynamicImage::ImageRgb8(checkers), ColorSpace::Srgb);
+        assert_eq!(texture.sizes.len(), 7);
+        assert_eq!(texture.sizes[6], (1, 1));
+
+        let point = Point3::default();
+        let sharp = texture.filtered(0.3, 0.6, &point, 0.0);
+        assert!(sharp.x() == 0.0 || sharp.x() == 1.0);
+        for footprint in [0.1, 1.0, 100.0] {
+            let blurred = texture.filtered(0.3, 0.6, &point, footprint);
+            assert!((blurred.x() - 0.5).abs() < 0.01, "{}", blurred);
+        }
+    }
+
+    #[test]
+    fn images_are_decoded_unless_linear() {
+        let gray =
+            || DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([128; 3])));
+        let point = Point3::default();
+
+        let srgb = ImageTexture::new(gray(), ColorSpace::Srgb).value(0.5, 0.5, &point);
+        assert!((srgb.x() - 0.2159).abs() < 1e-4, "{}", srgb);
+        let linear = ImageTexture::new(gray(), ColorSpace::Linear).value(0.5, 0.5, &point);
+        assert!((linear.x() - 128.0 / 255.0).abs() < 1e-6, "{}", linear);
+    }
+
+    #[test]
+    fn lazy_images_are_decoded_when_looked_up() {
+        let gradient = || {
+            let image =
+                image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([x as u8 * 4, y as u8, 0]));
+            DynamicImage::ImageRgb8(image)
+        };
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let decode = {
+            let decoded = decoded.clone();
+            move || {
+                decoded.fetch_add(1, Ordering::Relaxed);
+                Ok(gradient())
+            }
+        };
+        // room for the image, but not for its mipmaps as well
+        let cache = Arc::new(LevelCache::new(64 * 64 * 12));
+        let lazy = ImageTexture::lazy(64, 64, ColorSpace::Srgb, decode, &cache);
+        let eager = ImageTexture::new(gradient(), ColorSpace::Srgb);
+        assert_eq!(lazy.sizes, eager.sizes);
+        assert_eq!(decoded.load(Ordering::Relaxed), 0);
+        assert_eq!(cache.memory(), 0);
+
+        let point = Point3::default();
+        for (u, v, footprint) in [(0.3, 0.6, 0.0), (0.3, 0.6, 0.1), (0.7, 0.2, 0.0)] {
+            let expected = eager.filtered(u, v, &point, footprint);
+            assert_eq!(lazy.filtered(u, v, &point, footprint), expected);
+        }
+        // the mipmaps were made from the image, which was dropped to make room for them, and
+        // decoded again
+        assert_eq!(decoded.load(Ordering::Relaxed), 2);
+        assert!(cache.memory() <= cache.budget());
+
+        let failing = ImageTexture::lazy(
+            4,
+            4,
+            ColorSpace::Srgb,
+            || Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+            &cache,
+        );
+        assert_eq!(failing.value(0.5, 0.5, &point), Color::default());
+    }
+
+    #[test]
+    fn patterns_stay_between_0_and_1() {
+        let perlin: Arc<dyn Pattern> = Arc::new(Perlin::new(1));
+        let edges: Arc<dyn Pattern> = Arc::new(Cells {
+            worley: Worley::new(1),
+            feature: CellFeature::Edges,
+        });
+        let patterns = [
+            Arc::new(Fbm::new(perlin.clone(), 6, 2.0, 0.5).unwrap()) as Arc<dyn Pattern>,
+            Arc::new(Fbm::new(edges.clone(), 3, 2.5, 1.0).unwrap()),
+            perlin,
+            edges,
+        ];
+
+        for pattern in &patterns {
+            for i in 0..500 {
+                let point = Point3::new(i as f64 * 0.31, i as f64 * -0.17, i as f64 * 0.07);
+                assert!((0.0..=1.0).contains(&pattern.value(point)));
+            }
+        }
+        assert!(Fbm::new(patterns[0].clone(), 0, 2.0, 0.5).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{Point3, Vec3};
+
+use nalgebra::{Matrix3, Rotation3, Vector3};
+
+/// An affine transformation: scaling, then rotation, then translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    linear: Matrix3<f64>,
+    inverse: Matrix3<f64>,
+    translation: Vec3,
+}
+
+impl Transform {
+    /// `rotation` holds the angles in degrees around the x, y and z axes, applied in that order.
+    pub fn new(translation: Vec3, rotation: Vec3, scale: Vec3) -> Self {
+        let [x, y, z] = rotation.0;
+        let rotation = Rotation3::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians());
+        let linear = rotation.matrix() * Matrix3::from_diagonal(&scale.into());
+
+        Self::from_linear(linear, translation)
+    }
+
+    pub fn identity() -> Self {
+        Self::from_linear(Matrix3::identity(), Vec3::default())
+    }
+
+    pub fn translation(translation: Vec3) -> Self {
+        Self::from_linear(Matrix3::identity(), translation)
+    }
+
+    /// Panics if `linear` isn't invertible.
+    pub fn from_linear(linear: Matrix3<f64>, translation: Vec3) -> Self {
+        let inverse = linear
+            .try_inverse()
+            .expect("transformations must be invertible");
+
+        Self {
+            linear,
+            inverse,
+            translation,
+        }
+    }
+
+    /// Applies `other` first, then `self`.
+    pub fn after(&self, other: &Self) -> Self {
+        Self::from_linear(
+            self.linear * other.linear,
+            self.transform_point(other.translation),
+        )
+    }
+
+    pub fn inverse(&self) -> Self {
+        let translation: Vector3<f64> = -(self.inverse * Vector3::from(self.translation));
+
+        Self {
+            linear: self.inverse,
+            inverse: self.linear,
+            translation: translation.into(),
+        }
+    }
+
+    pub fn transform_point(&self, point: Point3) -> Point3 {
+        self.transform_vector(point) + self.translation
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        (self.linear * Vector3::from(vector)).into()
+    }
+
+    /// Normals need the inverse transpose to stay perpendicular to the surface.
+    pub fn transform_normal(&self, normal: Vec3) -> Vec3 {
+        (self.inverse.transpose() * Vector3::from(normal)).into()
+    }
+
+    /// How much the transformation scales volumes, negative if it mirrors them.
+    pub fn determinant(&self) -> f64 {
+        self.linear.determinant()
+    }
+
+    pub fn inverse_transform_point(&self, point: Point3) -> Point3 {
+        self.inverse_transform_vector(point - self.translation)
+    }
+
+    pub fn inverse_transform_vector(&self, vector: Vec3) -> Vec3 {
+        (self.inverse * Vector3::from(vector)).into()
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
@@ -0,0 +1,562 @@
+//! Checks of a scene before it's rendered, for the mistakes that don't stop it from building but
+//! do spoil the image or waste the render: objects too thin to be seen, placements that went
+//! wrong, materials and prototypes nothing uses, and lights stacked on top of each other, which
+//! flicker where they overlap and add up to more light than meant.
+//!
+//! Everything is checked on the descriptions, without building the scene, except for meshes,
+//! whose files are read for their triangles.
+
+use crate::collision::bvh::Aabb;
+use crate::scene::{
+    object_name, BsdfDescription, DistributionDescription, MaterialDescription, ObjectDescription,
+    Scene,
+};
+use crate::scene_graph::GraphNode;
+use crate::transform::Transform;
+use crate::{Point3, Vec3};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// What [`Scene::validate`] found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validation {
+    /// How many objects of each type there are, by the type scene files give, along with the
+    /// triangles of meshes as `triangle`. The objects of a prototype are counted once, however
+    /// many copies of it there are.
+    pub counts: BTreeMap<&'static str, usize>,
+    pub issues: Vec<Issue>,
+}
+
+/// Something that is likely a mistake. Objects are named like the IDs of
+/// [`Scene::labeled_world`] do, with the objects they're part of in front, like
+/// `lod 2/level 0/sphere 1`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// An object that has no area or volume, and so can't be seen.
+    Degenerate {
+        object: String,
+        problem: String,
+    },
+    /// A placement or graph node whose transformation isn't finite, or flattens what it places.
+    InvalidTransform {
+        object: String,
+    },
+    /// A file an object needs couldn't be read.
+    Unreadable {
+        object: String,
+        error: String,
+    },
+    UnusedMaterial(String),
+    UnusedPrototype(String),
+    /// Two lights whose bounding boxes overlap by more than an edge.
+    OverlappingEmitters(String, String),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Degenerate { object, problem } => write!(f, "{} {}", object, problem),
+            Self::InvalidTransform { object } => {
+                write!(f, "{} has an invalid transformation", object)
+            }
+            Self::Unreadable { object, error } => write!(f, "{}: {}", object, error),
+            Self::UnusedMaterial(name) => write!(f, "the material {:?} isn't used", name),
+            Self::UnusedPrototype(name) => write!(f, "the prototype {:?} isn't used", name),
+            Self::OverlappingEmitters(a, b) => write!(f, "the lights {} and {} overlap", a, b),
+        }
+    }
+}
+
+impl Validation {
+    /// Whether no issues were found.
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for Validation {
+    /// The counts, like `mesh 2, sphere 12`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut counts = self.counts.iter().peekable();
+        if counts.peek().is_none() {
+            return write!(f, "no objects");
+        }
+        while let Some((kind, count)) = counts.next() {
+            write!(f, "{} {}", kind, count)?;
+            if counts.peek().is_some() {
+                write!(f, ", ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Scene {
+    /// Counts the objects of the scene and looks for likely mistakes in it. Nothing here stops
+    /// the scene from building, which fails by itself on anything invalid.
+    pub fn validate(&self) -> Validation {
+        let mut checker = Checker {
+            scene: self,
+            validation: Validation::default(),
+            materials: BTreeSet::new(),
+            prototypes: BTreeSet::new(),
+            emitters: Vec::new(),
+        };
+
+        checker.check_list(&self.objects, "", Some(Transform::identity()));
+        match self.graph.flatten() {
+            Ok(placed) => {
+                for placed in placed {
+                    let prefix = format!("{}/", placed.node.name);
+                    checker.check_list(&placed.node.objects, &prefix, Some(placed.transform));
+                }
+            }
+            Err(_) => {
+                // flattening only fails on duplicate names, or a node's own transformation
+                for node in all_nodes(&self.graph.roots) {
+                    if node.transform().is_err() {
+                        checker.issue(Issue::InvalidTransform {
+                            object: format!("node {}", node.name),
+                        });
+                    }
+                }
+            }
+        }
+        let mut names: Vec<&String> = self.prototypes.keys().collect();
+        names.sort();
+        for name in names {
+            let prefix = format!("prototype {}/", name);
+            checker.check_list(&self.prototypes[name], &prefix, None);
+        }
+
+        for name in self.materials.keys() {
+            if !checker.materials.contains(name.as_str()) {
+                checker.issue(Issue::UnusedMaterial(name.clone()));
+            }
+        }
+        for name in self.prototypes.keys() {
+            if !checker.prototypes.contains(name.as_str()) {
+                checker.issue(Issue::UnusedPrototype(name.clone()));
+            }
+        }
+        for (i, (a, a_box)) in checker.emitters.iter().enumerate() {
+            for (b, b_box) in &checker.emitters[i + 1..] {
+                if overlap(a_box, b_box) {
+                    let issue = Issue::OverlappingEmitters(a.clone(), b.clone());
+                    checker.validation.issues.push(issue);
+                }
+            }
+        }
+
+        let mut validation = checker.validation;
+        // in a stable order, whatever order the maps were in
+        validation.issues.sort_by_key(|issue| issue.to_string());
+        validation
+    }
+}
+
+/// Every node of the graph, parents before their children.
+fn all_nodes(roots: &[GraphNode]) -> Vec<&GraphNode> {
+    let mut nodes = Vec::new();
+    let mut stack: Vec<_> = roots.iter().rev().collect();
+    while let Some(node) = stack.pop() {
+        nodes.push(node);
+        stack.extend(node.children.iter().rev());
+    }
+    nodes
+}
+
+struct Checker<'a> {
+    scene: &'a Scene,
+    validation: Validation,
+    materials: BTreeSet<&'a str>,
+    prototypes: BTreeSet<&'a str>,
+    /// The lights placed in the world, with their bounds.
+    emitters: Vec<(String, Aabb)>,
+}
+
+impl<'a> Checker<'a> {
+    fn issue(&mut self, issue: Issue) {
+        self.validation.issues.push(issue);
+    }
+
+    fn count(&mut self, kind: &'static str, count: usize) {
+        *self.validation.counts.entry(kind).or_insert(0) += count;
+    }
+
+    fn degenerate(&mut self, object: &str, problem: impl Into<String>) {
+        self.issue(Issue::Degenerate {
+            object: object.to_owned(),
+            problem: problem.into(),
+        });
+    }
+
+    /// Checks `objects`, naming them after `prefix`. Lights are looked at for overlaps only if
+    /// `transform` places them in the world once.
+    fn check_list(
+        &mut self,
+        objects: &'a [ObjectDescription],
+        prefix: &str,
+        transform: Option<Transform>,
+    ) {
+        for (index, object) in objects.iter().enumerate() {
+            let name = format!("{}{}", prefix, object_name(index, object));
+            self.check(object, &name, transform);
+        }
+    }
+
+    fn check(&mut self, object: &'a ObjectDescription, name: &str, transform: Option<Transform>) {
+        self.count(object.kind(), 1);
+        let mut bounds = None;
+        let mut material = None;
+
+        match object {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material: name_of,
+                ..
+            } => {
+                if !positive(radius.abs()) {
+                    self.degenerate(name, format!("has a radius of {}", radius));
+                }
+                let reach = Vec3::new(*radius, *radius, *radius);
+                bounds = Some(Aabb::new(*center - reach, *center + reach));
+                material = Some(name_of);
+            }
+            ObjectDescription::Parallelogram {
+                corner,
+                u,
+                v,
+                w,
+                material: name_of,
+                ..
+            } => {
+                if !positive(u.cross(v).length()) {
+                    self.degenerate(name, "has no area");
+                }
+                bounds = Some(bounds_of(
+                    (0..8).map(|i| corner_of(*corner, [*u, *v, *w], i)),
+                ));
+                material = Some(name_of);
+            }
+            ObjectDescription::BilinearPatch {
+                corners,
+                material: name_of,
+                ..
+            } => {
+                let [a, b, c, d] = *corners;
+                if !positive((c - a).cross(&(d - b)).length()) {
+                    self.degenerate(name, "has no area");
+                }
+                bounds = Some(bounds_of(corners.iter().copied()));
+                material = Some(name_of);
+            }
+            ObjectDescription::BezierPatch {
+                material: name_of, ..
+            } => material = Some(name_of),
+            ObjectDescription::Curves {
+                curves,
+                radius,
+                material: name_of,
+            } => {
+                self.count("curve", curves.len());
+                if !positive(radius[0]) && !positive(radius[1]) {
+                    self.degenerate(name, "have no thickness");
+                }
+                material = Some(name_of);
+            }
+            ObjectDescription::Metaballs {
+                balls,
+                material: name_of,
+                ..
+            } => {
+                for (index, ball) in balls.iter().enumerate() {
+                    if !positive(ball.radius) {
+                        let problem =
+                            format!("has a ball {} with a radius of {}", index, ball.radius);
+                        self.degenerate(name, problem);
+                    }
+                }
+                material = Some(name_of);
+            }
+            ObjectDescription::Instancer {
+                prototype,
+                distribution,
+                ..
+            } => {
+                self.check_list(prototype, &format!("{}/", name), None);
+                if let DistributionDescription::Surface { surface, .. } = distribution {
+                    self.check_list(surface, &format!("{}/surface/", name), None);
+                }
+            }
+            ObjectDescription::Instances {
+                prototype,
+                placements,
+            } => {
+                self.prototypes.insert(prototype);
+                for (index, placement) in placements.iter().enumerate() {
+                    if placement.transform().is_err() {
+                        self.issue(Issue::InvalidTransform {
+                            object: format!("{}/placement {}", name, index),
+                        });
+                    }
+                }
+            }
+            ObjectDescription::Lod { levels, .. } => {
+                for (index, level) in levels.iter().enumerate() {
+                    self.check_list(level, &format!("{}/level {}/", name, index), None);
+                }
+            }
+            ObjectDescription::Mesh {
+                path,
+                material: name_of,
+                ..
+            } => {
+                #[cfg(feature = "fs")]
+                match crate::obj::stats(path) {
+                    Ok(stats) => {
+                        self.count("triangle", stats.triangles);
+                        if stats.degenerate > 0 {
+                            let problem = format!(
+                                "has {} of its {} triangles without any area",
+                                stats.degenerate, stats.triangles
+                            );
+                            self.degenerate(name, problem);
+                        }
+                    }
+                    Err(err) => self.issue(Issue::Unreadable {
+                        object: name.to_owned(),
+                        error: err.to_string(),
+                    }),
+                }
+                #[cfg(not(feature = "fs"))]
+                let _ = path;
+                material = name_of.as_ref();
+            }
+            ObjectDescription::Voxels {
+                voxel_size,
+                materials,
+                ..
+            } => {
+                if !positive(*voxel_size) {
+                    self.degenerate(name, format!("has voxels {} across", voxel_size));
+                }
+                self.materials
+                    .extend(materials.values().map(String::as_str));
+            }
+            ObjectDescription::Vdb {
+                scale,
+                material: name_of,
+                ..
+            } => {
+                if !positive(*scale) {
+                    self.degenerate(name, format!("is scaled by {}", scale));
+                }
+                material = Some(name_of);
+            }
+            ObjectDescription::Cloud {
+                min,
+                max,
+                material: name_of,
+                ..
+            } => {
+                if (0..3).any(|axis| !positive(max[axis] - min[axis])) {
+                    self.degenerate(name, "has no volume");
+                }
+                material = Some(name_of);
+            }
+        }
+
+        let Some(material) = material else {
+            return;
+        };
+        self.materials.insert(material);
+        let emits = self.scene.materials.get(material).is_some_and(emits);
+        if let (true, Some(bounds), Some(transform)) = (emits, bounds, transform) {
+            let corners = (0..8).map(|i| {
+                let extent = bounds.max - bounds.min;
+                let axes = [
+                    Vec3::new(extent.x(), 0.0, 0.0),
+                    Vec3::new(0.0, extent.y(), 0.0),
+                    Vec3::new(0.0, 0.0, extent.z()),
+                ];
+                transform.transform_point(corner_of(bounds.min, axes, i))
+            });
+            self.emitters.push((name.to_owned(), bounds_of(corners)));
+        }
+    }
+}
+
+/// Whether `value` is over 0, and not NaN.
+fn positive(value: f64) -> bool {
+    value > 0.0
+}
+
+/// Whether the material gives off light.
+fn emits(material: &MaterialDescription) -> bool {
+    fn bsdf_emits(bsdf: &BsdfDescription) -> bool {
+        match bsdf {
+            BsdfDescription::Emission { .. } => true,
+            BsdfDescription::Mix { a, b, .. } => bsdf_emits(a) || bsdf_emits(b),
+            _ => false,
+        }
+    }
+
+    match material {
+        MaterialDescription::DiffuseLight { .. } => true,
+        MaterialDescription::Graph { bsdf } => bsdf_emits(bsdf),
+        MaterialDescription::ShadowCatcher { surface } => emits(surface),
+        _ => false,
+    }
+}
+
+/// The corner `i` of the box spanned by `axes` from `origin`, each bit of `i` picking an axis.
+fn corner_of(origin: Point3, axes: [Vec3; 3], i: usize) -> Point3 {
+    (0..3)
+        .filter(|axis| i & (1 << axis) != 0)
+        .fold(origin, |point, axis| point + axes[axis])
+}
+
+fn bounds_of(points: impl Iterator<Item = Point3>) -> Aabb {
+    points
+        .map(|point| Aabb::new(point, point))
+        .reduce(|a, b| a.union(&b))
+        .expect("at least one point")
+}
+
+/// Whether the boxes overlap over some area, so that flat lights lying on top of each other do,
+/// but ones only sharing an edge don't.
+fn overlap(a: &Aabb, b: &Aabb) -> bool {
+    let mut extents = 0;
+    for axis in 0..3 {
+        let extent = a.max[axis].min(b.max[axis]) - a.min[axis].max(b.min[axis]);
+        if extent < 0.0 {
+            return false;
+        }
+        if extent > 0.0 {
+            extents += 1;
+        }
+    }
+    extents >= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::PlacementDescription;
+    use crate::Color;
+
+    fn scene(json: &str) -> Scene {
+        json.parse().unwrap()
+    }
+
+    #[test]
+    fn counts_objects_and_finds_mistakes() {
+        let scene = scene(
+            r#"{
+                "materials": {
+                    "gray": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] },
+                    "spare": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }
+                },
+                "objects": [
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gray" },
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 0, "material": "gray" },
+                    {
+                        "type": "parallelogram", "corner": [0, 0, 0], "u": [1, 0, 0],
+                        "v": [2, 0, 0], "w": [0, 0, 0], "material": "gray"
+                    },
+                    {
+                        "type": "lod", "switch": { "distance": [10] },
+                        "levels": [
+                            [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gray" }],
+                            [{ "type": "sphere", "center": [0, 0, 0], "radius": -0.0, "material": "gray" }]
+                        ]
+                    }
+                ],
+                "prototypes": { "tree": [] }
+            }"#,
+        );
+
+        let validation = scene.validate();
+        assert_eq!(validation.counts["sphere"], 4);
+        assert_eq!(validation.counts["lod"], 1);
+        assert_eq!(validation.to_string(), "lod 1, parallelogram 1, sphere 4");
+
+        let issues: Vec<String> = validation.issues.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            issues,
+            [
+                "lod 3/level 1/sphere 0 has a radius of -0",
+                "parallelogram 2 has no area",
+                "sphere 1 has a radius of 0",
+                "the material \"spare\" isn't used",
+                "the prototype \"tree\" isn't used",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_stacked_lights_and_bad_placements() {
+        let mut scene = scene(
+            r#"{
+                "materials": {
+                    "light": { "type": "diffuse_light", "emit": [4, 4, 4] }
+                },
+                "objects": [
+                    {
+                        "type": "parallelogram", "corner": [0, 2, 0], "u": [1, 0, 0],
+                        "v": [0, 0, 1], "w": [0, 0, 0], "material": "light"
+                    },
+                    {
+                        "type": "parallelogram", "corner": [0.5, 2, 0.5], "u": [1, 0, 0],
+                        "v": [0, 0, 1], "w": [0, 0, 0], "material": "light"
+                    },
+                    {
+                        "type": "parallelogram", "corner": [1.5, 2, 0], "u": [1, 0, 0],
+                        "v": [0, 0, 1], "w": [0, 0, 0], "material": "light"
+                    },
+                    { "type": "instances", "prototype": "lamp", "placements": [{}] }
+                ],
+                "prototypes": {
+                    "lamp": [
+                        { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "light" }
+                    ]
+                }
+            }"#,
+        );
+        let flattened = PlacementDescription {
+            translation: Vec3::default(),
+            rotation: Vec3::default(),
+            scale: Vec3::new(1.0, 0.0, 1.0),
+        };
+        match &mut scene.objects[3] {
+            ObjectDescription::Instances { placements, .. } => placements.push(flattened),
+            _ => unreachable!(),
+        }
+
+        let issues = scene.validate().issues;
+        // the third light only shares an edge with the second, and the copies of the lamp are
+        // placed in the world too many times to compare
+        assert_eq!(
+            issues,
+            [
+                Issue::InvalidTransform {
+                    object: "instances 3/placement 1".to_owned()
+                },
+                Issue::OverlappingEmitters(
+                    "parallelogram 0".to_owned(),
+                    "parallelogram 1".to_owned()
+                ),
+            ]
+        );
+        assert!(emits(&MaterialDescription::DiffuseLight {
+            emit: Color::new(1.0, 1.0, 1.0),
+            double_sided: true,
+            illuminant: None,
+            ies: None,
+            texture: None,
+        }));
+    }
+}
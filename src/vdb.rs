@@ -0,0 +1,557 @@
+//! Density grids from NanoVDB files, for smoke and clouds made in other tools.
+//!
+//! NanoVDB is the flat version of OpenVDB, which can be read without rebuilding its tree, so
+//! OpenVDB's `.vdb` files have to be converted first, like with
+//! `nanovdb_convert smoke.vdb smoke.nvdb`. Float grids are supported, either uncompressed or
+//! compressed with zlib, and are sampled with trilinear interpolation.
+
+use crate::collision::bvh::Aabb;
+use crate::collision::volumes::DensityField;
+use crate::transform::Transform;
+use crate::{Error, Point3, Result, Vec3};
+
+use nalgebra::Matrix3;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_METADATA_SIZE: usize = 176;
+const GRID_DATA_SIZE: usize = 672;
+const GRID_TYPE_FLOAT: u32 = 1;
+
+const CODEC_NONE: u16 = 0;
+const CODEC_ZIP: u16 = 1;
+
+/// Where the values of a leaf start, after its header.
+const LEAF_VALUES: usize = 96;
+const LEAF_SIZE: usize = LEAF_VALUES + 4 * 512;
+
+/// The two levels of internal nodes between the root and the leaves.
+struct InternalNode {
+    /// Each node has 2^(3 * log2_dim) children or tiles.
+    log2_dim: u32,
+    /// How many voxels wide the children are, as a power of 2.
+    child_log2_dim: u32,
+    /// Where the table of children and tiles starts, after the header.
+    table: usize,
+}
+
+const UPPER: InternalNode = InternalNode {
+    log2_dim: 5,
+    child_log2_dim: 7,
+    table: 8256,
+};
+const LOWER: InternalNode = InternalNode {
+    log2_dim: 4,
+    child_log2_dim: 3,
+    table: 1088,
+};
+
+impl InternalNode {
+    fn entries(&self) -> usize {
+        1 << (3 * self.log2_dim)
+    }
+
+    fn child_mask(&self) -> usize {
+        // after the bounding box, the flags and the value mask
+        32 + self.entries() / 8
+    }
+
+    fn size(&self) -> usize {
+        self.table + 8 * self.entries()
+    }
+
+    /// The index of the child or tile that `ijk` is in.
+    fn index(&self, ijk: [i32; 3]) -> usize {
+        let mask = (1 << (self.log2_dim + self.child_log2_dim)) - 1;
+        let [x, y, z] = ijk.map(|c| ((c & mask) >> self.child_log2_dim) as usize);
+        (x << (2 * self.log2_dim)) | (y << self.log2_dim) | z
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Value(f32),
+    /// The offset of a child node in the grid's data.
+    Child(usize),
+}
+
+/// A float grid read from a NanoVDB file, as a density in world space.
+pub struct VdbGrid {
+    data: Vec<u8>,
+    /// The root's tiles by key.
+    tiles: HashMap<u64, Entry>,
+    background: f32,
+    max_value: f32,
+    /// The lowest and highest active voxels.
+    index_bounds: [[i32; 3]; 2],
+    index_to_world: Transform,
+    bounds: Aabb,
+}
+
+impl VdbGrid {
+    /// Loads the grid called `name`, or the first float grid if there's no name.
+    pub fn load(path: &Path, name: Option<&str>) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(Error::file(path))?;
+        parse_file(&bytes, name).map_err(|message| Error::Vdb {
+            path: path.to_owned(),
+            message,
+        })
+    }
+
+    /// Moves the grid by `transform`, on top of the placement the file gives it.
+    pub fn transformed(mut self, transform: &Transform) -> Self {
+        self.index_to_world = transform.after(&self.index_to_world);
+        self.bounds = self.world_bounds();
+        self
+    }
+
+    fn from_data(data: Vec<u8>) -> Result<Self, String> {
+        if !data.starts_with(b"NanoVDB") {
+            return Err(String::from("a grid is corrupt"));
+        }
+        let read = Reader(&data);
+        if read.u32(636)? != GRID_TYPE_FLOAT {
+            return Err(String::from("only float grids are supported"));
+        }
+
+        // the double precision map from index to world space, row by row
+        let mut linear = [0.0; 9];
+        for (i, element) in linear.iter_mut().enumerate() {
+            *element = read.f64(384 + 8 * i)?;
+        }
+        let linear = Matrix3::from_row_slice(&linear);
+        if linear.try_inverse().is_none() {
+            return Err(String::from("the grid's transform isn't invertible"));
+        }
+        let translation = Vec3::new(read.f64(528)?, read.f64(536)?, read.f64(544)?);
+        let index_to_world = Transform::from_linear(linear, translation);
+
+        let tree = GRID_DATA_SIZE;
+        let root = offset(tree, read.i64(tree + 24)?)?;
+        let corner = |at| -> Result<[i32; 3], String> {
+            Ok([read.i32(at)?, read.i32(at + 4)?, read.i32(at + 8)?])
+        };
+        let index_bounds = [corner(root)?, corner(root + 12)?];
+        let tile_count = read.u32(root + 24)? as usize;
+        let background = read.f32(root + 28)?;
+
+        let mut tiles = HashMap::new();
+        for i in 0..tile_count {
+            let tile = root + 64 + 32 * i;
+            let entry = match read.i64(tile + 8)? {
+                0 => Entry::Value(read.f32(tile + 20)?),
+                child => Entry::Child(offset(root, child)?),
+            };
+            tiles.insert(read.u64(tile)?, entry);
+        }
+
+        let mut grid = Self {
+            data,
+            tiles,
+            background,
+            max_value: 0.0,
+            index_bounds,
+            index_to_world,
+            bounds: Aabb::new(Vec3::default(), Vec3::default()),
+        };
+        grid.max_value = grid.scan_max_value()?;
+        grid.bounds = grid.world_bounds();
+
+        Ok(grid)
+    }
+
+    /// The value of the voxel at `ijk` in index space.
+    fn value(&self, ijk: [i32; 3]) -> f32 {
+        self.lookup(ijk).unwrap_or(self.background)
+    }
+
+    fn lookup(&self, ijk: [i32; 3]) -> Option<f32> {
+        let read = Reader(&self.data);
+        let descend = |entry| match entry {
+            Entry::Value(value) => Err(value),
+            Entry::Child(node) => Ok(node),
+        };
+
+        let upper = match descend(*self.tiles.get(&root_key(ijk))?) {
+            Ok(node) => node,
+            Err(value) => return Some(value),
+        };
+        let lower = match descend(self.entry(upper, &UPPER, UPPER.index(ijk))?) {
+            Ok(node) => node,
+            Err(value) => return Some(value),
+        };
+        let leaf = match descend(self.entry(lower, &LOWER, LOWER.index(ijk))?) {
+            Ok(node) => node,
+            Err(value) => return Some(value),
+        };
+
+        let [x, y, z] = ijk.map(|c| (c & 7) as usize);
+        read.f32(leaf + LEAF_VALUES + 4 * ((x << 6) | (y << 3) | z))
+            .ok()
+    }
+
+    fn entry(&self, node: usize, kind: &InternalNode, index: usize) -> Option<Entry> {
+        let read = Reader(&self.data);
+        let mask = read.bytes(node + kind.child_mask() + index / 8, 1).ok()?[0];
+        let entry = node + kind.table + 8 * index;
+
+        if mask & (1 << (index % 8)) != 0 {
+            Some(Entry::Child(offset(node, read.i64(entry).ok()?).ok()?))
+        } else {
+            read.f32(entry).ok().map(Entry::Value)
+        }
+    }
+
+    /// The largest value anywhere in the grid, looking at every voxel and tile rather than
+    /// trusting statistics the file might not have.
+    fn scan_max_value(&self) -> Result<f32, String> {
+        let read = Reader(&self.data);
+        let tree = GRID_DATA_SIZE;
+        let mut max = self.background;
+        for entry in self.tiles.values() {
+            if let Entry::Value(value) = *entry {
+                max = max.max(value);
+            }
+        }
+
+        for (level, kind) in [(2, &UPPER), (1, &LOWER)] {
+            let count = read.u32(tree + 32 + 4 * level)? as usize;
+            let first = offset(tree, read.i64(tree + 8 * level)?)?;
+            read.bytes(first, count.saturating_mul(kind.size()))?;
+            for node in (0..count).map(|i| first + i * kind.size()) {
+                for index in 0..kind.entries() {
+                    if let Some(Entry::Value(value)) = self.entry(node, kind, index) {
+                        max = max.max(value);
+                    }
+                }
+            }
+        }
+
+        let leaves = read.u32(tree + 32)? as usize;
+        let first = offset(tree, read.i64(tree)?)?;
+        read.bytes(first, leaves.saturating_mul(LEAF_SIZE))?;
+        for leaf in (0..leaves).map(|i| first + i * LEAF_SIZE) {
+            for value in read.bytes(leaf + LEAF_VALUES, 4 * 512)?.chunks_exact(4) {
+                max = max.max(f32::from_le_bytes(value.try_into().unwrap()));
+            }
+        }
+
+        Ok(max)
+    }
+
+    /// A box around the active voxels, padded by one voxel for the interpolation.
+    fn world_bounds(&self) -> Aabb {
+        let [min, max] = self.index_bounds;
+        let min = Vec3::new(min[0] as f64, min[1] as f64, min[2] as f64) - Vec3::new(1.0, 1.0, 1.0);
+        let max = Vec3::new(max[0] as f64, max[1] as f64, max[2] as f64) + Vec3::new(1.0, 1.0, 1.0);
+
+        (0..8)
+            .map(|i| {
+                let pick = |bit, axis: usize| if i & bit == 0 { min[axis] } else { max[axis] };
+                let point = Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2));
+                let point = self.index_to_world.transform_point(point);
+                Aabb::new(point, point)
+            })
+            .reduce(|acc, bbox| acc.union(&bbox))
+            .unwrap()
+    }
+}
+
+impl DensityField for VdbGrid {
+    fn density(&self, point: Point3) -> f64 {
+        let index = self.index_to_world.inverse_transform_point(point);
+        let base = index.0.map(f64::floor);
+        let fraction = [0, 1, 2].map(|axis| index[axis] - base[axis]);
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut ijk = [0; 3];
+            for axis in 0..3 {
+                let high = corner & (1 << axis) != 0;
+                weight *= if high {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                ijk[axis] = base[axis] as i32 + high as i32;
+            }
+            if weight > 0.0 {
+                density += weight * self.value(ijk) as f64;
+            }
+        }
+
+        density.max(0.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_value.max(0.0) as f64
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn memory(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.data.capacity()
+            + self.tiles.capacity() * std::mem::size_of::<(u64, Entry)>()
+    }
+}
+
+fn root_key(ijk: [i32; 3]) -> u64 {
+    let [x, y, z] = ijk.map(|c| (c as u32 >> 12) as u64);
+    z | (y << 21) | (x << 42)
+}
+
+/// Applies a byte offset between nodes, which should always point forwards.
+fn offset(base: usize, offset: i64) -> Result<usize, String> {
+    let offset: usize = offset
+        .try_into()
+        .map_err(|_| String::from("a grid is corrupt"))?;
+    base.checked_add(offset)
+        .ok_or_else(|| String::from("a grid is corrupt"))
+}
+
+fn parse_file(bytes: &[u8], name: Option<&str>) -> Result<VdbGrid, String> {
+    let read = Reader(bytes);
+    if !bytes.starts_with(b"NanoVDB") {
+        return Err(String::from(
+            "not a NanoVDB file, OpenVDB files have to be converted with nanovdb_convert",
+        ));
+    }
+    let grid_count = read.u16(12)?;
+    let codec = read.u16(14)?;
+
+    // the metadata and names of every grid come first
+    let mut grids = Vec::new();
+    let mut position = FILE_HEADER_SIZE;
+    for _ in 0..grid_count {
+        let grid_size = read.u64(position)? as usize;
+        let grid_type = read.u32(position + 32)?;
+        let name_size = read.u32(position + 136)? as usize;
+        let grid_name = read.bytes(position + FILE_METADATA_SIZE, name_size)?;
+        let grid_name = String::from_utf8_lossy(grid_name)
+            .trim_end_matches('\0')
+            .to_owned();
+
+        grids.push((grid_name, grid_size, grid_type));
+        position += FILE_METADATA_SIZE + name_size;
+    }
+
+    for (grid_name, grid_size, grid_type) in grids {
+        let data = match codec {
+            CODEC_NONE => {
+                let data = read.bytes(position, grid_size)?;
+                position += grid_size;
+                data.to_vec()
+            }
+            CODEC_ZIP => {
+                let compressed_size = read.u64(position)? as usize;
+                let compressed = read.bytes(position + 8, compressed_size)?;
+                position += 8 + compressed_size;
+                miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(compressed, grid_size)
+                    .map_err(|_| String::from("a grid is corrupt"))?
+            }
+            _ => {
+                return Err(String::from(
+                    "only uncompressed and zlib files are supported",
+                ))
+            }
+        };
+
+        let wanted = match name {
+            Some(name) => grid_name == name,
+            None => grid_type == GRID_TYPE_FLOAT,
+        };
+        if wanted {
+            return VdbGrid::from_data(data);
+        }
+    }
+
+    Err(match name {
+        Some(name) => format!("there's no grid called {:?}", name),
+        None => String::from("there are no float grids"),
+    })
+}
+
+/// Reads little-endian values at offsets into a buffer, failing instead of reading past its end.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, at: usize, len: usize) -> Result<&'a [u8], String> {
+        at.checked_add(len)
+            .and_then(|end| self.0.get(at..end))
+            .ok_or_else(|| String::from("the file is truncated"))
+    }
+
+    fn array<const N: usize>(&self, at: usize) -> Result<[u8; N], String> {
+        Ok(self.bytes(at, N)?.try_into().unwrap())
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        self.array(at).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        self.array(at).map(u32::from_le_bytes)
+    }
+
+    fn i32(&self, at: usize) -> Result<i32, String> {
+        self.array(at).map(i32::from_le_bytes)
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, String> {
+        self.array(at).map(u64::from_le_bytes)
+    }
+
+    fn i64(&self, at: usize) -> Result<i64, String> {
+        self.array(at).map(i64::from_le_bytes)
+    }
+
+    fn f32(&self, at: usize) -> Result<f32, String> {
+        self.array(at).map(f32::from_le_bytes)
+    }
+
+    fn f64(&self, at: usize) -> Result<f64, String> {
+        self.array(at).map(f64::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(data: &mut [u8], at: usize, bytes: &[u8]) {
+        data[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A grid with two voxels, 4 at (1, 2, 3) and 2 at (2, 2, 3), half a unit wide and moved
+    /// by (1, 2, 3).
+    fn grid_data() -> Vec<u8> {
+        let tree = GRID_DATA_SIZE;
+        let root = tree + 64;
+        let upper = root + 64 + 32;
+        let lower = upper + UPPER.size();
+        let leaf = lower + LOWER.size();
+        let mut data = vec![0; leaf + LEAF_SIZE];
+
+        put(&mut data, 0, b"NanoVDB1");
+        put(&mut data, 636, &GRID_TYPE_FLOAT.to_le_bytes());
+        for (i, element) in [0.5, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.5]
+            .iter()
+            .enumerate()
+        {
+            put(&mut data, 384 + 8 * i, &f64::to_le_bytes(*element));
+        }
+        for (i, element) in [1.0, 2.0, 3.0].iter().enumerate() {
+            put(&mut data, 528 + 8 * i, &f64::to_le_bytes(*element));
+        }
+
+        // one node of each level, in order
+        for (level, node) in [leaf, lower, upper, root].iter().enumerate() {
+            put(
+                &mut data,
+                tree + 8 * level,
+                &((node - tree) as i64).to_le_bytes(),
+            );
+        }
+        for level in 0..3 {
+            put(&mut data, tree + 32 + 4 * level, &1u32.to_le_bytes());
+        }
+
+        for (i, c) in [1, 2, 3, 2, 2, 3].iter().enumerate() {
+            put(&mut data, root + 4 * i, &i32::to_le_bytes(*c));
+        }
+        put(&mut data, root + 24, &1u32.to_le_bytes());
+        put(
+            &mut data,
+            root + 64 + 8,
+            &((upper - root) as i64).to_le_bytes(),
+        );
+
+        for (node, kind, child) in [(upper, &UPPER, lower), (lower, &LOWER, leaf)] {
+            data[node + kind.child_mask()] = 1;
+            put(
+                &mut data,
+                node + kind.table,
+                &((child - node) as i64).to_le_bytes(),
+            );
+        }
+
+        put(
+            &mut data,
+            leaf + LEAF_VALUES + 4 * ((1 << 6) | (2 << 3) | 3),
+            &4f32.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            leaf + LEAF_VALUES + 4 * ((2 << 6) | (2 << 3) | 3),
+            &2f32.to_le_bytes(),
+        );
+        data
+    }
+
+    fn file(codec: u16, name: &str, grid: &[u8], stored: &[u8]) -> Vec<u8> {
+        let mut file = b"NanoVDB2".to_vec();
+        file.extend(0u32.to_le_bytes());
+        file.extend(1u16.to_le_bytes());
+        file.extend(codec.to_le_bytes());
+
+        let mut meta = vec![0; FILE_METADATA_SIZE];
+        put(&mut meta, 0, &(grid.len() as u64).to_le_bytes());
+        put(&mut meta, 32, &GRID_TYPE_FLOAT.to_le_bytes());
+        put(&mut meta, 136, &(name.len() as u32 + 1).to_le_bytes());
+        file.extend(meta);
+        file.extend(name.as_bytes());
+        file.push(0);
+        file.extend(stored);
+        file
+    }
+
+    #[test]
+    fn looks_up_voxels() {
+        let grid = VdbGrid::from_data(grid_data()).unwrap();
+
+        assert_eq!(grid.value([1, 2, 3]), 4.0);
+        assert_eq!(grid.value([2, 2, 3]), 2.0);
+        assert_eq!(grid.value([3, 2, 3]), 0.0);
+        assert_eq!(grid.value([-5000, 0, 0]), 0.0);
+        assert_eq!(grid.max_density(), 4.0);
+    }
+
+    #[test]
+    fn samples_in_world_space() {
+        let grid = VdbGrid::from_data(grid_data()).unwrap();
+
+        // index (1, 2, 3) is at 0.5 * (1, 2, 3) + (1, 2, 3)
+        assert!((grid.density(Point3::new(1.5, 3.0, 4.5)) - 4.0).abs() < 1e-9);
+        assert!((grid.density(Point3::new(1.75, 3.0, 4.5)) - 3.0).abs() < 1e-9);
+
+        let bounds = grid.bounds();
+        assert!(bounds.min.x() <= 1.0 && bounds.max.x() >= 2.5);
+    }
+
+    #[test]
+    fn reads_files() {
+        let grid = grid_data();
+
+        let plain = file(CODEC_NONE, "density", &grid, &grid);
+        let loaded = parse_file(&plain, Some("density")).unwrap();
+        assert_eq!(loaded.value([1, 2, 3]), 4.0);
+
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&grid, 6);
+        let mut stored = (compressed.len() as u64).to_le_bytes().to_vec();
+        stored.extend(compressed);
+        let zipped = file(CODEC_ZIP, "density", &grid, &stored);
+        let loaded = parse_file(&zipped, None).unwrap();
+        assert_eq!(loaded.value([2, 2, 3]), 2.0);
+
+        assert!(parse_file(&plain, Some("temperature")).is_err());
+        assert!(parse_file(&plain[..plain.len() - 1], None).is_err());
+        assert!(parse_file(b"#VDB", None).is_err());
+    }
+}
@@ -1,13 +1,14 @@
 use nalgebra::Vector3;
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use std::fmt::Display;
 use std::ops::{
     Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
 };
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Vec3(pub [f64; 3]);
 
 impl Vec3 {
@@ -60,7 +61,9 @@ impl Vec3 {
     }
 
     pub fn random_in_unit_sphere<R: rand::Rng + ?Sized>(rng: &mut R) -> Self {
-        let r: f64 = rng.gen();
+        // the cube root spreads the points evenly over the volume instead of bunching them up
+        // near the center
+        let r: f64 = rng.gen::<f64>().cbrt();
 
         r * Self::random_unit_vec(rng)
     }
@@ -71,20 +74,17 @@ impl Vec3 {
         let sin_theta = (1.0 - z * z).sqrt();
         let phi: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
 
-        let cos_phi = phi.cos();
-
-        let x = sin_theta * cos_phi;
-        let y = sin_theta * (1.0 - cos_phi * cos_phi).sqrt();
+        let x = sin_theta * phi.cos();
+        let y = sin_theta * phi.sin();
 
         Self([x, y, z])
     }
 
     pub fn random_in_unit_disk<R: rand::Rng + ?Sized>(rng: &mut R) -> Self {
-        let x: f64 = rng.gen_range(-1.0..1.0);
-        let y = (1.0 - x * x).sqrt();
-        let r: f64 = rng.gen();
+        let r: f64 = rng.gen::<f64>().sqrt();
+        let phi: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
 
-        Self([r * x, r * y, 0.0])
+        Self([r * phi.cos(), r * phi.sin(), 0.0])
     }
 
     #[inline(always)]
@@ -243,3 +243,84 @@ impl From<Vector3<f64>> for Vec3 {
         Self([v.x, v.y, v.z])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn vec3() -> impl Strategy<Value = Vec3> {
+        prop::array::uniform3(-100.0..100.0f64).prop_map(Vec3)
+    }
+
+    fn close(a: f64, b: f64, scale: f64) -> bool {
+        (a - b).abs() <= 1e-9 * scale.max(1.0)
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_gives_unit_length(v in vec3().prop_filter("zero", |v| v.length() > 1e-6)) {
+            prop_assert!(close(v.normalize().length(), 1.0, 1.0));
+        }
+
+        #[test]
+        fn cross_is_orthogonal(a in vec3(), b in vec3()) {
+            let c = a.cross(&b);
+            let scale = a.length() * b.length() * c.length();
+
+            prop_assert!(close(c.dot(&a), 0.0, scale));
+            prop_assert!(close(c.dot(&b), 0.0, scale));
+        }
+
+        #[test]
+        fn cross_is_anticommutative(a in vec3(), b in vec3()) {
+            prop_assert_eq!(a.cross(&b), -b.cross(&a));
+        }
+
+        #[test]
+        fn dot_is_commutative(a in vec3(), b in vec3()) {
+            prop_assert_eq!(a.dot(&b), b.dot(&a));
+        }
+
+        #[test]
+        fn random_vectors_stay_in_bounds(seed: u64) {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let unit = Vec3::random_unit_vec(&mut rng);
+            prop_assert!(close(unit.length(), 1.0, 1.0));
+
+            let ball = Vec3::random_in_unit_sphere(&mut rng);
+            prop_assert!(ball.length() <= 1.0);
+
+            let disk = Vec3::random_in_unit_disk(&mut rng);
+            prop_assert!(disk.length() <= 1.0);
+            prop_assert_eq!(disk.z(), 0.0);
+        }
+
+        #[test]
+        fn random_vectors_cover_every_direction(seed: u64) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut unit_signs = [[false; 2]; 3];
+            let mut disk_signs = [[false; 2]; 2];
+
+            // with 256 samples, missing a half space has a chance of 2^-256
+            for _ in 0..256 {
+                let unit = Vec3::random_unit_vec(&mut rng);
+                for (axis, signs) in unit_signs.iter_mut().enumerate() {
+                    signs[(unit[axis] < 0.0) as usize] = true;
+                }
+
+                let disk = Vec3::random_in_unit_disk(&mut rng);
+                for (axis, signs) in disk_signs.iter_mut().enumerate() {
+                    signs[(disk[axis] < 0.0) as usize] = true;
+                }
+            }
+
+            prop_assert_eq!(unit_signs, [[true; 2]; 3]);
+            prop_assert_eq!(disk_signs, [[true; 2]; 2]);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Voxel models from MagicaVoxel's `.vox` files.
+//!
+//! Only the first model of a file is read, without the transforms of the scene graph. MagicaVoxel
+//! is z-up, so models are turned to stand along the y axis. Each palette index becomes the
+//! closest material the renderer has to its color and `MATL` settings: metals and glass stay
+//! metals and glass, emissive materials become lights, and everything else is lambertian.
+
+use crate::collision::voxels::VoxelGrid;
+use crate::collision::Material;
+use crate::scene::MaterialDescription;
+use crate::{srgb, Color, Error, Point3, Result};
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The most voxels a model has along each axis, since positions are single bytes.
+const MAX_SIZE: u32 = 256;
+
+/// Loads the first model of the `.vox` file at `path` as a grid. Palette indices in `materials`
+/// get that material, the rest one that matches the palette.
+pub fn load(
+    path: &Path,
+    corner: Point3,
+    voxel_size: f64,
+    materials: &HashMap<u8, Arc<dyn Material>>,
+) -> Result<VoxelGrid> {
+    let bytes = std::fs::read(path).map_err(Error::file(path))?;
+    let vox = parse(&bytes).map_err(|message| Error::Vox {
+        path: path.to_owned(),
+        message,
+    })?;
+
+    let palette = (1..=255u8)
+        .map(|index| match materials.get(&index) {
+            Some(material) => Ok(material.clone()),
+            None => vox.describe(index).build(),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let [width, depth, height] = vox.size;
+    let mut grid = VoxelGrid::new(corner, voxel_size, [width, height, depth], palette)?;
+    for &[x, y, z, index] in &vox.voxels {
+        // z-up to y-up, without mirroring the model
+        grid.set([x as u32, z as u32, depth - 1 - y as u32], index);
+    }
+
+    Ok(grid)
+}
+
+struct Vox {
+    /// The size of the model along MagicaVoxel's x, y and z axes.
+    size: [u32; 3],
+    /// The position and palette index of every filled voxel, with every position in range.
+    voxels: Vec<[u8; 4]>,
+    /// Colors by palette index, where 0 is unused.
+    palette: [[u8; 4]; 256],
+    /// The `MATL` settings of palette indices.
+    materials: HashMap<u8, HashMap<String, String>>,
+}
+
+impl Vox {
+    fn describe(&self, index: u8) -> MaterialDescription {
+        let [r, g, b, _] = self.palette[index as usize];
+        let linear = |c: u8| srgb::decode(c as f64 / 255.0);
+        let color = Color::new(linear(r), linear(g), linear(b));
+
+        let settings = match self.materials.get(&index) {
+            Some(settings) => settings,
+            None => return MaterialDescription::Lambertian { albedo: color },
+        };
+        let number = |key: &str| {
+            settings
+                .get(key)
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+
+        match settings.get("_type").map(String::as_str) {
+            Some("_metal") => MaterialDescription::Metal {
+                albedo: color,
+                fuzz: number("_rough").unwrap_or(0.0).clamp(0.0, 1.0),
+            },
+            Some("_glass") => MaterialDescription::Dielectric {
+                // older files only have _ior, which is one less than the index of refraction
+                ri: number("_ri")
+                    .or_else(|| number("_ior").map(|ior| ior + 1.0))
+                    .unwrap_or(1.5),
+            },
+            Some("_emit") => {
+                let strength =
+                    number("_emit").unwrap_or(1.0) * (1.0 + number("_flux").unwrap_or(0.0));
+                MaterialDescription::DiffuseLight {
+                    emit: color * strength.max(0.0),
+                    double_sided: true,
+                    illuminant: None,
+                    ies: None,
+                    texture: None,
+                }
+            }
+            _ => MaterialDescription::Lambertian { albedo: color },
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<Vox, String> {
+    let mut input = Reader(bytes);
+    if input.take(4)? != b"VOX " {
+        return Err(String::from("not a MagicaVoxel file"));
+    }
+    let _version = input.u32()?;
+
+    let main = input.chunk()?;
+    if main.id != b"MAIN" {
+        return Err(String::from("expected a MAIN chunk"));
+    }
+
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = None;
+    let mut materials = HashMap::new();
+    let mut input = Reader(main.children);
+    while !input.0.is_empty() {
+        let chunk = input.chunk()?;
+        let mut content = Reader(chunk.content);
+        match chunk.id {
+            // the first SIZE and XYZI pair is the first model
+            b"SIZE" if size.is_none() => {
+                size = Some([content.u32()?, content.u32()?, content.u32()?]);
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = content.u32()? as usize;
+                let data = content.take(count.checked_mul(4).ok_or("too many voxels")?)?;
+                voxels = Some(
+                    data.chunks_exact(4)
+                        .map(|voxel| [voxel[0], voxel[1], voxel[2], voxel[3]])
+                        .collect::<Vec<_>>(),
+                );
+            }
+            b"RGBA" => {
+                // the colors of indices 1 to 255, and one that's never used
+                let mut colors = [[0; 4]; 256];
+                for (i, color) in content.take(256 * 4)?.chunks_exact(4).take(255).enumerate() {
+                    colors[i + 1] = color.try_into().unwrap();
+                }
+                palette = Some(colors);
+            }
+            b"MATL" => {
+                let index = content.u32()?;
+                let settings = content.dict()?;
+                if let Ok(index @ 1..=255) = u8::try_from(index) {
+                    materials.insert(index, settings);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let size = size.ok_or("the file has no models")?;
+    let voxels = voxels.ok_or("the model has no XYZI chunk")?;
+    if size.contains(&0) {
+        return Err(String::from("the model is empty"));
+    }
+    if size.iter().any(|&dim| dim > MAX_SIZE) {
+        return Err(format!(
+            "the model is {}x{}x{}, but can be at most {} voxels along each axis",
+            size[0], size[1], size[2], MAX_SIZE
+        ));
+    }
+    for &[x, y, z, _] in &voxels {
+        let position = [x as u32, y as u32, z as u32];
+        if (0..3).any(|axis| position[axis] >= size[axis]) {
+            return Err(format!(
+                "voxel ({}, {}, {}) is outside of the model",
+                x, y, z
+            ));
+        }
+    }
+
+    Ok(Vox {
+        size,
+        voxels: voxels.into_iter().filter(|voxel| voxel[3] != 0).collect(),
+        palette: palette.unwrap_or_else(default_palette),
+        materials,
+    })
+}
+
+/// The palette of files without an `RGBA` chunk: a 6x6x6 color cube without black, followed by
+/// ramps of red, green, blue and gray.
+fn default_palette() -> [[u8; 4]; 256] {
+    const LEVELS: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut colors = Vec::with_capacity(256);
+    colors.push([0; 4]);
+    for r in LEVELS {
+        for g in LEVELS {
+            for b in LEVELS {
+                colors.push([r, g, b, 0xff]);
+            }
+        }
+    }
+    colors.pop();
+    for channel in 0..4 {
+        for level in RAMP {
+            let mut color = [0, 0, 0, 0xff];
+            match channel {
+                3 => color[..3].fill(level),
+                _ => color[channel] = level,
+            }
+            colors.push(color);
+        }
+    }
+
+    colors.try_into().unwrap()
+}
+
+/// Reads the little-endian values `.vox` files are made of.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err(String::from("the file is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, String> {
+        let len = self.u32()?;
+        (0..len)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn chunk(&mut self) -> Result<Chunk<'a>, String> {
+        let id = self.take(4)?;
+        let content_len = self.u32()? as usize;
+        let children_len = self.u32()? as usize;
+        Ok(Chunk {
+            id,
+            content: self.take(content_len)?,
+            children: self.take(children_len)?,
+        })
+    }
+}
+
+struct Chunk<'a> {
+    id: &'a [u8],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((content.len() as u32).to_le_bytes());
+        chunk.extend((children.len() as u32).to_le_bytes());
+        chunk.extend(content);
+        chunk.extend(children);
+        chunk
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut bytes = (text.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(text.as_bytes());
+        bytes
+    }
+
+    fn file(children: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = b"VOX ".to_vec();
+        file.extend(150u32.to_le_bytes());
+        file.extend(chunk(b"MAIN", &[], &children.concat()));
+        file
+    }
+
+    fn size(x: u32, y: u32, z: u32) -> Vec<u8> {
+        let content = [x, y, z].map(u32::to_le_bytes).concat();
+        chunk(b"SIZE", &content, &[])
+    }
+
+    fn xyzi(voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut content = (voxels.len() as u32).to_le_bytes().to_vec();
+        content.extend(voxels.concat());
+        chunk(b"XYZI", &content, &[])
+    }
+
+    #[test]
+    fn reads_the_first_model() {
+        let bytes = file(&[
+            size(2, 3, 4),
+            xyzi(&[[0, 0, 0, 1], [1, 2, 3, 5]]),
+            size(1, 1, 1),
+            xyzi(&[[0, 0, 0, 7]]),
+        ]);
+        let vox = parse(&bytes).unwrap();
+
+        assert_eq!(vox.size, [2, 3, 4]);
+        assert_eq!(vox.voxels, [[0, 0, 0, 1], [1, 2, 3, 5]]);
+        assert_eq!(vox.palette[1], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(vox.palette[2], [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(vox.palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn palettes_and_materials() {
+        let mut colors = vec![0; 256 * 4];
+        colors[..4].copy_from_slice(&[255, 0, 0, 255]);
+        colors[4..8].copy_from_slice(&[0, 255, 0, 255]);
+
+        let mut matl = 2u32.to_le_bytes().to_vec();
+        matl.extend(2u32.to_le_bytes());
+        for text in ["_type", "_metal", "_rough", "0.25"] {
+            matl.extend(string(text));
+        }
+
+        let bytes = file(&[
+            size(1, 1, 1),
+            xyzi(&[[0, 0, 0, 1]]),
+            chunk(b"RGBA", &colors, &[]),
+            chunk(b"MATL", &matl, &[]),
+        ]);
+        let vox = parse(&bytes).unwrap();
+
+        assert_eq!(
+            vox.describe(1),
+            MaterialDescription::Lambertian {
+                albedo: Color::new(1.0, 0.0, 0.0)
+            }
+        );
+        assert_eq!(
+            vox.describe(2),
+            MaterialDescription::Metal {
+                albedo: Color::new(0.0, 1.0, 0.0),
+                fuzz: 0.25
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(parse(b"PNG ").is_err());
+        assert!(parse(&file(&[size(1, 1, 1)])).is_err());
+        assert!(parse(&file(&[size(1, 1, 1), xyzi(&[[1, 0, 0, 1]])])).is_err());
+        assert!(parse(&file(&[size(1, 1 << 20, 1), xyzi(&[[0, 0, 0, 1]])])).is_err());
+
+        let mut truncated = file(&[size(1, 1, 1), xyzi(&[[0, 0, 0, 1]])]);
+        truncated.truncate(truncated.len() - 2);
+        assert!(parse(&truncated).is_err());
+    }
+}
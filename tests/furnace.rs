@@ -0,0 +1,161 @@
+//! White furnace tests: a single sphere under a sky that's uniformly white in every direction.
+//!
+//! A material that neither absorbs nor emits light passes on exactly as much of it as it gets,
+//! so with nothing else around, the sphere should vanish into the sky. Any material that comes
+//! out brighter creates energy, and one that comes out darker loses it, which is what a badly
+//! normalized BRDF, or a mismatched sampling density, looks like.
+
+use ray_tracing::collision::graph::{Bsdf, Node};
+use ray_tracing::collision::materials::{
+    Dielectric, Hair, Isotropic, Lambertian, Metal, MetallicRoughness, TexturedLambertian,
+};
+use ray_tracing::collision::objects::Sphere;
+use ray_tracing::collision::Material;
+use ray_tracing::render::{render, RenderSettings};
+use ray_tracing::sky::Gradient;
+use ray_tracing::texture::SolidColor;
+use ray_tracing::*;
+
+use std::sync::Arc;
+
+const SIZE: u32 = 16;
+/// How far the average over the sphere may be from the sky, relative to it.
+const MEAN_TOLERANCE: f64 = 0.01;
+/// How far any single pixel may be off, for materials whose samples don't all carry the same
+/// weight.
+const PIXEL_TOLERANCE: f64 = 0.1;
+
+/// Renders `material` on a sphere filling most of the image, under a white sky, and checks that
+/// the sphere can't be told apart from the sky.
+fn furnace(name: &str, material: Arc<dyn Material>) {
+    let sky = Color::new(1.0, 1.0, 1.0);
+    let world = Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material,
+    };
+    let camera = Camera::builder()
+        .look_from(Point3::new(0.0, 0.0, 3.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vertical_fov(40.0)
+        .aspect_ratio(1.0)
+        .build()
+        .unwrap();
+    let settings = RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        samples_per_pixel: 64,
+        // deep enough that paths caught inside glass practically always get out
+        max_depth: 100,
+        show_progress: false,
+        seed: Some(1),
+        background: Arc::new(Gradient {
+            horizon: sky,
+            zenith: sky,
+        }),
+        ..Default::default()
+    };
+
+    let pixels = render(&world, &camera, &settings);
+    let mut total = Color::new(0.0, 0.0, 0.0);
+    for (index, pixel) in pixels.iter().enumerate() {
+        let error = (pixel.color - sky).length() / sky.length();
+        assert!(
+            error <= PIXEL_TOLERANCE,
+            "{} isn't white in the furnace: pixel {} is {:?}",
+            name,
+            index,
+            pixel.color
+        );
+        total += pixel.color;
+    }
+
+    let mean = total / pixels.len() as f64;
+    let error = (mean - sky).length() / sky.length();
+    assert!(
+        error <= MEAN_TOLERANCE,
+        "{} isn't white in the furnace: the mean is {:?}",
+        name,
+        mean
+    );
+}
+
+fn white() -> Color {
+    Color::new(1.0, 1.0, 1.0)
+}
+
+#[test]
+fn lambertian() {
+    furnace("lambertian", Arc::new(Lambertian::new(white()).unwrap()));
+}
+
+#[test]
+fn textured_lambertian() {
+    furnace(
+        "textured lambertian",
+        Arc::new(TexturedLambertian {
+            texture: Arc::new(SolidColor(white())),
+        }),
+    );
+}
+
+#[test]
+fn metal() {
+    furnace("mirror", Arc::new(Metal::new(white(), 0.0).unwrap()));
+    furnace("fuzzy metal", Arc::new(Metal::new(white(), 0.5).unwrap()));
+}
+
+#[test]
+fn metallic_roughness() {
+    let texture = |value: f64| Arc::new(SolidColor(Color::new(value, value, value)));
+    furnace(
+        "metallic roughness",
+        Arc::new(MetallicRoughness {
+            base_color: texture(1.0),
+            metallic: texture(0.5),
+            roughness: texture(0.3),
+        }),
+    );
+}
+
+#[test]
+fn dielectric() {
+    furnace("glass", Arc::new(Dielectric::new(1.5).unwrap()));
+}
+
+#[test]
+fn isotropic() {
+    furnace("isotropic", Arc::new(Isotropic::new(white()).unwrap()));
+}
+
+#[test]
+fn hair() {
+    furnace("hair", Arc::new(Hair::new(white(), 0.3).unwrap()));
+}
+
+#[test]
+fn graph() {
+    let white = Arc::new(Node::value(1.0));
+    furnace(
+        "clear coat graph",
+        Arc::new(Bsdf::Mix {
+            factor: Arc::new(Node::Fresnel { ior: 1.5 }),
+            a: Arc::new(Bsdf::Diffuse {
+                color: white.clone(),
+            }),
+            b: Arc::new(Bsdf::Metal {
+                color: white,
+                roughness: Arc::new(Node::value(0.1)),
+            }),
+        }),
+    );
+}
+
+#[test]
+#[should_panic(expected = "isn't white in the furnace")]
+fn catches_absorption() {
+    furnace(
+        "gray lambertian",
+        Arc::new(Lambertian::new(Color::new(0.9, 0.9, 0.9)).unwrap()),
+    );
+}
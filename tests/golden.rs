@@ -0,0 +1,169 @@
+//! Renders a few tiny scenes with a fixed seed and compares them against the images in
+//! `tests/golden`.
+//!
+//! After an intentional change to the output, regenerate the images with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and check the new ones by eye before committing them.
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::collision::materials::{DiffuseLight, Lambertian, Metal};
+use ray_tracing::collision::objects::{Parallelogram, Sphere, Torus};
+use ray_tracing::collision::Hittable;
+use ray_tracing::output;
+use ray_tracing::render::{render, RenderMode, RenderSettings};
+use ray_tracing::scene::Scene;
+use ray_tracing::*;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 36;
+
+/// How far a channel may be off before the pixel counts as different. Paths can diverge between
+/// platforms on the last bits of a float, so a few pixels are allowed to be far off as well.
+const CHANNEL_TOLERANCE: u8 = 8;
+const MAX_DIFFERENT_PIXELS: f64 = 0.01;
+const MAX_MEAN_ERROR: f64 = 1.0;
+
+fn settings(mode: RenderMode, samples_per_pixel: u32) -> RenderSettings {
+    RenderSettings {
+        width: WIDTH,
+        height: HEIGHT,
+        samples_per_pixel,
+        mode,
+        show_progress: false,
+        seed: Some(1),
+        ..Default::default()
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn check<T>(name: &str, world: &T, camera: &Camera, settings: &RenderSettings)
+where
+    T: Hittable + Sync + ?Sized,
+{
+    let pixels = render(world, camera, settings);
+    let expected_path = golden_path(name);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        output::save(&expected_path, WIDTH, HEIGHT, &pixels, &Default::default()).unwrap();
+        return;
+    }
+
+    // go through an 8-bit PNG, so that the comparison sees exactly what got saved
+    let actual_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+    output::save(&actual_path, WIDTH, HEIGHT, &pixels, &Default::default()).unwrap();
+    let actual = image::open(&actual_path).unwrap().to_rgb8();
+    let expected = image::open(&expected_path)
+        .unwrap_or_else(|err| panic!("couldn't open {}: {}", expected_path.display(), err))
+        .to_rgb8();
+    assert_eq!(actual.dimensions(), expected.dimensions());
+
+    let mut different = 0;
+    let mut error = 0u64;
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        let diffs = a.0.iter().zip(&e.0).map(|(&a, &e)| a.abs_diff(e));
+        let max = diffs.clone().max().unwrap();
+        error += diffs.map(u64::from).sum::<u64>();
+        if max > CHANNEL_TOLERANCE {
+            different += 1;
+        }
+    }
+
+    let channels = (WIDTH * HEIGHT * 3) as f64;
+    let different = different as f64 / (WIDTH * HEIGHT) as f64;
+    let mean_error = error as f64 / channels;
+    assert!(
+        different <= MAX_DIFFERENT_PIXELS && mean_error <= MAX_MEAN_ERROR,
+        "{} differs from {}: {:.1}% of the pixels are off, with a mean error of {:.2}",
+        actual_path.display(),
+        expected_path.display(),
+        different * 100.0,
+        mean_error
+    );
+}
+
+fn spheres() -> (Bvh, Camera) {
+    let scene = Scene::load(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/spheres.json")).unwrap();
+    let camera = scene
+        .camera_builder()
+        .aspect_ratio(WIDTH as f64 / HEIGHT as f64)
+        .build()
+        .unwrap();
+
+    (Bvh::new(scene.world().unwrap()), camera)
+}
+
+#[test]
+fn spheres_shaded() {
+    let (world, camera) = spheres();
+    check(
+        "spheres_shaded",
+        &world,
+        &camera,
+        &settings(RenderMode::Shaded, 32),
+    );
+}
+
+#[test]
+fn spheres_normals() {
+    let (world, camera) = spheres();
+    check(
+        "spheres_normals",
+        &world,
+        &camera,
+        &settings(RenderMode::Normals, 1),
+    );
+}
+
+#[test]
+fn torus_lit() {
+    let white = Arc::new(Lambertian {
+        albedo: Color::new(0.73, 0.73, 0.73),
+    });
+    let world: Vec<Arc<dyn Hittable + Send + Sync>> = vec![
+        Arc::new(Sphere {
+            center: Point3::new(0.0, -100.3, 0.0),
+            radius: 100.0,
+            material: white.clone(),
+        }),
+        Arc::new(Torus {
+            center: Point3::new(0.0, 0.0, 0.0),
+            major_radius: 0.5,
+            minor_radius: 0.2,
+            material: Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.2).unwrap()),
+        }),
+        Arc::new(Parallelogram::new(
+            Point3::new(0.9, -0.3, -0.2),
+            Vec3::new(0.4, 0.0, 0.0),
+            Vec3::new(0.0, 0.4, 0.0),
+            Vec3::new(0.0, 0.0, 0.4),
+            white,
+        )),
+        Arc::new(Sphere {
+            center: Point3::new(-1.0, 1.2, 0.5),
+            radius: 0.4,
+            material: Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        }),
+    ];
+    let world = Bvh::new(world);
+    let camera = Camera::builder()
+        .look_from(Point3::new(0.5, 1.5, 3.0))
+        .look_at(Point3::new(0.3, 0.0, 0.0))
+        .vertical_fov(40.0)
+        .aspect_ratio(WIDTH as f64 / HEIGHT as f64)
+        .build()
+        .unwrap();
+
+    check(
+        "torus_lit",
+        &world,
+        &camera,
+        &settings(RenderMode::Shaded, 32),
+    );
+}
@@ -0,0 +1,90 @@
+//! A sphere resting on a shadow catcher, rendered over a transparent background: only its shadow
+//! and the sphere itself should end up in the image.
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::collision::materials::{Lambertian, ShadowCatcher};
+use ray_tracing::collision::objects::{Parallelogram, Sphere};
+use ray_tracing::render::{render, Pixel, RenderSettings};
+use ray_tracing::*;
+
+use std::sync::Arc;
+
+const SIZE: u32 = 32;
+
+fn render_catcher(transparent_background: bool) -> Vec<Pixel> {
+    let gray = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)).unwrap());
+    let world = Bvh::new(vec![
+        Arc::new(Sphere {
+            center: Point3::new(0.0, 0.5, 0.0),
+            radius: 0.5,
+            material: gray.clone(),
+        }),
+        Arc::new(Parallelogram::new(
+            Point3::new(-50.0, -1.0, -50.0),
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 100.0),
+            Arc::new(ShadowCatcher { surface: gray }),
+        )),
+    ]);
+    // looking straight down, with the sphere in the middle
+    let camera = Camera::builder()
+        .look_from(Point3::new(0.0, 6.0, 0.0))
+        .look_at(Point3::new(0.0, 0.0, 0.0))
+        .vup(Vec3::new(0.0, 0.0, -1.0))
+        .vertical_fov(40.0)
+        .aspect_ratio(1.0)
+        .build()
+        .unwrap();
+    let settings = RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        samples_per_pixel: 64,
+        show_progress: false,
+        seed: Some(1),
+        transparent_background,
+        shadow_catchers: true,
+        ..Default::default()
+    };
+
+    render(&world, &camera, &settings)
+}
+
+/// The pixel `offset` pixels to the right of the center of the image.
+fn pixel(pixels: &[Pixel], offset: u32) -> Pixel {
+    let middle = SIZE / 2;
+    pixels[(middle * SIZE + middle + offset) as usize]
+}
+
+#[test]
+fn catches_shadows() {
+    let pixels = render_catcher(true);
+
+    let sphere = pixel(&pixels, 0);
+    assert_eq!(sphere.alpha, 1.0);
+
+    // right next to the sphere, where it blocks some of the sky
+    let shadow = pixel(&pixels, 4);
+    assert!(
+        (0.1..0.5).contains(&shadow.alpha),
+        "{} isn't a soft shadow",
+        shadow.alpha
+    );
+
+    // in the corner, where it hardly blocks any
+    let open = pixels[0];
+    assert!(open.alpha < 0.1, "{} isn't open ground", open.alpha);
+    // the light of the sphere reflected onto the catcher, premultiplied by its alpha
+    assert!(open.color.length() <= open.alpha);
+}
+
+#[test]
+fn shows_the_sky_behind_the_catcher() {
+    let pixels = render_catcher(false);
+    let open = pixels[0];
+    assert_eq!(open.alpha, 1.0);
+
+    // the default sky looking straight down, hardly darkened
+    let sky = Color::new(1.0, 1.0, 1.0);
+    assert!((open.color - sky).length() < 0.2, "{:?}", open.color);
+}
@@ -0,0 +1,73 @@
+//! The renderer compiled to WebAssembly. Each web worker of the demo in `index.html` owns a
+//! [`WebRenderer`] and renders passes of one sample per pixel with it, which the page averages
+//! into the canvas as they come in.
+
+use ray_tracing::collision::bvh::Bvh;
+use ray_tracing::render::{RenderSettings, Renderer};
+use ray_tracing::scene::Scene;
+
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WebRenderer {
+    renderer: Renderer<Bvh>,
+    settings: RenderSettings,
+}
+
+#[wasm_bindgen]
+impl WebRenderer {
+    /// Parses a scene in the format of `scenes/spheres.json`, to be rendered at `width` by
+    /// `height` pixels.
+    #[wasm_bindgen(constructor)]
+    pub fn new(scene: &str, width: u32, height: u32) -> Result<WebRenderer, JsError> {
+        let scene: Scene = scene.parse()?;
+        let world = Bvh::new(scene.world()?);
+        let camera = scene
+            .focus(scene.camera_builder(), &world)?
+            .aspect_ratio(width as f64 / height as f64)
+            .build()?;
+
+        let mut settings = RenderSettings {
+            width,
+            height,
+            samples_per_pixel: 1,
+            show_progress: false,
+            ..Default::default()
+        };
+        scene.apply(&mut settings)?;
+
+        Ok(Self {
+            renderer: Renderer::new(world, camera),
+            settings,
+        })
+    }
+
+    /// Renders a single sample per pixel, as linear RGBA floats starting from the top row.
+    /// Passes with different seeds are independent, so averaging them converges to the full
+    /// render.
+    pub fn render_pass(&self, seed: u32) -> Vec<f32> {
+        let settings = RenderSettings {
+            seed: Some(seed as u64),
+            ..self.settings.clone()
+        };
+
+        self.renderer.render(&settings).into_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = include_str!("../../scenes/spheres.json");
+
+    #[test]
+    fn passes_depend_on_the_seed() {
+        let renderer = WebRenderer::new(SCENE, 8, 4).unwrap();
+
+        let first = renderer.render_pass(0);
+        assert_eq!(first.len(), 8 * 4 * 4);
+        assert_eq!(first, renderer.render_pass(0));
+        assert_ne!(first, renderer.render_pass(1));
+    }
+}
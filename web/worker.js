@@ -0,0 +1,21 @@
+// Renders passes of one sample per pixel until the page terminates the worker. Worker `first` of
+// `step` uses the seeds first, first + step, first + 2 * step and so on, so no two workers ever
+// render the same pass.
+import init, { WebRenderer } from "./pkg/ray_tracing_web.js";
+
+onmessage = async ({ data: { scene, width, height, first, step } }) => {
+    await init();
+
+    let renderer;
+    try {
+        renderer = new WebRenderer(scene, width, height);
+    } catch (error) {
+        postMessage({ error: error.message });
+        return;
+    }
+
+    for (let seed = first; ; seed += step) {
+        const pixels = renderer.render_pass(seed);
+        postMessage({ pixels }, [pixels.buffer]);
+    }
+};